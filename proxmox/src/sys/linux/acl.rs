@@ -0,0 +1,447 @@
+//! POSIX draft ACLs, see `acl(5)`.
+//!
+//! The kernel exposes ACLs as the extended attributes `system.posix_acl_access` and
+//! `system.posix_acl_default` in a binary format, which is what this module reads and writes.
+//! No `libacl` is required.
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::acl::{self, AclPerm, AclTag, AclType};
+//! # fn code() -> std::io::Result<()> {
+//! let mut acl = match acl::get_acl("/srv/data", AclType::Access)? {
+//!     Some(acl) => acl,
+//!     None => acl::Acl::from_mode(0o750),
+//! };
+//! acl.set(AclTag::User(1000), AclPerm::READ | AclPerm::EXECUTE);
+//! acl.update_mask();
+//! acl::set_acl("/srv/data", AclType::Access, &acl)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryInto;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::io::RawFd;
+
+use nix::NixPath;
+
+use crate::c_str;
+use crate::sys::linux::xattr;
+
+const ACL_EA_VERSION: u32 = 0x0002;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+/// Which of a file's ACLs to access.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AclType {
+    /// The ACL used for permission checks.
+    Access,
+    /// The ACL inherited by new files in a directory.
+    Default,
+}
+
+impl AclType {
+    /// The name of the extended attribute holding this ACL.
+    pub fn xattr_name(self) -> &'static CStr {
+        match self {
+            AclType::Access => c_str!("system.posix_acl_access"),
+            AclType::Default => c_str!("system.posix_acl_default"),
+        }
+    }
+}
+
+/// Read, write and execute permissions of an ACL entry.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AclPerm(u16);
+
+impl AclPerm {
+    pub const READ: AclPerm = AclPerm(0o4);
+    pub const WRITE: AclPerm = AclPerm(0o2);
+    pub const EXECUTE: AclPerm = AclPerm(0o1);
+
+    /// No permissions.
+    pub const fn empty() -> Self {
+        AclPerm(0)
+    }
+
+    /// All permissions.
+    pub const fn all() -> Self {
+        AclPerm(0o7)
+    }
+
+    /// Take the permissions from the lower 3 bits of `bits`, eg. `AclPerm::from_bits(mode >> 6)`.
+    pub const fn from_bits(bits: u32) -> Self {
+        AclPerm((bits & 0o7) as u16)
+    }
+
+    /// The permissions as `rwx` bits.
+    pub const fn bits(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Whether all permissions of `other` are contained.
+    pub fn contains(self, other: AclPerm) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The permissions contained in both `self` and `other`.
+    pub fn intersection(self, other: AclPerm) -> AclPerm {
+        AclPerm(self.0 & other.0)
+    }
+}
+
+impl BitOr for AclPerm {
+    type Output = AclPerm;
+
+    fn bitor(self, other: AclPerm) -> AclPerm {
+        AclPerm(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for AclPerm {
+    fn bitor_assign(&mut self, other: AclPerm) {
+        self.0 |= other.0;
+    }
+}
+
+impl fmt::Display for AclPerm {
+    /// Formats as `rwx`, with `-` for missing permissions.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |perm, c| if self.contains(perm) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(AclPerm::READ, 'r'),
+            flag(AclPerm::WRITE, 'w'),
+            flag(AclPerm::EXECUTE, 'x')
+        )
+    }
+}
+
+/// Whom an ACL entry applies to. The variants are ordered as the kernel expects the entries.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AclTag {
+    /// The file owner, corresponds to the owner bits of the mode.
+    UserObj,
+    /// A user by id.
+    User(u32),
+    /// The owning group, corresponds to the group bits of the mode if there is no mask.
+    GroupObj,
+    /// A group by id.
+    Group(u32),
+    /// The upper bound of permissions granted by named entries and the owning group. Corresponds
+    /// to the group bits of the mode.
+    Mask,
+    /// Everybody else, corresponds to the other bits of the mode.
+    Other,
+}
+
+impl AclTag {
+    fn raw(self) -> (u16, u32) {
+        match self {
+            AclTag::UserObj => (ACL_USER_OBJ, ACL_UNDEFINED_ID),
+            AclTag::User(uid) => (ACL_USER, uid),
+            AclTag::GroupObj => (ACL_GROUP_OBJ, ACL_UNDEFINED_ID),
+            AclTag::Group(gid) => (ACL_GROUP, gid),
+            AclTag::Mask => (ACL_MASK, ACL_UNDEFINED_ID),
+            AclTag::Other => (ACL_OTHER, ACL_UNDEFINED_ID),
+        }
+    }
+
+    fn from_raw(tag: u16, id: u32) -> Option<Self> {
+        Some(match tag {
+            ACL_USER_OBJ => AclTag::UserObj,
+            ACL_USER => AclTag::User(id),
+            ACL_GROUP_OBJ => AclTag::GroupObj,
+            ACL_GROUP => AclTag::Group(id),
+            ACL_MASK => AclTag::Mask,
+            ACL_OTHER => AclTag::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// A single ACL entry.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perm: AclPerm,
+}
+
+impl fmt::Display for AclEntry {
+    /// Formats like `getfacl` with numeric ids, eg. `user:1000:r-x`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tag {
+            AclTag::UserObj => write!(f, "user::{}", self.perm),
+            AclTag::User(uid) => write!(f, "user:{}:{}", uid, self.perm),
+            AclTag::GroupObj => write!(f, "group::{}", self.perm),
+            AclTag::Group(gid) => write!(f, "group:{}:{}", gid, self.perm),
+            AclTag::Mask => write!(f, "mask::{}", self.perm),
+            AclTag::Other => write!(f, "other::{}", self.perm),
+        }
+    }
+}
+
+/// An access control list. Entries are kept sorted by their tag.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Create the minimal ACL equivalent to the permission bits of `mode`.
+    pub fn from_mode(mode: u32) -> Self {
+        Self {
+            entries: vec![
+                AclEntry {
+                    tag: AclTag::UserObj,
+                    perm: AclPerm::from_bits(mode >> 6),
+                },
+                AclEntry {
+                    tag: AclTag::GroupObj,
+                    perm: AclPerm::from_bits(mode >> 3),
+                },
+                AclEntry {
+                    tag: AclTag::Other,
+                    perm: AclPerm::from_bits(mode),
+                },
+            ],
+        }
+    }
+
+    /// Decode the extended attribute representation.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid posix acl");
+
+        if data.len() < 4 || (data.len() - 4) % 8 != 0 {
+            return Err(invalid());
+        }
+        if u32::from_le_bytes(data[0..4].try_into().unwrap()) != ACL_EA_VERSION {
+            return Err(invalid());
+        }
+
+        let mut acl = Acl::default();
+        for raw in data[4..].chunks_exact(8) {
+            let tag = u16::from_le_bytes(raw[0..2].try_into().unwrap());
+            let perm = u16::from_le_bytes(raw[2..4].try_into().unwrap());
+            let id = u32::from_le_bytes(raw[4..8].try_into().unwrap());
+            let tag = AclTag::from_raw(tag, id).ok_or_else(invalid)?;
+            acl.set(tag, AclPerm::from_bits(u32::from(perm)));
+        }
+        Ok(acl)
+    }
+
+    /// Encode as extended attribute.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 + 8 * self.entries.len());
+        data.extend_from_slice(&ACL_EA_VERSION.to_le_bytes());
+        for entry in self.entries.iter() {
+            let (tag, id) = entry.tag.raw();
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&entry.perm.0.to_le_bytes());
+            data.extend_from_slice(&id.to_le_bytes());
+        }
+        data
+    }
+
+    /// The entries, sorted by their tag.
+    pub fn entries(&self) -> &[AclEntry] {
+        &self.entries
+    }
+
+    /// Get the permissions of an entry.
+    pub fn get(&self, tag: AclTag) -> Option<AclPerm> {
+        self.entries
+            .binary_search_by(|entry| entry.tag.cmp(&tag))
+            .ok()
+            .map(|pos| self.entries[pos].perm)
+    }
+
+    /// Add an entry, or change its permissions if it exists.
+    pub fn set(&mut self, tag: AclTag, perm: AclPerm) {
+        match self.entries.binary_search_by(|entry| entry.tag.cmp(&tag)) {
+            Ok(pos) => self.entries[pos].perm = perm,
+            Err(pos) => self.entries.insert(pos, AclEntry { tag, perm }),
+        }
+    }
+
+    /// Remove an entry, returns its permissions if it existed.
+    pub fn remove(&mut self, tag: AclTag) -> Option<AclPerm> {
+        match self.entries.binary_search_by(|entry| entry.tag.cmp(&tag)) {
+            Ok(pos) => Some(self.entries.remove(pos).perm),
+            Err(_) => None,
+        }
+    }
+
+    /// Whether the ACL contains more than the entries corresponding to the file mode.
+    pub fn is_extended(&self) -> bool {
+        self.entries.iter().any(|entry| match entry.tag {
+            AclTag::User(_) | AclTag::Group(_) | AclTag::Mask => true,
+            _ => false,
+        })
+    }
+
+    /// Set the mask entry to the union of the permissions of all named entries and the owning
+    /// group, like `setfacl` does by default. Removes the mask if it is not needed.
+    pub fn update_mask(&mut self) {
+        if !self.is_extended() {
+            return;
+        }
+
+        let mut mask = AclPerm::empty();
+        let mut needed = false;
+        for entry in self.entries.iter() {
+            match entry.tag {
+                AclTag::User(_) | AclTag::Group(_) => {
+                    needed = true;
+                    mask |= entry.perm;
+                }
+                AclTag::GroupObj => mask |= entry.perm,
+                _ => (),
+            }
+        }
+
+        if needed {
+            self.set(AclTag::Mask, mask);
+        } else {
+            self.remove(AclTag::Mask);
+        }
+    }
+
+    /// Check whether the kernel accepts this ACL: the owner, owning group and other entries must
+    /// exist, and a mask is required if there are named entries.
+    pub fn is_valid(&self) -> bool {
+        let has = |tag| self.get(tag).is_some();
+        let named = self.entries.iter().any(|entry| match entry.tag {
+            AclTag::User(_) | AclTag::Group(_) => true,
+            _ => false,
+        });
+        has(AclTag::UserObj)
+            && has(AclTag::GroupObj)
+            && has(AclTag::Other)
+            && (!named || has(AclTag::Mask))
+    }
+
+    /// The permission bits of the file mode corresponding to this ACL.
+    pub fn mode(&self) -> u32 {
+        let bits = |tag| self.get(tag).map(AclPerm::bits).unwrap_or(0);
+        let group = match self.get(AclTag::Mask) {
+            Some(mask) => mask.bits(),
+            None => bits(AclTag::GroupObj),
+        };
+        (bits(AclTag::UserObj) << 6) | (group << 3) | bits(AclTag::Other)
+    }
+}
+
+impl fmt::Display for Acl {
+    /// One entry per line, like `getfacl` with numeric ids.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.entries.iter() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn optional_acl(result: io::Result<Vec<u8>>) -> io::Result<Option<Acl>> {
+    match result {
+        Ok(data) => Ok(Some(Acl::parse(&data)?)),
+        Err(err) if err.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn check_valid(acl: &Acl) -> io::Result<()> {
+    if !acl.is_valid() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "incomplete posix acl",
+        ));
+    }
+    Ok(())
+}
+
+/// Read an ACL of an open file, returns `None` if the file has no such ACL.
+pub fn fget_acl(fd: RawFd, kind: AclType) -> io::Result<Option<Acl>> {
+    optional_acl(xattr::fgetxattr(fd, kind.xattr_name()))
+}
+
+/// Read an ACL of a file, returns `None` if the file has no such ACL.
+pub fn get_acl<P: ?Sized + NixPath>(path: &P, kind: AclType) -> io::Result<Option<Acl>> {
+    optional_acl(xattr::getxattr(path, kind.xattr_name()))
+}
+
+/// Set an ACL of an open file. Setting the access ACL also updates the file mode.
+pub fn fset_acl(fd: RawFd, kind: AclType, acl: &Acl) -> io::Result<()> {
+    check_valid(acl)?;
+    xattr::fsetxattr(fd, kind.xattr_name(), &acl.to_bytes(), 0)
+}
+
+/// Set an ACL of a file. Setting the access ACL also updates the file mode.
+pub fn set_acl<P: ?Sized + NixPath>(path: &P, kind: AclType, acl: &Acl) -> io::Result<()> {
+    check_valid(acl)?;
+    xattr::setxattr(path, kind.xattr_name(), &acl.to_bytes(), 0)
+}
+
+/// Remove an ACL of an open file. Removing a non-existent ACL is not an error.
+pub fn fremove_acl(fd: RawFd, kind: AclType) -> io::Result<()> {
+    match xattr::fremovexattr(fd, kind.xattr_name()) {
+        Err(err) if err.raw_os_error() != Some(libc::ENODATA) => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn test_acl() {
+    use std::os::unix::io::AsRawFd;
+
+    let mut acl = Acl::from_mode(0o754);
+    assert!(!acl.is_extended());
+    assert!(acl.is_valid());
+    assert_eq!(acl.mode(), 0o754);
+
+    acl.set(AclTag::Group(100), AclPerm::all());
+    acl.set(AclTag::User(1000), AclPerm::READ | AclPerm::WRITE);
+    assert!(!acl.is_valid());
+    acl.update_mask();
+    assert!(acl.is_valid());
+    assert_eq!(acl.get(AclTag::Mask), Some(AclPerm::all()));
+    assert_eq!(
+        acl.to_string(),
+        "user::rwx\nuser:1000:rw-\ngroup::r-x\ngroup:100:rwx\nmask::rwx\nother::r--\n"
+    );
+
+    let data = acl.to_bytes();
+    assert_eq!(
+        &data[..12],
+        b"\x02\x00\x00\x00\x01\x00\x07\x00\xff\xff\xff\xff"
+    );
+    assert_eq!(&data[12..20], b"\x02\x00\x06\x00\xe8\x03\x00\x00");
+    assert_eq!(Acl::parse(&data).unwrap(), acl);
+    assert!(Acl::parse(&data[..10]).is_err());
+
+    let path = std::env::temp_dir().join(format!("proxmox-acl-test-{}", std::process::id()));
+    let file = std::fs::File::create(&path).expect("failed to create test file");
+    let result = fset_acl(file.as_raw_fd(), AclType::Access, &acl);
+    if let Err(ref err) = result {
+        let _ = std::fs::remove_file(&path);
+        if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            eprintln!("skipping acl test, not supported by the file system");
+            return;
+        }
+    }
+    result.expect("failed to set acl");
+
+    let read = fget_acl(file.as_raw_fd(), AclType::Access).expect("failed to read acl");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(read, Some(acl));
+}
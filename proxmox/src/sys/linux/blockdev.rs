@@ -0,0 +1,94 @@
+//! Block device ioctls, see `/usr/include/linux/fs.h`.
+//!
+//! All functions operate on file descriptors of opened block devices and fail with `ENOTTY` for
+//! other files. Ranges for `discard` and `zero_out` have to be aligned to the logical sector
+//! size.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use crate::sys::error::SysResult;
+
+// #define BLKSSZGET _IO(0x12,104)
+nix::ioctl_read_bad!(blksszget, nix::request_code_none!(0x12, 104), libc::c_int);
+// #define BLKGETSIZE64 _IOR(0x12,114,size_t)
+// The request is encoded with size_t (4 bytes on 32 bit), but the kernel always writes a u64.
+nix::ioctl_read_bad!(
+    blkgetsize64,
+    nix::request_code_read!(0x12, 114, std::mem::size_of::<libc::size_t>()),
+    u64
+);
+// #define BLKDISCARD _IO(0x12,119)
+nix::ioctl_write_ptr_bad!(blkdiscard, nix::request_code_none!(0x12, 119), [u64; 2]);
+// #define BLKPBSZGET _IO(0x12,123)
+nix::ioctl_read_bad!(blkpbszget, nix::request_code_none!(0x12, 123), libc::c_uint);
+// #define BLKSECDISCARD _IO(0x12,125)
+nix::ioctl_write_ptr_bad!(blksecdiscard, nix::request_code_none!(0x12, 125), [u64; 2]);
+// #define BLKZEROOUT _IO(0x12,127)
+nix::ioctl_write_ptr_bad!(blkzeroout, nix::request_code_none!(0x12, 127), [u64; 2]);
+
+/// The size of the device in bytes.
+pub fn size(fd: RawFd) -> io::Result<u64> {
+    let mut size: u64 = 0;
+    unsafe { blkgetsize64(fd, &mut size) }.into_io_result()?;
+    Ok(size)
+}
+
+/// The logical sector size, the smallest unit the device can address.
+pub fn logical_sector_size(fd: RawFd) -> io::Result<u32> {
+    let mut size: libc::c_int = 0;
+    unsafe { blksszget(fd, &mut size) }.into_io_result()?;
+    Ok(size as u32)
+}
+
+/// The physical sector size, the smallest unit the device can write without a
+/// read-modify-write cycle.
+pub fn physical_sector_size(fd: RawFd) -> io::Result<u32> {
+    let mut size: libc::c_uint = 0;
+    unsafe { blkpbszget(fd, &mut size) }.into_io_result()?;
+    Ok(size as u32)
+}
+
+/// Discard (trim) a byte range, telling the device that the data is no longer needed. Whether
+/// discarded ranges read back as zeroes depends on the device.
+pub fn discard(fd: RawFd, offset: u64, len: u64) -> io::Result<()> {
+    unsafe { blkdiscard(fd, &[offset, len]) }
+        .into_io_result()
+        .map(drop)
+}
+
+/// Securely discard a byte range, so that the data cannot be recovered from the device. Fails
+/// with `EOPNOTSUPP` if the device does not support this.
+pub fn secure_discard(fd: RawFd, offset: u64, len: u64) -> io::Result<()> {
+    unsafe { blksecdiscard(fd, &[offset, len]) }
+        .into_io_result()
+        .map(drop)
+}
+
+/// Zero a byte range. The kernel uses the most efficient way the device offers, falling back to
+/// writing zeroes.
+pub fn zero_out(fd: RawFd, offset: u64, len: u64) -> io::Result<()> {
+    unsafe { blkzeroout(fd, &[offset, len]) }
+        .into_io_result()
+        .map(drop)
+}
+
+#[test]
+fn test_blockdev_regular_file() {
+    use std::os::unix::io::AsRawFd;
+
+    let path = std::env::temp_dir().join(format!("proxmox-blockdev-test-{}", std::process::id()));
+    let file = std::fs::File::create(&path).expect("failed to create test file");
+    let _ = std::fs::remove_file(&path);
+
+    let fd = file.as_raw_fd();
+    assert_eq!(size(fd).unwrap_err().raw_os_error(), Some(libc::ENOTTY));
+    assert_eq!(
+        logical_sector_size(fd).unwrap_err().raw_os_error(),
+        Some(libc::ENOTTY)
+    );
+    assert_eq!(
+        zero_out(fd, 0, 512).unwrap_err().raw_os_error(),
+        Some(libc::ENOTTY)
+    );
+}
@@ -0,0 +1,494 @@
+//! Process capabilities, see `capabilities(7)`.
+//!
+//! Capabilities are a per-thread attribute, so privileged daemons should shed the capabilities
+//! they do not need before spawning any threads:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::capability::{drop_all_except, Cap};
+//! # fn code() -> std::io::Result<()> {
+//! drop_all_except(&[Cap::NetBindService, Cap::Chown])?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitOr, Not};
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use nix::unistd::Pid;
+
+use crate::c_str;
+use crate::sys::linux::procfs::PidStatus;
+use crate::tools::fd::Fd;
+
+macro_rules! caps {
+    ($($cap:ident = $bit:expr, $name:literal;)+) => {
+        /// A single capability.
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum Cap {
+            $($cap = $bit,)+
+        }
+
+        impl Cap {
+            /// All capabilities known to this crate.
+            pub const ALL: &'static [Cap] = &[$(Cap::$cap,)+];
+
+            /// The name as used by `capabilities(7)` and `setcap(8)`, eg. `cap_chown`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Cap::$cap => $name,)+
+                }
+            }
+        }
+
+        impl FromStr for Cap {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Error> {
+                match s.to_ascii_lowercase().as_str() {
+                    $($name => Ok(Cap::$cap),)+
+                    _ => bail!("unknown capability '{}'", s),
+                }
+            }
+        }
+    };
+}
+
+caps! {
+    Chown = 0, "cap_chown";
+    DacOverride = 1, "cap_dac_override";
+    DacReadSearch = 2, "cap_dac_read_search";
+    Fowner = 3, "cap_fowner";
+    Fsetid = 4, "cap_fsetid";
+    Kill = 5, "cap_kill";
+    Setgid = 6, "cap_setgid";
+    Setuid = 7, "cap_setuid";
+    Setpcap = 8, "cap_setpcap";
+    LinuxImmutable = 9, "cap_linux_immutable";
+    NetBindService = 10, "cap_net_bind_service";
+    NetBroadcast = 11, "cap_net_broadcast";
+    NetAdmin = 12, "cap_net_admin";
+    NetRaw = 13, "cap_net_raw";
+    IpcLock = 14, "cap_ipc_lock";
+    IpcOwner = 15, "cap_ipc_owner";
+    SysModule = 16, "cap_sys_module";
+    SysRawio = 17, "cap_sys_rawio";
+    SysChroot = 18, "cap_sys_chroot";
+    SysPtrace = 19, "cap_sys_ptrace";
+    SysPacct = 20, "cap_sys_pacct";
+    SysAdmin = 21, "cap_sys_admin";
+    SysBoot = 22, "cap_sys_boot";
+    SysNice = 23, "cap_sys_nice";
+    SysResource = 24, "cap_sys_resource";
+    SysTime = 25, "cap_sys_time";
+    SysTtyConfig = 26, "cap_sys_tty_config";
+    Mknod = 27, "cap_mknod";
+    Lease = 28, "cap_lease";
+    AuditWrite = 29, "cap_audit_write";
+    AuditControl = 30, "cap_audit_control";
+    Setfcap = 31, "cap_setfcap";
+    MacOverride = 32, "cap_mac_override";
+    MacAdmin = 33, "cap_mac_admin";
+    Syslog = 34, "cap_syslog";
+    WakeAlarm = 35, "cap_wake_alarm";
+    BlockSuspend = 36, "cap_block_suspend";
+    AuditRead = 37, "cap_audit_read";
+    Perfmon = 38, "cap_perfmon";
+    Bpf = 39, "cap_bpf";
+    CheckpointRestore = 40, "cap_checkpoint_restore";
+}
+
+impl Cap {
+    /// The capability number.
+    pub fn number(self) -> u32 {
+        self as u32
+    }
+
+    /// The capability's bit in a capability set.
+    pub fn bit(self) -> u64 {
+        1u64 << (self as u32)
+    }
+}
+
+impl fmt::Display for Cap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A set of capabilities.
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct CapSet(u64);
+
+impl CapSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// All capabilities known to this crate.
+    pub fn all() -> Self {
+        Cap::ALL.iter().copied().collect()
+    }
+
+    /// Create from a raw bit mask as found in `/proc/<pid>/status`.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, cap: Cap) -> bool {
+        (self.0 & cap.bit()) != 0
+    }
+
+    pub fn insert(&mut self, cap: Cap) {
+        self.0 |= cap.bit();
+    }
+
+    pub fn remove(&mut self, cap: Cap) {
+        self.0 &= !cap.bit();
+    }
+
+    /// Iterate over the known capabilities contained in this set.
+    pub fn iter(self) -> impl Iterator<Item = Cap> {
+        Cap::ALL
+            .iter()
+            .copied()
+            .filter(move |cap| self.contains(*cap))
+    }
+}
+
+impl fmt::Debug for CapSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl From<Cap> for CapSet {
+    fn from(cap: Cap) -> Self {
+        Self(cap.bit())
+    }
+}
+
+impl FromIterator<Cap> for CapSet {
+    fn from_iter<I: IntoIterator<Item = Cap>>(iter: I) -> Self {
+        Self(iter.into_iter().fold(0, |bits, cap| bits | cap.bit()))
+    }
+}
+
+impl BitOr for CapSet {
+    type Output = CapSet;
+
+    fn bitor(self, other: CapSet) -> CapSet {
+        CapSet(self.0 | other.0)
+    }
+}
+
+impl BitAnd for CapSet {
+    type Output = CapSet;
+
+    fn bitand(self, other: CapSet) -> CapSet {
+        CapSet(self.0 & other.0)
+    }
+}
+
+impl Not for CapSet {
+    type Output = CapSet;
+
+    fn not(self) -> CapSet {
+        CapSet(!self.0)
+    }
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// The effective, permitted and inheritable capability sets of a thread.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Capabilities {
+    pub effective: CapSet,
+    pub permitted: CapSet,
+    pub inheritable: CapSet,
+}
+
+impl Capabilities {
+    /// Get the capabilities of the current thread via `capget(2)`.
+    pub fn current() -> io::Result<Self> {
+        Self::capget(0)
+    }
+
+    /// Get the capabilities of a process via `capget(2)`.
+    pub fn of_pid(pid: Pid) -> io::Result<Self> {
+        Self::capget(pid.as_raw())
+    }
+
+    fn capget(pid: libc::pid_t) -> io::Result<Self> {
+        let mut header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid,
+        };
+        let mut data = [CapUserData::default(); 2];
+
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_capget,
+                &mut header as *mut CapUserHeader,
+                data.as_mut_ptr(),
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let combine = |get: fn(&CapUserData) -> u32| {
+            CapSet(u64::from(get(&data[0])) | (u64::from(get(&data[1])) << 32))
+        };
+
+        Ok(Self {
+            effective: combine(|d| d.effective),
+            permitted: combine(|d| d.permitted),
+            inheritable: combine(|d| d.inheritable),
+        })
+    }
+
+    /// Read the capabilities of a process from `/proc/<pid>/status`.
+    pub fn read_proc(pid: Pid) -> Result<Self, Error> {
+        let status = PidStatus::read_from_pid(pid)?;
+        Ok(Self {
+            effective: CapSet(status.cap_eff),
+            permitted: CapSet(status.cap_prm),
+            inheritable: CapSet(status.cap_inh),
+        })
+    }
+
+    /// Apply these capability sets to the current thread via `capset(2)`.
+    ///
+    /// Capabilities can only be added to the permitted set with `CAP_SETPCAP`, and the effective
+    /// set must be a subset of the permitted set.
+    pub fn apply(&self) -> io::Result<()> {
+        let mut header = CapUserHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let split = |set: CapSet| (set.0 as u32, (set.0 >> 32) as u32);
+        let (eff_lo, eff_hi) = split(self.effective);
+        let (prm_lo, prm_hi) = split(self.permitted);
+        let (inh_lo, inh_hi) = split(self.inheritable);
+        let data = [
+            CapUserData {
+                effective: eff_lo,
+                permitted: prm_lo,
+                inheritable: inh_lo,
+            },
+            CapUserData {
+                effective: eff_hi,
+                permitted: prm_hi,
+                inheritable: inh_hi,
+            },
+        ];
+
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_capset,
+                &mut header as *mut CapUserHeader,
+                data.as_ptr(),
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Read the bounding set of a process from `/proc/<pid>/status`.
+pub fn read_bounding_set(pid: Pid) -> Result<CapSet, Error> {
+    Ok(CapSet(PidStatus::read_from_pid(pid)?.cap_bnd))
+}
+
+/// Check whether a capability is in the bounding set of the current thread.
+pub fn bounding_set_contains(cap: Cap) -> io::Result<bool> {
+    let rc = unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap.number() as libc::c_ulong) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc == 1)
+}
+
+/// Drop a capability from the bounding set of the current thread, this requires `CAP_SETPCAP`.
+pub fn drop_bounding(cap: Cap) -> io::Result<()> {
+    drop_bounding_number(cap.number())
+}
+
+fn drop_bounding_number(number: u32) -> io::Result<()> {
+    let rc = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, number as libc::c_ulong) };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        // capabilities unknown to the running kernel are not in the bounding set anyway
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The highest capability number supported by the running kernel, which may be higher than the
+/// ones known to this crate.
+///
+/// This reads `/proc/sys/kernel/cap_last_cap` without allocating, so it can be used between
+/// `fork` and `exec`, and probes the bounding set if `/proc` is not available.
+pub fn last_cap() -> io::Result<u32> {
+    if let Some(last) = read_last_cap() {
+        return Ok(last);
+    }
+
+    // unknown capabilities fail with EINVAL
+    let mut number = 0;
+    loop {
+        let rc = unsafe { libc::prctl(libc::PR_CAPBSET_READ, number as libc::c_ulong) };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EINVAL) && number > 0 {
+                return Ok(number - 1);
+            }
+            return Err(err);
+        }
+        number += 1;
+    }
+}
+
+fn read_last_cap() -> Option<u32> {
+    let fd = unsafe {
+        libc::open(
+            c_str!("/proc/sys/kernel/cap_last_cap").as_ptr(),
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return None;
+    }
+    let fd = Fd(fd);
+
+    let mut buf = [0u8; 16];
+    let len = unsafe { libc::read(fd.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+    if len <= 0 {
+        return None;
+    }
+    std::str::from_utf8(&buf[..(len as usize)])
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Clear the ambient capability set of the current thread.
+pub fn clear_ambient() -> io::Result<()> {
+    let rc = unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+        )
+    };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        // kernels before 4.3 do not support ambient capabilities
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Drop all capabilities of the current thread except for `keep`.
+///
+/// This removes them from the effective, permitted and inheritable sets, as well as, if we are
+/// allowed to, from the bounding set, so they cannot be regained by executing a privileged
+/// program. The bounding set is cleared up to `last_cap`, including capabilities unknown to this
+/// crate. The ambient set is cleared.
+pub fn drop_all_except(keep: &[Cap]) -> io::Result<()> {
+    let keep: CapSet = keep.iter().copied().collect();
+    let current = Capabilities::current()?;
+
+    // this needs CAP_SETPCAP, so do it before the capset call
+    if current.effective.contains(Cap::Setpcap) {
+        for number in 0..=last_cap()? {
+            if number >= 64 || keep.bits() & (1 << number) == 0 {
+                drop_bounding_number(number)?;
+            }
+        }
+    }
+
+    clear_ambient()?;
+
+    Capabilities {
+        effective: current.effective & keep,
+        permitted: current.permitted & keep,
+        inheritable: current.inheritable & keep,
+    }
+    .apply()
+}
+
+#[test]
+fn test_capset() {
+    let set: CapSet = vec![Cap::Chown, Cap::SysAdmin, Cap::Bpf]
+        .into_iter()
+        .collect();
+    assert_eq!(set.bits(), (1 << 0) | (1 << 21) | (1 << 39));
+    assert!(set.contains(Cap::SysAdmin));
+    assert!(!set.contains(Cap::Kill));
+    assert_eq!(
+        set.iter().collect::<Vec<_>>(),
+        vec![Cap::Chown, Cap::SysAdmin, Cap::Bpf]
+    );
+    assert_eq!(
+        (set & !CapSet::from(Cap::SysAdmin))
+            .iter()
+            .collect::<Vec<_>>(),
+        vec![Cap::Chown, Cap::Bpf]
+    );
+
+    assert_eq!(
+        "CAP_NET_BIND_SERVICE".parse::<Cap>().unwrap(),
+        Cap::NetBindService
+    );
+    assert!("cap_nonsense".parse::<Cap>().is_err());
+    assert_eq!(Cap::DacReadSearch.to_string(), "cap_dac_read_search");
+}
+
+#[test]
+fn test_capget() {
+    let caps = Capabilities::current().expect("capget failed");
+    let pid = nix::unistd::getpid();
+    let from_proc = Capabilities::read_proc(pid).expect("failed to read /proc/self/status");
+    assert_eq!(caps, from_proc);
+    assert_eq!(caps.effective & !caps.permitted, CapSet::empty());
+
+    // every supported kernel knows at least the capabilities up to CAP_SETFCAP
+    let last = last_cap().expect("failed to get the last capability");
+    assert!(last >= Cap::Setfcap.number());
+}
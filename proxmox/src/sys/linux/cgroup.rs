@@ -0,0 +1,344 @@
+//! cgroup v2 (unified hierarchy) helpers.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+use crate::sys::linux::procfs::{self, MountInfo};
+use crate::tools::fs::file_read_string;
+
+/// Find the mount point of the cgroup v2 hierarchy.
+///
+/// On a pure cgroup v2 system this is usually `/sys/fs/cgroup`, in hybrid mode it is usually
+/// `/sys/fs/cgroup/unified`.
+pub fn cgroup2_mount_point() -> Result<PathBuf, Error> {
+    MountInfo::read()?
+        .iter()
+        .find(|(_id, entry)| entry.fs_type == "cgroup2")
+        .map(|(_id, entry)| entry.mount_point.clone())
+        .ok_or_else(|| format_err!("no cgroup v2 hierarchy mounted"))
+}
+
+/// Get the cgroup v2 path of a process relative to the hierarchy's mount point.
+pub fn pid_cgroup(pid: Pid) -> Result<PathBuf, Error> {
+    procfs::read_proc_pid_cgroup(pid)?
+        .into_iter()
+        .find(|cgroup| cgroup.is_unified())
+        .map(|cgroup| cgroup.path)
+        .ok_or_else(|| format_err!("process {} is not part of a cgroup v2 hierarchy", pid))
+}
+
+/// Get the absolute path of a process' cgroup v2 directory.
+pub fn pid_cgroup_path(pid: Pid) -> Result<PathBuf, Error> {
+    let cgroup = pid_cgroup(pid)?;
+    Ok(cgroup2_mount_point()?.join(cgroup.strip_prefix("/").unwrap_or(&cgroup)))
+}
+
+fn read_value<T>(cgroup: &Path, file: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    <T as FromStr>::Err: std::fmt::Display,
+{
+    let path = cgroup.join(file);
+    file_read_string(&path)?
+        .trim_end()
+        .parse()
+        .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))
+}
+
+/// Read the current memory usage in bytes of a cgroup (`memory.current`).
+pub fn read_memory_current<P: AsRef<Path>>(cgroup: P) -> Result<u64, Error> {
+    read_value(cgroup.as_ref(), "memory.current")
+}
+
+/// Read the current number of processes in a cgroup (`pids.current`).
+pub fn read_pids_current<P: AsRef<Path>>(cgroup: P) -> Result<u64, Error> {
+    read_value(cgroup.as_ref(), "pids.current")
+}
+
+/// The contents of a cgroup's `cpu.stat` file. All times are in microseconds.
+///
+/// The throttling statistics are only available if the `cpu` controller is enabled for the
+/// cgroup.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuStat {
+    pub usage_usec: u64,
+    pub user_usec: u64,
+    pub system_usec: u64,
+    pub nr_periods: Option<u64>,
+    pub nr_throttled: Option<u64>,
+    pub throttled_usec: Option<u64>,
+}
+
+impl CpuStat {
+    /// Parse the contents of a `cpu.stat` file.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut stat = Self::default();
+        for line in data.lines() {
+            let mut parts = line.split_ascii_whitespace();
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value.parse::<u64>()?),
+                (None, _) => continue,
+                _ => bail!("bad line in cpu.stat: {:?}", line),
+            };
+
+            match key {
+                "usage_usec" => stat.usage_usec = value,
+                "user_usec" => stat.user_usec = value,
+                "system_usec" => stat.system_usec = value,
+                "nr_periods" => stat.nr_periods = Some(value),
+                "nr_throttled" => stat.nr_throttled = Some(value),
+                "throttled_usec" => stat.throttled_usec = Some(value),
+                _ => continue,
+            }
+        }
+        Ok(stat)
+    }
+}
+
+/// Read the CPU statistics of a cgroup (`cpu.stat`).
+pub fn read_cpu_stat<P: AsRef<Path>>(cgroup: P) -> Result<CpuStat, Error> {
+    CpuStat::parse(&file_read_string(cgroup.as_ref().join("cpu.stat"))?)
+}
+
+fn write_value<T: std::fmt::Display>(cgroup: &Path, file: &str, value: T) -> Result<(), Error> {
+    let path = cgroup.join(file);
+    std::fs::write(&path, value.to_string().as_bytes())
+        .map_err(|err| format_err!("unable to write {:?} - {}", path, err))
+}
+
+fn format_limit(limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => limit.to_string(),
+        None => "max".to_string(),
+    }
+}
+
+fn format_cpu_max(quota: Option<Duration>, period: Duration) -> String {
+    match quota {
+        Some(quota) => format!("{} {}", quota.as_micros(), period.as_micros()),
+        None => format!("max {}", period.as_micros()),
+    }
+}
+
+/// Builder to create a cgroup with resource limits.
+///
+/// The controllers required for the configured limits are enabled in the parent cgroup
+/// automatically:
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use proxmox::sys::linux::cgroup::CgroupBuilder;
+/// # fn code() -> Result<(), anyhow::Error> {
+/// let cgroup = CgroupBuilder::new("/sys/fs/cgroup/helpers.slice/job-1")
+///     .memory_max(Some(512 * 1024 * 1024))
+///     .cpu_max(Some(Duration::from_millis(50)), Duration::from_millis(100))
+///     .pids_max(Some(64))
+///     .create()?;
+/// cgroup.add_pid(nix::unistd::getpid())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CgroupBuilder {
+    path: PathBuf,
+    controllers: Vec<String>,
+    memory_max: Option<Option<u64>>,
+    cpu_max: Option<(Option<Duration>, Duration)>,
+    pids_max: Option<Option<u64>>,
+}
+
+impl CgroupBuilder {
+    /// Start building the cgroup at `path`, which is an absolute path inside the cgroup v2
+    /// hierarchy.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            controllers: Vec::new(),
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None,
+        }
+    }
+
+    /// Enable a controller for the cgroup, eg. `io`, in addition to the ones required for the
+    /// configured limits.
+    pub fn controller<S: Into<String>>(mut self, controller: S) -> Self {
+        let controller = controller.into();
+        if !self.controllers.contains(&controller) {
+            self.controllers.push(controller);
+        }
+        self
+    }
+
+    /// Limit the memory usage in bytes (`memory.max`), `None` means unlimited.
+    pub fn memory_max(mut self, limit: Option<u64>) -> Self {
+        self.memory_max = Some(limit);
+        self.controller("memory")
+    }
+
+    /// Limit the CPU time to `quota` per `period` (`cpu.max`), `None` means unlimited. A quota
+    /// larger than the period allows using multiple CPUs.
+    pub fn cpu_max(mut self, quota: Option<Duration>, period: Duration) -> Self {
+        self.cpu_max = Some((quota, period));
+        self.controller("cpu")
+    }
+
+    /// Limit the number of processes (`pids.max`), `None` means unlimited.
+    pub fn pids_max(mut self, limit: Option<u64>) -> Self {
+        self.pids_max = Some(limit);
+        self.controller("pids")
+    }
+
+    /// Create the cgroup (an existing one is reused), enable the controllers and apply the
+    /// limits.
+    pub fn create(self) -> Result<Cgroup, Error> {
+        let parent = self
+            .path
+            .parent()
+            .ok_or_else(|| format_err!("cannot create cgroup at {:?}", self.path))?;
+
+        if !self.controllers.is_empty() {
+            let enable = self
+                .controllers
+                .iter()
+                .map(|controller| format!("+{}", controller))
+                .collect::<Vec<_>>()
+                .join(" ");
+            write_value(parent, "cgroup.subtree_control", enable)?;
+        }
+
+        if let Err(err) = std::fs::create_dir(&self.path) {
+            if err.kind() != std::io::ErrorKind::AlreadyExists {
+                bail!("unable to create cgroup {:?} - {}", self.path, err);
+            }
+        }
+
+        let cgroup = Cgroup { path: self.path };
+        if let Some(limit) = self.memory_max {
+            write_value(&cgroup.path, "memory.max", format_limit(limit))?;
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            write_value(&cgroup.path, "cpu.max", format_cpu_max(quota, period))?;
+        }
+        if let Some(limit) = self.pids_max {
+            write_value(&cgroup.path, "pids.max", format_limit(limit))?;
+        }
+
+        Ok(cgroup)
+    }
+}
+
+/// An existing cgroup.
+#[derive(Clone, Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Refer to an existing cgroup by its absolute path.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        if !path.join("cgroup.procs").exists() {
+            bail!("{:?} is not a cgroup", path);
+        }
+        Ok(Self { path })
+    }
+
+    /// The absolute path of the cgroup directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move a process into the cgroup.
+    pub fn add_pid(&self, pid: Pid) -> Result<(), Error> {
+        write_value(&self.path, "cgroup.procs", pid)
+    }
+
+    /// Get the processes in the cgroup, not including child cgroups.
+    pub fn pids(&self) -> Result<Vec<Pid>, Error> {
+        let path = self.path.join("cgroup.procs");
+        file_read_string(&path)?
+            .lines()
+            .map(|line| {
+                line.parse()
+                    .map(Pid::from_raw)
+                    .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))
+            })
+            .collect()
+    }
+
+    /// Kill all processes in the cgroup and its child cgroups.
+    ///
+    /// This uses `cgroup.kill` (Linux 5.14), which cannot race with forking processes. On older
+    /// kernels the processes are sent `SIGKILL` one by one, which does not include child
+    /// cgroups.
+    pub fn kill(&self) -> Result<(), Error> {
+        if self.path.join("cgroup.kill").exists() {
+            return write_value(&self.path, "cgroup.kill", 1);
+        }
+
+        for pid in self.pids()? {
+            match signal::kill(pid, Signal::SIGKILL) {
+                Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => (),
+                Err(err) => bail!("failed to kill process {} - {}", pid, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the cgroup, which must not contain any processes or child cgroups anymore.
+    pub fn remove(self) -> Result<(), Error> {
+        std::fs::remove_dir(&self.path)
+            .map_err(|err| format_err!("unable to remove cgroup {:?} - {}", self.path, err))
+    }
+}
+
+#[test]
+fn test_cgroup_limit_format() {
+    assert_eq!(format_limit(Some(1024)), "1024");
+    assert_eq!(format_limit(None), "max");
+    assert_eq!(
+        format_cpu_max(Some(Duration::from_millis(50)), Duration::from_millis(100)),
+        "50000 100000"
+    );
+    assert_eq!(
+        format_cpu_max(None, Duration::from_millis(100)),
+        "max 100000"
+    );
+
+    let builder = CgroupBuilder::new("/sys/fs/cgroup/test")
+        .memory_max(Some(1 << 30))
+        .pids_max(None)
+        .controller("memory")
+        .controller("io");
+    assert_eq!(builder.controllers, ["memory", "pids", "io"]);
+}
+
+#[test]
+fn test_parse_cpu_stat() {
+    let stat = CpuStat::parse(
+        "usage_usec 1234567\n\
+         user_usec 1000000\n\
+         system_usec 234567\n\
+         nr_periods 10\n\
+         nr_throttled 2\n\
+         throttled_usec 3000\n",
+    )
+    .expect("failed to parse cpu.stat");
+    assert_eq!(stat.usage_usec, 1234567);
+    assert_eq!(stat.user_usec, 1000000);
+    assert_eq!(stat.system_usec, 234567);
+    assert_eq!(stat.nr_periods, Some(10));
+    assert_eq!(stat.nr_throttled, Some(2));
+    assert_eq!(stat.throttled_usec, Some(3000));
+
+    let stat = CpuStat::parse("usage_usec 5\nuser_usec 3\nsystem_usec 2\n")
+        .expect("failed to parse cpu.stat without the cpu controller");
+    assert_eq!(stat.usage_usec, 5);
+    assert_eq!(stat.nr_throttled, None);
+}
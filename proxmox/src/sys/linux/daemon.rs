@@ -0,0 +1,562 @@
+//! Daemon helpers.
+//!
+//! `Daemonize` detaches the current process from its terminal and session via the classic double
+//! fork. Optionally the original process waits until the daemon reports that it finished its
+//! initialization, so init scripts only return once the service is actually usable:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::daemon::Daemonize;
+//! # fn code() -> std::io::Result<()> {
+//! let mut ready = Daemonize::new()
+//!     .log_file("/var/log/mydaemon.log")
+//!     .wait_for_ready(true)
+//!     .start()?;
+//!
+//! // now running as the daemon: bind sockets, read the configuration, ...
+//!
+//! ready.notify_ready()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Since only the forking thread survives a `fork`, this must be called before spawning any
+//! threads, in particular before starting an async runtime.
+//!
+//! `ReexecContinue` allows reloading a daemon by executing the (possibly updated) binary again,
+//! passing on the listening sockets, so no connection attempts are refused in between:
+//!
+//! ```no_run
+//! # use std::net::TcpListener;
+//! # use std::os::unix::io::FromRawFd;
+//! # use nix::sys::signal::Signal;
+//! # use proxmox::sys::linux::daemon::{InheritedFds, ReexecContinue};
+//! # use proxmox::sys::linux::signalfd::SignalFd;
+//! # fn code() -> std::io::Result<()> {
+//! let mut inherited = InheritedFds::from_env()?;
+//! let listener = match inherited.take("api") {
+//!     Some(fd) => unsafe { TcpListener::from_raw_fd(fd.into_raw_fd()) },
+//!     None => TcpListener::bind("[::]:8007")?,
+//! };
+//!
+//! let mut reexec = ReexecContinue::new();
+//! reexec.add_fd("api", &listener)?;
+//!
+//! let mut signals = SignalFd::new(&[Signal::SIGHUP])?;
+//! while let Some(info) = signals.read_signal()? {
+//!     if info.signal() == Some(Signal::SIGHUP) {
+//!         // only returns on failure
+//!         let err = reexec.reexec();
+//!         eprintln!("reload failed: {}", err);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::ForkResult;
+
+use crate::c_str;
+use crate::sys::error::SysResult;
+use crate::sys::linux::prctl;
+use crate::tools::fd::{set_cloexec, Fd, RawFdNum};
+
+const READY: u8 = b'R';
+const FAILED: u8 = b'E';
+
+/// Builder for turning the current process into a daemon.
+#[derive(Clone, Debug)]
+pub struct Daemonize {
+    working_dir: PathBuf,
+    umask: Mode,
+    log_file: Option<PathBuf>,
+    wait_for_ready: bool,
+    name: Option<String>,
+}
+
+impl Default for Daemonize {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Daemonize {
+    /// Defaults to changing into `/`, a umask of `022` and redirecting all standard file
+    /// descriptors to `/dev/null`.
+    pub fn new() -> Self {
+        Self {
+            working_dir: PathBuf::from("/"),
+            umask: Mode::from_bits_truncate(0o022),
+            log_file: None,
+            wait_for_ready: false,
+            name: None,
+        }
+    }
+
+    /// Change into this directory instead of `/`.
+    pub fn working_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.working_dir = dir.as_ref().to_owned();
+        self
+    }
+
+    /// Set the file mode creation mask of the daemon.
+    pub fn umask(mut self, umask: Mode) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Append stdout and stderr to this file instead of discarding them.
+    pub fn log_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.log_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Set the process name shown by `ps` or `top`, see `prctl::set_name`.
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Make the original process wait until the daemon calls `ReadyNotifier::notify_ready`.
+    ///
+    /// The original process exits with status 0 once the daemon is ready. If the daemon reports
+    /// an error or exits without reporting anything, the original process prints the error and
+    /// exits with status 1. Without this, the original process exits as soon as the daemon was
+    /// set up.
+    pub fn wait_for_ready(mut self, wait: bool) -> Self {
+        self.wait_for_ready = wait;
+        self
+    }
+
+    /// Detach from the terminal. This only returns in the daemon process, the original process
+    /// exits, see `wait_for_ready`.
+    pub fn start(self) -> io::Result<ReadyNotifier> {
+        let (pipe_r, pipe_w) = nix::unistd::pipe2(OFlag::O_CLOEXEC).into_io_result()?;
+        let (pipe_r, pipe_w) = (Fd(pipe_r), Fd(pipe_w));
+
+        // buffered output would otherwise be written by more than one process
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+
+        match unsafe { nix::unistd::fork() }.into_io_result()? {
+            ForkResult::Parent { child } => {
+                drop(pipe_w);
+                let status = loop {
+                    match waitpid(child, None).into_io_result() {
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        other => break other?,
+                    }
+                };
+                if status != WaitStatus::Exited(child, 0) {
+                    eprintln!(
+                        "failed to daemonize: intermediate process failed ({:?})",
+                        status
+                    );
+                    std::process::exit(1);
+                }
+                std::process::exit(wait_for_daemon(pipe_r));
+            }
+            ForkResult::Child => (),
+        }
+
+        drop(pipe_r);
+        let mut notifier = ReadyNotifier { fd: Some(pipe_w) };
+
+        // From here on, errors are reported to the original process.
+        let result = (|| -> io::Result<()> {
+            nix::unistd::setsid().into_io_result()?;
+
+            // the session leader exits, so the daemon can never acquire a controlling terminal
+            match unsafe { nix::unistd::fork() }.into_io_result()? {
+                ForkResult::Parent { .. } => unsafe { libc::_exit(0) },
+                ForkResult::Child => (),
+            }
+
+            if let Some(ref name) = self.name {
+                prctl::set_name(name)?;
+            }
+            nix::unistd::chdir(&self.working_dir).into_io_result()?;
+            nix::sys::stat::umask(self.umask);
+            self.redirect_stdio()
+        })();
+
+        if let Err(err) = result {
+            notifier.notify_error(&err.to_string());
+            return Err(err);
+        }
+
+        if !self.wait_for_ready {
+            notifier.notify_ready()?;
+        }
+
+        Ok(notifier)
+    }
+
+    fn redirect_stdio(&self) -> io::Result<()> {
+        let null = Fd::open("/dev/null", OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())
+            .into_io_result()?;
+
+        let output = match self.log_file {
+            Some(ref path) => Fd::open(
+                path,
+                OFlag::O_WRONLY | OFlag::O_APPEND | OFlag::O_CREAT | OFlag::O_CLOEXEC,
+                Mode::from_bits_truncate(0o640),
+            )
+            .into_io_result()?,
+            None => Fd(nix::unistd::dup(null.as_raw_fd()).into_io_result()?),
+        };
+
+        nix::unistd::dup2(null.as_raw_fd(), libc::STDIN_FILENO).into_io_result()?;
+        nix::unistd::dup2(output.as_raw_fd(), libc::STDOUT_FILENO).into_io_result()?;
+        nix::unistd::dup2(output.as_raw_fd(), libc::STDERR_FILENO).into_io_result()?;
+        Ok(())
+    }
+}
+
+/// Runs in the original process, returns the exit status to use.
+fn wait_for_daemon(pipe: Fd) -> i32 {
+    let mut pipe = unsafe { File::from_raw_fd(pipe.into_raw_fd()) };
+    let mut data = Vec::new();
+    if let Err(err) = pipe.read_to_end(&mut data) {
+        eprintln!("failed to daemonize: {}", err);
+        return 1;
+    }
+
+    match data.split_first() {
+        Some((&READY, _)) => 0,
+        Some((&FAILED, msg)) => {
+            eprintln!("daemon failed to start: {}", String::from_utf8_lossy(msg));
+            1
+        }
+        _ => {
+            eprintln!("daemon exited unexpectedly");
+            1
+        }
+    }
+}
+
+/// Reports the daemon's startup result to the original process, see
+/// `Daemonize::wait_for_ready`.
+///
+/// Dropping it without reporting anything makes the original process fail.
+#[derive(Debug)]
+pub struct ReadyNotifier {
+    fd: Option<Fd>,
+}
+
+impl ReadyNotifier {
+    /// Tell the original process that the daemon is up and running, letting it exit
+    /// successfully. Does nothing if this was already reported.
+    pub fn notify_ready(&mut self) -> io::Result<()> {
+        if let Some(fd) = self.fd.take() {
+            write_all(fd.as_raw_fd(), &[READY])?;
+        }
+        Ok(())
+    }
+
+    /// Tell the original process that the daemon failed to start. The message is printed by the
+    /// original process, which then exits with status 1.
+    pub fn notify_error(&mut self, msg: &str) {
+        if let Some(fd) = self.fd.take() {
+            let mut data = Vec::with_capacity(msg.len() + 1);
+            data.push(FAILED);
+            data.extend_from_slice(msg.as_bytes());
+            let _ = write_all(fd.as_raw_fd(), &data);
+        }
+    }
+
+    /// Whether the startup result still has to be reported.
+    pub fn is_pending(&self) -> bool {
+        self.fd.is_some()
+    }
+}
+
+fn write_all(fd: RawFd, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data).into_io_result() {
+            Ok(written) => data = &data[written..],
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Environment variable containing the file descriptors passed on by `ReexecContinue`, as a comma
+/// separated list of `name=fd` pairs.
+pub const REEXEC_FDS_ENV: &str = "PROXMOX_REEXEC_FDS";
+
+fn check_fd_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains(|c| c == '=' || c == ',') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid file descriptor name {:?}", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Executes the current binary again, passing on a set of named file descriptors.
+///
+/// The process id stays the same, so a service manager does not notice the reload. Note that the
+/// signal mask is kept as well, so signals blocked for a `SignalFd` stay blocked, and signals
+/// arriving during the reload are pending until the new instance handles them.
+#[derive(Debug, Default)]
+pub struct ReexecContinue {
+    fds: Vec<(String, RawFd)>,
+}
+
+impl ReexecContinue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass on a file descriptor. It must stay open until `reexec` is called. Names must not be
+    /// empty nor contain `=` or `,`.
+    pub fn add_fd<F: ?Sized + AsRawFd>(&mut self, name: &str, fd: &F) -> io::Result<()> {
+        check_fd_name(name)?;
+        self.fds.retain(|(existing, _)| existing != name);
+        self.fds.push((name.to_string(), fd.as_raw_fd()));
+        Ok(())
+    }
+
+    /// The manifest passed via `REEXEC_FDS_ENV`.
+    fn manifest(&self) -> String {
+        self.fds
+            .iter()
+            .map(|(name, fd)| format!("{}={}", name, fd))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Execute `/proc/self/exe` with the original arguments. This only returns on failure, in
+    /// which case the file descriptors are left as they were.
+    pub fn reexec(&self) -> io::Error {
+        let exe = c_str!("/proc/self/exe");
+        let args: Result<Vec<CString>, _> = std::env::args_os()
+            .map(|arg| CString::new(arg.into_vec()))
+            .collect();
+        let args = match args {
+            Ok(args) => args,
+            Err(err) => return io::Error::new(io::ErrorKind::InvalidInput, err),
+        };
+
+        // modifying our own environment is not thread safe, so pass on a modified copy
+        let mut env: Vec<CString> = std::env::vars_os()
+            .filter(|(name, _)| name != REEXEC_FDS_ENV)
+            .filter_map(|(name, value)| {
+                let mut var = name.into_vec();
+                var.push(b'=');
+                var.extend(value.into_vec());
+                CString::new(var).ok()
+            })
+            .collect();
+        match CString::new(format!("{}={}", REEXEC_FDS_ENV, self.manifest())) {
+            Ok(var) => env.push(var),
+            Err(err) => return io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+
+        for (_, fd) in self.fds.iter() {
+            if let Err(err) = set_cloexec(&unsafe { RawFdNum::from_raw_fd(*fd) }, false) {
+                self.restore_cloexec();
+                return err;
+            }
+        }
+
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+
+        let err = match nix::unistd::execve(exe, &args, &env).into_io_result() {
+            Ok(never) => match never {},
+            Err(err) => err,
+        };
+
+        self.restore_cloexec();
+        err
+    }
+
+    fn restore_cloexec(&self) {
+        for (_, fd) in self.fds.iter() {
+            let _ = set_cloexec(&unsafe { RawFdNum::from_raw_fd(*fd) }, true);
+        }
+    }
+}
+
+/// File descriptors passed on by a previous instance via `ReexecContinue`.
+#[derive(Debug, Default)]
+pub struct InheritedFds {
+    fds: HashMap<String, Fd>,
+}
+
+impl InheritedFds {
+    /// Adopt the file descriptors listed in `REEXEC_FDS_ENV`. The file descriptors get the
+    /// close-on-exec flag again.
+    ///
+    /// Since modifying the environment is not thread safe, the variable is not removed. Child
+    /// processes should be started without it, e.g. via `Command::env_remove(REEXEC_FDS_ENV)`,
+    /// `ReexecContinue` replaces it.
+    ///
+    /// When not started via `ReexecContinue`, the result is empty.
+    pub fn from_env() -> io::Result<Self> {
+        let manifest = match std::env::var(REEXEC_FDS_ENV) {
+            Ok(manifest) => manifest,
+            Err(std::env::VarError::NotPresent) => return Ok(Self::default()),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        Self::from_manifest(&manifest)
+    }
+
+    fn from_manifest(manifest: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {} value: {:?}", REEXEC_FDS_ENV, manifest),
+            )
+        };
+
+        // validate everything before taking ownership, so nothing is closed on errors
+        let mut entries: Vec<(&str, RawFd)> = Vec::new();
+        for entry in manifest.split(',').filter(|entry| !entry.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let name = parts.next().ok_or_else(invalid)?;
+            let fd: RawFd = parts
+                .next()
+                .and_then(|fd| fd.parse().ok())
+                .filter(|fd| *fd > libc::STDERR_FILENO)
+                .ok_or_else(invalid)?;
+            check_fd_name(name)?;
+
+            // owning a file descriptor twice would close it twice
+            if entries
+                .iter()
+                .any(|(other_name, other_fd)| *other_name == name || *other_fd == fd)
+            {
+                return Err(invalid());
+            }
+
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            entries.push((name, fd));
+        }
+
+        for (_, fd) in entries.iter() {
+            set_cloexec(&unsafe { RawFdNum::from_raw_fd(*fd) }, true)?;
+        }
+
+        let fds = entries
+            .into_iter()
+            .map(|(name, fd)| (name.to_string(), Fd(fd)))
+            .collect();
+        Ok(Self { fds })
+    }
+
+    /// Take ownership of an inherited file descriptor.
+    pub fn take(&mut self, name: &str) -> Option<Fd> {
+        self.fds.remove(name)
+    }
+
+    /// Whether no (more) file descriptors were inherited. Remaining ones are closed on drop.
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+}
+
+#[test]
+fn test_daemonize() {
+    use crate::test::process::{is_helper, run_in_helper};
+
+    const NAME: &str = "sys::linux::daemon::test_daemonize";
+
+    let base = std::env::temp_dir().join(format!("proxmox-daemon-test-{}", std::process::id()));
+    let log = base.with_extension("log");
+
+    // the original process exits, so run this in a helper process
+    if !is_helper(NAME) {
+        let _ = std::fs::remove_file(&log);
+        run_in_helper(NAME, &[("PROXMOX_TEST_DAEMON_LOG", log.to_str().unwrap())]);
+
+        let output = std::fs::read_to_string(&log).expect("failed to read daemon log");
+        assert_eq!(output, "daemon running\n");
+        let _ = std::fs::remove_file(&log);
+        return;
+    }
+
+    let log = std::env::var_os("PROXMOX_TEST_DAEMON_LOG").unwrap();
+    let mut ready = Daemonize::new()
+        .log_file(&log)
+        .name("test-daemon")
+        .wait_for_ready(true)
+        .start()
+        .expect("failed to daemonize");
+
+    let sid = nix::unistd::getsid(None).unwrap();
+    assert_ne!(sid, nix::unistd::getpid());
+    assert_eq!(std::env::current_dir().unwrap(), Path::new("/"));
+    assert_eq!(prctl::get_name().unwrap(), "test-daemon");
+    // not `println!`, which the test runner captures
+    let mut stdout = std::io::stdout();
+    stdout.write_all(b"daemon running\n").unwrap();
+    stdout.flush().unwrap();
+    ready.notify_ready().unwrap();
+
+    // the original process reports the result, the test runner must not write to the log
+    unsafe { libc::_exit(0) };
+}
+
+#[test]
+fn test_reexec_fds() {
+    let (pipe_r, pipe_w) = nix::unistd::pipe2(OFlag::O_CLOEXEC).expect("failed to create pipe");
+    let (pipe_r, pipe_w) = (Fd(pipe_r), Fd(pipe_w));
+
+    let mut reexec = ReexecContinue::new();
+    assert!(reexec.add_fd("bad=name", &pipe_r).is_err());
+    reexec.add_fd("read", &pipe_r).unwrap();
+    reexec.add_fd("write", &pipe_w).unwrap();
+    assert_eq!(
+        reexec.manifest(),
+        format!("read={},write={}", pipe_r.as_raw_fd(), pipe_w.as_raw_fd())
+    );
+
+    // ownership moves to the adopting side
+    let manifest = reexec.manifest();
+    let (pipe_r, pipe_w) = (pipe_r.into_raw_fd(), pipe_w.into_raw_fd());
+    let mut inherited = InheritedFds::from_manifest(&manifest).expect("failed to adopt fds");
+    assert_eq!(
+        inherited.take("read").map(|fd| fd.into_raw_fd()),
+        Some(pipe_r)
+    );
+    assert!(inherited.take("read").is_none());
+    assert!(!inherited.is_empty());
+    assert_eq!(
+        inherited.take("write").map(|fd| fd.into_raw_fd()),
+        Some(pipe_w)
+    );
+    assert!(inherited.is_empty());
+    drop(Fd(pipe_r));
+    drop(Fd(pipe_w));
+
+    assert!(InheritedFds::from_manifest("read=1").is_err());
+
+    // the same file descriptor must not be owned twice, and nothing is adopted on errors
+    let (pipe_r, pipe_w) = nix::unistd::pipe().expect("failed to create pipe");
+    let (pipe_r, pipe_w) = (Fd(pipe_r), Fd(pipe_w));
+    let manifest = format!("a={},b={}", pipe_r.as_raw_fd(), pipe_r.as_raw_fd());
+    assert!(InheritedFds::from_manifest(&manifest).is_err());
+    let manifest = format!("a={},b=x", pipe_w.as_raw_fd());
+    assert!(InheritedFds::from_manifest(&manifest).is_err());
+    assert!(nix::fcntl::fcntl(pipe_w.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFD).is_ok());
+
+    assert!(InheritedFds::from_manifest("read").is_err());
+    assert!(InheritedFds::from_manifest("").unwrap().is_empty());
+}
@@ -0,0 +1,279 @@
+//! A thin, safe wrapper around `epoll(7)`.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use proxmox::sys::linux::epoll::{Epoll, Events, Interest};
+//! # fn code() -> std::io::Result<()> {
+//! let epoll = Epoll::new()?;
+//! let stdin = std::io::stdin();
+//! epoll.add(&stdin, Interest::readable(), 0)?;
+//!
+//! let mut events = Events::with_capacity(16);
+//! epoll.wait(&mut events, Some(Duration::from_secs(1)))?;
+//! for event in events.iter() {
+//!     if event.token() == 0 && event.is_readable() {
+//!         // read from stdin
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
+
+use nix::sys::epoll::{self, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp};
+
+use crate::sys::error::{SysError, SysResult};
+use crate::tools::fd::Fd;
+
+/// The events a file descriptor is registered for, along with the triggering mode.
+///
+/// By default registrations are level-triggered.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Interest(EpollFlags);
+
+impl Interest {
+    /// Wait for the file descriptor to become readable.
+    pub fn readable() -> Self {
+        Self(EpollFlags::EPOLLIN)
+    }
+
+    /// Wait for the file descriptor to become writable.
+    pub fn writable() -> Self {
+        Self(EpollFlags::EPOLLOUT)
+    }
+
+    /// Wait for the file descriptor to become readable or writable.
+    pub fn read_write() -> Self {
+        Self(EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT)
+    }
+
+    /// Also wait for the peer closing its writing end of a stream socket.
+    pub fn read_hangup(self) -> Self {
+        Self(self.0 | EpollFlags::EPOLLRDHUP)
+    }
+
+    /// Use edge-triggered instead of level-triggered notification.
+    pub fn edge_triggered(self) -> Self {
+        Self(self.0 | EpollFlags::EPOLLET)
+    }
+
+    /// Disable the registration after the first event, it needs to be re-armed via
+    /// `Epoll::modify`.
+    pub fn oneshot(self) -> Self {
+        Self(self.0 | EpollFlags::EPOLLONESHOT)
+    }
+
+    /// Access the raw flags.
+    pub fn flags(self) -> EpollFlags {
+        self.0
+    }
+}
+
+/// An epoll instance.
+pub struct Epoll {
+    fd: Fd,
+}
+
+impl Epoll {
+    /// Create a new epoll instance. The file descriptor is created with `O_CLOEXEC`.
+    pub fn new() -> io::Result<Self> {
+        let fd = epoll::epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).into_io_result()?;
+        Ok(Self { fd: Fd(fd) })
+    }
+
+    fn ctl(&self, op: EpollOp, fd: RawFd, event: Option<&mut EpollEvent>) -> io::Result<()> {
+        epoll::epoll_ctl(self.fd.as_raw_fd(), op, fd, event).into_io_result()
+    }
+
+    /// Register a file descriptor. The `token` is returned with every event of this file
+    /// descriptor.
+    pub fn add<F: ?Sized + AsRawFd>(
+        &self,
+        fd: &F,
+        interest: Interest,
+        token: u64,
+    ) -> io::Result<()> {
+        let mut event = EpollEvent::new(interest.0, token);
+        self.ctl(EpollOp::EpollCtlAdd, fd.as_raw_fd(), Some(&mut event))
+    }
+
+    /// Change the registration of a file descriptor.
+    pub fn modify<F: ?Sized + AsRawFd>(
+        &self,
+        fd: &F,
+        interest: Interest,
+        token: u64,
+    ) -> io::Result<()> {
+        let mut event = EpollEvent::new(interest.0, token);
+        self.ctl(EpollOp::EpollCtlMod, fd.as_raw_fd(), Some(&mut event))
+    }
+
+    /// Remove a file descriptor.
+    pub fn delete<F: ?Sized + AsRawFd>(&self, fd: &F) -> io::Result<()> {
+        // pre 2.6.9 kernels require a non-null event pointer
+        let mut event = EpollEvent::empty();
+        self.ctl(EpollOp::EpollCtlDel, fd.as_raw_fd(), Some(&mut event))
+    }
+
+    /// Wait for events, with an optional timeout.
+    ///
+    /// The previous contents of `events` are replaced. Returns the number of received events,
+    /// which is `0` if the timeout was reached or the call was interrupted by a signal.
+    pub fn wait(&self, events: &mut Events, timeout: Option<Duration>) -> io::Result<usize> {
+        let timeout = match timeout {
+            None => -1,
+            Some(timeout) => {
+                // round up to not wake up too early
+                let millis = (timeout.as_micros() + 999) / 1000;
+                millis.min(libc::c_int::max_value() as u128) as isize
+            }
+        };
+
+        events.len = 0;
+        match epoll::epoll_wait(self.fd.as_raw_fd(), &mut events.list, timeout) {
+            Ok(count) => {
+                events.len = count;
+                Ok(count)
+            }
+            Err(err) if err.is_errno(nix::errno::Errno::EINTR) => Ok(0),
+            Err(err) => Err(err.into_io_error()),
+        }
+    }
+}
+
+impl AsRawFd for Epoll {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Epoll {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl FromRawFd for Epoll {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self { fd: Fd(fd) }
+    }
+}
+
+/// A buffer for events received via `Epoll::wait`.
+pub struct Events {
+    list: Vec<EpollEvent>,
+    len: usize,
+}
+
+impl Events {
+    /// Create an event buffer receiving up to `capacity` events per call to `Epoll::wait`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            list: vec![EpollEvent::empty(); capacity.max(1)],
+            len: 0,
+        }
+    }
+
+    /// The number of events received by the last `Epoll::wait` call.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the events received by the last `Epoll::wait` call.
+    pub fn iter(&self) -> impl Iterator<Item = Event> + '_ {
+        self.list[..self.len].iter().map(|event| Event {
+            flags: event.events(),
+            token: event.data(),
+        })
+    }
+}
+
+/// A single event received via `Epoll::wait`.
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    flags: EpollFlags,
+    token: u64,
+}
+
+impl Event {
+    /// The token the file descriptor was registered with.
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.flags.contains(EpollFlags::EPOLLIN)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags.contains(EpollFlags::EPOLLOUT)
+    }
+
+    /// The peer closed its end of the connection (or its writing end for `EPOLLRDHUP`).
+    pub fn is_hangup(&self) -> bool {
+        self.flags
+            .intersects(EpollFlags::EPOLLHUP | EpollFlags::EPOLLRDHUP)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.flags.contains(EpollFlags::EPOLLERR)
+    }
+
+    /// Access the raw flags.
+    pub fn flags(&self) -> EpollFlags {
+        self.flags
+    }
+}
+
+#[test]
+fn test_epoll() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let (mut a, b) = UnixStream::pair().expect("failed to create socket pair");
+
+    let epoll = Epoll::new().expect("failed to create epoll instance");
+    epoll
+        .add(&b, Interest::readable().read_hangup(), 42)
+        .expect("failed to register socket");
+
+    let mut events = Events::with_capacity(4);
+    let count = epoll
+        .wait(&mut events, Some(Duration::from_millis(0)))
+        .expect("epoll_wait failed");
+    assert_eq!(count, 0);
+    assert!(events.is_empty());
+
+    a.write_all(b"x").expect("failed to write to socket");
+    let count = epoll
+        .wait(&mut events, Some(Duration::from_secs(5)))
+        .expect("epoll_wait failed");
+    assert_eq!(count, 1);
+    let event = events.iter().next().unwrap();
+    assert_eq!(event.token(), 42);
+    assert!(event.is_readable());
+    assert!(!event.is_hangup());
+
+    epoll
+        .modify(&b, Interest::writable(), 7)
+        .expect("failed to modify registration");
+    epoll
+        .wait(&mut events, Some(Duration::from_secs(5)))
+        .expect("epoll_wait failed");
+    let event = events.iter().next().unwrap();
+    assert_eq!(event.token(), 7);
+    assert!(event.is_writable());
+
+    epoll.delete(&b).expect("failed to remove socket");
+    let count = epoll
+        .wait(&mut events, Some(Duration::from_millis(0)))
+        .expect("epoll_wait failed");
+    assert_eq!(count, 0);
+}
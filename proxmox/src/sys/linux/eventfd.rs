@@ -0,0 +1,165 @@
+//! A file descriptor based event counter, see `eventfd(2)`.
+//!
+//! This is the standard mechanism to wake up an event loop (eg. one using `epoll`) from another
+//! thread.
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use proxmox::sys::linux::eventfd::EventFd;
+//! # fn code() -> std::io::Result<()> {
+//! let wakeup = Arc::new(EventFd::new(0)?);
+//!
+//! let waker = Arc::clone(&wakeup);
+//! std::thread::spawn(move || waker.notify());
+//!
+//! wakeup.read()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use crate::tools::fd::Fd;
+
+/// An event counter file descriptor.
+///
+/// In counter mode (the default) a read returns the current counter value and resets it to zero.
+/// In semaphore mode a read returns `1` and decrements the counter by one.
+///
+/// The counter cannot exceed `u64::max_value() - 1`, a write which would overflow blocks, or
+/// fails with `WouldBlock` for non-blocking file descriptors.
+pub struct EventFd {
+    fd: Fd,
+}
+
+impl EventFd {
+    /// Create a new event counter in counter mode with an initial value. The file descriptor is
+    /// created with `O_CLOEXEC`.
+    pub fn new(initval: u32) -> io::Result<Self> {
+        Self::with_flags(initval, libc::EFD_CLOEXEC)
+    }
+
+    /// Create a new event counter in semaphore mode.
+    pub fn new_semaphore(initval: u32) -> io::Result<Self> {
+        Self::with_flags(initval, libc::EFD_CLOEXEC | libc::EFD_SEMAPHORE)
+    }
+
+    /// Like `new`, but create a non-blocking file descriptor, suitable for use in event loops.
+    pub fn new_nonblocking(initval: u32) -> io::Result<Self> {
+        Self::with_flags(initval, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK)
+    }
+
+    /// Create a new event counter with raw `EFD_*` flags.
+    pub fn with_flags(initval: u32, flags: libc::c_int) -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(initval, flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd: Fd(fd) })
+    }
+
+    /// Read the counter. In counter mode, this returns the value and resets it to zero, in
+    /// semaphore mode this returns `1` and decrements the counter.
+    ///
+    /// This blocks while the counter is zero. For non-blocking file descriptors `0` is returned
+    /// instead.
+    pub fn read(&self) -> io::Result<u64> {
+        let mut value = 0u64;
+
+        loop {
+            let rc = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut value as *mut u64 as *mut libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+
+            if rc < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return Ok(0),
+                    _ => return Err(err),
+                }
+            }
+
+            return Ok(value);
+        }
+    }
+
+    /// Add `value` to the counter.
+    ///
+    /// A `value` of `u64::max_value()` is invalid and results in an `InvalidInput` error.
+    pub fn write(&self, value: u64) -> io::Result<()> {
+        loop {
+            let rc = unsafe {
+                libc::write(
+                    self.fd.as_raw_fd(),
+                    &value as *const u64 as *const libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+
+            if rc < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            return Ok(());
+        }
+    }
+
+    /// Increment the counter by one to wake up a reader.
+    ///
+    /// Failures are ignored, since the only possible error is an overflowing counter, in which
+    /// case a wakeup is pending anyway.
+    pub fn notify(&self) {
+        let _ = self.write(1);
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for EventFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl FromRawFd for EventFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self { fd: Fd(fd) }
+    }
+}
+
+#[test]
+fn test_eventfd() {
+    let counter = EventFd::new_nonblocking(3).expect("failed to create eventfd");
+    counter.write(2).expect("failed to write to eventfd");
+    assert_eq!(counter.read().expect("failed to read eventfd"), 5);
+    assert_eq!(counter.read().expect("failed to read eventfd"), 0);
+
+    let semaphore = EventFd::with_flags(
+        2,
+        libc::EFD_CLOEXEC | libc::EFD_NONBLOCK | libc::EFD_SEMAPHORE,
+    )
+    .expect("failed to create eventfd");
+    assert_eq!(semaphore.read().expect("failed to read eventfd"), 1);
+    assert_eq!(semaphore.read().expect("failed to read eventfd"), 1);
+    assert_eq!(semaphore.read().expect("failed to read eventfd"), 0);
+
+    let err = semaphore
+        .write(u64::max_value())
+        .expect_err("writing u64::MAX should fail");
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
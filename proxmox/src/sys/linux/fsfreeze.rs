@@ -0,0 +1,149 @@
+//! Freezing file systems for consistent snapshots, see `ioctl_fsfreeze(2)`.
+//!
+//! A frozen file system blocks all writers until it is thawed again, so freezing should always
+//! be done via `FreezeGuard`, which thaws on drop and optionally after a timeout, in case the
+//! snapshot code hangs:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use proxmox::sys::linux::fsfreeze::FreezeGuard;
+//! # fn take_snapshot() {}
+//! # fn code() -> std::io::Result<()> {
+//! let guard = FreezeGuard::freeze("/var/lib/data", Some(Duration::from_secs(60)))?;
+//! take_snapshot();
+//! if guard.timed_out() {
+//!     eprintln!("file system was thawed before the snapshot completed");
+//! }
+//! guard.thaw()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::sys::error::SysResult;
+
+// /usr/include/linux/fs.h: #define FIFREEZE _IOWR('X', 119, int)
+nix::ioctl_readwrite!(fifreeze, b'X', 119, libc::c_int);
+// /usr/include/linux/fs.h: #define FITHAW _IOWR('X', 120, int)
+nix::ioctl_readwrite!(fithaw, b'X', 120, libc::c_int);
+
+fn freeze_fd(fd: RawFd) -> io::Result<()> {
+    let mut arg: libc::c_int = 0;
+    unsafe { fifreeze(fd, &mut arg) }.into_io_result().map(drop)
+}
+
+fn thaw_fd(fd: RawFd) -> io::Result<()> {
+    let mut arg: libc::c_int = 0;
+    unsafe { fithaw(fd, &mut arg) }.into_io_result().map(drop)
+}
+
+fn open_mountpoint(path: &Path) -> io::Result<File> {
+    // the ioctls work on any file of the file system, but a directory can always be opened
+    // read-only
+    File::open(path)
+}
+
+/// Freeze the file system containing `path`. Requires `CAP_SYS_ADMIN`. Fails with `EBUSY` if it
+/// is already frozen.
+pub fn fs_freeze<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    freeze_fd(open_mountpoint(path.as_ref())?.as_raw_fd())
+}
+
+/// Thaw the file system containing `path`. Fails with `EINVAL` if it is not frozen.
+pub fn fs_thaw<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    thaw_fd(open_mountpoint(path.as_ref())?.as_raw_fd())
+}
+
+/// Keeps a file system frozen until it is dropped or thawed explicitly, see the module
+/// documentation.
+pub struct FreezeGuard {
+    file: Arc<File>,
+    thawed: bool,
+    timed_out: Arc<AtomicBool>,
+    watchdog: Option<(mpsc::Sender<()>, JoinHandle<()>)>,
+}
+
+impl FreezeGuard {
+    /// Freeze the file system containing `path`. With a `timeout`, a background thread thaws it
+    /// automatically once the timeout expires.
+    pub fn freeze<P: AsRef<Path>>(path: P, timeout: Option<Duration>) -> io::Result<Self> {
+        let file = Arc::new(open_mountpoint(path.as_ref())?);
+        freeze_fd(file.as_raw_fd())?;
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let watchdog = match timeout {
+            None => None,
+            Some(timeout) => {
+                let (sender, receiver) = mpsc::channel::<()>();
+                let file = Arc::clone(&file);
+                let timed_out = Arc::clone(&timed_out);
+                let spawned = std::thread::Builder::new()
+                    .name("fsfreeze-watchdog".to_string())
+                    .spawn(move || {
+                        // a message or a disconnect means the guard thawed the file system
+                        if let Err(mpsc::RecvTimeoutError::Timeout) = receiver.recv_timeout(timeout)
+                        {
+                            timed_out.store(true, Ordering::SeqCst);
+                            let _ = thaw_fd(file.as_raw_fd());
+                        }
+                    });
+                match spawned {
+                    Ok(handle) => Some((sender, handle)),
+                    Err(err) => {
+                        let _ = thaw_fd(file.as_raw_fd());
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        Ok(Self {
+            file,
+            thawed: false,
+            timed_out,
+            watchdog,
+        })
+    }
+
+    /// Whether the timeout expired and the file system was already thawed by the watchdog.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Thaw the file system. Succeeds if the watchdog already thawed it, check `timed_out` to
+    /// find out whether the file system stayed frozen all the time.
+    pub fn thaw(mut self) -> io::Result<()> {
+        self.do_thaw()
+    }
+
+    fn do_thaw(&mut self) -> io::Result<()> {
+        if self.thawed {
+            return Ok(());
+        }
+        self.thawed = true;
+
+        if let Some((sender, handle)) = self.watchdog.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+        if self.timed_out() {
+            return Ok(());
+        }
+
+        thaw_fd(self.file.as_raw_fd())
+    }
+}
+
+impl Drop for FreezeGuard {
+    fn drop(&mut self) {
+        let _ = self.do_thaw();
+    }
+}
@@ -0,0 +1,209 @@
+//! Querying and changing the host name.
+//!
+//! Besides the `sethostname(2)` wrapper, `set_system_hostname` also updates `/etc/hostname` and
+//! `/etc/hosts`, so the new name survives a reboot and still resolves:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::hostname;
+//! # fn code() -> Result<(), anyhow::Error> {
+//! hostname::set_system_hostname("pve2", Some("example.com"))?;
+//! assert_eq!(hostname::nodename()?, "pve2");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+
+use anyhow::{bail, format_err, Error};
+
+use crate::sys::error::SysResult;
+use crate::tools::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+const ETC_HOSTNAME: &str = "/etc/hostname";
+const ETC_HOSTS: &str = "/etc/hosts";
+
+/// The address used by Debian for the host's own name if there is no static address.
+const LOCAL_HOST_ADDRESS: &str = "127.0.1.1";
+
+/// Get the current host name as set in the kernel. Contrary to `crate::tools::nodename`, this is
+/// not cached and not shortened at the first dot.
+pub fn nodename() -> io::Result<String> {
+    let mut buf = [0u8; 256];
+    let name = nix::unistd::gethostname(&mut buf).into_io_result()?;
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Set the host name of the current UTS namespace. This does not touch any configuration files,
+/// see `set_system_hostname`.
+pub fn sethostname(name: &str) -> io::Result<()> {
+    if !is_valid_hostname(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid host name {:?}", name),
+        ));
+    }
+    nix::unistd::sethostname(name).into_io_result()
+}
+
+/// Check whether `name` is a valid DNS label: 1 to 63 ASCII letters, digits and hyphens, not
+/// starting or ending with a hyphen.
+pub fn is_valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+/// Check whether `domain` is a valid domain name consisting of valid labels.
+pub fn is_valid_domain(domain: &str) -> bool {
+    domain.len() <= 253 && domain.split('.').all(is_valid_hostname)
+}
+
+/// Rewrite the contents of an `/etc/hosts` file after renaming the host from `old` (a host name
+/// without domain) to `name`, optionally with a new `domain`.
+///
+/// All occurrences of the old name and of fully qualified names starting with it are replaced.
+/// Without a new domain, fully qualified names keep their old domain. If the new name does not
+/// appear anywhere, an entry for `127.0.1.1` is added. Comments and unrelated lines are kept.
+pub fn rewrite_hosts(content: &str, old: &str, name: &str, domain: Option<&str>) -> String {
+    let old_prefix = format!("{}.", old);
+    let mut found = false;
+    let mut out = String::with_capacity(content.len() + 64);
+
+    for line in content.lines() {
+        let (entry, comment) = match line.find('#') {
+            Some(pos) => line.split_at(pos),
+            None => (line, ""),
+        };
+
+        let mut fields = entry.split_whitespace();
+        let address = match fields.next() {
+            Some(address) => address,
+            None => {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            }
+        };
+
+        let mut changed = false;
+        let names: Vec<String> = fields
+            .map(|field| {
+                let new = if field == old {
+                    name.to_string()
+                } else if field.starts_with(&old_prefix) {
+                    match domain {
+                        Some(domain) => format!("{}.{}", name, domain),
+                        None => format!("{}.{}", name, &field[old_prefix.len()..]),
+                    }
+                } else {
+                    return field.to_string();
+                };
+                changed = true;
+                new
+            })
+            .collect();
+
+        found = found || names.iter().any(|n| n == name);
+
+        if !changed {
+            out.push_str(line);
+        } else {
+            out.push_str(address);
+            out.push('\t');
+            out.push_str(&names.join(" "));
+            if !comment.is_empty() {
+                out.push(' ');
+                out.push_str(comment);
+            }
+        }
+        out.push('\n');
+    }
+
+    if !found {
+        out.push_str(LOCAL_HOST_ADDRESS);
+        out.push('\t');
+        if let Some(domain) = domain {
+            out.push_str(&format!("{}.{} ", name, domain));
+        }
+        out.push_str(name);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Change the host name persistently: rewrite `/etc/hosts` (see `rewrite_hosts`) and
+/// `/etc/hostname`, then set the kernel's host name. Both files are replaced atomically.
+pub fn set_system_hostname(name: &str, domain: Option<&str>) -> Result<(), Error> {
+    if !is_valid_hostname(name) {
+        bail!("invalid host name {:?}", name);
+    }
+    if let Some(domain) = domain {
+        if !is_valid_domain(domain) {
+            bail!("invalid domain name {:?}", domain);
+        }
+    }
+
+    let old = match file_read_optional_string(ETC_HOSTNAME)? {
+        Some(old) if !old.trim().is_empty() => old.trim().to_string(),
+        _ => nodename()?,
+    };
+    let old = old.split('.').next().unwrap_or(&old).to_string();
+
+    let hosts = file_read_optional_string(ETC_HOSTS)?.unwrap_or_default();
+    let hosts = rewrite_hosts(&hosts, &old, name, domain);
+
+    replace_file(ETC_HOSTS, hosts.as_bytes(), CreateOptions::new())
+        .map_err(|err| format_err!("failed to update {} - {}", ETC_HOSTS, err))?;
+    replace_file(
+        ETC_HOSTNAME,
+        format!("{}\n", name).as_bytes(),
+        CreateOptions::new(),
+    )
+    .map_err(|err| format_err!("failed to update {} - {}", ETC_HOSTNAME, err))?;
+
+    sethostname(name).map_err(|err| format_err!("failed to set host name - {}", err))
+}
+
+#[test]
+fn test_rewrite_hosts() {
+    assert!(is_valid_hostname("pve-1"));
+    assert!(!is_valid_hostname("-pve"));
+    assert!(!is_valid_hostname("pve.local"));
+    assert!(!is_valid_hostname(""));
+    assert!(is_valid_domain("example.com"));
+    assert!(!is_valid_domain("example..com"));
+
+    let hosts = "\
+127.0.0.1 localhost
+# the host itself
+192.168.1.10 pve1.example.com pve1 # static
+::1 localhost ip6-localhost
+";
+    assert_eq!(
+        rewrite_hosts(hosts, "pve1", "pve2", None),
+        "\
+127.0.0.1 localhost
+# the host itself
+192.168.1.10\tpve2.example.com pve2 # static
+::1 localhost ip6-localhost
+",
+    );
+    assert_eq!(
+        rewrite_hosts(hosts, "pve1", "pve2", Some("lab.local")),
+        "\
+127.0.0.1 localhost
+# the host itself
+192.168.1.10\tpve2.lab.local pve2 # static
+::1 localhost ip6-localhost
+",
+    );
+
+    // names which merely start with the old name are left alone
+    assert_eq!(
+        rewrite_hosts("10.0.0.1 pve10\n", "pve1", "pve2", Some("example.com")),
+        "10.0.0.1 pve10\n127.0.1.1\tpve2.example.com pve2\n",
+    );
+}
@@ -0,0 +1,445 @@
+//! `inotify(7)` file system event monitoring.
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::inotify::{AddWatchFlags, Inotify};
+//! # fn code() -> std::io::Result<()> {
+//! let mut inotify = Inotify::new()?;
+//! inotify.add_watch("/etc/myservice", AddWatchFlags::IN_CLOSE_WRITE)?;
+//!
+//! for event in inotify.events() {
+//!     let event = event?;
+//!     println!("{:?} changed", event.path);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+#[doc(inline)]
+pub use nix::sys::inotify::AddWatchFlags;
+
+use crate::sys::error::io_err_other;
+use crate::tools::fd::Fd;
+use crate::{c_result, c_try};
+
+/// Size of the read buffer, this must at least fit one event with a maximum length name.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// A handle to a watch, identifying the watch events belong to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WatchHandle(libc::c_int);
+
+impl WatchHandle {
+    /// The raw watch descriptor.
+    pub fn as_raw(self) -> libc::c_int {
+        self.0
+    }
+}
+
+struct Watch {
+    path: PathBuf,
+    mask: AddWatchFlags,
+    recursive: bool,
+}
+
+/// An event read from an `Inotify` instance.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// The watch this event belongs to.
+    pub watch: WatchHandle,
+    /// The event type.
+    pub mask: AddWatchFlags,
+    /// Connects the `IN_MOVED_FROM` and `IN_MOVED_TO` events of a single rename.
+    pub cookie: u32,
+    /// The name of the affected file within a watched directory.
+    pub name: Option<OsString>,
+    /// The path of the affected file, composed of the watched path and `name`. This is `None`
+    /// for events of watches which have already been removed.
+    pub path: Option<PathBuf>,
+}
+
+/// An inotify instance.
+pub struct Inotify {
+    fd: Fd,
+    watches: HashMap<libc::c_int, Watch>,
+    buffer: Vec<u8>,
+}
+
+impl Inotify {
+    /// Create a new blocking inotify instance.
+    pub fn new() -> io::Result<Self> {
+        Self::with_flags(libc::IN_CLOEXEC)
+    }
+
+    /// Create a new non-blocking inotify instance.
+    pub fn new_nonblocking() -> io::Result<Self> {
+        Self::with_flags(libc::IN_CLOEXEC | libc::IN_NONBLOCK)
+    }
+
+    fn with_flags(flags: libc::c_int) -> io::Result<Self> {
+        let fd = Fd(c_try!(unsafe { libc::inotify_init1(flags) }));
+        Ok(Self {
+            fd,
+            watches: HashMap::new(),
+            buffer: vec![0u8; BUFFER_SIZE],
+        })
+    }
+
+    /// Watch a file or directory.
+    ///
+    /// Watching the same path again replaces the event mask and returns the same handle. A
+    /// recursive watch stays recursive.
+    pub fn add_watch<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mask: AddWatchFlags,
+    ) -> io::Result<WatchHandle> {
+        self.do_add_watch(path.as_ref(), mask, false)
+    }
+
+    /// Watch a directory and all its subdirectories.
+    ///
+    /// Directories created (or moved) into the tree later on are watched automatically as soon
+    /// as their creation event is read, for this `IN_CREATE` and `IN_MOVED_TO` are always added
+    /// to the mask. Symbolic links are not followed.
+    ///
+    /// Note that files created in a new subdirectory before its watch was added are not
+    /// reported.
+    pub fn add_watch_recursive<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mask: AddWatchFlags,
+    ) -> io::Result<Vec<WatchHandle>> {
+        let mask = mask | AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO;
+        let mut handles = Vec::new();
+        self.add_watch_tree(path.as_ref(), mask, &mut handles)?;
+        Ok(handles)
+    }
+
+    fn add_watch_tree(
+        &mut self,
+        path: &Path,
+        mask: AddWatchFlags,
+        handles: &mut Vec<WatchHandle>,
+    ) -> io::Result<()> {
+        handles.push(self.do_add_watch(path, mask | AddWatchFlags::IN_ONLYDIR, true)?);
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                self.add_watch_tree(&entry.path(), mask, handles)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn do_add_watch(
+        &mut self,
+        path: &Path,
+        mask: AddWatchFlags,
+        recursive: bool,
+    ) -> io::Result<WatchHandle> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(io_err_other)?;
+        let add = |mask: AddWatchFlags| {
+            c_result!(unsafe {
+                libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), mask.bits())
+            })
+        };
+
+        let wd = add(mask)?;
+        let recursive = recursive || self.watches.get(&wd).map_or(false, |watch| watch.recursive);
+        let recursive_mask = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO;
+        let mut mask = mask;
+        if recursive && !mask.contains(recursive_mask) {
+            // keep picking up new subdirectories of an existing recursive watch
+            mask |= recursive_mask;
+            add(mask)?;
+        }
+
+        self.watches.insert(
+            wd,
+            Watch {
+                path: path.to_owned(),
+                mask,
+                recursive,
+            },
+        );
+        Ok(WatchHandle(wd))
+    }
+
+    /// Remove a watch. The kernel generates an `IN_IGNORED` event for it.
+    pub fn remove_watch(&mut self, handle: WatchHandle) -> io::Result<()> {
+        c_try!(unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), handle.0) });
+        self.watches.remove(&handle.0);
+        Ok(())
+    }
+
+    /// Get the path a watch was created for.
+    pub fn watch_path(&self, handle: WatchHandle) -> Option<&Path> {
+        self.watches
+            .get(&handle.0)
+            .map(|watch| watch.path.as_path())
+    }
+
+    /// Read the currently available events.
+    ///
+    /// This blocks until events are available, unless the instance was created via
+    /// `new_nonblocking`, in which case an `io::ErrorKind::WouldBlock` error is returned.
+    pub fn read_events(&mut self) -> io::Result<Vec<Event>> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let result = loop {
+            match Self::read_raw(self.fd.as_raw_fd(), &mut buffer) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                other => break other,
+            }
+        };
+        let events = result.map(|len| self.process_events(&buffer[..len]));
+        self.buffer = buffer;
+        events
+    }
+
+    /// A blocking iterator over events.
+    pub fn events(&mut self) -> Events {
+        Events {
+            inotify: self,
+            pending: Vec::new().into_iter(),
+        }
+    }
+
+    fn read_raw(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+        let got = c_try!(unsafe {
+            libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+        });
+        Ok(got as usize)
+    }
+
+    /// Parse the events read from the inotify file descriptor and update the watch list.
+    fn process_events(&mut self, mut data: &[u8]) -> Vec<Event> {
+        const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+        let mut events = Vec::new();
+        while data.len() >= HEADER_SIZE {
+            let header: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(data.as_ptr() as *const libc::inotify_event) };
+            let name_end = (HEADER_SIZE + header.len as usize).min(data.len());
+            let name = &data[HEADER_SIZE..name_end];
+            data = &data[name_end..];
+
+            // the name is padded with zeroes
+            let name = match name.iter().position(|b| *b == 0) {
+                Some(end) => &name[..end],
+                None => name,
+            };
+            let name = if name.is_empty() {
+                None
+            } else {
+                Some(OsStr::from_bytes(name).to_owned())
+            };
+
+            let path = self.watches.get(&header.wd).map(|watch| match &name {
+                Some(name) => watch.path.join(name),
+                None => watch.path.clone(),
+            });
+
+            events.push(Event {
+                watch: WatchHandle(header.wd),
+                mask: AddWatchFlags::from_bits_truncate(header.mask),
+                cookie: header.cookie,
+                name,
+                path,
+            });
+        }
+
+        for event in &events {
+            self.update_watches(event);
+        }
+
+        events
+    }
+
+    fn update_watches(&mut self, event: &Event) {
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            self.watches.remove(&event.watch.0);
+            return;
+        }
+
+        let new_dir = event.mask.contains(AddWatchFlags::IN_ISDIR)
+            && event
+                .mask
+                .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO);
+        if !new_dir {
+            return;
+        }
+
+        let mask = match self.watches.get(&event.watch.0) {
+            Some(watch) if watch.recursive => watch.mask,
+            _ => return,
+        };
+
+        if let Some(path) = &event.path {
+            // the directory may already be gone again, nothing we can do about it
+            let _ = self.add_watch_tree(path, mask, &mut Vec::new());
+        }
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A blocking iterator over inotify events, see `Inotify::events`.
+pub struct Events<'a> {
+    inotify: &'a mut Inotify,
+    pending: std::vec::IntoIter<Event>,
+}
+
+impl Iterator for Events<'_> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.next() {
+                return Some(Ok(event));
+            }
+
+            match self.inotify.read_events() {
+                Ok(events) => self.pending = events.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inotify-stream")]
+mod stream {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::ready;
+    use futures::stream::Stream;
+    use tokio::io::unix::AsyncFd;
+
+    use super::{AddWatchFlags, Event, Inotify, WatchHandle, BUFFER_SIZE};
+
+    /// An inotify instance usable as an asynchronous `Stream` of events.
+    pub struct AsyncInotify {
+        inner: AsyncFd<Inotify>,
+        pending: VecDeque<Event>,
+        buffer: Vec<u8>,
+    }
+
+    impl AsyncInotify {
+        /// Create a new inotify instance registered with the current tokio reactor.
+        pub fn new() -> io::Result<Self> {
+            Ok(Self {
+                inner: AsyncFd::new(Inotify::new_nonblocking()?)?,
+                pending: VecDeque::new(),
+                buffer: vec![0u8; BUFFER_SIZE],
+            })
+        }
+
+        /// See `Inotify::add_watch`.
+        pub fn add_watch<P: AsRef<Path>>(
+            &mut self,
+            path: P,
+            mask: AddWatchFlags,
+        ) -> io::Result<WatchHandle> {
+            self.inner.get_mut().add_watch(path, mask)
+        }
+
+        /// See `Inotify::add_watch_recursive`.
+        pub fn add_watch_recursive<P: AsRef<Path>>(
+            &mut self,
+            path: P,
+            mask: AddWatchFlags,
+        ) -> io::Result<Vec<WatchHandle>> {
+            self.inner.get_mut().add_watch_recursive(path, mask)
+        }
+
+        /// See `Inotify::remove_watch`.
+        pub fn remove_watch(&mut self, handle: WatchHandle) -> io::Result<()> {
+            self.inner.get_mut().remove_watch(handle)
+        }
+    }
+
+    impl Stream for AsyncInotify {
+        type Item = io::Result<Event>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            loop {
+                if let Some(event) = this.pending.pop_front() {
+                    return Poll::Ready(Some(Ok(event)));
+                }
+
+                let mut guard = ready!(this.inner.poll_read_ready(cx))?;
+                let buffer = &mut this.buffer;
+                let result =
+                    guard.try_io(|inner| Inotify::read_raw(inner.get_ref().as_raw_fd(), buffer));
+                drop(guard);
+
+                match result {
+                    Ok(Ok(len)) => {
+                        let events = this.inner.get_mut().process_events(&this.buffer[..len]);
+                        this.pending.extend(events);
+                    }
+                    Ok(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inotify-stream")]
+pub use stream::AsyncInotify;
+
+#[test]
+fn test_inotify_recursive() {
+    let base = std::env::temp_dir().join(format!("proxmox-inotify-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(base.join("existing")).expect("failed to create test directory");
+
+    let mut inotify = Inotify::new().expect("failed to create inotify instance");
+    let handles = inotify
+        .add_watch_recursive(&base, AddWatchFlags::IN_CLOSE_WRITE)
+        .expect("failed to add recursive watch");
+    assert_eq!(handles.len(), 2);
+    assert_eq!(inotify.watch_path(handles[0]), Some(base.as_path()));
+
+    // watching the directory again must not turn off the recursion
+    let handle = inotify
+        .add_watch(&base, AddWatchFlags::IN_CLOSE_WRITE)
+        .expect("failed to add watch");
+    assert_eq!(handle, handles[0]);
+
+    std::fs::create_dir(base.join("new")).expect("failed to create subdirectory");
+    let events = inotify.read_events().expect("failed to read events");
+    assert!(events
+        .iter()
+        .any(|event| event.mask.contains(AddWatchFlags::IN_ISDIR)
+            && event.name.as_deref() == Some(OsStr::new("new"))));
+
+    // the new directory must be watched now
+    std::fs::write(base.join("new/file"), b"data").expect("failed to write test file");
+    let event = inotify
+        .events()
+        .map(|event| event.expect("failed to read event"))
+        .find(|event| event.mask.contains(AddWatchFlags::IN_CLOSE_WRITE))
+        .expect("missing close-write event");
+    assert_eq!(event.path, Some(base.join("new/file")));
+
+    let _ = std::fs::remove_dir_all(&base);
+}
@@ -0,0 +1,400 @@
+//! Zero-copy data transfer and file space management helpers.
+//!
+//! The `copy_*` wrappers map directly to the corresponding system calls, `copy_data` picks the
+//! most efficient one available and falls back to a plain read/write loop:
+//!
+//! 1. `copy_file_range(2)`, which can use reflinks or server side copies,
+//! 2. `sendfile(2)`, which avoids copying the data to user space,
+//! 3. `read(2)` and `write(2)` with an intermediate buffer.
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use proxmox::sys::linux::io::copy_data;
+//! # fn code() -> std::io::Result<()> {
+//! let src = File::open("/path/to/source")?;
+//! let dst = File::create("/path/to/target")?;
+//! let copied = copy_data(&src, &dst, None)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The `fallocate(2)` helpers allow preallocating space for files and deallocating ranges of
+//! them, to create or keep sparse files.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+use crate::tools::fd::BorrowedFd;
+
+/// Copy up to `len` bytes between two files via `copy_file_range(2)`.
+///
+/// If an offset is `None`, the file's current position is used and updated, otherwise the
+/// offset is used and updated instead. Returns the number of bytes copied, `0` at the end of the
+/// source file.
+pub fn copy_file_range<S: ?Sized + AsRawFd, D: ?Sized + AsRawFd>(
+    src: &S,
+    src_offset: Option<&mut i64>,
+    dst: &D,
+    dst_offset: Option<&mut i64>,
+    len: usize,
+) -> io::Result<usize> {
+    // use the syscall directly, older glibc versions do not provide a wrapper
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_copy_file_range,
+            src.as_raw_fd(),
+            offset_ptr(src_offset),
+            dst.as_raw_fd(),
+            offset_ptr(dst_offset),
+            len,
+            0 as libc::c_uint,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc as usize)
+}
+
+/// Send up to `len` bytes from a file to any other file descriptor via `sendfile(2)`.
+///
+/// If `src_offset` is `None`, the source file's current position is used and updated, otherwise
+/// the offset is used and updated instead. Returns the number of bytes transferred.
+pub fn sendfile<S: ?Sized + AsRawFd, D: ?Sized + AsRawFd>(
+    src: &S,
+    src_offset: Option<&mut i64>,
+    dst: &D,
+    len: usize,
+) -> io::Result<usize> {
+    let rc = unsafe {
+        libc::sendfile(
+            dst.as_raw_fd(),
+            src.as_raw_fd(),
+            offset_ptr(src_offset),
+            len,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc as usize)
+}
+
+/// Move up to `len` bytes between two file descriptors via `splice(2)`, one of which must be a
+/// pipe.
+///
+/// Offsets must be `None` for pipes. Returns the number of bytes moved.
+pub fn splice<S: ?Sized + AsRawFd, D: ?Sized + AsRawFd>(
+    src: &S,
+    src_offset: Option<&mut i64>,
+    dst: &D,
+    dst_offset: Option<&mut i64>,
+    len: usize,
+    flags: libc::c_uint,
+) -> io::Result<usize> {
+    let rc = unsafe {
+        libc::splice(
+            src.as_raw_fd(),
+            offset_ptr(src_offset),
+            dst.as_raw_fd(),
+            offset_ptr(dst_offset),
+            len,
+            flags,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc as usize)
+}
+
+fn offset_ptr(offset: Option<&mut i64>) -> *mut libc::loff_t {
+    match offset {
+        Some(offset) => offset as *mut i64 as *mut libc::loff_t,
+        None => ptr::null_mut(),
+    }
+}
+
+// Limit the size of a single call, the kernel limits this to about 2G anyway.
+const CHUNK_SIZE: usize = 1 << 30;
+
+fn chunk_size(remaining: Option<u64>) -> usize {
+    match remaining {
+        Some(remaining) if remaining < CHUNK_SIZE as u64 => remaining as usize,
+        _ => CHUNK_SIZE,
+    }
+}
+
+// Errors signaling that a method is not usable for this pair of file descriptors, in which case
+// we try the next one.
+fn is_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOSYS)
+            | Some(libc::EXDEV)
+            | Some(libc::EINVAL)
+            | Some(libc::EOPNOTSUPP)
+            | Some(libc::EBADF)
+            | Some(libc::ESPIPE)
+    )
+}
+
+/// Copy data from `src` to `dst` starting at their current positions, until either `len` bytes
+/// have been copied or the end of `src` is reached. A `len` of `None` copies everything.
+///
+/// This uses the most efficient method available for the two file descriptors, see the module
+/// documentation. Returns the number of bytes copied.
+pub fn copy_data<S: ?Sized + AsRawFd, D: ?Sized + AsRawFd>(
+    src: &S,
+    dst: &D,
+    len: Option<u64>,
+) -> io::Result<u64> {
+    let src = BorrowedFd::new(src);
+    let dst = BorrowedFd::new(dst);
+    let mut copied = 0u64;
+
+    let remaining = |copied: u64| len.map(|len| len - copied);
+
+    // When a method fails right away we can try the next one, since nothing has been consumed
+    // from `src` yet.
+    let methods: [fn(&BorrowedFd, &BorrowedFd, usize) -> io::Result<usize>; 2] = [
+        |src, dst, len| copy_file_range(src, None, dst, None, len),
+        |src, dst, len| sendfile(src, None, dst, len),
+    ];
+
+    for method in methods.iter() {
+        let mut first = true;
+        loop {
+            if remaining(copied) == Some(0) {
+                return Ok(copied);
+            }
+
+            match method(&src, &dst, chunk_size(remaining(copied))) {
+                Ok(0) => return Ok(copied),
+                Ok(count) => copied += count as u64,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref err) if first && is_unsupported(err) => break,
+                Err(err) => return Err(err),
+            }
+            first = false;
+        }
+    }
+
+    copied += read_write_loop(src.as_raw_fd(), dst.as_raw_fd(), remaining(copied))?;
+    Ok(copied)
+}
+
+fn read_write_loop(src: RawFd, dst: RawFd, len: Option<u64>) -> io::Result<u64> {
+    let mut buffer = vec![0u8; 128 * 1024];
+    let mut copied = 0u64;
+
+    loop {
+        let max = match len {
+            Some(len) if len - copied < buffer.len() as u64 => (len - copied) as usize,
+            _ => buffer.len(),
+        };
+        if max == 0 {
+            return Ok(copied);
+        }
+
+        let got = unsafe { libc::read(src, buffer.as_mut_ptr() as *mut libc::c_void, max) };
+        if got < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if got == 0 {
+            return Ok(copied);
+        }
+
+        let mut data = &buffer[..(got as usize)];
+        while !data.is_empty() {
+            let put = unsafe { libc::write(dst, data.as_ptr() as *const libc::c_void, data.len()) };
+            if put < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if put == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data = &data[(put as usize)..];
+        }
+
+        copied += got as u64;
+    }
+}
+
+/// Manipulate the allocated space of a file via `fallocate(2)`, `mode` is a combination of the
+/// `libc::FALLOC_FL_*` flags.
+pub fn fallocate<F: ?Sized + AsRawFd>(
+    fd: &F,
+    mode: libc::c_int,
+    offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    loop {
+        let rc = unsafe {
+            libc::fallocate(
+                fd.as_raw_fd(),
+                mode,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+        if rc == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Allocate disk space for a range of a file, so later writes to it cannot fail with `ENOSPC`.
+///
+/// If `keep_size` is set, the file size is not changed when allocating beyond its end.
+pub fn preallocate<F: ?Sized + AsRawFd>(
+    fd: &F,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> io::Result<()> {
+    let mode = if keep_size {
+        libc::FALLOC_FL_KEEP_SIZE
+    } else {
+        0
+    };
+    fallocate(fd, mode, offset, len)
+}
+
+/// Deallocate a range of a file, creating a hole which reads as zeroes. The file size does not
+/// change.
+pub fn punch_hole<F: ?Sized + AsRawFd>(fd: &F, offset: u64, len: u64) -> io::Result<()> {
+    fallocate(
+        fd,
+        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+        offset,
+        len,
+    )
+}
+
+/// Zero out a range of a file. Contrary to `punch_hole` the space stays allocated, which is
+/// usually more efficient than writing zeroes.
+///
+/// If `keep_size` is set, the file size is not changed when zeroing beyond its end.
+pub fn zero_range<F: ?Sized + AsRawFd>(
+    fd: &F,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> io::Result<()> {
+    let mut mode = libc::FALLOC_FL_ZERO_RANGE;
+    if keep_size {
+        mode |= libc::FALLOC_FL_KEEP_SIZE;
+    }
+    fallocate(fd, mode, offset, len)
+}
+
+#[test]
+fn test_copy_data() {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let base = std::env::temp_dir().join(format!("proxmox-io-test-{}", std::process::id()));
+    let src_path = base.with_extension("src");
+    let dst_path = base.with_extension("dst");
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+    let mut src = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&src_path)
+        .expect("failed to create source file");
+    src.write_all(&data).expect("failed to write source file");
+    src.seek(SeekFrom::Start(0)).unwrap();
+
+    let mut dst = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&dst_path)
+        .expect("failed to create target file");
+
+    let _ = std::fs::remove_file(&src_path);
+    let _ = std::fs::remove_file(&dst_path);
+
+    assert_eq!(
+        copy_data(&src, &dst, Some(1000)).expect("copy failed"),
+        1000
+    );
+    assert_eq!(copy_data(&src, &dst, None).expect("copy failed"), 199_000);
+    assert_eq!(copy_data(&src, &dst, None).expect("copy failed"), 0);
+
+    let mut copy = Vec::new();
+    dst.seek(SeekFrom::Start(0)).unwrap();
+    dst.read_to_end(&mut copy)
+        .expect("failed to read target file");
+    assert!(copy == data);
+
+    // sockets are neither supported by copy_file_range nor as sendfile source
+    let (a, mut b) = std::os::unix::net::UnixStream::pair().expect("failed to create sockets");
+    b.write_all(b"socket data").unwrap();
+    drop(b);
+    dst.seek(SeekFrom::Start(0)).unwrap();
+    dst.set_len(0).unwrap();
+    assert_eq!(copy_data(&a, &dst, None).expect("copy failed"), 11);
+}
+
+#[test]
+fn test_fallocate() {
+    use std::io::Read;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = std::env::temp_dir().join(format!("proxmox-fallocate-test-{}", std::process::id()));
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .expect("failed to create test file");
+    let _ = std::fs::remove_file(&path);
+
+    match preallocate(&file, 0, 1 << 20, false) {
+        // not every file system supports this
+        Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+        other => other.expect("preallocate failed"),
+    }
+    let meta = file.metadata().unwrap();
+    assert_eq!(meta.len(), 1 << 20);
+    assert!(meta.blocks() * 512 >= 1 << 20);
+
+    preallocate(&file, 1 << 20, 1 << 20, true).expect("preallocate failed");
+    assert_eq!(file.metadata().unwrap().len(), 1 << 20);
+
+    let blocks = file.metadata().unwrap().blocks();
+    match punch_hole(&file, 0, 1 << 20) {
+        Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => (),
+        other => {
+            other.expect("punching a hole failed");
+            let meta = file.metadata().unwrap();
+            assert_eq!(meta.len(), 1 << 20);
+            assert!(meta.blocks() < blocks);
+        }
+    }
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 1 << 20);
+    assert!(data.iter().all(|b| *b == 0));
+}
@@ -0,0 +1,760 @@
+//! Minimal `io_uring(7)` support for file I/O. Requires Linux 5.6.
+//!
+//! `IoUring` is a thin wrapper around the submission and completion queues. `AsyncIoUring` uses
+//! it to provide futures for positional reads and writes, with completions being reaped by a
+//! separate thread:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use proxmox::sys::linux::io_uring::AsyncIoUring;
+//! # async fn code() -> std::io::Result<()> {
+//! let ring = AsyncIoUring::new(64)?;
+//! let file = Arc::new(std::fs::File::open("/path/to/chunk")?);
+//! let data = ring.read_at(file, vec![0u8; 4096], 0).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Since operations may outlive the futures waiting for them, the async interface takes
+//! ownership of the buffers and files for the duration of the operation.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use nix::sys::mman::{MapFlags, ProtFlags};
+
+use crate::tools::fd::Fd;
+use crate::tools::mmap::Mmap;
+
+// these have the same numbers on all architectures
+const SYS_IO_URING_SETUP: libc::c_long = 425;
+const SYS_IO_URING_ENTER: libc::c_long = 426;
+
+const IORING_OFF_SQ_RING: u64 = 0;
+const IORING_OFF_CQ_RING: u64 = 0x800_0000;
+const IORING_OFF_SQES: u64 = 0x1000_0000;
+
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IORING_FSYNC_DATASYNC: u32 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// A submission queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+pub struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+impl Sqe {
+    fn new(opcode: u8, fd: RawFd) -> Self {
+        Self {
+            opcode,
+            flags: 0,
+            ioprio: 0,
+            fd,
+            off: 0,
+            addr: 0,
+            len: 0,
+            op_flags: 0,
+            user_data: 0,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            pad: [0; 2],
+        }
+    }
+
+    /// An operation doing nothing, useful to wake up a thread waiting for completions.
+    pub fn nop() -> Self {
+        Self::new(IORING_OP_NOP, -1)
+    }
+
+    /// Read up to `len` bytes at `offset` into `buf`, like `pread(2)`.
+    pub fn read(fd: RawFd, buf: *mut u8, len: u32, offset: u64) -> Self {
+        let mut sqe = Self::new(IORING_OP_READ, fd);
+        sqe.addr = buf as u64;
+        sqe.len = len;
+        sqe.off = offset;
+        sqe
+    }
+
+    /// Write up to `len` bytes from `buf` at `offset`, like `pwrite(2)`.
+    pub fn write(fd: RawFd, buf: *const u8, len: u32, offset: u64) -> Self {
+        let mut sqe = Self::new(IORING_OP_WRITE, fd);
+        sqe.addr = buf as u64;
+        sqe.len = len;
+        sqe.off = offset;
+        sqe
+    }
+
+    /// Flush a file to disk, like `fsync(2)` or `fdatasync(2)`.
+    pub fn fsync(fd: RawFd, datasync: bool) -> Self {
+        let mut sqe = Self::new(IORING_OP_FSYNC, fd);
+        if datasync {
+            sqe.op_flags = IORING_FSYNC_DATASYNC;
+        }
+        sqe
+    }
+
+    /// Set the value to identify the operation's completion with.
+    pub fn user_data(mut self, user_data: u64) -> Self {
+        self.user_data = user_data;
+        self
+    }
+}
+
+/// A completion queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Completion {
+    /// The `user_data` of the submitted entry.
+    pub user_data: u64,
+    /// The result of the operation, a negative error number on failure.
+    pub result: i32,
+    _flags: u32,
+}
+
+impl Completion {
+    /// Convert the result into an `io::Result`.
+    pub fn into_result(self) -> io::Result<u32> {
+        result_to_io(self.result)
+    }
+}
+
+fn result_to_io(result: i32) -> io::Result<u32> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as u32)
+    }
+}
+
+struct SubmissionQueue {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    mask: u32,
+    entries: u32,
+    array: *mut u32,
+    sqes: *mut Sqe,
+    unsubmitted: u32,
+    _ring: Mmap<u8>,
+    _sqes: Mmap<Sqe>,
+}
+
+struct CompletionQueue {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    mask: u32,
+    cqes: *const Completion,
+    _ring: Mmap<u8>,
+}
+
+/// An `io_uring` instance.
+///
+/// Entries can be pushed and completions popped from multiple threads.
+pub struct IoUring {
+    fd: Fd,
+    sq: Mutex<SubmissionQueue>,
+    cq: Mutex<CompletionQueue>,
+}
+
+// The raw pointers only point into the ring mappings, which live as long as the queues, and are
+// only accessed with the queue locks held.
+unsafe impl Send for IoUring {}
+unsafe impl Sync for IoUring {}
+
+impl IoUring {
+    /// Create a new ring with space for at least `entries` submission queue entries.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                SYS_IO_URING_SETUP,
+                entries as libc::c_uint,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = Fd(fd as RawFd);
+
+        let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+        let flags = MapFlags::MAP_SHARED | MapFlags::MAP_POPULATE;
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize
+            + params.cq_entries as usize * std::mem::size_of::<Completion>();
+
+        let sq_ring: Mmap<u8> =
+            unsafe { Mmap::map_fd(fd.as_raw_fd(), IORING_OFF_SQ_RING, sq_len, prot, flags)? };
+        let cq_ring: Mmap<u8> =
+            unsafe { Mmap::map_fd(fd.as_raw_fd(), IORING_OFF_CQ_RING, cq_len, prot, flags)? };
+        let sqes: Mmap<Sqe> = unsafe {
+            Mmap::map_fd(
+                fd.as_raw_fd(),
+                IORING_OFF_SQES,
+                params.sq_entries as usize,
+                prot,
+                flags,
+            )?
+        };
+
+        // The kernel writes to the rings concurrently, so never create references to them.
+        let sq_base = sq_ring.as_raw_ptr();
+        let cq_base = cq_ring.as_raw_ptr();
+        let off = &params.sq_off;
+        let sq = unsafe {
+            SubmissionQueue {
+                head: sq_base.add(off.head as usize) as *const AtomicU32,
+                tail: sq_base.add(off.tail as usize) as *const AtomicU32,
+                mask: *(sq_base.add(off.ring_mask as usize) as *const u32),
+                entries: *(sq_base.add(off.ring_entries as usize) as *const u32),
+                array: sq_base.add(off.array as usize) as *mut u32,
+                sqes: sqes.as_raw_ptr(),
+                unsubmitted: 0,
+                _ring: sq_ring,
+                _sqes: sqes,
+            }
+        };
+        let off = &params.cq_off;
+        let cq = unsafe {
+            CompletionQueue {
+                head: cq_base.add(off.head as usize) as *const AtomicU32,
+                tail: cq_base.add(off.tail as usize) as *const AtomicU32,
+                mask: *(cq_base.add(off.ring_mask as usize) as *const u32),
+                cqes: cq_base.add(off.cqes as usize) as *const Completion,
+                _ring: cq_ring,
+            }
+        };
+
+        Ok(Self {
+            fd,
+            sq: Mutex::new(sq),
+            cq: Mutex::new(cq),
+        })
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: libc::c_uint) -> io::Result<u32> {
+        let rc = unsafe {
+            libc::syscall(
+                SYS_IO_URING_ENTER,
+                self.fd.as_raw_fd(),
+                to_submit as libc::c_uint,
+                min_complete as libc::c_uint,
+                flags,
+                ptr::null::<libc::sigset_t>(),
+                0 as libc::size_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(rc as u32)
+    }
+
+    fn submit_locked(&self, sq: &mut SubmissionQueue) -> io::Result<u32> {
+        if sq.unsubmitted == 0 {
+            return Ok(0);
+        }
+        let submitted = self.enter(sq.unsubmitted, 0, 0)?;
+        sq.unsubmitted -= submitted.min(sq.unsubmitted);
+        Ok(submitted)
+    }
+
+    /// Add an entry to the submission queue. If the queue is full, the pending entries are
+    /// submitted first.
+    ///
+    /// # Safety
+    ///
+    /// The buffers and file descriptors referenced by the entry must stay valid until its
+    /// completion has been received.
+    pub unsafe fn push(&self, sqe: &Sqe) -> io::Result<()> {
+        let mut sq = self.sq.lock().unwrap();
+
+        let tail = (*sq.tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub((*sq.head).load(Ordering::Acquire)) == sq.entries {
+            self.submit_locked(&mut sq)?;
+            if tail.wrapping_sub((*sq.head).load(Ordering::Acquire)) == sq.entries {
+                return Err(io::Error::from_raw_os_error(libc::EBUSY));
+            }
+        }
+
+        let index = tail & sq.mask;
+        ptr::write(sq.sqes.add(index as usize), *sqe);
+        ptr::write(sq.array.add(index as usize), index);
+        (*sq.tail).store(tail.wrapping_add(1), Ordering::Release);
+        sq.unsubmitted += 1;
+
+        Ok(())
+    }
+
+    /// Submit all pushed entries to the kernel, returns the number of entries submitted.
+    pub fn submit(&self) -> io::Result<u32> {
+        let mut sq = self.sq.lock().unwrap();
+        loop {
+            match self.submit_locked(&mut sq) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                other => return other,
+            }
+        }
+    }
+
+    /// Wait until at least `min_complete` completions are available.
+    pub fn wait(&self, min_complete: u32) -> io::Result<()> {
+        loop {
+            match self.enter(0, min_complete, IORING_ENTER_GETEVENTS) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                other => return other.map(drop),
+            }
+        }
+    }
+
+    /// Take the next completion off the completion queue, if any.
+    pub fn pop_completion(&self) -> Option<Completion> {
+        let cq = self.cq.lock().unwrap();
+        unsafe {
+            let head = (*cq.head).load(Ordering::Relaxed);
+            if head == (*cq.tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let completion = ptr::read(cq.cqes.add((head & cq.mask) as usize));
+            (*cq.head).store(head.wrapping_add(1), Ordering::Release);
+            Some(completion)
+        }
+    }
+}
+
+impl AsRawFd for IoUring {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+const WAKEUP_ID: u64 = u64::max_value();
+
+struct PendingOp {
+    result: Option<i32>,
+    waker: Option<Waker>,
+    buffer: Option<Vec<u8>>,
+    abandoned: bool,
+    _file: Arc<dyn AsRawFd + Send + Sync>,
+}
+
+#[derive(Default)]
+struct Ops {
+    next_id: u64,
+    in_flight: usize,
+    shutdown: bool,
+    /// Error number the completion thread failed with.
+    failed: Option<i32>,
+    pending: HashMap<u64, PendingOp>,
+}
+
+struct Shared {
+    ring: IoUring,
+    ops: Mutex<Ops>,
+}
+
+impl Shared {
+    fn reap(&self) -> io::Result<()> {
+        loop {
+            self.ring.wait(1)?;
+
+            while let Some(completion) = self.ring.pop_completion() {
+                if completion.user_data == WAKEUP_ID {
+                    continue;
+                }
+
+                let mut ops = self.ops.lock().unwrap();
+                ops.in_flight -= 1;
+                let abandoned = match ops.pending.get_mut(&completion.user_data) {
+                    Some(op) if op.abandoned => true,
+                    Some(op) => {
+                        op.result = Some(completion.result);
+                        if let Some(waker) = op.waker.take() {
+                            waker.wake();
+                        }
+                        false
+                    }
+                    None => false,
+                };
+                if abandoned {
+                    ops.pending.remove(&completion.user_data);
+                }
+            }
+
+            // retry entries whose submission failed earlier
+            let _ = self.ring.submit();
+
+            let ops = self.ops.lock().unwrap();
+            if ops.shutdown && ops.in_flight == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Complete all pending operations with an error after the completion thread failed.
+    fn fail(&self, err: io::Error) {
+        let errno = err.raw_os_error().unwrap_or(libc::EIO);
+        let mut ops = self.ops.lock().unwrap();
+        ops.failed = Some(errno);
+        ops.in_flight = 0;
+        ops.pending.retain(|_, op| {
+            if op.result.is_some() {
+                return true;
+            }
+            // The kernel may still access the buffer and file of an operation which never
+            // completed, so they must never be freed.
+            std::mem::forget(op.buffer.take());
+            std::mem::forget(Arc::clone(&op._file));
+            if op.abandoned {
+                return false;
+            }
+            op.result = Some(-errno);
+            if let Some(waker) = op.waker.take() {
+                waker.wake();
+            }
+            true
+        });
+    }
+
+    /// Tell the completion thread to exit once all operations in flight completed.
+    fn stop(&self) -> io::Result<()> {
+        {
+            let mut ops = self.ops.lock().unwrap();
+            if ops.shutdown || ops.failed.is_some() {
+                return Ok(());
+            }
+            ops.shutdown = true;
+        }
+        unsafe { self.ring.push(&Sqe::nop().user_data(WAKEUP_ID))? };
+        self.ring.submit().map(drop)
+    }
+}
+
+/// An `io_uring` instance with a thread reaping completions, providing futures for file I/O.
+pub struct AsyncIoUring {
+    shared: Arc<Shared>,
+}
+
+impl AsyncIoUring {
+    /// Create a new ring with space for at least `entries` concurrent submissions.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            ring: IoUring::new(entries)?,
+            ops: Mutex::new(Ops::default()),
+        });
+
+        let reaper = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("io-uring-reaper".to_string())
+            .spawn(move || {
+                if let Err(err) = reaper.reap() {
+                    reaper.fail(err);
+                }
+            })?;
+
+        Ok(Self { shared })
+    }
+
+    fn submit(
+        &self,
+        sqe: Sqe,
+        buffer: Option<Vec<u8>>,
+        file: Arc<dyn AsRawFd + Send + Sync>,
+    ) -> io::Result<Operation> {
+        let mut ops = self.shared.ops.lock().unwrap();
+        if let Some(errno) = ops.failed {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        let id = ops.next_id;
+        ops.next_id = (id + 1) % WAKEUP_ID;
+
+        // the entry must exist before the completion can arrive
+        ops.pending.insert(
+            id,
+            PendingOp {
+                result: None,
+                waker: None,
+                buffer,
+                abandoned: false,
+                _file: file,
+            },
+        );
+
+        if let Err(err) = unsafe { self.shared.ring.push(&sqe.user_data(id)) } {
+            ops.pending.remove(&id);
+            return Err(err);
+        }
+        ops.in_flight += 1;
+
+        // Once pushed, the entry is owned by the ring and the kernel may use the buffer and fd
+        // at any time, so the pending entry must stay. If submitting fails now, the entry stays
+        // queued and is submitted by the next `submit` call or by the completion thread.
+        let _ = self.shared.ring.submit();
+
+        Ok(Operation {
+            shared: Arc::clone(&self.shared),
+            id,
+        })
+    }
+
+    /// Read up to `buffer.len()` bytes at `offset` from a file. Returns the buffer truncated to
+    /// the number of bytes read.
+    pub async fn read_at<F>(
+        &self,
+        file: Arc<F>,
+        mut buffer: Vec<u8>,
+        offset: u64,
+    ) -> io::Result<Vec<u8>>
+    where
+        F: AsRawFd + Send + Sync + 'static,
+    {
+        let len = buffer.len().min(u32::max_value() as usize) as u32;
+        let sqe = Sqe::read(file.as_raw_fd(), buffer.as_mut_ptr(), len, offset);
+        let (result, buffer) = self.submit(sqe, Some(buffer), file)?.await;
+        let result = result?;
+        let mut buffer = buffer.unwrap();
+        buffer.truncate(result as usize);
+        Ok(buffer)
+    }
+
+    /// Write up to `data.len()` bytes at `offset` to a file. Returns the number of bytes written.
+    pub async fn write_at<F>(&self, file: Arc<F>, data: Vec<u8>, offset: u64) -> io::Result<usize>
+    where
+        F: AsRawFd + Send + Sync + 'static,
+    {
+        let len = data.len().min(u32::max_value() as usize) as u32;
+        let sqe = Sqe::write(file.as_raw_fd(), data.as_ptr(), len, offset);
+        let (result, _) = self.submit(sqe, Some(data), file)?.await;
+        Ok(result? as usize)
+    }
+
+    /// Write all of `data` at `offset` to a file.
+    pub async fn write_all_at<F>(
+        &self,
+        file: Arc<F>,
+        mut data: Vec<u8>,
+        mut offset: u64,
+    ) -> io::Result<()>
+    where
+        F: AsRawFd + Send + Sync + 'static,
+    {
+        while !data.is_empty() {
+            let written = self
+                .write_at(Arc::clone(&file), data.clone(), offset)
+                .await?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data.drain(..written);
+            offset += written as u64;
+        }
+        Ok(())
+    }
+
+    /// Flush a file to disk, only flushing the metadata required to read the data if `datasync`
+    /// is set.
+    pub async fn fsync<F>(&self, file: Arc<F>, datasync: bool) -> io::Result<()>
+    where
+        F: AsRawFd + Send + Sync + 'static,
+    {
+        let sqe = Sqe::fsync(file.as_raw_fd(), datasync);
+        let (result, _) = self.submit(sqe, None, file)?.await;
+        result.map(drop)
+    }
+}
+
+impl AsyncIoUring {
+    /// Stop the completion thread once all operations in flight completed.
+    ///
+    /// This happens implicitly when dropping the ring, use this to see whether the completion
+    /// thread could be woken up.
+    pub fn shutdown(self) -> io::Result<()> {
+        self.shared.stop()
+    }
+}
+
+impl Drop for AsyncIoUring {
+    fn drop(&mut self) {
+        // errors are reported by `shutdown`, which makes this a no-op
+        let _ = self.shared.stop();
+    }
+}
+
+/// Future waiting for a submitted operation.
+struct Operation {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl Future for Operation {
+    type Output = (io::Result<u32>, Option<Vec<u8>>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut ops = self.shared.ops.lock().unwrap();
+        let op = ops
+            .pending
+            .get_mut(&self.id)
+            .expect("io_uring operation polled after completion");
+
+        match op.result {
+            Some(result) => {
+                let op = ops.pending.remove(&self.id).unwrap();
+                Poll::Ready((result_to_io(result), op.buffer))
+            }
+            None => {
+                op.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        // keep the buffer alive until the kernel is done with it
+        let mut ops = self.shared.ops.lock().unwrap();
+        let completed = match ops.pending.get_mut(&self.id) {
+            Some(op) if op.result.is_some() => true,
+            Some(op) => {
+                op.abandoned = true;
+                false
+            }
+            None => false,
+        };
+        if completed {
+            ops.pending.remove(&self.id);
+        }
+    }
+}
+
+#[test]
+fn test_io_uring() {
+    use std::io::Write;
+
+    let ring = match IoUring::new(8) {
+        // may be unavailable or disabled
+        Err(ref err) if matches!(err.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => {
+            return
+        }
+        other => other.expect("failed to create io_uring"),
+    };
+
+    let path = std::env::temp_dir().join(format!("proxmox-io-uring-test-{}", std::process::id()));
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .expect("failed to create test file");
+    let _ = std::fs::remove_file(&path);
+    file.write_all(b"hello io_uring").unwrap();
+
+    let mut buf = [0u8; 8];
+    unsafe {
+        ring.push(&Sqe::read(file.as_raw_fd(), buf.as_mut_ptr(), 8, 6).user_data(42))
+            .expect("failed to push entry");
+    }
+    assert_eq!(ring.submit().expect("submit failed"), 1);
+    ring.wait(1).expect("wait failed");
+
+    let completion = ring.pop_completion().expect("no completion");
+    assert_eq!(completion.user_data, 42);
+    assert_eq!(completion.into_result().unwrap(), 8);
+    assert_eq!(&buf, b"io_uring");
+    assert!(ring.pop_completion().is_none());
+
+    #[cfg(feature = "futures")]
+    {
+        drop(ring);
+        let ring = AsyncIoUring::new(8).expect("failed to create io_uring");
+        let file = Arc::new(file);
+        futures::executor::block_on(async {
+            ring.write_all_at(Arc::clone(&file), b"HELLO".to_vec(), 0)
+                .await
+                .expect("write failed");
+            ring.fsync(Arc::clone(&file), true)
+                .await
+                .expect("fsync failed");
+            let data = ring
+                .read_at(Arc::clone(&file), vec![0u8; 64], 0)
+                .await
+                .expect("read failed");
+            assert_eq!(data, b"HELLO io_uring");
+        });
+    }
+}
@@ -0,0 +1,409 @@
+//! Confining processes to a minimal file system view.
+//!
+//! A `Jail` creates a new mount namespace with an empty root file system, bind mounts a
+//! whitelist of paths into it, switches to it and drops capabilities. This is meant for helpers
+//! processing untrusted data, like file restore extractors:
+//!
+//! ```no_run
+//! # use std::os::unix::process::CommandExt;
+//! # use std::process::Command;
+//! # use proxmox::sys::linux::jail::Jail;
+//! # fn code() -> std::io::Result<()> {
+//! let jail = Jail::new("/run/restore-jail")
+//!     .bind_ro("/usr")
+//!     .bind_ro("/lib")
+//!     .bind_ro("/lib64")
+//!     .bind_ro("/bin")
+//!     .bind_rw_to("/var/tmp/restore-1234", "/work")
+//!     .tmpfs("/tmp")
+//!     .prepare()?;
+//!
+//! let mut cmd = Command::new("/usr/bin/extract");
+//! unsafe {
+//!     cmd.pre_exec(move || jail.enter());
+//! }
+//! cmd.spawn()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Entering a new mount namespace fails in multi threaded processes, so a jail should be entered
+//! in a freshly forked child process. Since only async-signal-safe functions may be used there,
+//! `prepare` converts all paths before forking, and `PreparedJail::enter` only performs raw
+//! system calls. Requires `CAP_SYS_ADMIN`.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::sys::error::io_err_other;
+use crate::sys::linux::capability::{self, Cap};
+use crate::sys::linux::mount::MountFlags;
+use crate::sys::linux::ns::{self, Namespace};
+use crate::sys::linux::prctl;
+use crate::{c_result, c_str, c_try};
+
+const OLD_ROOT: &str = ".old-root";
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(io_err_other)
+}
+
+#[derive(Clone, Debug)]
+struct Bind {
+    source: PathBuf,
+    target: PathBuf,
+    writable: bool,
+}
+
+/// Builder for a jailed file system view, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct Jail {
+    root: PathBuf,
+    binds: Vec<Bind>,
+    tmpfs: Vec<PathBuf>,
+    proc: bool,
+    keep_caps: Vec<Cap>,
+}
+
+impl Jail {
+    /// Use the existing, empty directory `root` as mount point for the new root file system.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+            binds: Vec::new(),
+            tmpfs: Vec::new(),
+            proc: false,
+            keep_caps: Vec::new(),
+        }
+    }
+
+    /// Make a file or directory available read-only at the same path. Missing paths are skipped.
+    pub fn bind_ro<P: AsRef<Path>>(self, path: P) -> Self {
+        let path = path.as_ref();
+        self.bind(path, path, false)
+    }
+
+    /// Make a file or directory available read-only at a different path.
+    pub fn bind_ro_to<S: AsRef<Path>, T: AsRef<Path>>(self, source: S, target: T) -> Self {
+        self.bind(source.as_ref(), target.as_ref(), false)
+    }
+
+    /// Make a file or directory available writable at the same path.
+    pub fn bind_rw<P: AsRef<Path>>(self, path: P) -> Self {
+        let path = path.as_ref();
+        self.bind(path, path, true)
+    }
+
+    /// Make a file or directory available writable at a different path.
+    pub fn bind_rw_to<S: AsRef<Path>, T: AsRef<Path>>(self, source: S, target: T) -> Self {
+        self.bind(source.as_ref(), target.as_ref(), true)
+    }
+
+    fn bind(mut self, source: &Path, target: &Path, writable: bool) -> Self {
+        self.binds.push(Bind {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            writable,
+        });
+        self
+    }
+
+    /// Mount an empty, writable tmpfs at `path`.
+    pub fn tmpfs<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.tmpfs.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Mount `/proc`. This only hides other processes if a new pid namespace was entered before.
+    pub fn mount_proc(mut self, proc: bool) -> Self {
+        self.proc = proc;
+        self
+    }
+
+    /// Keep these capabilities instead of dropping all of them.
+    pub fn keep_capabilities(mut self, caps: &[Cap]) -> Self {
+        self.keep_caps = caps.to_vec();
+        self
+    }
+
+    /// The path of `path` below the new root while it is being set up.
+    fn inside(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// The directories leading up to `path` below the new root, excluding the root itself.
+    fn parents_inside(&self, path: &Path) -> io::Result<Vec<CString>> {
+        let path = path.strip_prefix("/").unwrap_or(path);
+        let mut parents = path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| cstring(&self.root.join(dir)))
+            .collect::<io::Result<Vec<_>>>()?;
+        parents.reverse();
+        Ok(parents)
+    }
+
+    /// Convert all paths for entering the jail, which has to happen before forking.
+    pub fn prepare(&self) -> io::Result<PreparedJail> {
+        let mut binds = Vec::with_capacity(self.binds.len());
+        for bind in self.binds.iter() {
+            binds.push(PreparedBind {
+                source: cstring(&bind.source)?,
+                parents: self.parents_inside(&bind.target)?,
+                target: cstring(&self.inside(&bind.target))?,
+                writable: bind.writable,
+            });
+        }
+
+        let mut mounts = Vec::with_capacity(self.tmpfs.len() + 1);
+        for path in self.tmpfs.iter() {
+            mounts.push(PreparedMount {
+                parents: self.parents_inside(path)?,
+                target: cstring(&self.inside(path))?,
+                fstype: c_str!("tmpfs"),
+                flags: MountFlags::new().nodev().nosuid(),
+                data: Some(c_str!("mode=1777")),
+            });
+        }
+        if self.proc {
+            let path = Path::new("/proc");
+            mounts.push(PreparedMount {
+                parents: self.parents_inside(path)?,
+                target: cstring(&self.inside(path))?,
+                fstype: c_str!("proc"),
+                flags: MountFlags::new().nodev().nosuid().noexec(),
+                data: None,
+            });
+        }
+
+        Ok(PreparedJail {
+            root: cstring(&self.root)?,
+            old_root: cstring(&self.root.join(OLD_ROOT))?,
+            old_root_inside: cstring(&Path::new("/").join(OLD_ROOT))?,
+            binds,
+            mounts,
+            keep_caps: self.keep_caps.clone(),
+        })
+    }
+
+    /// Set up the jail and move the current process into it, see `PreparedJail::enter`.
+    ///
+    /// This allocates, so it must not be used between `fork` and `exec` in a multi threaded
+    /// process. Use `prepare` before forking instead.
+    pub fn enter(&self) -> io::Result<()> {
+        self.prepare()?.enter()
+    }
+}
+
+struct PreparedBind {
+    source: CString,
+    parents: Vec<CString>,
+    target: CString,
+    writable: bool,
+}
+
+struct PreparedMount {
+    parents: Vec<CString>,
+    target: CString,
+    fstype: &'static CStr,
+    flags: MountFlags,
+    data: Option<&'static CStr>,
+}
+
+/// A jail with all paths converted, which can be entered in a forked child process.
+pub struct PreparedJail {
+    root: CString,
+    old_root: CString,
+    old_root_inside: CString,
+    binds: Vec<PreparedBind>,
+    mounts: Vec<PreparedMount>,
+    keep_caps: Vec<Cap>,
+}
+
+impl PreparedJail {
+    /// Set up the jail and move the current process into it. Afterwards the process has no
+    /// capabilities (except for those kept via `keep_capabilities`) and the `no_new_privs` flag
+    /// is set, so it cannot regain any privileges by executing programs.
+    ///
+    /// This does not allocate and only performs system calls, so it can be used in
+    /// `CommandExt::pre_exec`.
+    pub fn enter(&self) -> io::Result<()> {
+        ns::unshare(&[Namespace::Mount])?;
+        // do not propagate anything we do back to the parent namespace
+        mount(
+            Some(c_str!("none")),
+            c_str!("/"),
+            None,
+            MountFlags::new().private().recursive(),
+            None,
+        )?;
+
+        mount(
+            Some(c_str!("jail")),
+            &self.root,
+            Some(c_str!("tmpfs")),
+            MountFlags::new().nodev().nosuid(),
+            Some(c_str!("mode=0755,size=1M")),
+        )?;
+
+        for bind in self.binds.iter() {
+            let mut stat = MaybeUninit::<libc::stat>::uninit();
+            match c_result!(unsafe { libc::stat(bind.source.as_ptr(), stat.as_mut_ptr()) }) {
+                Ok(_) => (),
+                Err(ref err) if err.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(err) => return Err(err),
+            }
+            let is_dir = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT == libc::S_IFDIR;
+
+            create_dirs(&bind.parents)?;
+            if is_dir {
+                create_dir(&bind.target)?;
+            } else {
+                let fd = c_try!(unsafe {
+                    libc::open(
+                        bind.target.as_ptr(),
+                        libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                        0o644,
+                    )
+                });
+                unsafe { libc::close(fd) };
+            }
+
+            let flags = MountFlags::new().bind().recursive();
+            mount(Some(&bind.source), &bind.target, None, flags, None)?;
+            let mut flags = MountFlags::new().bind().remount().nosuid().nodev();
+            if !bind.writable {
+                flags = flags.read_only();
+            }
+            mount(None, &bind.target, None, flags, None)?;
+        }
+
+        for target in self.mounts.iter() {
+            create_dirs(&target.parents)?;
+            create_dir(&target.target)?;
+            mount(
+                Some(target.fstype),
+                &target.target,
+                Some(target.fstype),
+                target.flags,
+                target.data,
+            )?;
+        }
+
+        self.switch_root()?;
+
+        // the directories for the mount points are created, now freeze the root file system
+        let flags = MountFlags::new().remount().read_only().nodev().nosuid();
+        mount(None, c_str!("/"), None, flags, None)?;
+
+        prctl::set_no_new_privs()?;
+        capability::drop_all_except(&self.keep_caps)
+    }
+
+    /// `pivot_root` into the new root, or `chroot` if that is not possible (eg. on an initramfs).
+    fn switch_root(&self) -> io::Result<()> {
+        c_try!(unsafe { libc::mkdir(self.old_root.as_ptr(), 0o700) });
+
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_pivot_root,
+                self.root.as_ptr(),
+                self.old_root.as_ptr(),
+            )
+        };
+        match c_result!(rc) {
+            Ok(_) => {
+                c_try!(unsafe { libc::chdir(c_str!("/").as_ptr()) });
+                c_try!(unsafe { libc::umount2(self.old_root_inside.as_ptr(), libc::MNT_DETACH) });
+                c_try!(unsafe { libc::rmdir(self.old_root_inside.as_ptr()) });
+            }
+            Err(ref err) if err.raw_os_error() == Some(libc::EINVAL) => {
+                c_try!(unsafe { libc::rmdir(self.old_root.as_ptr()) });
+                c_try!(unsafe { libc::chroot(self.root.as_ptr()) });
+                c_try!(unsafe { libc::chdir(c_str!("/").as_ptr()) });
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+fn mount(
+    source: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: MountFlags,
+    data: Option<&CStr>,
+) -> io::Result<()> {
+    c_try!(unsafe {
+        libc::mount(
+            source.map_or(ptr::null(), CStr::as_ptr),
+            target.as_ptr(),
+            fstype.map_or(ptr::null(), CStr::as_ptr),
+            flags.flags().bits(),
+            data.map_or(ptr::null(), |data| data.as_ptr() as *const libc::c_void),
+        )
+    });
+    Ok(())
+}
+
+fn create_dir(path: &CStr) -> io::Result<()> {
+    match c_result!(unsafe { libc::mkdir(path.as_ptr(), 0o755) }) {
+        Ok(_) => Ok(()),
+        Err(ref err) if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn create_dirs(paths: &[CString]) -> io::Result<()> {
+    for path in paths {
+        create_dir(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_jail() {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    let root = std::env::temp_dir().join(format!("proxmox-jail-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).expect("failed to create jail directory");
+
+    let jail = Jail::new(&root)
+        .bind_ro("/usr")
+        .bind_ro("/bin")
+        .bind_ro("/lib")
+        .bind_ro("/lib64")
+        .bind_ro_to("/etc/passwd", "/etc/name")
+        .tmpfs("/tmp")
+        .mount_proc(true)
+        .prepare()
+        .expect("failed to prepare jail");
+
+    // a new mount namespace needs a single threaded process, so use a helper process
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c").arg(concat!(
+        "test -d /usr && test -f /etc/name && ! test -e /etc/passwd && ! test -e /home",
+        " && echo data > /tmp/file && ! (echo data > /usr/file) 2>/dev/null",
+        " && ! mkdir /new 2>/dev/null",
+        " && grep -q '^CapEff:[[:space:]]*0*$' /proc/self/status",
+    ));
+    unsafe {
+        cmd.pre_exec(move || jail.enter());
+    }
+    let status = cmd.status();
+    let _ = std::fs::remove_dir(&root);
+
+    match status {
+        Err(ref err) if err.raw_os_error() == Some(libc::EPERM) => {
+            eprintln!("skipping jail test, not privileged")
+        }
+        other => assert!(other.expect("failed to run jail helper").success()),
+    }
+}
@@ -0,0 +1,354 @@
+//! Kernel key retention service, see `keyrings(7)`.
+//!
+//! Keys live in kernel memory and are attached to keyrings, which in turn belong to threads,
+//! processes, sessions or users. This allows keeping encryption keys around without writing
+//! them to disk:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::keyring::{Key, KeyPerm, SpecialKeyring};
+//! # fn code() -> std::io::Result<()> {
+//! let key = Key::add_user(SpecialKeyring::Session, "pbs:encryption-key", b"secret")?;
+//! key.set_perm(KeyPerm::POS_ALL | KeyPerm::USR_VIEW)?;
+//!
+//! let found = Key::search(SpecialKeyring::Session, "pbs:encryption-key")?;
+//! assert_eq!(found.read()?, b"secret");
+//! found.revoke()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::CString;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::time::Duration;
+
+const KEYCTL_GET_KEYRING_ID: libc::c_long = 0;
+const KEYCTL_REVOKE: libc::c_long = 3;
+const KEYCTL_SETPERM: libc::c_long = 5;
+const KEYCTL_DESCRIBE: libc::c_long = 6;
+const KEYCTL_LINK: libc::c_long = 8;
+const KEYCTL_UNLINK: libc::c_long = 9;
+const KEYCTL_SEARCH: libc::c_long = 10;
+const KEYCTL_READ: libc::c_long = 11;
+const KEYCTL_SET_TIMEOUT: libc::c_long = 15;
+const KEYCTL_INVALIDATE: libc::c_long = 21;
+
+/// The keyrings which can be addressed relative to the calling thread.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpecialKeyring {
+    /// The calling thread's keyring.
+    Thread,
+    /// The calling process' keyring.
+    Process,
+    /// The session keyring, usually shared by all processes of a login session.
+    Session,
+    /// The keyring of the calling user, shared by all their processes.
+    User,
+    /// The user's default session keyring.
+    UserSession,
+}
+
+impl SpecialKeyring {
+    fn serial(self) -> i32 {
+        match self {
+            SpecialKeyring::Thread => -1,
+            SpecialKeyring::Process => -2,
+            SpecialKeyring::Session => -3,
+            SpecialKeyring::User => -4,
+            SpecialKeyring::UserSession => -5,
+        }
+    }
+}
+
+impl From<SpecialKeyring> for Key {
+    fn from(keyring: SpecialKeyring) -> Key {
+        Key(keyring.serial())
+    }
+}
+
+/// Permissions of a key, see `keyctl_setperm(3)`.
+///
+/// There are separate sets of permissions for possessors of the key (processes which can reach
+/// it via their keyrings), its owner, its group and everybody else.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyPerm(u32);
+
+macro_rules! key_perms {
+    ($($pos:ident $usr:ident $grp:ident $oth:ident = $bit:expr, $what:expr;)+) => {
+        impl KeyPerm {
+            $(
+                #[doc = "Possessors may "]
+                #[doc = $what]
+                pub const $pos: KeyPerm = KeyPerm($bit << 24);
+                #[doc = "The owner may "]
+                #[doc = $what]
+                pub const $usr: KeyPerm = KeyPerm($bit << 16);
+                #[doc = "The group may "]
+                #[doc = $what]
+                pub const $grp: KeyPerm = KeyPerm($bit << 8);
+                #[doc = "Everybody may "]
+                #[doc = $what]
+                pub const $oth: KeyPerm = KeyPerm($bit);
+            )+
+        }
+    };
+}
+
+key_perms! {
+    POS_VIEW USR_VIEW GRP_VIEW OTH_VIEW = 0x01, "view the key's attributes.";
+    POS_READ USR_READ GRP_READ OTH_READ = 0x02, "read the key's payload.";
+    POS_WRITE USR_WRITE GRP_WRITE OTH_WRITE = 0x04, "update the key's payload.";
+    POS_SEARCH USR_SEARCH GRP_SEARCH OTH_SEARCH = 0x08, "find the key when searching keyrings.";
+    POS_LINK USR_LINK GRP_LINK OTH_LINK = 0x10, "link the key to keyrings.";
+    POS_SETATTR USR_SETATTR GRP_SETATTR OTH_SETATTR = 0x20, "change the key's attributes.";
+    POS_ALL USR_ALL GRP_ALL OTH_ALL = 0x3f, "do anything with the key.";
+}
+
+impl KeyPerm {
+    /// No permissions at all.
+    pub const fn empty() -> Self {
+        KeyPerm(0)
+    }
+
+    /// The raw permission mask.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether all permissions of `other` are contained.
+    pub fn contains(self, other: KeyPerm) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for KeyPerm {
+    type Output = KeyPerm;
+
+    fn bitor(self, other: KeyPerm) -> KeyPerm {
+        KeyPerm(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for KeyPerm {
+    fn bitor_assign(&mut self, other: KeyPerm) {
+        self.0 |= other.0;
+    }
+}
+
+fn check(rc: libc::c_long) -> io::Result<libc::c_long> {
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc)
+}
+
+fn cstring(s: &str) -> io::Result<CString> {
+    CString::new(s).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "nul byte in string"))
+}
+
+/// A key or keyring, identified by its serial number.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Key(pub i32);
+
+impl Key {
+    /// Add a key of type `key_type` to a keyring, or update the payload of an existing key of the
+    /// same type and description in that keyring.
+    pub fn add<K: Into<Key>>(
+        keyring: K,
+        key_type: &str,
+        description: &str,
+        payload: &[u8],
+    ) -> io::Result<Key> {
+        let key_type = cstring(key_type)?;
+        let description = cstring(description)?;
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                key_type.as_ptr(),
+                description.as_ptr(),
+                payload.as_ptr(),
+                payload.len(),
+                keyring.into().0,
+            )
+        };
+        Ok(Key(check(rc)? as i32))
+    }
+
+    /// Add a key of the `user` type, which holds arbitrary data of up to 32 KiB.
+    pub fn add_user<K: Into<Key>>(
+        keyring: K,
+        description: &str,
+        payload: &[u8],
+    ) -> io::Result<Key> {
+        Self::add(keyring, "user", description, payload)
+    }
+
+    /// Search a keyring and the keyrings linked to it for a `user` key. Fails with `ENOKEY` if it
+    /// cannot be found.
+    pub fn search<K: Into<Key>>(keyring: K, description: &str) -> io::Result<Key> {
+        Self::search_type(keyring, "user", description)
+    }
+
+    /// Search a keyring and the keyrings linked to it for a key of type `key_type`.
+    pub fn search_type<K: Into<Key>>(
+        keyring: K,
+        key_type: &str,
+        description: &str,
+    ) -> io::Result<Key> {
+        let key_type = cstring(key_type)?;
+        let description = cstring(description)?;
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                KEYCTL_SEARCH,
+                keyring.into().0,
+                key_type.as_ptr(),
+                description.as_ptr(),
+                0,
+            )
+        };
+        Ok(Key(check(rc)? as i32))
+    }
+
+    /// Resolve a special keyring to its actual serial number, creating it if necessary.
+    pub fn keyring(keyring: SpecialKeyring) -> io::Result<Key> {
+        let rc =
+            unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_GET_KEYRING_ID, keyring.serial(), 1) };
+        Ok(Key(check(rc)? as i32))
+    }
+
+    fn keyctl(self, cmd: libc::c_long, arg: libc::c_long) -> io::Result<libc::c_long> {
+        check(unsafe { libc::syscall(libc::SYS_keyctl, cmd, self.0, arg) })
+    }
+
+    /// Read the payload of the key.
+    pub fn read(self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        loop {
+            let rc = check(unsafe {
+                libc::syscall(
+                    libc::SYS_keyctl,
+                    KEYCTL_READ,
+                    self.0,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                )
+            })? as usize;
+            // the size may have changed in between if the key was updated
+            if rc <= buf.len() {
+                buf.truncate(rc);
+                return Ok(buf);
+            }
+            buf.resize(rc, 0);
+        }
+    }
+
+    /// Get the description of the key, in the kernel's format
+    /// `type;uid;gid;perm;description`.
+    pub fn describe(self) -> io::Result<String> {
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            let rc = check(unsafe {
+                libc::syscall(
+                    libc::SYS_keyctl,
+                    KEYCTL_DESCRIBE,
+                    self.0,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                )
+            })? as usize;
+            if rc <= buf.len() {
+                // includes the terminating nul byte
+                buf.truncate(rc.saturating_sub(1));
+                return Ok(String::from_utf8_lossy(&buf).into_owned());
+            }
+            buf.resize(rc, 0);
+        }
+    }
+
+    /// Change the permissions of the key. The permissions of new keys usually allow everything
+    /// for possessors and only viewing for the owner.
+    pub fn set_perm(self, perm: KeyPerm) -> io::Result<()> {
+        self.keyctl(KEYCTL_SETPERM, perm.0 as libc::c_long)
+            .map(drop)
+    }
+
+    /// Expire the key after `timeout`, or disable an existing timeout with `None`.
+    pub fn set_timeout(self, timeout: Option<Duration>) -> io::Result<()> {
+        let secs = match timeout {
+            // 0 means no timeout, so round up
+            Some(timeout) => timeout
+                .as_secs()
+                .max(1)
+                .min(libc::c_uint::max_value() as u64),
+            None => 0,
+        };
+        self.keyctl(KEYCTL_SET_TIMEOUT, secs as libc::c_long)
+            .map(drop)
+    }
+
+    /// Link the key into another keyring.
+    pub fn link<K: Into<Key>>(self, keyring: K) -> io::Result<()> {
+        check(unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_LINK, self.0, keyring.into().0) })
+            .map(drop)
+    }
+
+    /// Remove the key from a keyring. The key is destroyed once it is not linked anywhere else.
+    pub fn unlink<K: Into<Key>>(self, keyring: K) -> io::Result<()> {
+        check(unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_UNLINK, self.0, keyring.into().0) })
+            .map(drop)
+    }
+
+    /// Revoke the key, so that any further operation on it except unlinking fails with
+    /// `EKEYREVOKED`.
+    pub fn revoke(self) -> io::Result<()> {
+        self.keyctl(KEYCTL_REVOKE, 0).map(drop)
+    }
+
+    /// Invalidate the key, which removes it from all keyrings and destroys it right away.
+    pub fn invalidate(self) -> io::Result<()> {
+        self.keyctl(KEYCTL_INVALIDATE, 0).map(drop)
+    }
+}
+
+#[test]
+fn test_keyring() {
+    // the thread keyring is private to the test thread
+    let key = match Key::add_user(SpecialKeyring::Thread, "proxmox:test", b"first") {
+        Ok(key) => key,
+        Err(err) => {
+            // keyctl is commonly blocked in containers
+            eprintln!("skipping keyring test - {}", err);
+            return;
+        }
+    };
+
+    assert_eq!(key.read().unwrap(), b"first");
+    assert!(key.describe().unwrap().starts_with("user;"));
+    assert!(key.describe().unwrap().ends_with(";proxmox:test"));
+
+    // adding again updates the existing key
+    let updated = Key::add_user(SpecialKeyring::Thread, "proxmox:test", b"second").unwrap();
+    assert_eq!(updated, key);
+    assert_eq!(key.read().unwrap(), b"second");
+
+    let found = Key::search(SpecialKeyring::Thread, "proxmox:test").unwrap();
+    assert_eq!(found, key);
+
+    key.set_perm(KeyPerm::POS_ALL | KeyPerm::USR_VIEW).unwrap();
+    assert!(KeyPerm::POS_ALL.contains(KeyPerm::POS_READ));
+    assert_eq!(KeyPerm::USR_VIEW.bits(), 0x0001_0000);
+
+    key.revoke().unwrap();
+    assert_eq!(
+        key.read().unwrap_err().raw_os_error(),
+        Some(libc::EKEYREVOKED)
+    );
+    key.unlink(SpecialKeyring::Thread).unwrap();
+    assert_eq!(
+        Key::search(SpecialKeyring::Thread, "proxmox:test")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::ENOKEY)
+    );
+}
@@ -0,0 +1,221 @@
+//! Anonymous memory backed files with support for file sealing, see `memfd_create(2)`.
+//!
+//! A sealed memfd can be handed to another process (eg. via `SCM_RIGHTS`), which can then verify
+//! via `Memfd::seals` that the contents cannot change underneath it anymore.
+//!
+//! ```no_run
+//! # use std::io::Write;
+//! # use proxmox::c_str;
+//! # use proxmox::sys::linux::memfd::{Memfd, MemFdCreateFlag};
+//! # fn code() -> std::io::Result<()> {
+//! let memfd = Memfd::create(
+//!     c_str!("blob"),
+//!     MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+//! )?;
+//! memfd.as_file().write_all(b"immutable data")?;
+//! memfd.seal_immutable()?;
+//! assert_eq!(&memfd.map()?[..], b"immutable data");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::mman::{MapFlags, ProtFlags};
+
+use crate::sys::error::SysResult;
+use crate::tools::mmap::Mmap;
+
+pub use nix::fcntl::SealFlag;
+pub use nix::sys::memfd::MemFdCreateFlag;
+
+/// An anonymous memory backed file.
+pub struct Memfd {
+    file: File,
+}
+
+impl Memfd {
+    /// Create a new memfd. The `name` is only used for debugging purposes and shows up as the
+    /// target of the `/proc/self/fd` symlink.
+    ///
+    /// Note that `MFD_CLOEXEC` needs to be passed explicitly, and that seals can only be added if
+    /// the memfd was created with `MFD_ALLOW_SEALING`.
+    pub fn create(name: &CStr, flags: MemFdCreateFlag) -> io::Result<Self> {
+        let fd = nix::sys::memfd::memfd_create(name, flags).into_io_result()?;
+        Ok(Self {
+            file: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Access the memfd as a `File` to read, write or resize it.
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// Turn the memfd into a `File`.
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    /// Get the current size.
+    pub fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Resize the memfd, this fails if the corresponding `F_SEAL_GROW` or `F_SEAL_SHRINK` seal is
+    /// set.
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        self.file.set_len(size)
+    }
+
+    /// Get the seals currently set.
+    pub fn seals(&self) -> io::Result<SealFlag> {
+        get_seals(self.file.as_raw_fd())
+    }
+
+    /// Add seals. Once `F_SEAL_SEAL` is set, no more seals can be added.
+    ///
+    /// Note that `F_SEAL_WRITE` cannot be added while writable shared mappings of the memfd exist.
+    pub fn add_seals(&self, seals: SealFlag) -> io::Result<()> {
+        fcntl(self.file.as_raw_fd(), FcntlArg::F_ADD_SEALS(seals)).into_io_result()?;
+        Ok(())
+    }
+
+    /// Prevent any further modification of the contents.
+    pub fn seal_write(&self) -> io::Result<()> {
+        self.add_seals(SealFlag::F_SEAL_WRITE)
+    }
+
+    /// Prevent the memfd from growing.
+    pub fn seal_grow(&self) -> io::Result<()> {
+        self.add_seals(SealFlag::F_SEAL_GROW)
+    }
+
+    /// Prevent the memfd from shrinking.
+    pub fn seal_shrink(&self) -> io::Result<()> {
+        self.add_seals(SealFlag::F_SEAL_SHRINK)
+    }
+
+    /// Make the memfd completely immutable by adding the write, grow, shrink seals, as well as
+    /// `F_SEAL_SEAL` to prevent changing the seals afterwards.
+    pub fn seal_immutable(&self) -> io::Result<()> {
+        self.add_seals(
+            SealFlag::F_SEAL_WRITE
+                | SealFlag::F_SEAL_GROW
+                | SealFlag::F_SEAL_SHRINK
+                | SealFlag::F_SEAL_SEAL,
+        )
+    }
+
+    /// Map the current contents read-only.
+    ///
+    /// Requires the `F_SEAL_SHRINK` seal, since accessing a mapping beyond the end of a file which
+    /// was shrunk by another holder of the memfd raises `SIGBUS`.
+    pub fn map(&self) -> io::Result<ReadOnlyMap> {
+        self.map_with(ProtFlags::PROT_READ).map(ReadOnlyMap)
+    }
+
+    /// Map the current contents writable. The mapping is shared, so changes are visible via the
+    /// file descriptor.
+    ///
+    /// This requires the `F_SEAL_SHRINK` seal, see `map`, and fails if the `F_SEAL_WRITE` seal is
+    /// set.
+    pub fn map_mut(&self) -> io::Result<Mmap<u8>> {
+        self.map_with(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)
+    }
+
+    fn map_with(&self, prot: ProtFlags) -> io::Result<Mmap<u8>> {
+        if !self.seals()?.contains(SealFlag::F_SEAL_SHRINK) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "memfd must be sealed against shrinking to be mapped",
+            ));
+        }
+
+        let len = self.len()? as usize;
+        unsafe { Mmap::map_fd(self.file.as_raw_fd(), 0, len, prot, MapFlags::MAP_SHARED) }
+    }
+}
+
+/// A read-only mapping of a memfd, see `Memfd::map`.
+pub struct ReadOnlyMap(Mmap<u8>);
+
+impl std::ops::Deref for ReadOnlyMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Get the seals of an arbitrary file descriptor, eg. a memfd received from another process.
+pub fn get_seals(fd: RawFd) -> io::Result<SealFlag> {
+    let seals = fcntl(fd, FcntlArg::F_GET_SEALS).into_io_result()?;
+    Ok(SealFlag::from_bits_truncate(seals))
+}
+
+impl AsRawFd for Memfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Memfd {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
+
+impl FromRawFd for Memfd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self {
+            file: File::from_raw_fd(fd),
+        }
+    }
+}
+
+#[test]
+fn test_memfd_seals() {
+    use std::io::Write;
+
+    use crate::c_str;
+
+    let memfd = Memfd::create(
+        c_str!("test"),
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    )
+    .expect("failed to create memfd");
+
+    memfd
+        .as_file()
+        .write_all(b"some data")
+        .expect("failed to write to memfd");
+    assert_eq!(memfd.len().unwrap(), 9);
+    assert!(memfd.seals().expect("failed to get seals").is_empty());
+    assert!(memfd.map().is_err());
+
+    memfd.seal_shrink().expect("failed to seal memfd");
+    {
+        let mut map = memfd.map_mut().expect("failed to map memfd");
+        map[0] = b'S';
+    }
+
+    memfd.seal_immutable().expect("failed to seal memfd");
+    let seals = memfd.seals().expect("failed to get seals");
+    assert!(seals.contains(SealFlag::F_SEAL_WRITE | SealFlag::F_SEAL_SEAL));
+
+    assert!(memfd.as_file().write_all(b"more").is_err());
+    assert!(memfd.set_len(4).is_err());
+    assert!(memfd.map_mut().is_err());
+    assert!(memfd.add_seals(SealFlag::F_SEAL_GROW).is_err());
+
+    assert_eq!(&memfd.map().expect("failed to map memfd")[..], b"Some data");
+}
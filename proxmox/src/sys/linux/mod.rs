@@ -1,16 +1,52 @@
 //! Linux specific helpers and syscall wrapper
 
+use std::io;
+use std::time::Duration;
+
 use anyhow::*;
 
+pub mod acl;
+pub mod blockdev;
+pub mod capability;
+pub mod cgroup;
+pub mod daemon;
+pub mod epoll;
+pub mod eventfd;
+pub mod fsfreeze;
+pub mod hostname;
+pub mod inotify;
+pub mod io;
+#[cfg(feature = "io-uring")]
+pub mod io_uring;
+pub mod jail;
+pub mod keyring;
 pub mod magic;
+pub mod memfd;
+pub mod mount;
+pub mod netlink;
+pub mod ns;
+pub mod openat2;
 pub mod pid;
+pub mod prctl;
+pub mod priority;
 pub mod procfs;
 pub mod pty;
+pub mod reaper;
+pub mod reboot;
+pub mod rlimit;
+pub mod seccomp;
+pub mod signalfd;
+pub mod socket;
+pub mod statx;
+pub mod supervisor;
+pub mod sysctl;
+pub mod timerfd;
 pub mod tty;
+pub mod xattr;
 
 /// Get pseudo random data (/dev/urandom)
 pub fn random_data(size: usize) -> Result<Vec<u8>, Error> {
-    let mut buffer = crate::tools::vec::undefined(size);
+    let mut buffer = vec![0u8; size];
     fill_with_random_data(&mut buffer)?;
 
     Ok(buffer)
@@ -20,22 +56,197 @@ pub fn random_data(size: usize) -> Result<Vec<u8>, Error> {
 ///
 /// This code uses the Linux syscall getrandom() - see "man 2 getrandom".
 pub fn fill_with_random_data(buffer: &mut [u8]) -> Result<(), Error> {
+    fill_with_random_data_flags(buffer, GetRandomFlags::empty())
+}
+
+/// Flags for `getrandom(2)`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GetRandomFlags(libc::c_uint);
+
+impl GetRandomFlags {
+    /// Fail with `WouldBlock` instead of blocking if the entropy pool is not initialized yet.
+    pub const NONBLOCK: GetRandomFlags = GetRandomFlags(libc::GRND_NONBLOCK);
+    /// Use the `/dev/random` source instead of `/dev/urandom`.
+    pub const RANDOM: GetRandomFlags = GetRandomFlags(libc::GRND_RANDOM);
+
+    pub const fn empty() -> Self {
+        GetRandomFlags(0)
+    }
+
+    pub const fn bits(self) -> libc::c_uint {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for GetRandomFlags {
+    type Output = GetRandomFlags;
+
+    fn bitor(self, other: GetRandomFlags) -> GetRandomFlags {
+        GetRandomFlags(self.0 | other.0)
+    }
+}
+
+/// Call `getrandom(2)` once, returns the number of bytes written to `buffer`.
+pub fn getrandom(buffer: &mut [u8], flags: GetRandomFlags) -> io::Result<usize> {
     let res = unsafe {
         libc::getrandom(
             buffer.as_mut_ptr() as *mut libc::c_void,
             buffer.len() as libc::size_t,
-            0 as libc::c_uint,
+            flags.0,
         )
     };
 
-    if res == -1 {
-        return Err(std::io::Error::last_os_error().into());
+    if res < 0 {
+        return Err(io::Error::last_os_error());
     }
 
-    if res as usize != buffer.len() {
-        // should not happen
-        bail!("short getrandom read");
+    Ok(res as usize)
+}
+
+/// Fill buffer with random data using `getrandom(2)` with the specified flags.
+///
+/// With `GetRandomFlags::NONBLOCK` this fails with `WouldBlock` if the entropy pool is not
+/// initialized yet, or if `GetRandomFlags::RANDOM` is used and not enough entropy is available.
+pub fn fill_with_random_data_flags(buffer: &mut [u8], flags: GetRandomFlags) -> Result<(), Error> {
+    // reads may be interrupted by signals, and GRND_RANDOM may return less than requested
+    let mut pos = 0;
+    while pos < buffer.len() {
+        match getrandom(&mut buffer[pos..], flags) {
+            Ok(got) => pos += got,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
     }
 
     Ok(())
 }
+
+/// Fill buffer with pseudo random data, without blocking.
+///
+/// If the entropy pool is not initialized yet (eg. early during boot), or `getrandom(2)` is not
+/// available, this reads from `/dev/urandom` instead, which never blocks. Note that the data may
+/// be predictable in that case, so this is not meant for long term secret keys.
+pub fn fill_with_urandom(buffer: &mut [u8]) -> Result<(), Error> {
+    match fill_with_random_data_flags(buffer, GetRandomFlags::NONBLOCK) {
+        Err(err)
+            if err.downcast_ref::<io::Error>().map_or(false, |err| {
+                matches!(err.raw_os_error(), Some(libc::EAGAIN) | Some(libc::ENOSYS))
+            }) => {}
+        other => return other,
+    }
+
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(buffer))
+        .map_err(|err| format_err!("failed to read /dev/urandom - {}", err))
+}
+
+/// Get a random `u64`.
+pub fn random_u64() -> Result<u64, Error> {
+    let mut buffer = [0u8; 8];
+    fill_with_random_data(&mut buffer)?;
+    Ok(u64::from_ne_bytes(buffer))
+}
+
+/// Get a random byte array, eg. `random_array::<32>()`.
+pub fn random_array<const N: usize>() -> Result<[u8; N], Error> {
+    let mut array = [0u8; N];
+    fill_with_random_data(&mut array)?;
+    Ok(array)
+}
+
+/// Kernel and machine information as returned by `uname(2)`.
+#[derive(Clone, Debug)]
+pub struct UtsName {
+    /// The operating system name, eg. `Linux`.
+    pub sysname: String,
+    /// The host name.
+    pub nodename: String,
+    /// The kernel release, eg. `5.4.78-2-pve`.
+    pub release: String,
+    /// The kernel version, usually containing the build date.
+    pub version: String,
+    /// The hardware architecture, eg. `x86_64`.
+    pub machine: String,
+}
+
+/// Get kernel and machine information.
+pub fn uname() -> UtsName {
+    let uts = nix::sys::utsname::uname();
+    UtsName {
+        sysname: uts.sysname().to_owned(),
+        nodename: uts.nodename().to_owned(),
+        release: uts.release().to_owned(),
+        version: uts.version().to_owned(),
+        machine: uts.machine().to_owned(),
+    }
+}
+
+/// Overall system statistics as returned by `sysinfo(2)`. Memory sizes are in bytes.
+#[derive(Clone, Debug)]
+pub struct SysInfo {
+    /// Time since boot.
+    pub uptime: Duration,
+    /// The 1, 5 and 15 minute load averages.
+    pub loads: [f64; 3],
+    pub total_ram: u64,
+    pub free_ram: u64,
+    pub shared_ram: u64,
+    pub buffer_ram: u64,
+    pub total_swap: u64,
+    pub free_swap: u64,
+    /// The current number of processes (threads, actually).
+    pub procs: u16,
+}
+
+/// Get overall system statistics.
+pub fn sysinfo() -> io::Result<SysInfo> {
+    let mut info: libc::sysinfo = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sysinfo(&mut info) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // memory sizes are in multiples of mem_unit, load averages are fixed point values
+    let unit = u64::from(info.mem_unit.max(1));
+    let load = |value: libc::c_ulong| value as f64 / f64::from(1u32 << 16); // SI_LOAD_SHIFT
+
+    Ok(SysInfo {
+        uptime: Duration::from_secs(info.uptime.max(0) as u64),
+        loads: [
+            load(info.loads[0]),
+            load(info.loads[1]),
+            load(info.loads[2]),
+        ],
+        total_ram: info.totalram as u64 * unit,
+        free_ram: info.freeram as u64 * unit,
+        shared_ram: info.sharedram as u64 * unit,
+        buffer_ram: info.bufferram as u64 * unit,
+        total_swap: info.totalswap as u64 * unit,
+        free_swap: info.freeswap as u64 * unit,
+        procs: info.procs,
+    })
+}
+
+#[test]
+fn test_sysinfo() {
+    let info = sysinfo().expect("sysinfo failed");
+    assert!(info.total_ram > 0);
+    assert!(info.free_ram <= info.total_ram);
+    assert!(info.procs > 0);
+
+    assert_eq!(uname().sysname, "Linux");
+}
+
+#[test]
+fn test_random_data() {
+    let a: [u8; 32] = random_array().expect("failed to get random data");
+    let c = random_array::<64>().expect("failed to get random data");
+    assert!(c.iter().any(|b| *b != 0));
+    let b: [u8; 32] = random_array().expect("failed to get random data");
+    assert_ne!(a, b);
+    assert_ne!(random_u64().unwrap(), random_u64().unwrap());
+
+    let mut buffer = vec![0u8; 4096];
+    fill_with_urandom(&mut buffer).expect("failed to get random data");
+    assert!(buffer.iter().any(|b| *b != 0));
+}
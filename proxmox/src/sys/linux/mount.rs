@@ -0,0 +1,208 @@
+//! `mount(2)` and `umount2(2)` wrappers.
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::mount::{self, MountFlags, UnmountFlags};
+//! # fn code() -> std::io::Result<()> {
+//! mount::mount(
+//!     Some("tmpfs"),
+//!     "/mnt/scratch",
+//!     Some("tmpfs"),
+//!     MountFlags::new().nodev().nosuid().noatime(),
+//!     Some("size=64M,mode=0700"),
+//! )?;
+//!
+//! mount::bind_mount("/etc", "/mnt/chroot/etc", true)?;
+//!
+//! mount::umount("/mnt/scratch", UnmountFlags::new().lazy())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::path::Path;
+
+use nix::mount::{MntFlags, MsFlags};
+
+use crate::sys::error::SysResult;
+
+/// Flags for `mount`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MountFlags(MsFlags);
+
+impl Default for MountFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MountFlags {
+    pub fn new() -> Self {
+        Self(MsFlags::empty())
+    }
+
+    /// Create a bind mount (`MS_BIND`).
+    pub fn bind(self) -> Self {
+        Self(self.0 | MsFlags::MS_BIND)
+    }
+
+    /// Apply to all mounts in a subtree (`MS_REC`), used together with `bind` or the propagation
+    /// flags.
+    pub fn recursive(self) -> Self {
+        Self(self.0 | MsFlags::MS_REC)
+    }
+
+    /// Change the flags of an existing mount (`MS_REMOUNT`).
+    pub fn remount(self) -> Self {
+        Self(self.0 | MsFlags::MS_REMOUNT)
+    }
+
+    /// Mount read-only (`MS_RDONLY`).
+    pub fn read_only(self) -> Self {
+        Self(self.0 | MsFlags::MS_RDONLY)
+    }
+
+    /// Do not update access times (`MS_NOATIME`).
+    pub fn noatime(self) -> Self {
+        Self(self.0 | MsFlags::MS_NOATIME)
+    }
+
+    /// Do not allow access to device nodes (`MS_NODEV`).
+    pub fn nodev(self) -> Self {
+        Self(self.0 | MsFlags::MS_NODEV)
+    }
+
+    /// Ignore set-user-id and set-group-id bits (`MS_NOSUID`).
+    pub fn nosuid(self) -> Self {
+        Self(self.0 | MsFlags::MS_NOSUID)
+    }
+
+    /// Do not allow programs to be executed (`MS_NOEXEC`).
+    pub fn noexec(self) -> Self {
+        Self(self.0 | MsFlags::MS_NOEXEC)
+    }
+
+    /// Change the propagation type to private (`MS_PRIVATE`).
+    pub fn private(self) -> Self {
+        Self(self.0 | MsFlags::MS_PRIVATE)
+    }
+
+    /// Change the propagation type to slave (`MS_SLAVE`).
+    pub fn slave(self) -> Self {
+        Self(self.0 | MsFlags::MS_SLAVE)
+    }
+
+    /// Change the propagation type to shared (`MS_SHARED`).
+    pub fn shared(self) -> Self {
+        Self(self.0 | MsFlags::MS_SHARED)
+    }
+
+    /// Add arbitrary flags.
+    pub fn with(self, flags: MsFlags) -> Self {
+        Self(self.0 | flags)
+    }
+
+    /// Access the raw flags.
+    pub fn flags(self) -> MsFlags {
+        self.0
+    }
+}
+
+/// Flags for `umount`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnmountFlags(MntFlags);
+
+impl Default for UnmountFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UnmountFlags {
+    pub fn new() -> Self {
+        Self(MntFlags::empty())
+    }
+
+    /// Detach the mount point immediately, cleaning up once it is not busy anymore
+    /// (`MNT_DETACH`).
+    pub fn lazy(self) -> Self {
+        Self(self.0 | MntFlags::MNT_DETACH)
+    }
+
+    /// Force the unmount even if busy, only supported by some network file systems
+    /// (`MNT_FORCE`).
+    pub fn force(self) -> Self {
+        Self(self.0 | MntFlags::MNT_FORCE)
+    }
+
+    /// Mark the mount point as expired, a second call with this flag unmounts it if it was not
+    /// accessed in the meantime (`MNT_EXPIRE`).
+    pub fn expire(self) -> Self {
+        Self(self.0 | MntFlags::MNT_EXPIRE)
+    }
+
+    /// Access the raw flags.
+    pub fn flags(self) -> MntFlags {
+        self.0
+    }
+}
+
+/// Mount a file system. See `mount(2)` for the meaning of the parameters for the various kinds of
+/// mount operations.
+pub fn mount<S, T>(
+    source: Option<&S>,
+    target: &T,
+    fstype: Option<&str>,
+    flags: MountFlags,
+    data: Option<&str>,
+) -> io::Result<()>
+where
+    S: ?Sized + AsRef<Path>,
+    T: ?Sized + AsRef<Path>,
+{
+    nix::mount::mount(
+        source.map(|source| source.as_ref()),
+        target.as_ref(),
+        fstype,
+        flags.0,
+        data,
+    )
+    .into_io_result()
+}
+
+/// Bind mount `source` onto `target`, including all mounts below `source`.
+///
+/// Since the kernel ignores `MS_RDONLY` when creating a bind mount, a read-only bind mount needs
+/// an additional remount, which is performed if `read_only` is set.
+pub fn bind_mount<S, T>(source: &S, target: &T, read_only: bool) -> io::Result<()>
+where
+    S: ?Sized + AsRef<Path>,
+    T: ?Sized + AsRef<Path>,
+{
+    let flags = MountFlags::new().bind().recursive();
+    mount(Some(source), target, None, flags, None)?;
+
+    if read_only {
+        let flags = MountFlags::new().bind().remount().read_only();
+        if let Err(err) = mount(None::<&Path>, target, None, flags, None) {
+            let _ = umount(target, UnmountFlags::new().lazy());
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Change the flags of an existing mount point.
+pub fn remount<T: ?Sized + AsRef<Path>>(target: &T, flags: MountFlags) -> io::Result<()> {
+    mount(None::<&Path>, target, None, flags.remount(), None)
+}
+
+/// Change the propagation type of a mount point, eg. `MountFlags::new().private().recursive()`.
+pub fn set_propagation<T: ?Sized + AsRef<Path>>(target: &T, flags: MountFlags) -> io::Result<()> {
+    mount(Some("none"), target, None, flags, None)
+}
+
+/// Unmount a file system.
+pub fn umount<T: ?Sized + AsRef<Path>>(target: &T, flags: UnmountFlags) -> io::Result<()> {
+    nix::mount::umount2(target.as_ref(), flags.0).into_io_result()
+}
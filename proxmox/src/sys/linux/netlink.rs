@@ -0,0 +1,456 @@
+//! A minimal `NETLINK_ROUTE` client, see `rtnetlink(7)`.
+//!
+//! This can enumerate network interfaces and their addresses, and wait for link state changes:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::netlink::{LinkEvent, LinkMonitor, NetlinkRoute};
+//! # fn code() -> std::io::Result<()> {
+//! for link in NetlinkRoute::new()?.links()? {
+//!     println!("{}: {}", link.name, if link.is_up() { "up" } else { "down" });
+//! }
+//!
+//! let mut monitor = LinkMonitor::new()?;
+//! loop {
+//!     match monitor.next_event()? {
+//!         LinkEvent::Changed(link) => println!("{} running: {}", link.name, link.is_running()),
+//!         LinkEvent::Removed(link) => println!("{} removed", link.name),
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::convert::TryInto;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::tools::fd::Fd;
+
+const NETLINK_ROUTE: libc::c_int = 0;
+const RTMGRP_LINK: u32 = 1;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x001;
+const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_LABEL: u16 = 3;
+
+const NLMSG_HDR_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTATTR_HDR_LEN: usize = 4;
+
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes(data[offset..(offset + 2)].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(data[offset..(offset + 4)].try_into().unwrap())
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A network interface.
+#[derive(Clone, Debug)]
+pub struct Link {
+    pub index: u32,
+    pub name: String,
+    /// The interface flags (`libc::IFF_*`).
+    pub flags: u32,
+    pub mtu: Option<u32>,
+    /// The hardware address, if the interface has one.
+    pub hwaddr: Option<Vec<u8>>,
+}
+
+impl Link {
+    /// Check whether the interface is administratively up (`IFF_UP`).
+    pub fn is_up(&self) -> bool {
+        self.flags & (libc::IFF_UP as u32) != 0
+    }
+
+    /// Check whether the interface is operational (`IFF_RUNNING`), eg. has a carrier.
+    pub fn is_running(&self) -> bool {
+        self.flags & (libc::IFF_RUNNING as u32) != 0
+    }
+
+    fn parse(payload: &[u8]) -> io::Result<Self> {
+        if payload.len() < IFINFOMSG_LEN {
+            return Err(invalid_data("short ifinfomsg"));
+        }
+
+        let mut link = Link {
+            index: read_u32(payload, 4),
+            name: String::new(),
+            flags: read_u32(payload, 8),
+            mtu: None,
+            hwaddr: None,
+        };
+
+        for (ty, data) in Attributes::new(&payload[IFINFOMSG_LEN..]) {
+            match ty {
+                IFLA_IFNAME => link.name = attr_string(data),
+                IFLA_MTU if data.len() >= 4 => link.mtu = Some(read_u32(data, 0)),
+                IFLA_ADDRESS => link.hwaddr = Some(data.to_vec()),
+                _ => (),
+            }
+        }
+
+        Ok(link)
+    }
+}
+
+/// An address assigned to a network interface.
+#[derive(Clone, Debug)]
+pub struct Address {
+    /// The index of the interface.
+    pub index: u32,
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    /// The address label, only available for IPv4 addresses.
+    pub label: Option<String>,
+}
+
+impl Address {
+    fn parse(payload: &[u8]) -> io::Result<Option<Self>> {
+        if payload.len() < IFADDRMSG_LEN {
+            return Err(invalid_data("short ifaddrmsg"));
+        }
+
+        let family = libc::c_int::from(payload[0]);
+        let prefix_len = payload[1];
+        let index = read_u32(payload, 4);
+
+        let mut address = None;
+        let mut local = None;
+        let mut label = None;
+        for (ty, data) in Attributes::new(&payload[IFADDRMSG_LEN..]) {
+            match ty {
+                IFA_ADDRESS => address = parse_ip(family, data),
+                IFA_LOCAL => local = parse_ip(family, data),
+                IFA_LABEL => label = Some(attr_string(data)),
+                _ => (),
+            }
+        }
+
+        // for point-to-point interfaces IFA_ADDRESS is the peer address
+        Ok(local.or(address).map(|address| Address {
+            index,
+            address,
+            prefix_len,
+            label,
+        }))
+    }
+}
+
+fn parse_ip(family: libc::c_int, data: &[u8]) -> Option<IpAddr> {
+    match family {
+        libc::AF_INET if data.len() == 4 => {
+            Some(Ipv4Addr::new(data[0], data[1], data[2], data[3]).into())
+        }
+        libc::AF_INET6 if data.len() == 16 => {
+            let data: [u8; 16] = data.try_into().unwrap();
+            Some(Ipv6Addr::from(data).into())
+        }
+        _ => None,
+    }
+}
+
+fn attr_string(data: &[u8]) -> String {
+    let data = match data.iter().position(|b| *b == 0) {
+        Some(end) => &data[..end],
+        None => data,
+    };
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// Iterator over the `rtattr` attributes of a message payload.
+struct Attributes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Attributes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < RTATTR_HDR_LEN {
+            return None;
+        }
+
+        let len = read_u16(self.data, 0) as usize;
+        let ty = read_u16(self.data, 2);
+        if len < RTATTR_HDR_LEN || len > self.data.len() {
+            return None;
+        }
+
+        let value = &self.data[RTATTR_HDR_LEN..len];
+        self.data = &self.data[align(len).min(self.data.len())..];
+        Some((ty, value))
+    }
+}
+
+/// A single message, `(type, payload)`.
+type Message<'a> = (u16, &'a [u8]);
+
+fn parse_messages(mut data: &[u8]) -> io::Result<Vec<Message>> {
+    let mut messages = Vec::new();
+    while data.len() >= NLMSG_HDR_LEN {
+        let len = read_u32(data, 0) as usize;
+        let ty = read_u16(data, 4);
+        if len < NLMSG_HDR_LEN || len > data.len() {
+            return Err(invalid_data("invalid netlink message length"));
+        }
+        messages.push((ty, &data[NLMSG_HDR_LEN..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    Ok(messages)
+}
+
+fn open_socket(groups: u32) -> io::Result<Fd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = Fd(fd);
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = groups;
+
+    let rc = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(fd)
+}
+
+fn recv(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let got = unsafe {
+            libc::recv(
+                fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if got >= 0 {
+            return Ok(got as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// A `NETLINK_ROUTE` socket for queries.
+pub struct NetlinkRoute {
+    fd: Fd,
+    seq: u32,
+    buffer: Vec<u8>,
+}
+
+impl NetlinkRoute {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            fd: open_socket(0)?,
+            seq: 0,
+            buffer: vec![0u8; RECV_BUFFER_SIZE],
+        })
+    }
+
+    /// Get all network interfaces.
+    pub fn links(&mut self) -> io::Result<Vec<Link>> {
+        let mut links = Vec::new();
+        self.dump(RTM_GETLINK, IFINFOMSG_LEN, |ty, payload| {
+            if ty == RTM_NEWLINK {
+                links.push(Link::parse(payload)?);
+            }
+            Ok(())
+        })?;
+        Ok(links)
+    }
+
+    /// Get all IPv4 and IPv6 addresses.
+    pub fn addresses(&mut self) -> io::Result<Vec<Address>> {
+        let mut addresses = Vec::new();
+        self.dump(RTM_GETADDR, IFADDRMSG_LEN, |ty, payload| {
+            if ty == RTM_NEWADDR {
+                if let Some(address) = Address::parse(payload)? {
+                    addresses.push(address);
+                }
+            }
+            Ok(())
+        })?;
+        Ok(addresses)
+    }
+
+    /// Send a dump request with an all-zero family header of `header_len` bytes and pass all
+    /// response messages to `func`.
+    fn dump<F>(&mut self, request: u16, header_len: usize, mut func: F) -> io::Result<()>
+    where
+        F: FnMut(u16, &[u8]) -> io::Result<()>,
+    {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+
+        let len = NLMSG_HDR_LEN + header_len;
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&request.to_ne_bytes());
+        msg.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+        msg.extend_from_slice(&seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.resize(len, 0);
+
+        let rc = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        loop {
+            let got = recv(self.fd.as_raw_fd(), &mut self.buffer)?;
+            for (ty, payload) in parse_messages(&self.buffer[..got])? {
+                match ty {
+                    NLMSG_DONE => return Ok(()),
+                    NLMSG_ERROR => {
+                        if payload.len() < 4 {
+                            return Err(invalid_data("short netlink error message"));
+                        }
+                        let errno = read_u32(payload, 0) as i32;
+                        if errno != 0 {
+                            return Err(io::Error::from_raw_os_error(-errno));
+                        }
+                    }
+                    _ => func(ty, payload)?,
+                }
+            }
+        }
+    }
+}
+
+/// A link state change reported by `LinkMonitor`.
+#[derive(Clone, Debug)]
+pub enum LinkEvent {
+    /// An interface was added or its state (eg. flags or name) changed.
+    Changed(Link),
+    /// An interface was removed.
+    Removed(Link),
+}
+
+/// Receives link notifications via the `RTMGRP_LINK` multicast group.
+pub struct LinkMonitor {
+    fd: Fd,
+    buffer: Vec<u8>,
+    pending: std::collections::VecDeque<LinkEvent>,
+}
+
+impl LinkMonitor {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            fd: open_socket(RTMGRP_LINK)?,
+            buffer: vec![0u8; RECV_BUFFER_SIZE],
+            pending: std::collections::VecDeque::new(),
+        })
+    }
+
+    /// Wait for the next link event.
+    ///
+    /// If events are generated faster than they are read, the kernel drops them and this fails
+    /// with `ENOBUFS`, in which case the current state should be queried again via
+    /// `NetlinkRoute::links`.
+    pub fn next_event(&mut self) -> io::Result<LinkEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            let got = recv(self.fd.as_raw_fd(), &mut self.buffer)?;
+            for (ty, payload) in parse_messages(&self.buffer[..got])? {
+                match ty {
+                    RTM_NEWLINK => self
+                        .pending
+                        .push_back(LinkEvent::Changed(Link::parse(payload)?)),
+                    RTM_DELLINK => self
+                        .pending
+                        .push_back(LinkEvent::Removed(Link::parse(payload)?)),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_netlink_route() {
+    let mut netlink = NetlinkRoute::new().expect("failed to open netlink socket");
+
+    let links = netlink.links().expect("failed to query links");
+    let lo = links
+        .iter()
+        .find(|link| link.name == "lo")
+        .expect("no loopback interface");
+    assert!(lo.flags & (libc::IFF_LOOPBACK as u32) != 0);
+
+    let addresses = netlink.addresses().expect("failed to query addresses");
+    for address in addresses {
+        assert!(links.iter().any(|link| link.index == address.index));
+        if address.address == IpAddr::from(Ipv4Addr::LOCALHOST) {
+            assert_eq!(address.index, lo.index);
+            assert_eq!(address.prefix_len, 8);
+        }
+    }
+}
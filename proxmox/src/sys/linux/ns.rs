@@ -0,0 +1,254 @@
+//! Linux namespace helpers, see `namespaces(7)`.
+//!
+//! ```no_run
+//! # use nix::unistd::Pid;
+//! # use proxmox::sys::linux::ns::{self, Namespace};
+//! # fn code() -> std::io::Result<()> {
+//! // run something in the network namespace of a container's init process
+//! let guard = ns::enter(Pid::from_raw(12345), &[Namespace::Net, Namespace::Uts])?;
+//! // ...
+//! guard.restore()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+
+use nix::fcntl::OFlag;
+use nix::sched::CloneFlags;
+use nix::sys::stat::Mode;
+use nix::unistd::Pid;
+
+use crate::sys::error::SysResult;
+use crate::tools::fd::Fd;
+
+/// The namespace types.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Namespace {
+    Cgroup,
+    Ipc,
+    Mount,
+    Net,
+    Pid,
+    User,
+    Uts,
+}
+
+impl Namespace {
+    /// The name of the namespace in `/proc/<pid>/ns/`.
+    pub fn proc_name(self) -> &'static str {
+        match self {
+            Namespace::Cgroup => "cgroup",
+            Namespace::Ipc => "ipc",
+            Namespace::Mount => "mnt",
+            Namespace::Net => "net",
+            Namespace::Pid => "pid",
+            Namespace::User => "user",
+            Namespace::Uts => "uts",
+        }
+    }
+
+    /// The corresponding `CLONE_NEW*` flag.
+    pub fn clone_flag(self) -> CloneFlags {
+        match self {
+            Namespace::Cgroup => CloneFlags::CLONE_NEWCGROUP,
+            Namespace::Ipc => CloneFlags::CLONE_NEWIPC,
+            Namespace::Mount => CloneFlags::CLONE_NEWNS,
+            Namespace::Net => CloneFlags::CLONE_NEWNET,
+            Namespace::Pid => CloneFlags::CLONE_NEWPID,
+            Namespace::User => CloneFlags::CLONE_NEWUSER,
+            Namespace::Uts => CloneFlags::CLONE_NEWUTS,
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.proc_name())
+    }
+}
+
+fn clone_flags(namespaces: &[Namespace]) -> CloneFlags {
+    namespaces
+        .iter()
+        .fold(CloneFlags::empty(), |flags, ns| flags | ns.clone_flag())
+}
+
+/// Move the current process into new namespaces.
+///
+/// Note that a new pid namespace only affects child processes created afterwards, and that
+/// creating a new user namespace fails in multi threaded processes.
+pub fn unshare(namespaces: &[Namespace]) -> io::Result<()> {
+    nix::sched::unshare(clone_flags(namespaces)).into_io_result()
+}
+
+/// A file descriptor referring to a namespace.
+pub struct NsFd {
+    fd: Fd,
+    ns: Namespace,
+}
+
+impl NsFd {
+    /// Open a namespace of a process.
+    pub fn open(pid: Pid, ns: Namespace) -> io::Result<Self> {
+        let path = format!("/proc/{}/ns/{}", pid, ns.proc_name());
+        Self::open_path(&path, ns)
+    }
+
+    /// Open a namespace of the current thread.
+    pub fn current(ns: Namespace) -> io::Result<Self> {
+        let path = format!("/proc/thread-self/ns/{}", ns.proc_name());
+        Self::open_path(&path, ns)
+    }
+
+    fn open_path(path: &str, ns: Namespace) -> io::Result<Self> {
+        let fd =
+            Fd::open(path, OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty()).into_io_result()?;
+        Ok(Self { fd, ns })
+    }
+
+    /// Create from a raw file descriptor, eg. one of a bind mounted namespace file.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a valid file descriptor referring to a namespace of type `ns`, and is closed
+    /// when the `NsFd` is dropped.
+    pub unsafe fn from_raw_fd(fd: RawFd, ns: Namespace) -> Self {
+        Self { fd: Fd(fd), ns }
+    }
+
+    /// The namespace type.
+    pub fn namespace(&self) -> Namespace {
+        self.ns
+    }
+
+    /// Move the current thread into this namespace.
+    pub fn enter(&self) -> io::Result<()> {
+        nix::sched::setns(self.fd.as_raw_fd(), self.ns.clone_flag()).into_io_result()
+    }
+}
+
+impl AsRawFd for NsFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for NsFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+// The user namespace must be entered first, since it may grant the capabilities required to enter
+// the other namespaces.
+fn sort_namespaces(namespaces: &[Namespace]) -> Vec<Namespace> {
+    let mut list: Vec<Namespace> = Vec::with_capacity(namespaces.len());
+    for ns in namespaces {
+        if !list.contains(ns) {
+            list.push(*ns);
+        }
+    }
+    list.sort_by_key(|ns| *ns != Namespace::User);
+    list
+}
+
+/// Move the current thread into namespaces of another process, like `nsenter(1)`.
+///
+/// Returns a guard which moves the thread back into its original namespaces when dropped. Note
+/// that the user and pid namespaces cannot be left like this: joining a pid namespace only affects
+/// child processes, and a user namespace cannot be re-entered from inside of it. These are not
+/// restored by the guard. Also, the mount namespace can only be changed by single threaded
+/// processes.
+pub fn enter(pid: Pid, namespaces: &[Namespace]) -> io::Result<NsGuard> {
+    let namespaces = sort_namespaces(namespaces);
+
+    // open everything first, so we fail before changing anything
+    let mut targets = Vec::with_capacity(namespaces.len());
+    let mut original = Vec::with_capacity(namespaces.len());
+    for ns in namespaces {
+        targets.push(NsFd::open(pid, ns)?);
+        original.push(match ns {
+            Namespace::User | Namespace::Pid => None,
+            _ => Some(NsFd::current(ns)?),
+        });
+    }
+
+    let mut guard = NsGuard {
+        original: Vec::with_capacity(targets.len()),
+    };
+    for (target, original) in targets.iter().zip(original) {
+        target.enter()?;
+        guard.original.extend(original);
+    }
+
+    Ok(guard)
+}
+
+/// Restores the original namespaces of the current thread when dropped, see `enter`.
+pub struct NsGuard {
+    original: Vec<NsFd>,
+}
+
+impl NsGuard {
+    /// Move back into the original namespaces, reporting errors.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.do_restore()
+    }
+
+    /// Stay in the entered namespaces.
+    pub fn forget(mut self) {
+        self.original.clear();
+    }
+
+    fn do_restore(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        while let Some(ns) = self.original.pop() {
+            if let Err(err) = ns.enter() {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for NsGuard {
+    fn drop(&mut self) {
+        // use `restore` to handle errors, panicking here could abort the process
+        if let Err(err) = self.do_restore() {
+            eprintln!("failed to restore original namespaces: {}", err);
+        }
+    }
+}
+
+#[test]
+fn test_ns_sort() {
+    assert_eq!(
+        sort_namespaces(&[
+            Namespace::Net,
+            Namespace::User,
+            Namespace::Mount,
+            Namespace::Net
+        ]),
+        vec![Namespace::User, Namespace::Net, Namespace::Mount],
+    );
+    assert_eq!(
+        clone_flags(&[Namespace::Net, Namespace::Mount]),
+        CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_NEWNS,
+    );
+}
+
+#[test]
+fn test_ns_enter_self() {
+    // joining our own namespaces should always work for the uts namespace if we're privileged,
+    // otherwise at least opening the namespace files must work
+    let pid = nix::unistd::getpid();
+    NsFd::open(pid, Namespace::Uts).expect("failed to open uts namespace");
+    if let Ok(guard) = enter(pid, &[Namespace::Uts]) {
+        guard.restore().expect("failed to restore namespaces");
+    }
+}
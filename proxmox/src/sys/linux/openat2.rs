@@ -0,0 +1,241 @@
+//! `openat2(2)` with path resolution restrictions.
+//!
+//! This is mainly useful to open files below a directory with user controlled relative paths,
+//! without having to worry about `..` components or symlinks pointing elsewhere:
+//!
+//! ```no_run
+//! # use nix::fcntl::OFlag;
+//! # use proxmox::sys::linux::openat2::safe_open_beneath;
+//! # fn code(user_path: &str) -> std::io::Result<()> {
+//! let root = std::fs::File::open("/var/lib/datastore")?;
+//! let file = safe_open_beneath(&root, user_path, OFlag::O_RDONLY)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::OsStr;
+use std::io;
+use std::ops::BitOr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::NixPath;
+
+use crate::sys::error::SysResult;
+use crate::tools::fd::{BorrowedFd, Fd};
+
+// openat2 has the same number on all architectures, but is not available in all libc versions
+const SYS_OPENAT2: libc::c_long = 437;
+
+/// Restrictions for the path resolution of `openat2`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ResolveFlags(u64);
+
+impl ResolveFlags {
+    pub const EMPTY: ResolveFlags = ResolveFlags(0);
+    /// Do not cross mount points (including bind mounts).
+    pub const NO_XDEV: ResolveFlags = ResolveFlags(0x01);
+    /// Do not follow magic links like `/proc/<pid>/fd/<n>`.
+    pub const NO_MAGICLINKS: ResolveFlags = ResolveFlags(0x02);
+    /// Do not follow any symlinks.
+    pub const NO_SYMLINKS: ResolveFlags = ResolveFlags(0x04);
+    /// Do not allow the path to leave the directory, neither via `..` nor via symlinks or
+    /// absolute paths.
+    pub const BENEATH: ResolveFlags = ResolveFlags(0x08);
+    /// Treat the directory as the root directory, like a `chroot`.
+    pub const IN_ROOT: ResolveFlags = ResolveFlags(0x10);
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: ResolveFlags) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for ResolveFlags {
+    type Output = ResolveFlags;
+
+    fn bitor(self, other: ResolveFlags) -> ResolveFlags {
+        ResolveFlags(self.0 | other.0)
+    }
+}
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Open a file relative to a directory with restricted path resolution. Requires Linux 5.6.
+///
+/// Contrary to `openat(2)` unknown open flags are rejected, and a `mode` must only be passed
+/// along with `O_CREAT` or `O_TMPFILE`.
+pub fn openat2<D, P>(
+    dirfd: &D,
+    path: &P,
+    flags: OFlag,
+    mode: Mode,
+    resolve: ResolveFlags,
+) -> io::Result<Fd>
+where
+    D: ?Sized + AsRawFd,
+    P: ?Sized + NixPath,
+{
+    let how = OpenHow {
+        flags: flags.bits() as u64,
+        mode: u64::from(mode.bits()),
+        resolve: resolve.0,
+    };
+
+    let fd = path
+        .with_nix_path(|path| unsafe {
+            libc::syscall(
+                SYS_OPENAT2,
+                dirfd.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const OpenHow,
+                std::mem::size_of::<OpenHow>(),
+            )
+        })
+        .into_io_result()?;
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Fd(fd as libc::c_int))
+}
+
+/// Open a file below the directory `dirfd`, making sure the path cannot escape it.
+///
+/// `O_CLOEXEC` is always added to `flags`. This uses `openat2` with `RESOLVE_BENEATH` and
+/// `RESOLVE_NO_MAGICLINKS`. On kernels without `openat2`, the path is walked component by
+/// component instead, in which case symlinks are not followed at all.
+pub fn safe_open_beneath<D, P>(dirfd: &D, relpath: &P, flags: OFlag) -> io::Result<Fd>
+where
+    D: ?Sized + AsRawFd,
+    P: ?Sized + AsRef<Path>,
+{
+    let relpath = relpath.as_ref();
+    let flags = flags | OFlag::O_CLOEXEC;
+    let mode = if flags.intersects(OFlag::O_CREAT | OFlag::O_TMPFILE) {
+        Mode::from_bits_truncate(0o644)
+    } else {
+        Mode::empty()
+    };
+
+    match openat2(
+        dirfd,
+        relpath,
+        flags,
+        mode,
+        ResolveFlags::BENEATH | ResolveFlags::NO_MAGICLINKS,
+    ) {
+        Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => (),
+        other => return other,
+    }
+
+    open_beneath_fallback(dirfd, relpath, flags, mode)
+}
+
+fn open_beneath_fallback<D: ?Sized + AsRawFd>(
+    dirfd: &D,
+    relpath: &Path,
+    flags: OFlag,
+    mode: Mode,
+) -> io::Result<Fd> {
+    let escape = || {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "path escapes the base directory",
+        )
+    };
+
+    let mut names: Vec<&OsStr> = Vec::new();
+    for component in relpath.components() {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(escape())
+            }
+        }
+    }
+
+    let last = match names.pop() {
+        Some(last) => last,
+        None => OsStr::from_bytes(b"."),
+    };
+
+    // walk the intermediate directories without following symlinks
+    let mut dir: Option<Fd> = None;
+    for name in names {
+        let parent = match dir {
+            Some(ref fd) => BorrowedFd::new(fd),
+            None => BorrowedFd::new(dirfd),
+        };
+        let next = Fd::openat(
+            &parent,
+            name,
+            OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .into_io_result()?;
+        dir = Some(next);
+    }
+
+    let parent = match dir {
+        Some(ref fd) => BorrowedFd::new(fd),
+        None => BorrowedFd::new(dirfd),
+    };
+    Fd::openat(&parent, last, flags | OFlag::O_NOFOLLOW, mode).into_io_result()
+}
+
+#[test]
+fn test_safe_open_beneath() {
+    let base = std::env::temp_dir().join(format!("proxmox-openat2-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(base.join("sub")).expect("failed to create test directory");
+    std::fs::write(base.join("sub/file"), b"data").expect("failed to create test file");
+    std::os::unix::fs::symlink("/etc/passwd", base.join("escape"))
+        .expect("failed to create test symlink");
+
+    let root = Fd::open(&base, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())
+        .expect("failed to open test directory");
+
+    let fallback =
+        |path: &str| open_beneath_fallback(&root, Path::new(path), OFlag::O_RDONLY, Mode::empty());
+    assert!(fallback("sub/file").is_ok());
+    assert!(fallback("./sub/file").is_ok());
+    assert!(fallback("sub/../sub/file").is_err());
+    assert!(fallback("../etc/passwd").is_err());
+    assert!(fallback("/etc/passwd").is_err());
+    assert!(fallback("escape").is_err());
+
+    match openat2(
+        &root,
+        "sub/file",
+        OFlag::O_RDONLY,
+        Mode::empty(),
+        ResolveFlags::BENEATH,
+    ) {
+        Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => (),
+        other => {
+            other.expect("openat2 failed");
+            let open = |path: &str| safe_open_beneath(&root, path, OFlag::O_RDONLY);
+            assert!(open("sub/file").is_ok());
+            // `..` is fine as long as it does not leave the directory
+            assert!(open("sub/../sub/file").is_ok());
+            assert!(open("../etc/passwd").is_err());
+            assert!(open("/etc/passwd").is_err());
+            assert!(open("escape").is_err());
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&base);
+}
@@ -1,8 +1,16 @@
-//! PID file descriptor handling.
+//! PID file descriptor handling and PID files.
+//!
+//! A `PidFd` refers to a specific process rather than a process id, so signaling or waiting for
+//! it is not subject to races with the pid being reused by a new process.
+//!
+//! A `PidFile` makes sure only a single instance of a daemon is running.
 
-use std::fs::File;
-use std::io;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use nix::fcntl::OFlag;
 use nix::sys::signal::Signal;
@@ -12,7 +20,7 @@ use nix::unistd::Pid;
 use nix::NixPath;
 
 use crate::sys::error::{io_err_other, SysResult};
-use crate::sys::linux::procfs::{MountInfo, PidStat};
+use crate::sys::linux::procfs::{self, MountInfo, PidStat, PidStatus};
 use crate::tools::fd::Fd;
 use crate::{c_result, c_str, c_try};
 
@@ -72,6 +80,50 @@ impl PidFd {
         c_result!(unsafe { pidfd_send_signal(self.fd.as_raw_fd(), sig, info, 0) }).map(drop)
     }
 
+    /// Wait for the process to exit, with an optional timeout.
+    ///
+    /// This polls the pidfd, which becomes readable when the process terminates. Note that this
+    /// does not reap the process if it is our child.
+    ///
+    /// Returns `true` if the process has exited and `false` if the timeout was reached.
+    pub fn wait_timeout(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        loop {
+            let timeout_ms = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    // round up to not wake up too early
+                    let millis = (remaining.as_micros() + 999) / 1000;
+                    millis.min(libc::c_int::max_value() as u128) as libc::c_int
+                }
+            };
+
+            match c_result!(unsafe { libc::poll(&mut pfd, 1, timeout_ms) }) {
+                Ok(0) => return Ok(false),
+                Ok(_) => return Ok(true),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Block until the process has exited. See `wait_timeout` for details.
+    pub fn wait(&self) -> io::Result<()> {
+        self.wait_timeout(None).map(drop)
+    }
+
+    /// Check whether the process has exited without blocking.
+    pub fn has_exited(&self) -> io::Result<bool> {
+        self.wait_timeout(Some(Duration::from_secs(0)))
+    }
+
     /// Get the original PID number used to open this pidfd. Note that this may not be the correct
     /// pid if the PID namespace was changed (which is currently only possible by forking, which is
     /// why this is usually safe under normal circumstances.)
@@ -112,6 +164,13 @@ impl PidFd {
         PidStat::parse(&data).map_err(io_err_other)
     }
 
+    /// Get the `PidStatus` structure for this process. (`/proc/PID/status`)
+    pub fn get_status(&self) -> io::Result<PidStatus> {
+        let data = self.read_file(c_str!("status"))?;
+        let data = String::from_utf8(data).map_err(io_err_other)?;
+        PidStatus::parse(&data).map_err(io_err_other)
+    }
+
     /// Read this process' `/proc/PID/mountinfo` file.
     pub fn get_mount_info(&self) -> io::Result<MountInfo> {
         MountInfo::parse(&self.read_file(c_str!("mountinfo"))?).map_err(io_err_other)
@@ -173,3 +232,237 @@ impl FromRawFd for PidFd {
         Self::try_from_raw_fd(fd).unwrap()
     }
 }
+
+/// A locked PID file, removed again when dropped.
+///
+/// The file contains only the process id followed by a newline. It stays locked (via an open
+/// file description lock, see `fcntl(2)`) for as long as the `PidFile` exists, so a running instance can be detected reliably. Files
+/// which are not locked are considered stale unless they contain the pid of a running process
+/// which was started before the file was written, in case they were written by a process not
+/// locking them.
+///
+/// ```no_run
+/// # use proxmox::sys::linux::pid::PidFile;
+/// # fn code() -> std::io::Result<()> {
+/// let _pidfile = PidFile::create("/run/mydaemon.pid")?;
+/// // run the daemon, the pid file is removed on drop
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Create and lock a PID file for the current process, replacing stale files.
+    ///
+    /// Fails with `io::ErrorKind::AlreadyExists` if another running process owns the file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .mode(0o644)
+                .custom_flags(libc::O_CLOEXEC)
+                .open(path)?;
+
+            if !try_lock(&file)? {
+                return Err(already_running(path, read_pid(&mut file)?));
+            }
+
+            // The previous owner may have removed the file between our open and lock calls, in
+            // which case we locked an orphaned inode and need to start over.
+            if !is_same_file(&file, path)? {
+                continue;
+            }
+
+            if let Some(pid) = read_pid(&mut file)? {
+                if pid != Pid::this() && !is_stale(&file, pid)? {
+                    return Err(already_running(path, Some(pid)));
+                }
+            }
+
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(format!("{}\n", Pid::this()).as_bytes())?;
+            file.sync_all()?;
+
+            return Ok(Self {
+                file,
+                path: path.to_owned(),
+            });
+        }
+    }
+
+    /// Check whether a PID file is owned by a running process and return its pid.
+    ///
+    /// This only tests the lock without taking it, so it does not interfere with a concurrent
+    /// `create`.
+    pub fn running<P: AsRef<Path>>(path: P) -> io::Result<Option<Pid>> {
+        let mut file = match File::open(path.as_ref()) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let pid = read_pid(&mut file)?;
+
+        if !is_locked(&file)? {
+            // nobody holds the lock, so only a non-locking writer can still be running
+            return match pid {
+                Some(pid) if !is_stale(&file, pid)? => Ok(Some(pid)),
+                _ => Ok(None),
+            };
+        }
+
+        Ok(pid)
+    }
+
+    /// The path of the PID file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRawFd for PidFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Do not remove a file which was replaced in the meantime. The lock is released when the
+        // file is closed after the removal, so a new owner cannot lock the old inode unnoticed.
+        if let Ok(true) = is_same_file(&self.file, &self.path) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// An exclusive lock on the whole file.
+fn whole_file_lock() -> libc::flock {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    // a zero l_start and l_len cover the whole file, l_pid must be zero for OFD locks
+    lock
+}
+
+fn try_lock(file: &File) -> io::Result<bool> {
+    let lock = whole_file_lock();
+    match c_result!(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &lock) }) {
+        Ok(_) => Ok(true),
+        Err(ref err) if matches!(err.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EACCES)) => {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether another open file description holds a lock on the file.
+fn is_locked(file: &File) -> io::Result<bool> {
+    let mut lock = whole_file_lock();
+    c_try!(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_GETLK, &mut lock) });
+    Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+fn is_same_file(file: &File, path: &Path) -> io::Result<bool> {
+    let opened = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_pid(file: &mut File) -> io::Result<Option<Pid>> {
+    let mut data = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut data)?;
+    Ok(data
+        .lines()
+        .next()
+        .and_then(|line| line.trim().parse::<libc::pid_t>().ok())
+        .filter(|pid| *pid > 0)
+        .map(Pid::from_raw))
+}
+
+fn already_running(path: &Path, pid: Option<Pid>) -> io::Error {
+    let msg = match pid {
+        Some(pid) => format!("{:?} is owned by running process {}", path, pid),
+        None => format!("{:?} is locked by another process", path),
+    };
+    io::Error::new(io::ErrorKind::AlreadyExists, msg)
+}
+
+/// A pid in a PID file is stale if no such process exists, or if it was started after the file
+/// was last written, in which case the pid was reused.
+fn is_stale(file: &File, pid: Pid) -> io::Result<bool> {
+    let stat = match procfs::check_process_running(pid.as_raw()) {
+        Some(stat) => stat,
+        None => return Ok(true),
+    };
+
+    let age = SystemTime::now()
+        .duration_since(file.metadata()?.modified()?)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+    let (uptime, _) = procfs::read_proc_uptime().map_err(io_err_other)?;
+
+    let written_ticks = (uptime - age.as_secs_f64()) * *procfs::CLOCK_TICKS;
+    // allow for one second of rounding errors
+    Ok(stat.starttime as f64 > written_ticks + *procfs::CLOCK_TICKS)
+}
+
+#[test]
+fn test_pidfd_wait() {
+    let mut child = std::process::Command::new("sleep")
+        .arg("0.2")
+        .spawn()
+        .expect("failed to spawn test process");
+    let pidfd = PidFd::open(Pid::from_raw(child.id() as i32)).expect("failed to open pidfd");
+
+    assert!(!pidfd.has_exited().expect("failed to poll pidfd"));
+    assert!(pidfd
+        .wait_timeout(Some(Duration::from_secs(10)))
+        .expect("failed to wait for process"));
+    assert!(pidfd.has_exited().expect("failed to poll pidfd"));
+
+    child.wait().expect("failed to reap test process");
+}
+
+#[test]
+fn test_pid_file() {
+    let path = std::env::temp_dir().join(format!("proxmox-pidfile-test-{}", std::process::id()));
+
+    // stale: a pid which cannot exist
+    std::fs::write(&path, b"4194305\n").expect("failed to write stale pid file");
+    assert_eq!(
+        PidFile::running(&path).expect("failed to check pid file"),
+        None
+    );
+
+    let pidfile = PidFile::create(&path).expect("failed to create pid file");
+    let data = std::fs::read_to_string(&path).expect("failed to read pid file");
+    assert_eq!(data, format!("{}\n", std::process::id()));
+    assert_eq!(
+        PidFile::running(&path).expect("failed to check pid file"),
+        Some(Pid::this())
+    );
+
+    // the lock is per open file description, so a second attempt fails even in this process
+    let err = PidFile::create(&path).expect_err("pid file was locked twice");
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    drop(pidfile);
+    assert!(!path.exists());
+    assert_eq!(
+        PidFile::running(&path).expect("failed to check pid file"),
+        None
+    );
+}
@@ -0,0 +1,116 @@
+//! Typed `prctl(2)` wrappers for the process attributes we commonly need.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::io;
+
+use nix::sys::signal::Signal;
+
+// prctl is variadic, and the kernel checks unused arguments to be zero, so always pass full width
+// arguments.
+fn prctl(option: libc::c_int, arg2: libc::c_ulong) -> io::Result<libc::c_int> {
+    let rc = unsafe {
+        libc::prctl(
+            option,
+            arg2,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+            0 as libc::c_ulong,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc)
+}
+
+/// Set the name of the current thread (`PR_SET_NAME`), as shown by `ps` or `top`.
+///
+/// Names longer than 15 bytes are truncated, names containing nul bytes are rejected.
+pub fn set_name(name: &str) -> io::Result<()> {
+    let name = &name.as_bytes()[..name.len().min(15)];
+    let name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a nul byte"))?;
+    prctl(libc::PR_SET_NAME, name.as_ptr() as libc::c_ulong)?;
+    Ok(())
+}
+
+/// Get the name of the current thread (`PR_GET_NAME`).
+pub fn get_name() -> io::Result<String> {
+    let mut buffer = [0u8; 16];
+    prctl(libc::PR_GET_NAME, buffer.as_mut_ptr() as libc::c_ulong)?;
+    let name = unsafe { CStr::from_ptr(buffer.as_ptr() as *const libc::c_char) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Set the signal the current thread receives when its parent thread dies (`PR_SET_PDEATHSIG`),
+/// or clear it with `None`.
+///
+/// Note that this refers to the parent *thread*, and that the setting is cleared when executing a
+/// set-user-id binary.
+pub fn set_pdeathsig(signal: Option<Signal>) -> io::Result<()> {
+    let signo = signal.map(|signal| signal as libc::c_int).unwrap_or(0);
+    prctl(libc::PR_SET_PDEATHSIG, signo as libc::c_ulong)?;
+    Ok(())
+}
+
+/// Get the parent death signal (`PR_GET_PDEATHSIG`).
+pub fn get_pdeathsig() -> io::Result<Option<Signal>> {
+    let mut signo: libc::c_int = 0;
+    prctl(
+        libc::PR_GET_PDEATHSIG,
+        &mut signo as *mut libc::c_int as libc::c_ulong,
+    )?;
+    if signo == 0 {
+        return Ok(None);
+    }
+    Signal::try_from(signo)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Set whether the process can produce core dumps and be attached to via `ptrace`
+/// (`PR_SET_DUMPABLE`). Daemons handling secrets usually want to disable this.
+pub fn set_dumpable(dumpable: bool) -> io::Result<()> {
+    prctl(libc::PR_SET_DUMPABLE, dumpable as libc::c_ulong)?;
+    Ok(())
+}
+
+/// Get the dumpable flag (`PR_GET_DUMPABLE`).
+pub fn get_dumpable() -> io::Result<bool> {
+    Ok(prctl(libc::PR_GET_DUMPABLE, 0)? == 1)
+}
+
+/// Set the `no_new_privs` flag of the current thread (`PR_SET_NO_NEW_PRIVS`). Once set, it cannot
+/// be unset, and is inherited by child processes.
+///
+/// This prevents `execve` from granting privileges (eg. via set-user-id binaries or file
+/// capabilities), and is required to install seccomp filters without `CAP_SYS_ADMIN`.
+pub fn set_no_new_privs() -> io::Result<()> {
+    prctl(libc::PR_SET_NO_NEW_PRIVS, 1)?;
+    Ok(())
+}
+
+/// Get the `no_new_privs` flag (`PR_GET_NO_NEW_PRIVS`).
+pub fn get_no_new_privs() -> io::Result<bool> {
+    Ok(prctl(libc::PR_GET_NO_NEW_PRIVS, 0)? == 1)
+}
+
+#[test]
+fn test_prctl() {
+    // these are per thread attributes, so use a separate thread
+    std::thread::spawn(|| {
+        set_name("proxmox-prctl-test-thread").expect("failed to set thread name");
+        assert_eq!(get_name().expect("failed to get name"), "proxmox-prctl-t");
+
+        set_pdeathsig(Some(Signal::SIGTERM)).expect("failed to set pdeathsig");
+        assert_eq!(get_pdeathsig().unwrap(), Some(Signal::SIGTERM));
+        set_pdeathsig(None).expect("failed to clear pdeathsig");
+        assert_eq!(get_pdeathsig().unwrap(), None);
+
+        set_no_new_privs().expect("failed to set no_new_privs");
+        assert!(get_no_new_privs().unwrap());
+    })
+    .join()
+    .expect("prctl test thread failed");
+}
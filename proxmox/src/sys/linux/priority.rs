@@ -0,0 +1,263 @@
+//! CPU and I/O scheduling priorities.
+//!
+//! Background jobs can deprioritize themselves with:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::priority::ProcessPriority;
+//! # fn code() -> std::io::Result<()> {
+//! ProcessPriority::idle_io().nice(19).apply()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Note that on Linux all of these are actually per-thread attributes, which are inherited by new
+//! threads and child processes. A pid of `0` refers to the calling thread.
+
+use std::io;
+
+use nix::unistd::Pid;
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_PRIO_MASK: libc::c_int = (1 << IOPRIO_CLASS_SHIFT) - 1;
+
+const IOPRIO_CLASS_NONE: libc::c_int = 0;
+const IOPRIO_CLASS_RT: libc::c_int = 1;
+const IOPRIO_CLASS_BE: libc::c_int = 2;
+const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+/// An I/O scheduling class and priority, see `ioprio_set(2)`. Levels range from `0` (highest) to
+/// `7` (lowest).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IoPriority {
+    /// No explicit priority, derived from the CPU nice value.
+    None,
+    /// Real time I/O, always served first. Requires `CAP_SYS_ADMIN`.
+    RealTime(u8),
+    /// The default class.
+    BestEffort(u8),
+    /// Only served when no other process needs the disk.
+    Idle,
+}
+
+impl IoPriority {
+    fn to_raw(self) -> libc::c_int {
+        let (class, level) = match self {
+            IoPriority::None => (IOPRIO_CLASS_NONE, 0),
+            IoPriority::RealTime(level) => (IOPRIO_CLASS_RT, level.min(7)),
+            IoPriority::BestEffort(level) => (IOPRIO_CLASS_BE, level.min(7)),
+            IoPriority::Idle => (IOPRIO_CLASS_IDLE, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level)
+    }
+
+    fn from_raw(raw: libc::c_int) -> io::Result<Self> {
+        let level = (raw & IOPRIO_PRIO_MASK) as u8;
+        Ok(match raw >> IOPRIO_CLASS_SHIFT {
+            IOPRIO_CLASS_NONE => IoPriority::None,
+            IOPRIO_CLASS_RT => IoPriority::RealTime(level),
+            IOPRIO_CLASS_BE => IoPriority::BestEffort(level),
+            IOPRIO_CLASS_IDLE => IoPriority::Idle,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown I/O priority class",
+                ))
+            }
+        })
+    }
+}
+
+/// Get the I/O priority of a thread.
+pub fn ioprio_get(pid: Pid) -> io::Result<IoPriority> {
+    let rc = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, pid.as_raw()) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    IoPriority::from_raw(rc as libc::c_int)
+}
+
+/// Set the I/O priority of a thread.
+pub fn ioprio_set(pid: Pid, priority: IoPriority) -> io::Result<()> {
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            pid.as_raw(),
+            priority.to_raw(),
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Get the nice value of a thread.
+pub fn get_nice(pid: Pid) -> io::Result<i32> {
+    // -1 is a valid return value, so errors can only be detected via errno
+    nix::errno::Errno::clear();
+    let rc = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, pid.as_raw() as libc::id_t) };
+    if rc == -1 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(0) {
+            return Err(err);
+        }
+    }
+    Ok(rc)
+}
+
+/// Set the nice value of a thread, ranging from `-20` (highest priority) to `19` (lowest).
+/// Lowering the value requires `CAP_SYS_NICE`.
+pub fn set_nice(pid: Pid, nice: i32) -> io::Result<()> {
+    let rc =
+        unsafe { libc::setpriority(libc::PRIO_PROCESS as _, pid.as_raw() as libc::id_t, nice) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A CPU scheduling policy, see `sched(7)`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy.
+    Other,
+    /// Like `Other`, but for non-interactive CPU intensive jobs.
+    Batch,
+    /// For jobs with very low priority, running only when the CPU is otherwise idle.
+    Idle,
+    /// Real time first-in first-out policy with a priority from `1` to `99`.
+    Fifo(i32),
+    /// Real time round-robin policy with a priority from `1` to `99`.
+    RoundRobin(i32),
+}
+
+impl SchedPolicy {
+    fn to_raw(self) -> (libc::c_int, libc::c_int) {
+        match self {
+            SchedPolicy::Other => (libc::SCHED_OTHER, 0),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
+            SchedPolicy::Fifo(prio) => (libc::SCHED_FIFO, prio),
+            SchedPolicy::RoundRobin(prio) => (libc::SCHED_RR, prio),
+        }
+    }
+}
+
+/// Get the scheduling policy of a thread.
+pub fn get_scheduler(pid: Pid) -> io::Result<SchedPolicy> {
+    let policy = unsafe { libc::sched_getscheduler(pid.as_raw()) };
+    if policy < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut param = libc::sched_param { sched_priority: 0 };
+    if unsafe { libc::sched_getparam(pid.as_raw(), &mut param) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // mask out SCHED_RESET_ON_FORK
+    Ok(match policy & !0x4000_0000 {
+        libc::SCHED_BATCH => SchedPolicy::Batch,
+        libc::SCHED_IDLE => SchedPolicy::Idle,
+        libc::SCHED_FIFO => SchedPolicy::Fifo(param.sched_priority),
+        libc::SCHED_RR => SchedPolicy::RoundRobin(param.sched_priority),
+        _ => SchedPolicy::Other,
+    })
+}
+
+/// Set the scheduling policy of a thread. The real time policies require `CAP_SYS_NICE`.
+pub fn set_scheduler(pid: Pid, policy: SchedPolicy) -> io::Result<()> {
+    let (policy, priority) = policy.to_raw();
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    if unsafe { libc::sched_setscheduler(pid.as_raw(), policy, &param) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A combination of scheduling settings to apply at once.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProcessPriority {
+    io: Option<IoPriority>,
+    nice: Option<i32>,
+    policy: Option<SchedPolicy>,
+}
+
+impl ProcessPriority {
+    /// Create an empty setting, which does not change anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shortcut for `ProcessPriority::new().io(IoPriority::Idle)`.
+    pub fn idle_io() -> Self {
+        Self::new().io(IoPriority::Idle)
+    }
+
+    /// Set the I/O priority.
+    pub fn io(mut self, priority: IoPriority) -> Self {
+        self.io = Some(priority);
+        self
+    }
+
+    /// Set the nice value.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Set the CPU scheduling policy.
+    pub fn scheduler(mut self, policy: SchedPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Apply the settings to the calling thread.
+    pub fn apply(&self) -> io::Result<()> {
+        self.apply_to(Pid::from_raw(0))
+    }
+
+    /// Apply the settings to a thread or process.
+    pub fn apply_to(&self, pid: Pid) -> io::Result<()> {
+        // the nice value is only used by the non real time policies, so switch policies first
+        if let Some(policy) = self.policy {
+            set_scheduler(pid, policy)?;
+        }
+        if let Some(nice) = self.nice {
+            set_nice(pid, nice)?;
+        }
+        if let Some(priority) = self.io {
+            ioprio_set(pid, priority)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_process_priority() {
+    assert_eq!(IoPriority::BestEffort(4).to_raw(), (2 << 13) | 4);
+    assert_eq!(
+        IoPriority::from_raw(IoPriority::RealTime(3).to_raw()).unwrap(),
+        IoPriority::RealTime(3)
+    );
+
+    // lowering priorities is always allowed, but keep it to a separate thread
+    std::thread::spawn(|| {
+        let this = Pid::from_raw(0);
+        ProcessPriority::idle_io()
+            .nice(19)
+            .scheduler(SchedPolicy::Batch)
+            .apply()
+            .expect("failed to lower priority");
+
+        assert_eq!(ioprio_get(this).unwrap(), IoPriority::Idle);
+        assert_eq!(get_nice(this).unwrap(), 19);
+        assert_eq!(get_scheduler(this).unwrap(), SchedPolicy::Batch);
+    })
+    .join()
+    .expect("priority test thread failed");
+}
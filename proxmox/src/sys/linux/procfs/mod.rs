@@ -1,19 +1,24 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::RwLock;
 use std::time::Instant;
 
 use anyhow::*;
 use lazy_static::lazy_static;
-use nix::unistd::Pid;
+use nix::fcntl::OFlag;
+use nix::unistd::{Gid, Pid, Uid};
 
+use crate::sys::error::{io_err_other, SysError};
 use crate::tools::fs::file_read_firstline;
-use crate::tools::parse::hex_nibble;
+use crate::tools::hex;
 
 pub mod mountinfo;
 #[doc(inline)]
@@ -34,6 +39,8 @@ lazy_static! {
 /// Selected contents of the `/proc/PID/stat` file.
 pub struct PidStat {
     pub pid: Pid,
+    /// The command name, without the surrounding parentheses.
+    pub comm: String,
     pub ppid: Pid,
     pub status: u8,
     pub utime: u64,
@@ -80,6 +87,7 @@ impl PidStat {
         // After the '(' we have an arbitrary command name, then ')' and the remaining values
         let cmdend = statstr
             .rfind(')')
+            .filter(|&end| end > cmdbeg)
             .ok_or_else(|| format_err!("missing ')' in /proc/PID/stat"))?;
         let mut parts = statstr[cmdend + 1..].trim_start().split_ascii_whitespace();
 
@@ -106,6 +114,7 @@ impl PidStat {
 
         let out = PidStat {
             pid,
+            comm: statstr[(cmdbeg + 1)..cmdend].to_string(),
             status: req_byte(parts.next(), "status")?,
             ppid: Pid::from_raw(req_num::<u32>(parts.next(), "ppid")? as i32),
             utime: req_num::<u64>(parts.nth(9), "utime")?,
@@ -150,6 +159,596 @@ fn test_read_proc_pid_stat() {
     assert_eq!(stat.starttime, 287592);
     assert_eq!(stat.vsize, 12496896);
     assert_eq!(stat.rss, 1910 * 4096);
+    assert_eq!(stat.comm, "zsh");
+
+    // the command name may contain spaces and parentheses
+    let stat = PidStat::parse(
+        "1234 (a) b (c)) R 1 1234 1234 0 -1 4194560 100 0 0 0 17 4 0 0 20 0 3 0 5000 \
+         10240000 300 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 1 0 0 0 0 0",
+    )
+    .expect("successful parsing of a /proc/PID/stat entry with a complex comm");
+    assert_eq!(stat.pid, Pid::from_raw(1234));
+    assert_eq!(stat.comm, "a) b (c)");
+    assert_eq!(stat.status, b'R');
+    assert_eq!(stat.ppid, Pid::from_raw(1));
+    assert_eq!(stat.utime, 17);
+    assert_eq!(stat.stime, 4);
+    assert_eq!(stat.num_threads, 3);
+    assert_eq!(stat.starttime, 5000);
+    assert_eq!(stat.vsize, 10240000);
+    assert_eq!(stat.rss, 300 * 4096);
+}
+
+/// Read and parse `/proc/PID/stat` of a process.
+pub fn read_proc_pid_stat(pid: libc::pid_t) -> Result<PidStat, Error> {
+    PidStat::read_from_pid(Pid::from_raw(pid))
+}
+
+/// Selected contents of the `/proc/PID/status` file.
+#[derive(Clone, Debug)]
+pub struct PidStatus {
+    pub pid: Pid,
+    pub ppid: Pid,
+    pub ruid: Uid,
+    pub euid: Uid,
+    pub suid: Uid,
+    pub fsuid: Uid,
+    pub rgid: Gid,
+    pub egid: Gid,
+    pub sgid: Gid,
+    pub fsgid: Gid,
+    /// Peak virtual memory size in bytes. This is `0` for kernel threads.
+    pub vm_peak: u64,
+    /// Resident set size in bytes. This is `0` for kernel threads.
+    pub vm_rss: u64,
+    pub threads: u64,
+    /// Inheritable capability set.
+    pub cap_inh: u64,
+    /// Permitted capability set.
+    pub cap_prm: u64,
+    /// Effective capability set.
+    pub cap_eff: u64,
+    /// Capability bounding set.
+    pub cap_bnd: u64,
+}
+
+impl PidStatus {
+    /// Retrieve the `status` file contents of a process.
+    pub fn read_from_pid(pid: Pid) -> Result<Self, Error> {
+        let status = Self::parse(&std::fs::read_to_string(format!("/proc/{}/status", pid))?)?;
+        if status.pid != pid {
+            bail!(
+                "unexpected pid for process: found pid {} in /proc/{}/status",
+                status.pid.as_raw(),
+                pid
+            );
+        }
+        Ok(status)
+    }
+
+    /// Parse the contents of a `/proc/PID/status` file.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        fn ids(value: &str, what: &'static str) -> Result<[u32; 4], Error> {
+            let mut out = [0u32; 4];
+            let mut parts = value.split_ascii_whitespace();
+            for id in out.iter_mut() {
+                *id = parts
+                    .next()
+                    .ok_or_else(|| format_err!("missing value in '{}' of /proc/PID/status", what))?
+                    .parse()
+                    .map_err(|e| format_err!("bad '{}' in /proc/PID/status: {}", what, e))?;
+            }
+            Ok(out)
+        }
+
+        fn num(value: &str, what: &'static str) -> Result<u64, Error> {
+            value
+                .parse()
+                .map_err(|e| format_err!("bad '{}' in /proc/PID/status: {}", what, e))
+        }
+
+        fn kb(value: &str, what: &'static str) -> Result<u64, Error> {
+            match value.strip_suffix(" kB") {
+                Some(value) => Ok(num(value.trim(), what)? * 1024),
+                None => bail!("bad '{}' in /proc/PID/status: missing unit", what),
+            }
+        }
+
+        fn cap(value: &str, what: &'static str) -> Result<u64, Error> {
+            u64::from_str_radix(value, 16)
+                .map_err(|e| format_err!("bad '{}' in /proc/PID/status: {}", what, e))
+        }
+
+        let (mut pid, mut ppid, mut uids, mut gids) = (None, None, None, None);
+        let mut threads = None;
+        let (mut vm_peak, mut vm_rss) = (0, 0);
+        let (mut cap_inh, mut cap_prm, mut cap_eff, mut cap_bnd) = (0, 0, 0, 0);
+
+        for line in data.lines() {
+            let (key, value) = match line.find(':') {
+                Some(pos) => (&line[..pos], line[(pos + 1)..].trim()),
+                None => continue,
+            };
+
+            match key {
+                "Pid" => pid = Some(Pid::from_raw(num(value, "Pid")? as i32)),
+                "PPid" => ppid = Some(Pid::from_raw(num(value, "PPid")? as i32)),
+                "Uid" => uids = Some(ids(value, "Uid")?),
+                "Gid" => gids = Some(ids(value, "Gid")?),
+                "VmPeak" => vm_peak = kb(value, "VmPeak")?,
+                "VmRSS" => vm_rss = kb(value, "VmRSS")?,
+                "Threads" => threads = Some(num(value, "Threads")?),
+                "CapInh" => cap_inh = cap(value, "CapInh")?,
+                "CapPrm" => cap_prm = cap(value, "CapPrm")?,
+                "CapEff" => cap_eff = cap(value, "CapEff")?,
+                "CapBnd" => cap_bnd = cap(value, "CapBnd")?,
+                _ => continue,
+            }
+        }
+
+        let missing = |what: &'static str| format_err!("missing '{}' in /proc/PID/status", what);
+        let uids = uids.ok_or_else(|| missing("Uid"))?;
+        let gids = gids.ok_or_else(|| missing("Gid"))?;
+
+        Ok(Self {
+            pid: pid.ok_or_else(|| missing("Pid"))?,
+            ppid: ppid.ok_or_else(|| missing("PPid"))?,
+            ruid: Uid::from_raw(uids[0]),
+            euid: Uid::from_raw(uids[1]),
+            suid: Uid::from_raw(uids[2]),
+            fsuid: Uid::from_raw(uids[3]),
+            rgid: Gid::from_raw(gids[0]),
+            egid: Gid::from_raw(gids[1]),
+            sgid: Gid::from_raw(gids[2]),
+            fsgid: Gid::from_raw(gids[3]),
+            vm_peak,
+            vm_rss,
+            threads: threads.ok_or_else(|| missing("Threads"))?,
+            cap_inh,
+            cap_prm,
+            cap_eff,
+            cap_bnd,
+        })
+    }
+}
+
+impl TryFrom<Pid> for PidStatus {
+    type Error = Error;
+
+    fn try_from(pid: Pid) -> Result<Self, Error> {
+        Self::read_from_pid(pid)
+    }
+}
+
+#[test]
+fn test_read_proc_pid_status() {
+    let status = PidStatus::parse(
+        "Name:\tzsh\n\
+         Umask:\t0022\n\
+         State:\tS (sleeping)\n\
+         Tgid:\t28900\n\
+         Ngid:\t0\n\
+         Pid:\t28900\n\
+         PPid:\t22489\n\
+         TracerPid:\t0\n\
+         Uid:\t1000\t1001\t1002\t1003\n\
+         Gid:\t100\t101\t102\t103\n\
+         FDSize:\t64\n\
+         Groups:\t24 25 100\n\
+         VmPeak:\t   12400 kB\n\
+         VmSize:\t   12204 kB\n\
+         VmRSS:\t    7640 kB\n\
+         Threads:\t1\n\
+         CapInh:\t0000000000000000\n\
+         CapPrm:\t0000000000000000\n\
+         CapEff:\t0000000000000000\n\
+         CapBnd:\t000001ffffffffff\n\
+         CapAmb:\t0000000000000000\n",
+    )
+    .expect("successful parsing of a sample /proc/PID/status entry");
+    assert_eq!(status.pid, Pid::from_raw(28900));
+    assert_eq!(status.ppid, Pid::from_raw(22489));
+    assert_eq!(status.ruid, Uid::from_raw(1000));
+    assert_eq!(status.euid, Uid::from_raw(1001));
+    assert_eq!(status.suid, Uid::from_raw(1002));
+    assert_eq!(status.fsuid, Uid::from_raw(1003));
+    assert_eq!(status.rgid, Gid::from_raw(100));
+    assert_eq!(status.egid, Gid::from_raw(101));
+    assert_eq!(status.sgid, Gid::from_raw(102));
+    assert_eq!(status.fsgid, Gid::from_raw(103));
+    assert_eq!(status.vm_peak, 12400 * 1024);
+    assert_eq!(status.vm_rss, 7640 * 1024);
+    assert_eq!(status.threads, 1);
+    assert_eq!(status.cap_eff, 0);
+    assert_eq!(status.cap_bnd, 0x1ff_ffff_ffff);
+}
+
+/// The I/O counters from `/proc/PID/io`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PidIo {
+    /// Bytes read via `read(2)` and similar calls, including from caches and pipes.
+    pub rchar: u64,
+    /// Bytes written via `write(2)` and similar calls.
+    pub wchar: u64,
+    /// Number of read syscalls.
+    pub syscr: u64,
+    /// Number of write syscalls.
+    pub syscw: u64,
+    /// Bytes actually fetched from the storage layer.
+    pub read_bytes: u64,
+    /// Bytes sent to the storage layer.
+    pub write_bytes: u64,
+    /// Written bytes which were truncated again before reaching the storage layer.
+    pub cancelled_write_bytes: u64,
+}
+
+impl PidIo {
+    /// Parse the contents of a `/proc/PID/io` file.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut io = Self::default();
+        for line in data.lines() {
+            let mut parts = line.split_ascii_whitespace();
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                (None, _) => continue,
+                _ => bail!("bad line in /proc/PID/io: {:?}", line),
+            };
+            let value = value
+                .parse::<u64>()
+                .map_err(|err| format_err!("bad value for {:?} in /proc/PID/io - {}", key, err))?;
+
+            match key {
+                "rchar:" => io.rchar = value,
+                "wchar:" => io.wchar = value,
+                "syscr:" => io.syscr = value,
+                "syscw:" => io.syscw = value,
+                "read_bytes:" => io.read_bytes = value,
+                "write_bytes:" => io.write_bytes = value,
+                "cancelled_write_bytes:" => io.cancelled_write_bytes = value,
+                _ => continue,
+            }
+        }
+        Ok(io)
+    }
+}
+
+/// Read the I/O counters of a process from `/proc/PID/io`.
+///
+/// Returns `Ok(None)` if we lack the permission to access the counters, which is usually the
+/// case for processes of other users.
+pub fn read_proc_pid_io(pid: Pid) -> Result<Option<PidIo>, Error> {
+    let path = format!("/proc/{}/io", pid);
+    match std::fs::read_to_string(&path) {
+        Ok(data) => Ok(Some(PidIo::parse(&data)?)),
+        Err(ref err) if err.is_errno(nix::errno::Errno::EACCES) => Ok(None),
+        Err(err) => bail!("unable to read {:?} - {}", path, err),
+    }
+}
+
+#[test]
+fn test_parse_proc_pid_io() {
+    let io = PidIo::parse(
+        "rchar: 323934931\n\
+         wchar: 323929600\n\
+         syscr: 632687\n\
+         syscw: 632675\n\
+         read_bytes: 4096\n\
+         write_bytes: 323932160\n\
+         cancelled_write_bytes: 8192\n",
+    )
+    .expect("successful parsing of a sample /proc/PID/io file");
+    assert_eq!(io.rchar, 323934931);
+    assert_eq!(io.wchar, 323929600);
+    assert_eq!(io.syscr, 632687);
+    assert_eq!(io.syscw, 632675);
+    assert_eq!(io.read_bytes, 4096);
+    assert_eq!(io.write_bytes, 323932160);
+    assert_eq!(io.cancelled_write_bytes, 8192);
+
+    read_proc_pid_io(Pid::this())
+        .expect("failed to read our own /proc/PID/io")
+        .expect("no permission to read our own /proc/PID/io");
+}
+
+/// An open file descriptor of a process as found in `/proc/PID/fd` and `/proc/PID/fdinfo`.
+#[derive(Clone, Debug)]
+pub struct ProcFsFd {
+    pub fd: RawFd,
+    /// The link target, eg. a path or something like `socket:[12345]`.
+    pub target: PathBuf,
+    /// The file status flags the file was opened with.
+    pub flags: OFlag,
+    /// The current file offset.
+    pub pos: u64,
+}
+
+/// Iterator over the open file descriptors of a process, see `proc_pid_fds`.
+pub struct ProcPidFds {
+    pid: Pid,
+    dir: std::fs::ReadDir,
+}
+
+/// Iterate over the open file descriptors of a process.
+///
+/// File descriptors which get closed while iterating are skipped.
+pub fn proc_pid_fds(pid: Pid) -> Result<ProcPidFds, Error> {
+    let path = format!("/proc/{}/fd", pid);
+    let dir = std::fs::read_dir(&path)
+        .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+    Ok(ProcPidFds { pid, dir })
+}
+
+impl ProcPidFds {
+    fn read_entry(&self, fd: RawFd) -> io::Result<ProcFsFd> {
+        let target = std::fs::read_link(format!("/proc/{}/fd/{}", self.pid, fd))?;
+        let info = std::fs::read_to_string(format!("/proc/{}/fdinfo/{}", self.pid, fd))?;
+        let (pos, flags) = parse_fdinfo(&info).map_err(io_err_other)?;
+        Ok(ProcFsFd {
+            fd,
+            target,
+            flags,
+            pos,
+        })
+    }
+}
+
+impl Iterator for ProcPidFds {
+    type Item = Result<ProcFsFd, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.dir.next()? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            let fd: RawFd = match entry.file_name().to_str().map(str::parse) {
+                Some(Ok(fd)) => fd,
+                _ => {
+                    return Some(Err(format_err!(
+                        "unexpected entry {:?} in /proc/{}/fd",
+                        entry.file_name(),
+                        self.pid
+                    )))
+                }
+            };
+
+            match self.read_entry(fd) {
+                Ok(entry) => return Some(Ok(entry)),
+                // the file descriptor was closed in the meantime
+                Err(ref err) if err.not_found() => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// Parse the `pos` and `flags` values of a `/proc/PID/fdinfo/FD` file.
+fn parse_fdinfo(data: &str) -> Result<(u64, OFlag), Error> {
+    let (mut pos, mut flags) = (None, None);
+    for line in data.lines() {
+        let mut parts = line.split_ascii_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("pos:"), Some(value)) => pos = Some(value.parse::<u64>()?),
+            (Some("flags:"), Some(value)) => {
+                // the flags are printed in octal
+                flags = Some(OFlag::from_bits_truncate(i32::from_str_radix(value, 8)?));
+            }
+            _ => continue,
+        }
+    }
+
+    match (pos, flags) {
+        (Some(pos), Some(flags)) => Ok((pos, flags)),
+        _ => bail!("missing 'pos' or 'flags' in fdinfo"),
+    }
+}
+
+#[test]
+fn test_parse_fdinfo() {
+    let (pos, flags) = parse_fdinfo("pos:\t4096\nflags:\t02100002\nmnt_id:\t25\nino:\t1234\n")
+        .expect("successful parsing of a sample fdinfo file");
+    assert_eq!(pos, 4096);
+    assert!(flags.contains(OFlag::O_RDWR));
+    assert!(flags.contains(OFlag::O_CLOEXEC));
+    assert!(!flags.contains(OFlag::O_APPEND));
+
+    parse_fdinfo("flags:\t02\n").expect_err("parsed fdinfo without 'pos'");
+}
+
+#[test]
+fn test_proc_pid_fds() {
+    let file = std::fs::File::open("/proc/self/stat").expect("failed to open test file");
+    let raw_fd = std::os::unix::io::AsRawFd::as_raw_fd(&file);
+
+    let entry = proc_pid_fds(Pid::this())
+        .expect("failed to list our own file descriptors")
+        .filter_map(Result::ok)
+        .find(|entry| entry.fd == raw_fd)
+        .expect("failed to find test file descriptor");
+    assert_eq!(entry.pos, 0);
+    assert!(entry.flags.contains(OFlag::O_CLOEXEC));
+    assert!(entry.target.ends_with("stat"));
+}
+
+/// An entry of `/proc/PID/cgroup`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcFsCgroup {
+    /// The hierarchy ID, which is always `0` for the cgroup v2 (unified) hierarchy.
+    pub hierarchy: u32,
+    /// The controllers bound to the hierarchy. Empty for the unified hierarchy.
+    pub controllers: Vec<String>,
+    /// The path of the cgroup relative to the hierarchy's mount point.
+    pub path: PathBuf,
+}
+
+impl ProcFsCgroup {
+    /// Check whether this is the entry for the cgroup v2 (unified) hierarchy.
+    pub fn is_unified(&self) -> bool {
+        self.hierarchy == 0 && self.controllers.is_empty()
+    }
+}
+
+/// Read the cgroup membership of a process from `/proc/PID/cgroup`.
+pub fn read_proc_pid_cgroup(pid: Pid) -> Result<Vec<ProcFsCgroup>, Error> {
+    let path = format!("/proc/{}/cgroup", pid);
+    let data = std::fs::read_to_string(&path)
+        .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+    parse_proc_pid_cgroup(&data)
+}
+
+fn parse_proc_pid_cgroup(data: &str) -> Result<Vec<ProcFsCgroup>, Error> {
+    let mut result = Vec::new();
+    for line in data.lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        // the path itself may contain colons
+        let mut parts = line.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(hierarchy), Some(controllers), Some(path)) => result.push(ProcFsCgroup {
+                hierarchy: hierarchy.parse()?,
+                controllers: controllers
+                    .split(',')
+                    .filter(|c| !c.is_empty())
+                    .map(String::from)
+                    .collect(),
+                path: PathBuf::from(path),
+            }),
+            _ => bail!("bad line in /proc/PID/cgroup: {:?}", line),
+        }
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_parse_proc_pid_cgroup() {
+    let cgroups = parse_proc_pid_cgroup(
+        "12:pids:/user.slice/user-1000.slice\n\
+         3:cpu,cpuacct:/user.slice\n\
+         1:name=systemd:/user.slice/user-1000.slice/session-2.scope\n\
+         0::/user.slice/user-1000.slice/session-2.scope\n",
+    )
+    .expect("successful parsing of a sample /proc/PID/cgroup file");
+    assert_eq!(cgroups.len(), 4);
+    assert_eq!(cgroups[1].hierarchy, 3);
+    assert_eq!(cgroups[1].controllers, vec!["cpu", "cpuacct"]);
+    assert!(!cgroups[1].is_unified());
+    assert_eq!(cgroups[3].hierarchy, 0);
+    assert!(cgroups[3].is_unified());
+    assert_eq!(
+        cgroups[3].path,
+        PathBuf::from("/user.slice/user-1000.slice/session-2.scope")
+    );
+
+    let cgroups = parse_proc_pid_cgroup("0::/lxc/100/ns:weird\n").expect("failed to parse cgroup");
+    assert_eq!(cgroups[0].path, PathBuf::from("/lxc/100/ns:weird"));
+}
+
+/// A process found in `/proc`, see `all_processes`.
+///
+/// Process information is only read when requested, so the entry is cheap to create. Note that
+/// the process may exit at any time, in which case the accessors return an error.
+#[derive(Clone, Debug)]
+pub struct ProcessEntry {
+    pid: Pid,
+}
+
+impl ProcessEntry {
+    /// The process id.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Read the command name from `/proc/PID/comm`.
+    pub fn comm(&self) -> Result<String, Error> {
+        let mut comm = file_read_firstline(format!("/proc/{}/comm", self.pid))?;
+        if comm.ends_with('\n') {
+            comm.pop();
+        }
+        Ok(comm)
+    }
+
+    /// Read `/proc/PID/stat`.
+    pub fn stat(&self) -> Result<PidStat, Error> {
+        PidStat::read_from_pid(self.pid)
+    }
+
+    /// Read `/proc/PID/status`.
+    pub fn status(&self) -> Result<PidStatus, Error> {
+        PidStatus::read_from_pid(self.pid)
+    }
+
+    /// Read `/proc/PID/cgroup`.
+    pub fn cgroups(&self) -> Result<Vec<ProcFsCgroup>, Error> {
+        read_proc_pid_cgroup(self.pid)
+    }
+}
+
+/// Iterator over all processes in `/proc`, see `all_processes`.
+pub struct AllProcesses {
+    dir: std::fs::ReadDir,
+}
+
+/// Iterate over all processes currently found in `/proc`.
+///
+/// ```no_run
+/// # use anyhow::Error;
+/// # use proxmox::sys::linux::procfs::all_processes;
+/// # fn code() -> Result<(), Error> {
+/// for process in all_processes()? {
+///     let process = process?;
+///     if process.comm().ok().as_deref() == Some("sshd") {
+///         println!("found sshd with pid {}", process.pid());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn all_processes() -> Result<AllProcesses, Error> {
+    let dir =
+        std::fs::read_dir("/proc").map_err(|err| format_err!("unable to read /proc - {}", err))?;
+    Ok(AllProcesses { dir })
+}
+
+impl Iterator for AllProcesses {
+    type Item = Result<ProcessEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.dir.next()? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            // skip everything which is not a pid directory
+            if let Some(Ok(pid)) = entry.file_name().to_str().map(str::parse::<libc::pid_t>) {
+                return Some(Ok(ProcessEntry {
+                    pid: Pid::from_raw(pid),
+                }));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_all_processes() {
+    let this = Pid::this();
+    let entry = all_processes()
+        .expect("failed to read /proc")
+        .filter_map(Result::ok)
+        .find(|entry| entry.pid() == this)
+        .expect("failed to find our own process in /proc");
+    assert_eq!(entry.stat().expect("failed to read our own stat").pid, this);
+    assert_eq!(
+        entry.status().expect("failed to read our own status").pid,
+        this
+    );
+    assert!(!entry
+        .comm()
+        .expect("failed to read our own comm")
+        .is_empty());
 }
 
 pub fn check_process_running(pid: libc::pid_t) -> Option<PidStat> {
@@ -185,6 +784,27 @@ pub fn read_proc_uptime_ticks() -> Result<(u64, u64), Error> {
     Ok((up as u64, idle as u64))
 }
 
+/// System uptime information from `/proc/uptime`.
+#[derive(Clone, Debug)]
+pub struct Uptime {
+    /// Seconds since boot.
+    pub uptime: f64,
+    /// Seconds the CPUs spent idle, summed up over all CPUs.
+    pub idle: f64,
+    /// The boot time as epoch, derived from the current time and `uptime`.
+    pub boot_time: i64,
+}
+
+/// Read the system uptime from `/proc/uptime`.
+pub fn read_uptime() -> Result<Uptime, Error> {
+    let (uptime, idle) = read_proc_uptime()?;
+    Ok(Uptime {
+        uptime,
+        idle,
+        boot_time: (crate::tools::time::epoch_f64() - uptime).round() as i64,
+    })
+}
+
 #[derive(Debug, Default)]
 /// The CPU fields from `/proc/stat` with their native time value. Multiply
 /// with CLOCK_TICKS to get the real value.
@@ -279,24 +899,28 @@ pub fn read_proc_stat() -> Result<ProcFsStat, Error> {
 }
 
 fn parse_proc_stat(statstr: &str) -> Result<ProcFsStat, Error> {
-    let mut cpu_count = 0u32;
+    let (mut data, cpus) = parse_proc_stat_cpus(statstr)?;
+    data.cpu_count = cpus.len() as u32;
+    Ok(data)
+}
+
+/// Parse the aggregated and the per-CPU lines of `/proc/stat`.
+fn parse_proc_stat_cpus(statstr: &str) -> Result<(ProcFsStat, Vec<ProcFsStat>), Error> {
+    let mut cpus = Vec::new();
     let mut data = None;
     for line in statstr.lines() {
         let mut parts = line.trim_start().split_ascii_whitespace();
         match parts.next() {
             None => continue,
             Some("cpu") => data = Some(parse_proc_stat_cpu_line(parts)?),
-            Some(key) if key.starts_with("cpu") => cpu_count += 1,
+            Some(key) if key.starts_with("cpu") => cpus.push(parse_proc_stat_cpu_line(parts)?),
             _ => (),
         }
     }
 
     match data {
         None => bail!("failed to find 'cpu' line in /proc/stat"),
-        Some(mut data) => {
-            data.cpu_count = cpu_count;
-            Ok(data)
-        }
+        Some(data) => Ok((data, cpus)),
     }
 }
 
@@ -408,59 +1032,324 @@ fn test_read_proc_stat() {
     assert_eq!(stat.iowait_percent, 0.0);
 }
 
-#[derive(Debug)]
+/// CPU utilization between two samples of `/proc/stat`.
+#[derive(Clone, Debug)]
+pub struct CpuUsage {
+    /// The utilization (0 - 1.0) of the whole system.
+    pub cpu: f64,
+    /// The utilization (0 - 1.0) of the individual CPUs in the order listed in `/proc/stat`.
+    pub cpus: Vec<f64>,
+    /// The percentage (0 - 1.0) of system wide iowait.
+    pub iowait_percent: f64,
+}
+
+/// Keeps the previous `/proc/stat` sample to compute the CPU utilization since the last call.
+///
+/// Contrary to `read_proc_stat` this does not share any global state, so multiple independent
+/// trackers with different sampling intervals can be used.
+#[derive(Default)]
+pub struct CpuUsageTracker {
+    last: Option<(ProcFsStat, Vec<ProcFsStat>)>,
+}
+
+impl CpuUsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `/proc/stat` and return the utilization since the previous call.
+    ///
+    /// The first call returns the average utilization since boot.
+    pub fn update(&mut self) -> Result<CpuUsage, Error> {
+        self.update_from_str(&std::fs::read_to_string("/proc/stat")?)
+    }
+
+    fn update_from_str(&mut self, statstr: &str) -> Result<CpuUsage, Error> {
+        let (total, cpus) = parse_proc_stat_cpus(statstr)?;
+
+        let usage = match &self.last {
+            None => CpuUsage {
+                cpu: Self::utilization(&total, None),
+                cpus: cpus
+                    .iter()
+                    .map(|cpu| Self::utilization(cpu, None))
+                    .collect(),
+                iowait_percent: Self::iowait(&total, None),
+            },
+            Some((last_total, last_cpus)) => CpuUsage {
+                cpu: Self::utilization(&total, Some(last_total)),
+                cpus: cpus
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cpu)| Self::utilization(cpu, last_cpus.get(i)))
+                    .collect(),
+                iowait_percent: Self::iowait(&total, Some(last_total)),
+            },
+        };
+
+        self.last = Some((total, cpus));
+        Ok(usage)
+    }
+
+    fn utilization(current: &ProcFsStat, last: Option<&ProcFsStat>) -> f64 {
+        let (total, idle) = match last {
+            Some(last) => (
+                current.total.saturating_sub(last.total),
+                current.idle.saturating_sub(last.idle),
+            ),
+            None => (current.total, current.idle),
+        };
+
+        if total == 0 {
+            0.0
+        } else {
+            1. - (idle as f64) / (total as f64)
+        }
+    }
+
+    fn iowait(current: &ProcFsStat, last: Option<&ProcFsStat>) -> f64 {
+        let (total, iowait) = match last {
+            Some(last) => (
+                current.total.saturating_sub(last.total),
+                current.iowait.saturating_sub(last.iowait),
+            ),
+            None => (current.total, current.iowait),
+        };
+
+        if total == 0 {
+            0.0
+        } else {
+            (iowait as f64) / (total as f64)
+        }
+    }
+}
+
+#[test]
+fn test_cpu_usage_tracker() {
+    let mut tracker = CpuUsageTracker::new();
+
+    let usage = tracker
+        .update_from_str(
+            "cpu  100 0 100 600 200 0 0 0 0 0\n\
+             cpu0 50 0 50 300 100 0 0 0 0 0\n\
+             cpu1 50 0 50 300 100 0 0 0 0 0\n\
+             ctxt 27543372\n",
+        )
+        .expect("successful parsing of a sample /proc/stat entry");
+    // since boot
+    assert_eq!(usage.cpu, 0.4);
+    assert_eq!(usage.cpus, vec![0.4, 0.4]);
+    assert_eq!(usage.iowait_percent, 0.2);
+
+    let usage = tracker
+        .update_from_str(
+            "cpu  200 0 200 700 200 0 0 0 0 0\n\
+             cpu0 150 0 150 300 100 0 0 0 0 0\n\
+             cpu1 50 0 50 400 100 0 0 0 0 0\n",
+        )
+        .expect("successful parsing of a sample /proc/stat entry");
+    assert_eq!(usage.cpu, 1. - 100. / 300.);
+    assert_eq!(usage.cpus, vec![1.0, 0.0]);
+    assert_eq!(usage.iowait_percent, 0.0);
+
+    // no time passed
+    let usage = tracker
+        .update_from_str(
+            "cpu  200 0 200 700 200 0 0 0 0 0\n\
+             cpu0 150 0 150 300 100 0 0 0 0 0\n\
+             cpu1 50 0 50 400 100 0 0 0 0 0\n",
+        )
+        .expect("successful parsing of a sample /proc/stat entry");
+    assert_eq!(usage.cpu, 0.0);
+    assert_eq!(usage.cpus, vec![0.0, 0.0]);
+}
+
+/// Memory statistics from `/proc/meminfo`. All values are in bytes.
+#[derive(Debug, Default)]
 pub struct ProcFsMemInfo {
     pub memtotal: u64,
+    /// Free memory including buffers and page cache.
     pub memfree: u64,
     pub memused: u64,
+    /// Memory shared via KSM.
     pub memshared: u64,
+    /// Estimate of memory available for new applications without swapping (`MemAvailable`).
+    pub memavailable: u64,
+    pub buffers: u64,
+    pub cached: u64,
     pub swaptotal: u64,
     pub swapfree: u64,
     pub swapused: u64,
+    /// Size of the huge page pool (`HugePages_Total * Hugepagesize`).
+    pub hugepages_total: u64,
+    /// Unallocated part of the huge page pool (`HugePages_Free * Hugepagesize`).
+    pub hugepages_free: u64,
+    /// Default huge page size.
+    pub hugepagesize: u64,
 }
 
+/// Read `/proc/meminfo` and the KSM `pages_sharing` counter.
 pub fn read_meminfo() -> Result<ProcFsMemInfo, Error> {
-    let path = "/proc/meminfo";
-    let file = OpenOptions::new().read(true).open(&path)?;
-
-    let mut meminfo = ProcFsMemInfo {
-        memtotal: 0,
-        memfree: 0,
-        memused: 0,
-        memshared: 0,
-        swaptotal: 0,
-        swapfree: 0,
-        swapused: 0,
-    };
+    let mut meminfo = parse_meminfo(&std::fs::read_to_string("/proc/meminfo")?)?;
 
-    let (mut buffers, mut cached) = (0, 0);
-    for line in BufReader::new(&file).lines() {
-        let content = line?;
-        let mut content_iter = content.split_whitespace();
+    let spages_line = file_read_firstline("/sys/kernel/mm/ksm/pages_sharing")?;
+    meminfo.memshared = spages_line.trim_end().parse::<u64>()? * 4096;
+
+    Ok(meminfo)
+}
+
+fn parse_meminfo(data: &str) -> Result<ProcFsMemInfo, Error> {
+    let mut meminfo = ProcFsMemInfo::default();
+
+    let (mut hugepages_total, mut hugepages_free) = (0, 0);
+    for line in data.lines() {
+        let mut content_iter = line.split_whitespace();
         if let (Some(key), Some(value)) = (content_iter.next(), content_iter.next()) {
+            let value = value
+                .parse::<u64>()
+                .map_err(|err| format_err!("bad value for {:?} in /proc/meminfo - {}", key, err))?;
+            // all values except the huge page counters are in kB
             match key {
-                "MemTotal:" => meminfo.memtotal = value.parse::<u64>()? * 1024,
-                "MemFree:" => meminfo.memfree = value.parse::<u64>()? * 1024,
-                "SwapTotal:" => meminfo.swaptotal = value.parse::<u64>()? * 1024,
-                "SwapFree:" => meminfo.swapfree = value.parse::<u64>()? * 1024,
-                "Buffers:" => buffers = value.parse::<u64>()? * 1024,
-                "Cached:" => cached = value.parse::<u64>()? * 1024,
+                "MemTotal:" => meminfo.memtotal = value * 1024,
+                "MemFree:" => meminfo.memfree = value * 1024,
+                "MemAvailable:" => meminfo.memavailable = value * 1024,
+                "Buffers:" => meminfo.buffers = value * 1024,
+                "Cached:" => meminfo.cached = value * 1024,
+                "SwapTotal:" => meminfo.swaptotal = value * 1024,
+                "SwapFree:" => meminfo.swapfree = value * 1024,
+                "HugePages_Total:" => hugepages_total = value,
+                "HugePages_Free:" => hugepages_free = value,
+                "Hugepagesize:" => meminfo.hugepagesize = value * 1024,
                 _ => continue,
             }
         }
     }
 
-    meminfo.memfree += buffers + cached;
-    meminfo.memused = meminfo.memtotal - meminfo.memfree;
+    meminfo.memfree += meminfo.buffers + meminfo.cached;
+    meminfo.memused = meminfo.memtotal.saturating_sub(meminfo.memfree);
 
-    meminfo.swapused = meminfo.swaptotal - meminfo.swapfree;
+    meminfo.swapused = meminfo.swaptotal.saturating_sub(meminfo.swapfree);
 
-    let spages_line = file_read_firstline("/sys/kernel/mm/ksm/pages_sharing")?;
-    meminfo.memshared = spages_line.trim_end().parse::<u64>()? * 4096;
+    meminfo.hugepages_total = hugepages_total * meminfo.hugepagesize;
+    meminfo.hugepages_free = hugepages_free * meminfo.hugepagesize;
 
     Ok(meminfo)
 }
 
+#[test]
+fn test_parse_meminfo() {
+    let meminfo = parse_meminfo(
+        "MemTotal:       32912860 kB\n\
+         MemFree:         1440920 kB\n\
+         MemAvailable:   20374520 kB\n\
+         Buffers:         1204572 kB\n\
+         Cached:         17018248 kB\n\
+         SwapCached:          120 kB\n\
+         Active:         13418940 kB\n\
+         SwapTotal:       8388604 kB\n\
+         SwapFree:        8383484 kB\n\
+         HugePages_Total:       4\n\
+         HugePages_Free:        1\n\
+         HugePages_Rsvd:        0\n\
+         HugePages_Surp:        0\n\
+         Hugepagesize:       2048 kB\n\
+         Hugetlb:            8192 kB\n",
+    )
+    .expect("successful parsing of a sample /proc/meminfo file");
+    assert_eq!(meminfo.memtotal, 32912860 * 1024);
+    assert_eq!(meminfo.memavailable, 20374520 * 1024);
+    assert_eq!(meminfo.buffers, 1204572 * 1024);
+    assert_eq!(meminfo.cached, 17018248 * 1024);
+    assert_eq!(meminfo.memfree, (1440920 + 1204572 + 17018248) * 1024);
+    assert_eq!(meminfo.memused, meminfo.memtotal - meminfo.memfree);
+    assert_eq!(meminfo.swaptotal, 8388604 * 1024);
+    assert_eq!(meminfo.swapfree, 8383484 * 1024);
+    assert_eq!(meminfo.swapused, (8388604 - 8383484) * 1024);
+    assert_eq!(meminfo.hugepagesize, 2048 * 1024);
+    assert_eq!(meminfo.hugepages_total, 4 * 2048 * 1024);
+    assert_eq!(meminfo.hugepages_free, 2048 * 1024);
+}
+
+/// Selected counters from `/proc/vmstat`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcFsVmStat {
+    /// Kilobytes paged in from disk since boot.
+    pub pgpgin: u64,
+    /// Kilobytes paged out to disk since boot.
+    pub pgpgout: u64,
+    /// Pages swapped in since boot.
+    pub pswpin: u64,
+    /// Pages swapped out since boot.
+    pub pswpout: u64,
+    /// Page faults since boot.
+    pub pgfault: u64,
+    /// Major page faults (which required loading from disk) since boot.
+    pub pgmajfault: u64,
+}
+
+/// Read selected counters from `/proc/vmstat`.
+pub fn read_proc_vmstat() -> Result<ProcFsVmStat, Error> {
+    parse_proc_vmstat(&std::fs::read_to_string("/proc/vmstat")?)
+}
+
+fn parse_proc_vmstat(data: &str) -> Result<ProcFsVmStat, Error> {
+    let mut vmstat = ProcFsVmStat::default();
+    for line in data.lines() {
+        let mut parts = line.split_ascii_whitespace();
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            let field = match key {
+                "pgpgin" => &mut vmstat.pgpgin,
+                "pgpgout" => &mut vmstat.pgpgout,
+                "pswpin" => &mut vmstat.pswpin,
+                "pswpout" => &mut vmstat.pswpout,
+                "pgfault" => &mut vmstat.pgfault,
+                "pgmajfault" => &mut vmstat.pgmajfault,
+                _ => continue,
+            };
+            *field = value
+                .parse()
+                .map_err(|err| format_err!("bad value for {:?} in /proc/vmstat - {}", key, err))?;
+        }
+    }
+    Ok(vmstat)
+}
+
+#[test]
+fn test_parse_proc_vmstat() {
+    let vmstat = parse_proc_vmstat(
+        "nr_free_pages 2150523\n\
+         pgpgin 32547372\n\
+         pgpgout 87354248\n\
+         pswpin 12\n\
+         pswpout 345\n\
+         pgalloc_dma 0\n\
+         pgfault 1234567890\n\
+         pgmajfault 54321\n",
+    )
+    .expect("successful parsing of a sample /proc/vmstat file");
+    assert_eq!(vmstat.pgpgin, 32547372);
+    assert_eq!(vmstat.pgpgout, 87354248);
+    assert_eq!(vmstat.pswpin, 12);
+    assert_eq!(vmstat.pswpout, 345);
+    assert_eq!(vmstat.pgfault, 1234567890);
+    assert_eq!(vmstat.pgmajfault, 54321);
+}
+
+/// A single logical CPU entry of `/proc/cpuinfo`.
+#[derive(Clone, Debug, Default)]
+pub struct ProcFsCPUProcessor {
+    /// The logical CPU number.
+    pub processor: usize,
+    pub model: String,
+    pub mhz: f64,
+    /// The socket this CPU belongs to, if known.
+    pub physical_id: Option<usize>,
+    /// The core (within the socket) this CPU belongs to, if known.
+    pub core_id: Option<usize>,
+    pub flags: Vec<String>,
+}
+
+/// Summary of `/proc/cpuinfo`.
 #[derive(Clone, Debug)]
 pub struct ProcFsCPUInfo {
     pub user_hz: f64,
@@ -468,53 +1357,126 @@ pub struct ProcFsCPUInfo {
     pub model: String,
     pub hvm: bool,
     pub sockets: usize,
+    /// The number of physical cores.
+    pub cores: usize,
+    /// The number of logical CPUs (threads).
     pub cpus: usize,
+    /// The individual logical CPUs.
+    pub processors: Vec<ProcFsCPUProcessor>,
 }
 
-static CPU_INFO: Option<ProcFsCPUInfo> = None;
-
+/// Read and summarize `/proc/cpuinfo`.
 pub fn read_cpuinfo() -> Result<ProcFsCPUInfo, Error> {
-    if let Some(cpu_info) = &CPU_INFO {
-        return Ok(cpu_info.clone());
-    }
+    parse_cpuinfo(&std::fs::read_to_string("/proc/cpuinfo")?)
+}
 
-    let path = "/proc/cpuinfo";
-    let file = OpenOptions::new().read(true).open(&path)?;
+fn parse_cpuinfo(data: &str) -> Result<ProcFsCPUInfo, Error> {
+    let mut processors = Vec::new();
+    let mut current: Option<ProcFsCPUProcessor> = None;
 
-    let mut cpuinfo = ProcFsCPUInfo {
-        user_hz: *CLOCK_TICKS,
-        mhz: 0.0,
-        model: String::new(),
-        hvm: false,
-        sockets: 0,
-        cpus: 0,
-    };
+    for line in data.lines() {
+        if line.trim().is_empty() {
+            processors.extend(current.take());
+            continue;
+        }
 
-    let mut socket_ids = HashSet::new();
-    for line in BufReader::new(&file).lines() {
-        let content = line?;
-        if content.is_empty() {
+        let mut content_iter = line.splitn(2, ':');
+        let (key, value) = match (content_iter.next(), content_iter.next()) {
+            (Some(key), Some(value)) => (key.trim_end(), value.trim()),
+            _ => bail!("Error while parsing '/proc/cpuinfo'"),
+        };
+
+        if key == "processor" {
+            processors.extend(current.take());
+            current = Some(ProcFsCPUProcessor {
+                processor: value.parse()?,
+                ..Default::default()
+            });
             continue;
         }
-        let mut content_iter = content.split(':');
-        match (content_iter.next(), content_iter.next()) {
-            (Some(key), Some(value)) => match key.trim_end() {
-                "processor" => cpuinfo.cpus += 1,
-                "model name" => cpuinfo.model = value.trim().to_string(),
-                "cpu MHz" => cpuinfo.mhz = value.trim().parse::<f64>()?,
-                "flags" => cpuinfo.hvm = value.contains(" vmx ") || value.contains(" svm "),
-                "physical id" => {
-                    let id = value.trim().parse::<u8>()?;
-                    socket_ids.insert(id);
-                }
-                _ => continue,
-            },
-            _ => bail!("Error while parsing '{}'", path),
+
+        // some architectures have global entries outside of the processor blocks
+        let cpu = match current.as_mut() {
+            Some(cpu) => cpu,
+            None => continue,
+        };
+
+        match key {
+            "model name" => cpu.model = value.to_string(),
+            "cpu MHz" => cpu.mhz = value.parse::<f64>()?,
+            "physical id" => cpu.physical_id = Some(value.parse()?),
+            "core id" => cpu.core_id = Some(value.parse()?),
+            "flags" => cpu.flags = value.split_ascii_whitespace().map(String::from).collect(),
+            _ => continue,
         }
     }
-    cpuinfo.sockets = socket_ids.len();
+    processors.extend(current.take());
 
-    Ok(cpuinfo)
+    let socket_ids: HashSet<usize> = processors.iter().filter_map(|p| p.physical_id).collect();
+    let core_ids: HashSet<(Option<usize>, usize)> = processors
+        .iter()
+        .filter_map(|p| p.core_id.map(|core| (p.physical_id, core)))
+        .collect();
+
+    let first = processors.first();
+    Ok(ProcFsCPUInfo {
+        user_hz: *CLOCK_TICKS,
+        mhz: processors.last().map(|p| p.mhz).unwrap_or(0.0),
+        model: first.map(|p| p.model.clone()).unwrap_or_default(),
+        hvm: first
+            .map(|p| p.flags.iter().any(|f| f == "vmx" || f == "svm"))
+            .unwrap_or(false),
+        sockets: socket_ids.len(),
+        cores: if core_ids.is_empty() {
+            processors.len()
+        } else {
+            core_ids.len()
+        },
+        cpus: processors.len(),
+        processors,
+    })
+}
+
+#[test]
+fn test_parse_cpuinfo() {
+    let mut data = String::new();
+    for (processor, physical_id, core_id) in &[(0, 0, 0), (1, 0, 1), (2, 0, 0), (3, 0, 1)] {
+        data.push_str(&format!(
+            "processor\t: {}\n\
+             vendor_id\t: GenuineIntel\n\
+             model name\t: Intel(R) Xeon(R) CPU E3-1230 v5 @ 3.40GHz\n\
+             cpu MHz\t\t: {}.000\n\
+             physical id\t: {}\n\
+             core id\t\t: {}\n\
+             flags\t\t: fpu vme de pse tsc msr vmx ssse3\n\n",
+            processor,
+            3400 + processor,
+            physical_id,
+            core_id,
+        ));
+    }
+
+    let info = parse_cpuinfo(&data).expect("successful parsing of a sample /proc/cpuinfo file");
+    assert_eq!(info.model, "Intel(R) Xeon(R) CPU E3-1230 v5 @ 3.40GHz");
+    assert_eq!(info.sockets, 1);
+    assert_eq!(info.cores, 2);
+    assert_eq!(info.cpus, 4);
+    assert!(info.hvm);
+    assert_eq!(info.mhz, 3403.0);
+    assert_eq!(info.processors.len(), 4);
+    assert_eq!(info.processors[2].processor, 2);
+    assert_eq!(info.processors[2].mhz, 3402.0);
+    assert_eq!(info.processors[2].physical_id, Some(0));
+    assert_eq!(info.processors[3].core_id, Some(1));
+    assert_eq!(info.processors[1].flags.len(), 7);
+
+    // no topology information (eg. some virtual machines)
+    let info = parse_cpuinfo("processor\t: 0\nmodel name\t: QEMU Virtual CPU\n")
+        .expect("successful parsing of a minimal /proc/cpuinfo file");
+    assert_eq!(info.sockets, 0);
+    assert_eq!(info.cores, 1);
+    assert_eq!(info.cpus, 1);
+    assert!(!info.hvm);
 }
 
 #[derive(Debug)]
@@ -540,36 +1502,345 @@ pub fn read_memory_usage() -> Result<ProcFsMemUsage, Error> {
     }
 }
 
-#[derive(Debug)]
+/// Interface counters from `/proc/net/dev`.
+#[derive(Debug, Default)]
 pub struct ProcFsNetDev {
     pub device: String,
+    /// Received bytes.
     pub receive: u64,
+    pub receive_packets: u64,
+    pub receive_errors: u64,
+    pub receive_drop: u64,
+    /// Transmitted bytes.
     pub send: u64,
+    pub send_packets: u64,
+    pub send_errors: u64,
+    pub send_drop: u64,
 }
 
-pub fn read_proc_net_dev() -> Result<Vec<ProcFsNetDev>, Error> {
-    let path = "/proc/net/dev";
-    let file = OpenOptions::new().read(true).open(&path)?;
+/// Interface counters from `/proc/net/dev` by device name.
+pub type NetDevStats = BTreeMap<String, ProcFsNetDev>;
 
-    let mut result = Vec::new();
-    for line in BufReader::new(&file).lines().skip(2) {
-        let content = line?;
-        let mut iter = content.split_whitespace();
-        match (iter.next(), iter.next(), iter.nth(7)) {
-            (Some(device), Some(receive), Some(send)) => {
-                result.push(ProcFsNetDev {
-                    device: device[..device.len() - 1].to_string(),
-                    receive: receive.parse::<u64>()?,
-                    send: send.parse::<u64>()?,
-                });
-            }
-            _ => bail!("Error while parsing '{}'", path),
+/// Read the per-interface counters from `/proc/net/dev`.
+pub fn read_proc_net_dev() -> Result<NetDevStats, Error> {
+    parse_proc_net_dev(&std::fs::read_to_string("/proc/net/dev")?)
+}
+
+fn parse_proc_net_dev(data: &str) -> Result<NetDevStats, Error> {
+    let mut result = NetDevStats::new();
+    // the first two lines are the table header
+    for line in data.lines().skip(2) {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // large counters may not be separated from the device name by whitespace
+        let colon = line
+            .find(':')
+            .ok_or_else(|| format_err!("missing ':' in /proc/net/dev line"))?;
+        let device = line[..colon].trim();
+
+        let mut values = line[(colon + 1)..].split_ascii_whitespace();
+        let mut next = || -> Result<u64, Error> {
+            values
+                .next()
+                .ok_or_else(|| format_err!("missing field for {:?} in /proc/net/dev", device))?
+                .parse::<u64>()
+                .map_err(|err| format_err!("bad value for {:?} in /proc/net/dev - {}", device, err))
+        };
+
+        let mut stats = ProcFsNetDev {
+            device: device.to_string(),
+            ..Default::default()
+        };
+        stats.receive = next()?;
+        stats.receive_packets = next()?;
+        stats.receive_errors = next()?;
+        stats.receive_drop = next()?;
+        for _ in 0..4 {
+            next()?; // fifo, frame, compressed, multicast
         }
+        stats.send = next()?;
+        stats.send_packets = next()?;
+        stats.send_errors = next()?;
+        stats.send_drop = next()?;
+
+        result.insert(device.to_string(), stats);
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_parse_proc_net_dev() {
+    let stats = parse_proc_net_dev(
+        "Inter-|   Receive                                                |  Transmit\n \
+         face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    \
+         lo: 3716853   35620    0    0    0     0          0         0  3716853   35620    0    0    0     0       0          0\n  \
+         eth0:12345678901 4556301 2 17 0 0 0 1234 987654321 3021330 1 5 0 0 0 0\n",
+    )
+    .expect("successful parsing of a sample /proc/net/dev file");
+
+    assert_eq!(stats.len(), 2);
+
+    let lo = &stats["lo"];
+    assert_eq!(lo.device, "lo");
+    assert_eq!(lo.receive, 3716853);
+    assert_eq!(lo.receive_packets, 35620);
+    assert_eq!(lo.send, 3716853);
+    assert_eq!(lo.send_packets, 35620);
+
+    let eth0 = &stats["eth0"];
+    assert_eq!(eth0.receive, 12345678901);
+    assert_eq!(eth0.receive_packets, 4556301);
+    assert_eq!(eth0.receive_errors, 2);
+    assert_eq!(eth0.receive_drop, 17);
+    assert_eq!(eth0.send, 987654321);
+    assert_eq!(eth0.send_packets, 3021330);
+    assert_eq!(eth0.send_errors, 1);
+    assert_eq!(eth0.send_drop, 5);
+}
+
+/// Block device counters from `/proc/diskstats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcFsDiskStat {
+    pub major: u32,
+    pub minor: u32,
+    pub device: String,
+    /// Number of completed read requests.
+    pub read_ios: u64,
+    /// Number of adjacent read requests merged into a single one.
+    pub read_merges: u64,
+    /// Number of 512 byte sectors read.
+    pub read_sectors: u64,
+    /// Time spent reading, in milliseconds.
+    pub read_ticks: u64,
+    /// Number of completed write requests.
+    pub write_ios: u64,
+    /// Number of adjacent write requests merged into a single one.
+    pub write_merges: u64,
+    /// Number of 512 byte sectors written.
+    pub write_sectors: u64,
+    /// Time spent writing, in milliseconds.
+    pub write_ticks: u64,
+    /// Number of requests currently in flight. This is not a counter.
+    pub in_flight: u64,
+    /// Time the device had I/O requests queued, in milliseconds.
+    pub io_ticks: u64,
+    /// Weighted time requests spent in the queue, in milliseconds.
+    pub time_in_queue: u64,
+}
+
+impl ProcFsDiskStat {
+    /// Compute the difference to an older sample of the same device.
+    ///
+    /// Counters which went backwards (eg. due to a wrap-around or because the device was
+    /// replaced) result in `0`. The `in_flight` value is taken from `self`.
+    pub fn delta(&self, older: &Self) -> Self {
+        Self {
+            major: self.major,
+            minor: self.minor,
+            device: self.device.clone(),
+            read_ios: self.read_ios.saturating_sub(older.read_ios),
+            read_merges: self.read_merges.saturating_sub(older.read_merges),
+            read_sectors: self.read_sectors.saturating_sub(older.read_sectors),
+            read_ticks: self.read_ticks.saturating_sub(older.read_ticks),
+            write_ios: self.write_ios.saturating_sub(older.write_ios),
+            write_merges: self.write_merges.saturating_sub(older.write_merges),
+            write_sectors: self.write_sectors.saturating_sub(older.write_sectors),
+            write_ticks: self.write_ticks.saturating_sub(older.write_ticks),
+            in_flight: self.in_flight,
+            io_ticks: self.io_ticks.saturating_sub(older.io_ticks),
+            time_in_queue: self.time_in_queue.saturating_sub(older.time_in_queue),
+        }
+    }
+}
+
+/// Block device counters from `/proc/diskstats` by device name.
+pub type DiskStats = BTreeMap<String, ProcFsDiskStat>;
+
+/// Read the per-device counters from `/proc/diskstats`.
+pub fn read_proc_diskstats() -> Result<DiskStats, Error> {
+    parse_proc_diskstats(&std::fs::read_to_string("/proc/diskstats")?)
+}
+
+/// Compute the per-device deltas between two `/proc/diskstats` samples.
+///
+/// Devices which are not present in both samples are skipped.
+pub fn diskstats_delta(older: &DiskStats, newer: &DiskStats) -> DiskStats {
+    newer
+        .iter()
+        .filter_map(|(device, stat)| {
+            older
+                .get(device)
+                .map(|old| (device.clone(), stat.delta(old)))
+        })
+        .collect()
+}
+
+fn parse_proc_diskstats(data: &str) -> Result<DiskStats, Error> {
+    let mut result = DiskStats::new();
+    for line in data.lines() {
+        let mut parts = line.split_ascii_whitespace();
+        let major = match parts.next() {
+            Some(major) => major,
+            None => continue,
+        };
+
+        let mut next = || {
+            parts
+                .next()
+                .ok_or_else(|| format_err!("missing field in /proc/diskstats line"))
+        };
+        let minor = next()?;
+        let device = next()?;
+
+        let mut num = || -> Result<u64, Error> {
+            next()?.parse::<u64>().map_err(|err| {
+                format_err!("bad value for {:?} in /proc/diskstats - {}", device, err)
+            })
+        };
+
+        // newer kernels append discard and flush counters, which we ignore for now
+        let stat = ProcFsDiskStat {
+            major: major.parse()?,
+            minor: minor.parse()?,
+            device: device.to_string(),
+            read_ios: num()?,
+            read_merges: num()?,
+            read_sectors: num()?,
+            read_ticks: num()?,
+            write_ios: num()?,
+            write_merges: num()?,
+            write_sectors: num()?,
+            write_ticks: num()?,
+            in_flight: num()?,
+            io_ticks: num()?,
+            time_in_queue: num()?,
+        };
+
+        result.insert(stat.device.clone(), stat);
     }
 
     Ok(result)
 }
 
+#[test]
+fn test_parse_proc_diskstats() {
+    let older = parse_proc_diskstats(
+        "   8       0 sda 48613 12160 3585770 36409 89734 66281 5226688 149838 0 82052 192144 0 0 0 0\n   \
+            8       1 sda1 40 0 2226 8 0 0 0 0 0 28 8 0 0 0 0\n 253       0 dm-0 100 0 800 20 50 0 400 10 1 40 30\n",
+    )
+    .expect("successful parsing of a sample /proc/diskstats file");
+
+    assert_eq!(older.len(), 3);
+    let sda = &older["sda"];
+    assert_eq!((sda.major, sda.minor), (8, 0));
+    assert_eq!(sda.read_ios, 48613);
+    assert_eq!(sda.read_merges, 12160);
+    assert_eq!(sda.read_sectors, 3585770);
+    assert_eq!(sda.read_ticks, 36409);
+    assert_eq!(sda.write_ios, 89734);
+    assert_eq!(sda.write_merges, 66281);
+    assert_eq!(sda.write_sectors, 5226688);
+    assert_eq!(sda.write_ticks, 149838);
+    assert_eq!(sda.in_flight, 0);
+    assert_eq!(sda.io_ticks, 82052);
+    assert_eq!(sda.time_in_queue, 192144);
+    assert_eq!(older["dm-0"].in_flight, 1);
+
+    let newer = parse_proc_diskstats(
+        "   8       0 sda 48713 12160 3586570 36500 89834 66300 5228688 150000 2 82152 192400 0 0 0 0\n",
+    )
+    .expect("successful parsing of a sample /proc/diskstats file");
+
+    let delta = diskstats_delta(&older, &newer);
+    assert_eq!(delta.len(), 1);
+    let sda = &delta["sda"];
+    assert_eq!(sda.read_ios, 100);
+    assert_eq!(sda.read_sectors, 800);
+    assert_eq!(sda.write_ios, 100);
+    assert_eq!(sda.write_merges, 19);
+    assert_eq!(sda.write_sectors, 2000);
+    assert_eq!(sda.in_flight, 2);
+    assert_eq!(sda.io_ticks, 100);
+    assert_eq!(sda.time_in_queue, 256);
+}
+
+/// An active swap area from `/proc/swaps`.
+#[derive(Clone, Debug)]
+pub struct ProcFsSwap {
+    /// The swap device or file.
+    pub filename: PathBuf,
+    /// The type of the swap area, usually `partition` or `file`.
+    pub swap_type: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Used space in bytes.
+    pub used: u64,
+    pub priority: i32,
+}
+
+/// Read the list of active swap areas from `/proc/swaps`.
+pub fn read_proc_swaps() -> Result<Vec<ProcFsSwap>, Error> {
+    parse_proc_swaps(&std::fs::read("/proc/swaps")?)
+}
+
+fn parse_proc_swaps(data: &[u8]) -> Result<Vec<ProcFsSwap>, Error> {
+    fn field(part: Option<&[u8]>) -> Result<&str, Error> {
+        let part = part.ok_or_else(|| format_err!("missing field in /proc/swaps"))?;
+        Ok(std::str::from_utf8(part)?)
+    }
+
+    let mut result = Vec::new();
+    // skip the table header
+    for line in data.split(|b| *b == b'\n').skip(1) {
+        let mut parts = line
+            .split(u8::is_ascii_whitespace)
+            .filter(|part| !part.is_empty());
+
+        let filename = match parts.next() {
+            Some(filename) => filename,
+            None => continue,
+        };
+
+        let mut next = || field(parts.next());
+
+        result.push(ProcFsSwap {
+            filename: OsString::from_vec(mountinfo::unescape(filename)).into(),
+            swap_type: next()?.to_string(),
+            size: next()?.parse::<u64>()? * 1024,
+            used: next()?.parse::<u64>()? * 1024,
+            priority: next()?.parse()?,
+        });
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_parse_proc_swaps() {
+    let swaps = parse_proc_swaps(
+        b"Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n\
+          /dev/sda2                               partition\t8388604\t\t5120\t\t-2\n\
+          /var/swap\\040file                        file\t\t1048572\t\t0\t\t10\n",
+    )
+    .expect("successful parsing of a sample /proc/swaps file");
+    assert_eq!(swaps.len(), 2);
+    assert_eq!(swaps[0].filename, PathBuf::from("/dev/sda2"));
+    assert_eq!(swaps[0].swap_type, "partition");
+    assert_eq!(swaps[0].size, 8388604 * 1024);
+    assert_eq!(swaps[0].used, 5120 * 1024);
+    assert_eq!(swaps[0].priority, -2);
+    assert_eq!(swaps[1].filename, PathBuf::from("/var/swap file"));
+    assert_eq!(swaps[1].swap_type, "file");
+    assert_eq!(swaps[1].priority, 10);
+
+    let swaps = parse_proc_swaps(b"Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n")
+        .expect("successful parsing of an empty /proc/swaps file");
+    assert!(swaps.is_empty());
+}
+
 fn hexstr_to_ipv4addr<T: AsRef<[u8]>>(hex: T) -> Result<Ipv4Addr, Error> {
     let hex = hex.as_ref();
     if hex.len() != 8 {
@@ -577,14 +1848,19 @@ fn hexstr_to_ipv4addr<T: AsRef<[u8]>>(hex: T) -> Result<Ipv4Addr, Error> {
     }
 
     let mut addr = [0u8; 4];
-    for i in 0..4 {
-        addr[3 - i] = (hex_nibble(hex[i * 2])? << 4) + hex_nibble(hex[i * 2 + 1])?;
-    }
+    hex::decode_to_slice(hex, &mut addr)?;
+    addr.reverse();
 
     Ok(Ipv4Addr::from(addr))
 }
 
-#[derive(Debug)]
+/// Route flag: the route is usable.
+pub const RTF_UP: u32 = 0x0001;
+/// Route flag: the destination is reached via a gateway.
+pub const RTF_GATEWAY: u32 = 0x0002;
+
+/// An IPv4 route from `/proc/net/route`.
+#[derive(Clone, Debug)]
 pub struct ProcFsNetRoute {
     pub dest: Ipv4Addr,
     pub gateway: Ipv4Addr,
@@ -592,27 +1868,28 @@ pub struct ProcFsNetRoute {
     pub metric: u32,
     pub mtu: u32,
     pub iface: String,
+    /// The `RTF_*` route flags.
+    pub flags: u32,
 }
 
 pub fn read_proc_net_route() -> Result<Vec<ProcFsNetRoute>, Error> {
     let path = "/proc/net/route";
-    let file = OpenOptions::new().read(true).open(&path)?;
+    parse_proc_net_route(&std::fs::read_to_string(path)?)
+        .map_err(|err| format_err!("Error while parsing '{}' - {}", path, err))
+}
 
+fn parse_proc_net_route(data: &str) -> Result<Vec<ProcFsNetRoute>, Error> {
     let mut result = Vec::new();
-    for line in BufReader::new(&file).lines().skip(1) {
-        let content = line?;
+    for content in data.lines().skip(1) {
         if content.is_empty() {
             continue;
         }
         let mut iter = content.split_whitespace();
 
-        let mut next = || {
-            iter.next()
-                .ok_or_else(|| format_err!("Error while parsing '{}'", path))
-        };
+        let mut next = || iter.next().ok_or_else(|| format_err!("missing field"));
 
-        let (iface, dest, gateway) = (next()?, next()?, next()?);
-        for _ in 0..3 {
+        let (iface, dest, gateway, flags) = (next()?, next()?, next()?, next()?);
+        for _ in 0..2 {
             next()?;
         }
         let (metric, mask, mtu) = (next()?, next()?, next()?);
@@ -624,26 +1901,59 @@ pub fn read_proc_net_route() -> Result<Vec<ProcFsNetRoute>, Error> {
             metric: metric.parse()?,
             mtu: mtu.parse()?,
             iface: iface.to_string(),
+            flags: u32::from_str_radix(flags, 16)?,
         });
     }
 
     Ok(result)
 }
 
+/// Get the IPv4 default route with the lowest metric.
+pub fn default_gateway() -> Result<Option<ProcFsNetRoute>, Error> {
+    Ok(find_default_gateway(read_proc_net_route()?))
+}
+
+fn find_default_gateway(routes: Vec<ProcFsNetRoute>) -> Option<ProcFsNetRoute> {
+    routes
+        .into_iter()
+        .filter(|route| {
+            route.dest.is_unspecified()
+                && route.mask.is_unspecified()
+                && route.flags & (RTF_UP | RTF_GATEWAY) == (RTF_UP | RTF_GATEWAY)
+        })
+        .min_by_key(|route| route.metric)
+}
+
+#[test]
+fn test_parse_proc_net_route() {
+    let routes = parse_proc_net_route(
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+         eth0\t00000000\t0102A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+         wlan0\t00000000\t0100000A\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+         eth0\t0002A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t1500\t0\t0\n",
+    )
+    .expect("successful parsing of a sample /proc/net/route file");
+    assert_eq!(routes.len(), 3);
+    assert_eq!(routes[2].dest, Ipv4Addr::new(192, 168, 2, 0));
+    assert_eq!(routes[2].mask, Ipv4Addr::new(255, 255, 255, 0));
+    assert_eq!(routes[2].gateway, Ipv4Addr::UNSPECIFIED);
+    assert_eq!(routes[2].flags, RTF_UP);
+    assert_eq!(routes[2].mtu, 1500);
+
+    let gw = find_default_gateway(routes).expect("failed to find default gateway");
+    assert_eq!(gw.iface, "eth0");
+    assert_eq!(gw.gateway, Ipv4Addr::new(192, 168, 2, 1));
+    assert_eq!(gw.metric, 100);
+}
+
 fn hexstr_to_ipv6addr<T: AsRef<[u8]>>(hex: T) -> Result<Ipv6Addr, Error> {
     let hex = hex.as_ref();
     if hex.len() != 32 {
         bail!("Error while converting hex string to IPv6 address: unexpected string length");
     }
 
-    let mut addr = std::mem::MaybeUninit::<[u8; 16]>::uninit();
-    let addr = unsafe {
-        let ap = &mut *addr.as_mut_ptr();
-        for i in 0..16 {
-            ap[i] = (hex_nibble(hex[i * 2])? << 4) + hex_nibble(hex[i * 2 + 1])?;
-        }
-        addr.assume_init()
-    };
+    let mut addr = [0u8; 16];
+    hex::decode_to_slice(hex, &mut addr)?;
 
     Ok(Ipv6Addr::from(addr))
 }
@@ -654,7 +1964,10 @@ fn hexstr_to_u8<T: AsRef<[u8]>>(hex: T) -> Result<u8, Error> {
         bail!("Error while converting hex string to u8: unexpected string length");
     }
 
-    Ok((hex_nibble(hex[0])? << 4) + hex_nibble(hex[1])?)
+    let mut byte = [0u8; 1];
+    hex::decode_to_slice(hex, &mut byte)?;
+
+    Ok(byte[0])
 }
 
 fn hexstr_to_u32<T: AsRef<[u8]>>(hex: T) -> Result<u32, Error> {
@@ -664,48 +1977,48 @@ fn hexstr_to_u32<T: AsRef<[u8]>>(hex: T) -> Result<u32, Error> {
     }
 
     let mut bytes = [0u8; 4];
-    for i in 0..4 {
-        bytes[i] = (hex_nibble(hex[i * 2])? << 4) + hex_nibble(hex[i * 2 + 1])?;
-    }
+    hex::decode_to_slice(hex, &mut bytes)?;
 
     Ok(u32::from_be_bytes(bytes))
 }
 
-#[derive(Debug)]
+/// An IPv6 route from `/proc/net/ipv6_route`.
+#[derive(Clone, Debug)]
 pub struct ProcFsNetIPv6Route {
     pub dest: Ipv6Addr,
     pub prefix: u8,
     pub gateway: Ipv6Addr,
     pub metric: u32,
     pub iface: String,
+    /// The `RTF_*` route flags.
+    pub flags: u32,
 }
 
 pub fn read_proc_net_ipv6_route() -> Result<Vec<ProcFsNetIPv6Route>, Error> {
     let path = "/proc/net/ipv6_route";
-    let file = OpenOptions::new().read(true).open(&path)?;
+    parse_proc_net_ipv6_route(&std::fs::read_to_string(path)?)
+        .map_err(|err| format_err!("Error while parsing '{}' - {}", path, err))
+}
 
+fn parse_proc_net_ipv6_route(data: &str) -> Result<Vec<ProcFsNetIPv6Route>, Error> {
     let mut result = Vec::new();
-    for line in BufReader::new(&file).lines() {
-        let content = line?;
+    for content in data.lines() {
         if content.is_empty() {
             continue;
         }
         let mut iter = content.split_whitespace();
 
-        let mut next = || {
-            iter.next()
-                .ok_or_else(|| format_err!("Error while parsing '{}'", path))
-        };
+        let mut next = || iter.next().ok_or_else(|| format_err!("missing field"));
 
         let (dest, prefix) = (next()?, next()?);
         for _ in 0..2 {
             next()?;
         }
         let (nexthop, metric) = (next()?, next()?);
-        for _ in 0..3 {
+        for _ in 0..2 {
             next()?;
         }
-        let iface = next()?;
+        let (flags, iface) = (next()?, next()?);
 
         result.push(ProcFsNetIPv6Route {
             dest: hexstr_to_ipv6addr(dest)?,
@@ -713,12 +2026,345 @@ pub fn read_proc_net_ipv6_route() -> Result<Vec<ProcFsNetIPv6Route>, Error> {
             gateway: hexstr_to_ipv6addr(nexthop)?,
             metric: hexstr_to_u32(metric)?,
             iface: iface.to_string(),
+            flags: hexstr_to_u32(flags)?,
         });
     }
 
     Ok(result)
 }
 
+/// Get the IPv6 default route with the lowest metric.
+pub fn default_ipv6_gateway() -> Result<Option<ProcFsNetIPv6Route>, Error> {
+    Ok(find_default_ipv6_gateway(read_proc_net_ipv6_route()?))
+}
+
+fn find_default_ipv6_gateway(routes: Vec<ProcFsNetIPv6Route>) -> Option<ProcFsNetIPv6Route> {
+    routes
+        .into_iter()
+        .filter(|route| {
+            route.dest.is_unspecified()
+                && route.prefix == 0
+                && !route.gateway.is_unspecified()
+                && route.flags & (RTF_UP | RTF_GATEWAY) == (RTF_UP | RTF_GATEWAY)
+        })
+        .min_by_key(|route| route.metric)
+}
+
+#[test]
+fn test_parse_proc_net_ipv6_route() {
+    let routes = parse_proc_net_ipv6_route(
+        "20010db8000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0\n\
+         00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003     eth0\n\
+         00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo\n",
+    )
+    .expect("successful parsing of a sample /proc/net/ipv6_route file");
+    assert_eq!(routes.len(), 3);
+    assert_eq!(routes[0].dest, "2001:db8::".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(routes[0].prefix, 64);
+    assert_eq!(routes[0].metric, 256);
+    assert_eq!(routes[0].flags, RTF_UP);
+    assert_eq!(routes[2].iface, "lo");
+
+    let gw = find_default_ipv6_gateway(routes).expect("failed to find default gateway");
+    assert_eq!(gw.iface, "eth0");
+    assert_eq!(gw.gateway, "fe80::1".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(gw.metric, 1024);
+}
+
+/// Socket state as found in the `st` column of `/proc/net/{tcp,udp}`. For UDP sockets only
+/// `Established` (connected) and `Close` (unconnected) are used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProcFsSocketState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+    NewSynRecv,
+    Unknown(u8),
+}
+
+impl From<u8> for ProcFsSocketState {
+    fn from(state: u8) -> Self {
+        match state {
+            0x01 => ProcFsSocketState::Established,
+            0x02 => ProcFsSocketState::SynSent,
+            0x03 => ProcFsSocketState::SynRecv,
+            0x04 => ProcFsSocketState::FinWait1,
+            0x05 => ProcFsSocketState::FinWait2,
+            0x06 => ProcFsSocketState::TimeWait,
+            0x07 => ProcFsSocketState::Close,
+            0x08 => ProcFsSocketState::CloseWait,
+            0x09 => ProcFsSocketState::LastAck,
+            0x0A => ProcFsSocketState::Listen,
+            0x0B => ProcFsSocketState::Closing,
+            0x0C => ProcFsSocketState::NewSynRecv,
+            other => ProcFsSocketState::Unknown(other),
+        }
+    }
+}
+
+/// An entry of the `/proc/net/{tcp,tcp6,udp,udp6}` socket tables.
+#[derive(Clone, Debug)]
+pub struct ProcFsNetSocket {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    pub state: ProcFsSocketState,
+    pub uid: Uid,
+    /// The socket inode, which can be matched against `socket:[INODE]` links in `/proc/PID/fd`.
+    pub inode: u64,
+}
+
+/// Read the IPv4 TCP socket table from `/proc/net/tcp`.
+pub fn read_proc_net_tcp() -> Result<Vec<ProcFsNetSocket>, Error> {
+    read_proc_net_sockets("/proc/net/tcp", false)
+}
+
+/// Read the IPv6 TCP socket table from `/proc/net/tcp6`.
+pub fn read_proc_net_tcp6() -> Result<Vec<ProcFsNetSocket>, Error> {
+    read_proc_net_sockets("/proc/net/tcp6", true)
+}
+
+/// Read the IPv4 UDP socket table from `/proc/net/udp`.
+pub fn read_proc_net_udp() -> Result<Vec<ProcFsNetSocket>, Error> {
+    read_proc_net_sockets("/proc/net/udp", false)
+}
+
+/// Read the IPv6 UDP socket table from `/proc/net/udp6`.
+pub fn read_proc_net_udp6() -> Result<Vec<ProcFsNetSocket>, Error> {
+    read_proc_net_sockets("/proc/net/udp6", true)
+}
+
+fn read_proc_net_sockets(path: &str, ipv6: bool) -> Result<Vec<ProcFsNetSocket>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    parse_proc_net_sockets(&data, ipv6)
+        .map_err(|err| format_err!("Error while parsing '{}' - {}", path, err))
+}
+
+/// Socket addresses are printed as 32 bit words in host byte order, so converting them back to
+/// native endian bytes yields the address in network byte order.
+fn hexstr_to_socket_addr(hex: &str, ipv6: bool) -> Result<SocketAddr, Error> {
+    let colon = hex
+        .find(':')
+        .ok_or_else(|| format_err!("missing port in socket address"))?;
+    let (addr, port) = (&hex[..colon], &hex[(colon + 1)..]);
+    let port = u16::from_str_radix(port, 16)?;
+
+    let word = |i: usize| -> Result<[u8; 4], Error> {
+        let part = addr
+            .get((i * 8)..((i + 1) * 8))
+            .ok_or_else(|| format_err!("unexpected socket address length"))?;
+        Ok(u32::from_str_radix(part, 16)?.to_ne_bytes())
+    };
+
+    if ipv6 {
+        if addr.len() != 32 {
+            bail!("unexpected IPv6 socket address length");
+        }
+        let mut bytes = [0u8; 16];
+        for i in 0..4 {
+            bytes[(i * 4)..((i + 1) * 4)].copy_from_slice(&word(i)?);
+        }
+        Ok(SocketAddr::new(Ipv6Addr::from(bytes).into(), port))
+    } else {
+        if addr.len() != 8 {
+            bail!("unexpected IPv4 socket address length");
+        }
+        Ok(SocketAddr::new(Ipv4Addr::from(word(0)?).into(), port))
+    }
+}
+
+fn parse_proc_net_sockets(data: &str, ipv6: bool) -> Result<Vec<ProcFsNetSocket>, Error> {
+    let mut result = Vec::new();
+    // skip the table header
+    for line in data.lines().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut iter = line.split_ascii_whitespace();
+
+        let mut next = || {
+            iter.next()
+                .ok_or_else(|| format_err!("missing field in socket table"))
+        };
+
+        next()?; // sl
+        let (local, remote, state) = (next()?, next()?, next()?);
+        for _ in 0..3 {
+            next()?; // tx_queue:rx_queue, tr:tm->when, retrnsmt
+        }
+        let uid = next()?;
+        next()?; // timeout
+        let inode = next()?;
+
+        result.push(ProcFsNetSocket {
+            local: hexstr_to_socket_addr(local, ipv6)?,
+            remote: hexstr_to_socket_addr(remote, ipv6)?,
+            state: hexstr_to_u8(state)?.into(),
+            uid: Uid::from_raw(uid.parse()?),
+            inode: inode.parse()?,
+        });
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_parse_proc_net_sockets() {
+    // the sample addresses are little endian, as printed on x86
+    let sockets = parse_proc_net_sockets(
+        "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+            0: 0100007F:0019 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 18372 1 0000000000000000 100 0 0 10 0\n   \
+            1: 0F02000A:0016 0202000A:C350 01 00000000:00000000 02:0009C9E1 00000000  1000        0 24961 4 0000000000000000 20 4 30 10 -1\n",
+        false,
+    )
+    .expect("successful parsing of a sample /proc/net/tcp file");
+    assert_eq!(sockets.len(), 2);
+    if cfg!(target_endian = "little") {
+        assert_eq!(sockets[0].local, "127.0.0.1:25".parse().unwrap());
+        assert_eq!(sockets[0].remote, "0.0.0.0:0".parse().unwrap());
+        assert_eq!(sockets[1].local, "10.0.2.15:22".parse().unwrap());
+        assert_eq!(sockets[1].remote, "10.0.2.2:50000".parse().unwrap());
+    }
+    assert_eq!(sockets[0].state, ProcFsSocketState::Listen);
+    assert_eq!(sockets[0].uid, Uid::from_raw(0));
+    assert_eq!(sockets[0].inode, 18372);
+    assert_eq!(sockets[1].state, ProcFsSocketState::Established);
+    assert_eq!(sockets[1].uid, Uid::from_raw(1000));
+    assert_eq!(sockets[1].inode, 24961);
+
+    let sockets = parse_proc_net_sockets(
+        "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+            0: 00000000000000000000000001000000:0277 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   108        0 20581 2 0000000000000000 0\n",
+        true,
+    )
+    .expect("successful parsing of a sample /proc/net/udp6 file");
+    assert_eq!(sockets.len(), 1);
+    if cfg!(target_endian = "little") {
+        assert_eq!(sockets[0].local, "[::1]:631".parse().unwrap());
+        assert_eq!(sockets[0].remote, "[::]:0".parse().unwrap());
+    }
+    assert_eq!(sockets[0].state, ProcFsSocketState::Close);
+    assert_eq!(sockets[0].uid, Uid::from_raw(108));
+    assert_eq!(sockets[0].inode, 20581);
+}
+
+/// An entry of `/proc/net/unix`.
+#[derive(Clone, Debug)]
+pub struct ProcFsNetUnix {
+    /// The socket type, eg. `libc::SOCK_STREAM`.
+    pub socket_type: i32,
+    /// The raw socket state (`1` unconnected, `2` connecting, `3` connected, `4` disconnecting).
+    pub state: u8,
+    /// Whether the socket is listening for connections.
+    pub listening: bool,
+    /// The socket inode, which can be matched against `socket:[INODE]` links in `/proc/PID/fd`.
+    pub inode: u64,
+    /// The bound path. Abstract socket names are prefixed with an `@`.
+    pub path: Option<PathBuf>,
+}
+
+/// `__SO_ACCEPTCON` in the flags column marks listening sockets.
+const UNIX_SOCKET_ACCEPTCON: u32 = 0x0001_0000;
+
+/// Read the unix socket table from `/proc/net/unix`.
+pub fn read_proc_net_unix() -> Result<Vec<ProcFsNetUnix>, Error> {
+    let path = "/proc/net/unix";
+    let data = std::fs::read(path)?;
+    parse_proc_net_unix(&data)
+        .map_err(|err| format_err!("Error while parsing '{}' - {}", path, err))
+}
+
+/// Check whether a unix socket is listening on a path.
+pub fn unix_socket_is_listening<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+    let path = path.as_ref();
+    Ok(read_proc_net_unix()?
+        .iter()
+        .any(|socket| socket.listening && socket.path.as_deref() == Some(path)))
+}
+
+fn parse_proc_net_unix(data: &[u8]) -> Result<Vec<ProcFsNetUnix>, Error> {
+    // Split off the next space separated field, returns the field and the remaining line.
+    fn field(line: &[u8]) -> Result<(&str, &[u8]), Error> {
+        let start = line
+            .iter()
+            .position(|b| *b != b' ')
+            .ok_or_else(|| format_err!("missing field"))?;
+        let line = &line[start..];
+        let end = line.iter().position(|b| *b == b' ').unwrap_or(line.len());
+        Ok((std::str::from_utf8(&line[..end])?, &line[end..]))
+    }
+
+    let mut result = Vec::new();
+    // skip the table header
+    for line in data.split(|b| *b == b'\n').skip(1) {
+        if line.is_empty() {
+            continue;
+        }
+
+        // the path may contain whitespace, so only split off the fixed columns
+        let mut fields = [""; 7]; // Num, RefCount, Protocol, Flags, Type, St, Inode
+        let mut rest = line;
+        for f in fields.iter_mut() {
+            let (value, remaining) = field(rest)?;
+            *f = value;
+            rest = remaining;
+        }
+
+        let path = match rest.iter().position(|b| *b != b' ') {
+            Some(start) => Some(PathBuf::from(OsStr::from_bytes(&rest[start..]))),
+            None => None,
+        };
+
+        result.push(ProcFsNetUnix {
+            socket_type: i32::from_str_radix(fields[4], 16)?,
+            state: hexstr_to_u8(fields[5])?,
+            listening: u32::from_str_radix(fields[3], 16)? & UNIX_SOCKET_ACCEPTCON != 0,
+            inode: fields[6].parse()?,
+            path,
+        });
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_parse_proc_net_unix() {
+    let sockets = parse_proc_net_unix(
+        b"Num       RefCount Protocol Flags    Type St Inode Path\n\
+          0000000000000000: 00000002 00000000 00010000 0001 01 23456 /run/systemd/notify me\n\
+          0000000000000000: 00000003 00000000 00000000 0001 03 23460\n\
+          0000000000000000: 00000002 00000000 00010000 0005 01 23470 @/tmp/.X11-unix/X0\n\
+          0000000000000000: 00000002 00000000 00000000 0002 01 23480 /run/dgram\n",
+    )
+    .expect("successful parsing of a sample /proc/net/unix file");
+    assert_eq!(sockets.len(), 4);
+    assert_eq!(sockets[0].socket_type, libc::SOCK_STREAM);
+    assert_eq!(sockets[0].state, 1);
+    assert!(sockets[0].listening);
+    assert_eq!(sockets[0].inode, 23456);
+    assert_eq!(
+        sockets[0].path.as_deref(),
+        Some(Path::new("/run/systemd/notify me"))
+    );
+    assert_eq!(sockets[1].state, 3);
+    assert!(!sockets[1].listening);
+    assert_eq!(sockets[1].path, None);
+    assert_eq!(sockets[2].socket_type, libc::SOCK_SEQPACKET);
+    assert_eq!(
+        sockets[2].path.as_deref(),
+        Some(Path::new("@/tmp/.X11-unix/X0"))
+    );
+    assert_eq!(sockets[3].socket_type, libc::SOCK_DGRAM);
+    assert!(!sockets[3].listening);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -732,6 +2378,121 @@ mod tests {
     fn test_read_proc_net_ipv6_route() {
         read_proc_net_ipv6_route().unwrap();
     }
+
+    #[test]
+    fn test_read_proc_net_tcp() {
+        read_proc_net_tcp().unwrap();
+    }
+}
+
+/// A line of a pressure stall information (PSI) file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PressureStat {
+    /// Percentage of time stalled during the last 10 seconds.
+    pub avg10: f64,
+    /// Percentage of time stalled during the last 60 seconds.
+    pub avg60: f64,
+    /// Percentage of time stalled during the last 300 seconds.
+    pub avg300: f64,
+    /// Total stall time in microseconds.
+    pub total: u64,
+}
+
+/// Pressure stall information as found in `/proc/pressure/{cpu,memory,io}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcFsPressure {
+    /// Time in which at least some tasks were stalled.
+    pub some: PressureStat,
+    /// Time in which all non-idle tasks were stalled. Older kernels do not provide this for
+    /// `cpu`.
+    pub full: Option<PressureStat>,
+}
+
+impl ProcFsPressure {
+    /// Parse the contents of a PSI file. This also works for the `*.pressure` files of cgroup v2.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut some = None;
+        let mut full = None;
+
+        for line in data.lines() {
+            let mut parts = line.split_ascii_whitespace();
+            let kind = match parts.next() {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let mut stat = PressureStat::default();
+            for part in parts {
+                let eq = part
+                    .find('=')
+                    .ok_or_else(|| format_err!("bad value {:?} in pressure file", part))?;
+                let (key, value) = (&part[..eq], &part[(eq + 1)..]);
+                match key {
+                    "avg10" => stat.avg10 = value.parse()?,
+                    "avg60" => stat.avg60 = value.parse()?,
+                    "avg300" => stat.avg300 = value.parse()?,
+                    "total" => stat.total = value.parse()?,
+                    _ => continue,
+                }
+            }
+
+            match kind {
+                "some" => some = Some(stat),
+                "full" => full = Some(stat),
+                other => bail!("unexpected line type {:?} in pressure file", other),
+            }
+        }
+
+        Ok(Self {
+            some: some.ok_or_else(|| format_err!("missing 'some' line in pressure file"))?,
+            full,
+        })
+    }
+
+    fn read(resource: &str) -> Result<Self, Error> {
+        let path = format!("/proc/pressure/{}", resource);
+        let data = std::fs::read_to_string(&path)
+            .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+        Self::parse(&data)
+    }
+}
+
+/// Read the CPU pressure stall information from `/proc/pressure/cpu`.
+pub fn read_proc_pressure_cpu() -> Result<ProcFsPressure, Error> {
+    ProcFsPressure::read("cpu")
+}
+
+/// Read the memory pressure stall information from `/proc/pressure/memory`.
+pub fn read_proc_pressure_memory() -> Result<ProcFsPressure, Error> {
+    ProcFsPressure::read("memory")
+}
+
+/// Read the I/O pressure stall information from `/proc/pressure/io`.
+pub fn read_proc_pressure_io() -> Result<ProcFsPressure, Error> {
+    ProcFsPressure::read("io")
+}
+
+#[test]
+fn test_parse_pressure() {
+    let psi = ProcFsPressure::parse(
+        "some avg10=1.53 avg60=0.87 avg300=0.25 total=12345678\n\
+         full avg10=0.10 avg60=0.05 avg300=0.00 total=456789\n",
+    )
+    .expect("successful parsing of a sample pressure file");
+    assert_eq!(psi.some.avg10, 1.53);
+    assert_eq!(psi.some.avg60, 0.87);
+    assert_eq!(psi.some.avg300, 0.25);
+    assert_eq!(psi.some.total, 12345678);
+    let full = psi.full.expect("missing 'full' pressure line");
+    assert_eq!(full.avg10, 0.10);
+    assert_eq!(full.total, 456789);
+
+    let psi = ProcFsPressure::parse("some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n")
+        .expect("successful parsing of a sample cpu pressure file");
+    assert_eq!(psi.some, PressureStat::default());
+    assert_eq!(psi.full, None);
+
+    ProcFsPressure::parse("").expect_err("parsed pressure file without 'some' line");
 }
 
 /// Read the load avage from `/proc/loadavg`.
@@ -788,3 +2549,49 @@ fn test_loadavg() {
     assert_eq!((avg.five() * 1000.0) as u64, 480u64);
     assert_eq!((avg.fifteen() * 1000.0) as u64, 440u64);
 }
+
+/// Read the task counts from `/proc/loadavg`.
+pub fn read_loadavg_tasks() -> Result<LoadavgTasks, Error> {
+    LoadavgTasks::read()
+}
+
+/// The task counts from the fourth field of `/proc/loadavg`.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadavgTasks {
+    /// The number of currently runnable tasks.
+    pub running: u64,
+    /// The number of tasks currently existing on the system.
+    pub total: u64,
+}
+
+impl LoadavgTasks {
+    /// Read the task counts from `/proc/loadavg`.
+    pub fn read() -> Result<Self, Error> {
+        Self::parse(unsafe { std::str::from_utf8_unchecked(&std::fs::read("/proc/loadavg")?) })
+    }
+
+    /// Parse the `running/total` field.
+    fn parse(line: &str) -> Result<Self, Error> {
+        let tasks = line
+            .split_ascii_whitespace()
+            .nth(3)
+            .ok_or_else(|| format_err!("missing field in /proc/loadavg"))?;
+        let pos = tasks
+            .find('/')
+            .ok_or_else(|| format_err!("bad task count in /proc/loadavg"))?;
+        Ok(Self {
+            running: tasks[..pos].parse()?,
+            total: tasks[(pos + 1)..].parse()?,
+        })
+    }
+}
+
+#[test]
+fn test_loadavg_tasks() {
+    let tasks =
+        LoadavgTasks::parse("0.44 0.48 0.44 2/1062 18549").expect("task count parser failed");
+    assert_eq!(tasks.running, 2);
+    assert_eq!(tasks.total, 1062);
+
+    LoadavgTasks::parse("0.44 0.48 0.44").expect_err("parsed /proc/loadavg without task counts");
+}
@@ -2,8 +2,8 @@
 
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{bail, format_err, Error};
@@ -83,6 +83,35 @@ impl Tag {
     }
 }
 
+/// Undo the octal escaping (eg. `\040` for a space) the kernel applies to paths and names in
+/// `mountinfo`. Anything which does not form a valid escape sequence is passed through unchanged.
+pub(crate) fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'\\' && i + 3 < data.len() && is_octal_escape(&data[(i + 1)..(i + 4)]) {
+            let octal = &data[(i + 1)..(i + 4)];
+            out.push(((octal[0] - b'0') << 6) | ((octal[1] - b'0') << 3) | (octal[2] - b'0'));
+            i += 4;
+        } else {
+            out.push(data[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn is_octal_escape(digits: &[u8]) -> bool {
+    // the first digit must be 0..=3 to fit into a byte
+    matches!(digits[0], b'0'..=b'3')
+        && matches!(digits[1], b'0'..=b'7')
+        && matches!(digits[2], b'0'..=b'7')
+}
+
+fn unescape_os(data: &[u8]) -> OsString {
+    OsString::from_vec(unescape(data))
+}
+
 #[derive(Clone, Debug)]
 pub struct Entry {
     /// unique identifier of the mount (may be reused after being unmounted)
@@ -131,8 +160,8 @@ impl Entry {
             id: std::str::from_utf8(next()?)?.parse()?,
             parent: std::str::from_utf8(next()?)?.parse()?,
             device: std::str::from_utf8(next()?)?.parse()?,
-            root: OsStr::from_bytes(next()?).to_owned().into(),
-            mount_point: OsStr::from_bytes(next()?).to_owned().into(),
+            root: unescape_os(next()?).into(),
+            mount_point: unescape_os(next()?).into(),
             mount_options: OsStr::from_bytes(next()?).to_owned(),
             tags: {
                 let mut tags = Vec::new();
@@ -145,10 +174,10 @@ impl Entry {
                 }
                 tags
             },
-            fs_type: std::str::from_utf8(next()?)?.to_string(),
+            fs_type: String::from_utf8(unescape(next()?))?,
             mount_source: next().map(|src| match src {
                 b"none" => None,
-                other => Some(OsStr::from_bytes(other).to_owned()),
+                other => Some(unescape_os(other)),
             })?,
             super_options: OsStr::from_bytes(next()?).to_owned(),
         };
@@ -166,6 +195,8 @@ impl Entry {
 #[derive(Clone, Debug)]
 pub struct MountInfo {
     entries: BTreeMap<MountId, Entry>,
+    // the position of each id in the mountinfo file, which is the order the mounts were made in
+    positions: BTreeMap<MountId, usize>,
 }
 
 /// An iterator over entries in a `MountInfo`.
@@ -205,9 +236,14 @@ impl MountInfo {
                 Ok(acc)
             })?;
 
+        let positions = entries
+            .iter()
+            .enumerate()
+            .map(|(pos, entry)| (entry.id, pos))
+            .collect();
         let entries = entries.into_iter().map(|entry| (entry.id, entry)).collect();
 
-        Ok(Self { entries })
+        Ok(Self { entries, positions })
     }
 
     /// Iterate over mount entries.
@@ -227,6 +263,37 @@ impl MountInfo {
         self.iter().any(|(_id, entry)| entry.mount_point == *path)
     }
 
+    /// Find the mount entry a path belongs to, that is, the entry with the longest mount point
+    /// containing `path`.
+    ///
+    /// The path is compared component-wise and is not resolved in any way, so it should be an
+    /// absolute, canonical path (eg. the result of `std::fs::canonicalize`). If multiple entries
+    /// share the same mount point, the last one in mountinfo order is returned, since that is the
+    /// one which is actually visible.
+    pub fn find_mount_point<P: AsRef<Path>>(&self, path: P) -> Option<&Entry> {
+        let path = path.as_ref();
+
+        let mut best: Option<((usize, usize), &Entry)> = None;
+        for entry in self.entries.values() {
+            if !path.starts_with(&entry.mount_point) {
+                continue;
+            }
+
+            // entries added via `DerefMut` come last
+            let pos = self
+                .positions
+                .get(&entry.id)
+                .copied()
+                .unwrap_or(usize::max_value());
+            let key = (entry.mount_point.as_os_str().len(), pos);
+            if best.map_or(true, |(best, _)| key >= best) {
+                best = Some((key, entry));
+            }
+        }
+
+        best.map(|(_, entry)| entry)
+    }
+
     /// Check whether there exists a mount point for a specified source.
     pub fn source_is_mounted<T>(&self, source: &T) -> bool
     where
@@ -281,8 +348,6 @@ impl std::ops::DerefMut for MountInfo {
 
 #[test]
 fn test_entry() {
-    use std::path::Path;
-
     let l1: &[u8] =
         b"48 32 0:43 / /sys/fs/cgroup/blkio rw,nosuid,nodev,noexec,relatime shared:26 - cgroup \
           cgroup rw,blkio";
@@ -382,4 +447,54 @@ fn test_entry() {
 
     let mount_info = [l1, l2].join(&b"\n"[..]);
     MountInfo::parse(&mount_info).expect("failed to parse mount info file");
+
+    // escaped whitespace and backslashes
+    let l5: &[u8] =
+        b"60 28 8:17 /sub\\134dir /mnt/with\\040space\\011tab rw,relatime shared:40 - ext4 \
+          /dev/disk/by-label/my\\040disk rw";
+    let entry = Entry::parse(l5).expect("failed to parse mountinfo entry with escapes");
+    assert_eq!(entry.root, Path::new("/sub\\dir"));
+    assert_eq!(entry.mount_point, Path::new("/mnt/with space\ttab"));
+    assert_eq!(
+        entry.mount_source.as_ref().map(|s| s.as_os_str()),
+        Some(OsStr::new("/dev/disk/by-label/my disk"))
+    );
+
+    // invalid escape sequences are kept as they are
+    assert_eq!(unescape(b"a\\09b\\4\\"), b"a\\09b\\4\\");
+    assert_eq!(unescape(b"\\101\\102"), b"AB");
+}
+
+#[test]
+fn test_find_mount_point() {
+    let info = MountInfo::parse(
+        b"20 1 0:19 / / rw - ext4 /dev/sda1 rw\n\
+          21 20 0:20 / /var rw - ext4 /dev/sda2 rw\n\
+          22 21 0:21 / /var/lib rw - ext4 /dev/sda3 rw\n\
+          23 20 0:22 / /variable rw - tmpfs tmpfs rw\n\
+          24 22 0:23 / /var/lib rw - tmpfs tmpfs rw\n",
+    )
+    .expect("failed to parse mount info file");
+
+    let id = |path: &str| info.find_mount_point(Path::new(path)).map(|e| e.id);
+
+    assert_eq!(id("/"), Some(MountId(20)));
+    assert_eq!(id("/etc/hosts"), Some(MountId(20)));
+    assert_eq!(id("/var/log/syslog"), Some(MountId(21)));
+    assert_eq!(id("/variable/x"), Some(MountId(23)));
+    // /var/lib is mounted twice, the top-most mount wins
+    assert_eq!(id("/var/lib/foo"), Some(MountId(24)));
+    assert_eq!(id("relative/path"), None);
+
+    // mount ids are reused, so the visible mount does not necessarily have the highest id
+    let info = MountInfo::parse(
+        b"20 1 0:19 / / rw - ext4 /dev/sda1 rw\n\
+          25 20 0:20 / /mnt rw - ext4 /dev/sda2 rw\n\
+          21 20 0:21 / /mnt rw - tmpfs tmpfs rw\n",
+    )
+    .expect("failed to parse mount info file");
+    assert_eq!(
+        info.find_mount_point(Path::new("/mnt/x")).map(|e| e.id),
+        Some(MountId(21))
+    );
 }
@@ -1,20 +1,57 @@
 //! Helper for creating a pseudo-terminal
 //!
-//! see [PTY](struct.PTY.html) for an example on how to use it
+//! see [PTY](struct.PTY.html) for an example on how to use it, or [open_pty](fn.open_pty.html)
+//! for a master/slave pair with window size and packet mode control.
 
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
 
 use nix::errno::Errno::EINVAL;
 use nix::fcntl::OFlag;
-use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt, PtyMaster};
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+use nix::sys::signal::Signal;
 use nix::sys::stat::Mode;
-use nix::unistd::{dup2, setsid};
-use nix::{ioctl_write_int_bad, ioctl_write_ptr_bad, Result};
+use nix::unistd::{dup2, setsid, ForkResult, Gid, Pid, Uid};
+use nix::{ioctl_read_bad, ioctl_write_int_bad, ioctl_write_ptr_bad, Result};
 
+use crate::sys::error::SysResult;
+use crate::sys::linux::prctl;
 use crate::tools::fd::Fd;
 
 ioctl_write_int_bad!(set_controlling_tty, libc::TIOCSCTTY);
 ioctl_write_ptr_bad!(set_size, libc::TIOCSWINSZ, nix::pty::Winsize);
+ioctl_read_bad!(get_size, libc::TIOCGWINSZ, nix::pty::Winsize);
+ioctl_write_ptr_bad!(set_packet_mode, libc::TIOCPKT, libc::c_int);
+
+fn set_window_size(fd: RawFd, cols: u16, rows: u16) -> Result<()> {
+    let size = nix::pty::Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    unsafe { set_size(fd, &size) }?;
+
+    Ok(())
+}
+
+fn get_window_size(fd: RawFd) -> Result<(u16, u16)> {
+    let mut size = nix::pty::Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    unsafe { get_size(fd, &mut size) }?;
+
+    Ok((size.ws_col, size.ws_row))
+}
 
 /// Represents a PTY
 ///
@@ -52,7 +89,7 @@ ioctl_write_ptr_bad!(set_size, libc::TIOCSWINSZ, nix::pty::Winsize);
 ///  }
 /// ```
 pub struct PTY {
-    primary: PtyMaster,
+    primary: nix::pty::PtyMaster,
 }
 
 /// Used to make a new process group of the current process,
@@ -94,16 +131,7 @@ impl PTY {
     /// Uses the ioctl 'TIOCSWINSZ' on the terminal fd to set the terminals
     /// columns and rows
     pub fn set_size(&mut self, col: u16, row: u16) -> Result<()> {
-        let size = nix::pty::Winsize {
-            ws_row: row,
-            ws_col: col,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
-
-        unsafe { set_size(self.primary.as_raw_fd(), &size) }?;
-
-        Ok(())
+        set_window_size(self.primary.as_raw_fd(), col, row)
     }
 }
 
@@ -134,3 +162,613 @@ impl AsRawFd for PTY {
         self.primary.as_raw_fd()
     }
 }
+
+/// Open a new pseudo-terminal, returning its master and slave side.
+///
+/// Both file descriptors have the close-on-exec flag set. The slave side is meant to be passed
+/// to a child process, see [PtySlave::make_controlling_terminal].
+pub fn open_pty() -> Result<(PtyMaster, PtySlave)> {
+    let primary = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC)?;
+    grantpt(&primary)?;
+    unlockpt(&primary)?;
+    let path = ptsname_r(&primary)?;
+    let secondary = Fd::open(
+        path.as_str(),
+        OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    Ok((
+        PtyMaster {
+            fd: Fd(primary.into_raw_fd()),
+            path: path.clone(),
+        },
+        PtySlave {
+            fd: secondary,
+            path,
+        },
+    ))
+}
+
+/// The master side of a pseudo-terminal.
+///
+/// Reading returns the output of the programs running on the terminal, writing provides their
+/// input. Once all file descriptors of the slave side are closed, reads return end of file.
+pub struct PtyMaster {
+    fd: Fd,
+    path: String,
+}
+
+impl PtyMaster {
+    /// Set the terminal's window size (`TIOCSWINSZ`). This sends a `SIGWINCH` to the terminal's
+    /// foreground process group.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        set_window_size(self.fd.as_raw_fd(), cols, rows)
+    }
+
+    /// Get the terminal's window size as `(cols, rows)` (`TIOCGWINSZ`).
+    pub fn window_size(&self) -> Result<(u16, u16)> {
+        get_window_size(self.fd.as_raw_fd())
+    }
+
+    /// Enable or disable packet mode (`TIOCPKT`).
+    ///
+    /// In packet mode every read returns a leading status byte, which is `0` for regular data or
+    /// a combination of the `libc::TIOCPKT_*` flags signaling changes in the terminal's flow
+    /// control state, in which case no data follows.
+    pub fn set_packet_mode(&self, enable: bool) -> Result<()> {
+        let enable = enable as libc::c_int;
+        unsafe { set_packet_mode(self.fd.as_raw_fd(), &enable) }?;
+        Ok(())
+    }
+
+    /// Get the path of the slave side.
+    pub fn slave_path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl PtyMaster {
+    fn read_raw(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match nix::unistd::read(self.fd.as_raw_fd(), buf) {
+            Ok(size) => Ok(size),
+            // the slave side was closed, eg. because the child exited
+            Err(nix::Error::Sys(nix::errno::Errno::EIO)) => Ok(0),
+            Err(err) => Err(err.as_errno().unwrap_or(EINVAL).into()),
+        }
+    }
+
+    fn write_raw(&self, buf: &[u8]) -> io::Result<usize> {
+        match nix::unistd::write(self.fd.as_raw_fd(), buf) {
+            Ok(size) => Ok(size),
+            Err(err) => Err(err.as_errno().unwrap_or(EINVAL).into()),
+        }
+    }
+}
+
+impl io::Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_raw(buf)
+    }
+}
+
+impl io::Write for PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_raw(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for PtyMaster {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PtyMaster {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+/// The slave side of a pseudo-terminal, as used by the programs running on it.
+pub struct PtySlave {
+    fd: Fd,
+    path: String,
+}
+
+impl PtySlave {
+    /// The path of the terminal device, eg. `/dev/pts/3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Get the terminal's window size as `(cols, rows)` (`TIOCGWINSZ`).
+    pub fn window_size(&self) -> Result<(u16, u16)> {
+        get_window_size(self.fd.as_raw_fd())
+    }
+
+    /// Make this the controlling terminal of a new session and use it as standard input, output
+    /// and error. This is meant to be called in a child process before executing a program.
+    pub fn make_controlling_terminal(&self) -> Result<()> {
+        setsid()?;
+        let fd = self.fd.as_raw_fd();
+        unsafe { set_controlling_tty(fd, 0) }?;
+        dup2(fd, 0)?;
+        dup2(fd, 1)?;
+        dup2(fd, 2)?;
+        Ok(())
+    }
+}
+
+impl AsRawFd for PtySlave {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for PtySlave {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+fn to_cstring<S: AsRef<OsStr>>(value: S) -> io::Result<CString> {
+    CString::new(value.as_ref().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "string contains a nul byte"))
+}
+
+/// Look up a program in `PATH` like the shell would, if it does not contain a slash.
+fn find_program(program: &OsStr, env: &BTreeMap<OsString, OsString>) -> PathBuf {
+    if program.as_bytes().contains(&b'/') {
+        return PathBuf::from(program);
+    }
+
+    let path = env
+        .get(OsStr::new("PATH"))
+        .cloned()
+        .unwrap_or_else(|| OsString::from("/usr/local/bin:/usr/bin:/bin"));
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| nix::unistd::access(candidate, nix::unistd::AccessFlags::X_OK).is_ok())
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+/// Builder to run a program in a new session on a new pseudo-terminal.
+///
+/// ```no_run
+/// # use proxmox::sys::linux::pty::PtyCommand;
+/// # fn code() -> std::io::Result<()> {
+/// let (master, pid) = PtyCommand::new("/bin/login")
+///     .arg("-f")
+///     .arg("root")
+///     .env("TERM", "xterm-256color")
+///     .window_size(80, 24)
+///     .spawn()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PtyCommand {
+    program: OsString,
+    args: Vec<OsString>,
+    env: BTreeMap<OsString, OsString>,
+    cwd: Option<PathBuf>,
+    uid: Option<Uid>,
+    gid: Option<Gid>,
+    size: Option<(u16, u16)>,
+    parent_death_signal: Option<Signal>,
+    no_new_privs: bool,
+}
+
+impl PtyCommand {
+    /// Prepare to run `program`, which is looked up in `PATH` if it does not contain a slash. The
+    /// environment of the current process is inherited.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env: std::env::vars_os().collect(),
+            cwd: None,
+            uid: None,
+            gid: None,
+            size: None,
+            parent_death_signal: None,
+            no_new_privs: false,
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add multiple arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Set an environment variable.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, value: V) -> Self {
+        self.env
+            .insert(key.as_ref().to_owned(), value.as_ref().to_owned());
+        self
+    }
+
+    /// Remove an environment variable.
+    pub fn env_remove<K: AsRef<OsStr>>(mut self, key: K) -> Self {
+        self.env.remove(key.as_ref());
+        self
+    }
+
+    /// Do not inherit the environment of the current process.
+    pub fn env_clear(mut self) -> Self {
+        self.env.clear();
+        self
+    }
+
+    /// Set the working directory of the program.
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cwd = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Run the program as a different user. Requires `CAP_SETUID`. Unless a group is set as well,
+    /// supplementary groups are dropped, which requires `CAP_SETGID`.
+    pub fn uid(mut self, uid: Uid) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Run the program with a different group, which also becomes its only supplementary
+    /// group. Requires `CAP_SETGID`.
+    pub fn gid(mut self, gid: Gid) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Set the initial window size of the terminal.
+    pub fn window_size(mut self, cols: u16, rows: u16) -> Self {
+        self.size = Some((cols, rows));
+        self
+    }
+
+    /// Send `signal` to the program when the thread calling `spawn` exits, see
+    /// `prctl::set_pdeathsig`.
+    pub fn parent_death_signal(mut self, signal: Signal) -> Self {
+        self.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// Prevent the program from gaining privileges, see `prctl::set_no_new_privs`.
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Open a new pseudo-terminal and run the program on it, returning the master side and the
+    /// pid of the child process, which has to be waited for by the caller.
+    ///
+    /// Errors happening in the child process before executing the program (eg. a missing
+    /// program) are reported here.
+    pub fn spawn(&self) -> io::Result<(PtyMaster, Pid)> {
+        // allocate everything before forking, the child must only use async-signal-safe calls
+        let program = to_cstring(find_program(&self.program, &self.env))?;
+        let mut argv = vec![to_cstring(&self.program)?];
+        for arg in self.args.iter() {
+            argv.push(to_cstring(arg)?);
+        }
+        let mut envp = Vec::with_capacity(self.env.len());
+        for (key, value) in self.env.iter() {
+            let mut entry = key.clone();
+            entry.push("=");
+            entry.push(value);
+            envp.push(to_cstring(entry)?);
+        }
+        let cwd = match self.cwd {
+            Some(ref cwd) => Some(to_cstring(cwd)?),
+            None => None,
+        };
+
+        let argv_ptrs: Vec<*const libc::c_char> = argv
+            .iter()
+            .map(|arg| arg.as_ptr())
+            .chain(std::iter::once(std::ptr::null()))
+            .collect();
+        let envp_ptrs: Vec<*const libc::c_char> = envp
+            .iter()
+            .map(|entry| entry.as_ptr())
+            .chain(std::iter::once(std::ptr::null()))
+            .collect();
+
+        let (master, slave) = open_pty().into_io_result()?;
+        if let Some((cols, rows)) = self.size {
+            master.resize(cols, rows).into_io_result()?;
+        }
+
+        // reports errors in the child, closed by a successful exec
+        let (err_r, err_w) = nix::unistd::pipe2(OFlag::O_CLOEXEC).into_io_result()?;
+        let (err_r, err_w) = (Fd(err_r), Fd(err_w));
+
+        let parent = Pid::this();
+        match unsafe { nix::unistd::fork() }.into_io_result()? {
+            ForkResult::Child => {
+                drop(master);
+                let errno = self.exec_child(parent, &slave, &program, &argv_ptrs, &envp_ptrs, cwd);
+                unsafe {
+                    libc::write(
+                        err_w.as_raw_fd(),
+                        &errno as *const i32 as *const libc::c_void,
+                        std::mem::size_of::<i32>(),
+                    );
+                    libc::_exit(127);
+                }
+            }
+            ForkResult::Parent { child } => {
+                drop(slave);
+                drop(err_w);
+
+                let mut errno = 0i32;
+                let got = loop {
+                    let got = unsafe {
+                        libc::read(
+                            err_r.as_raw_fd(),
+                            &mut errno as *mut i32 as *mut libc::c_void,
+                            std::mem::size_of::<i32>(),
+                        )
+                    };
+                    if got >= 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                        break got;
+                    }
+                };
+
+                if got == std::mem::size_of::<i32>() as isize {
+                    let _ = nix::sys::wait::waitpid(child, None);
+                    return Err(io::Error::from_raw_os_error(errno));
+                }
+
+                Ok((master, child))
+            }
+        }
+    }
+
+    /// Runs in the forked child, returns an error number on failure.
+    fn exec_child(
+        &self,
+        parent: Pid,
+        slave: &PtySlave,
+        program: &CString,
+        argv: &[*const libc::c_char],
+        envp: &[*const libc::c_char],
+        cwd: Option<CString>,
+    ) -> i32 {
+        let errno = || {
+            io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EINVAL)
+        };
+
+        unsafe {
+            // do not inherit blocked signals, eg. when the parent uses a signalfd
+            let mut empty: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut empty);
+            libc::sigprocmask(libc::SIG_SETMASK, &empty, std::ptr::null_mut());
+        }
+
+        if let Err(err) = slave.make_controlling_terminal() {
+            return err
+                .as_errno()
+                .map(|errno| errno as i32)
+                .unwrap_or(libc::EINVAL);
+        }
+
+        unsafe {
+            if let Some(gid) = self.gid {
+                let gid = gid.as_raw();
+                if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 {
+                    return errno();
+                }
+            }
+            if let Some(uid) = self.uid {
+                // do not keep our supplementary groups when only the user is changed
+                if self.gid.is_none() && libc::setgroups(0, std::ptr::null()) != 0 {
+                    return errno();
+                }
+                if libc::setuid(uid.as_raw()) != 0 {
+                    return errno();
+                }
+            }
+            if let Some(cwd) = cwd {
+                if libc::chdir(cwd.as_ptr()) != 0 {
+                    return errno();
+                }
+            }
+        }
+
+        // changing the credentials resets the parent death signal, so set it afterwards
+        if let Some(signal) = self.parent_death_signal {
+            if let Err(err) = prctl::set_pdeathsig(Some(signal)) {
+                return err.raw_os_error().unwrap_or(libc::EINVAL);
+            }
+            // the parent may have exited before the signal was set up
+            if nix::unistd::getppid() != parent {
+                return libc::ESRCH;
+            }
+        }
+        if self.no_new_privs {
+            if let Err(err) = prctl::set_no_new_privs() {
+                return err.raw_os_error().unwrap_or(libc::EINVAL);
+            }
+        }
+
+        unsafe {
+            libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+        }
+
+        errno()
+    }
+}
+
+#[cfg(feature = "async")]
+mod tokio_pty {
+    use std::io;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::ready;
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::PtyMaster;
+
+    /// A `PtyMaster` registered with the tokio reactor, implementing `AsyncRead` and
+    /// `AsyncWrite`.
+    ///
+    /// As with `PtyMaster`, reads signal end of file once the slave side was closed, eg. because
+    /// the child process exited.
+    pub struct AsyncPtyMaster {
+        inner: AsyncFd<PtyMaster>,
+    }
+
+    impl AsyncPtyMaster {
+        /// Switch the master into non-blocking mode and register it with the current tokio
+        /// reactor.
+        pub fn new(master: PtyMaster) -> io::Result<Self> {
+            crate::tools::fd::set_nonblocking(&master, true)?;
+            Ok(Self {
+                inner: AsyncFd::new(master)?,
+            })
+        }
+
+        /// Access the underlying `PtyMaster`, eg. to resize the terminal.
+        pub fn get_ref(&self) -> &PtyMaster {
+            self.inner.get_ref()
+        }
+
+        /// Get back the underlying (non-blocking) `PtyMaster`.
+        pub fn into_inner(self) -> PtyMaster {
+            self.inner.into_inner()
+        }
+    }
+
+    impl AsyncRead for AsyncPtyMaster {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                match guard.try_io(|inner| inner.get_ref().read_raw(unfilled)) {
+                    Ok(Ok(len)) => {
+                        buf.advance(len);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Ok(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(Err(err)) => return Poll::Ready(Err(err)),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for AsyncPtyMaster {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            loop {
+                let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+                match guard.try_io(|inner| inner.get_ref().write_raw(buf)) {
+                    Ok(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(result) => return Poll::Ready(result),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsRawFd for AsyncPtyMaster {
+        fn as_raw_fd(&self) -> RawFd {
+            self.inner.as_raw_fd()
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use tokio_pty::AsyncPtyMaster;
+
+#[test]
+fn test_open_pty() {
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    let (mut master, slave) = open_pty().expect("failed to open pty");
+    assert_eq!(master.slave_path(), slave.path());
+
+    master.resize(132, 43).expect("failed to resize pty");
+    assert_eq!(master.window_size().unwrap(), (132, 43));
+    assert_eq!(slave.window_size().unwrap(), (132, 43));
+
+    let mut output = unsafe { std::fs::File::from_raw_fd(slave.into_raw_fd()) };
+    output.write_all(b"output").unwrap();
+    drop(output);
+
+    let mut data = Vec::new();
+    master
+        .read_to_end(&mut data)
+        .expect("failed to read from pty");
+    assert_eq!(data, b"output");
+}
+
+#[test]
+fn test_pty_command() {
+    use std::io::Read;
+
+    let (mut master, pid) = PtyCommand::new("sh")
+        .args(&[
+            "-c",
+            "echo $PTY_TEST; stty size; grep NoNewPrivs /proc/$$/status",
+        ])
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .env("PTY_TEST", "hello")
+        .window_size(100, 30)
+        .parent_death_signal(Signal::SIGKILL)
+        .no_new_privs(true)
+        .spawn()
+        .expect("failed to spawn command");
+
+    let mut output = String::new();
+    master
+        .read_to_string(&mut output)
+        .expect("failed to read output");
+    assert_eq!(output, "hello\r\n30 100\r\nNoNewPrivs:\t1\r\n");
+
+    let status = nix::sys::wait::waitpid(pid, None).expect("waitpid failed");
+    assert_eq!(status, nix::sys::wait::WaitStatus::Exited(pid, 0));
+
+    let err = PtyCommand::new("/nonexistent/program").spawn().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+}
@@ -0,0 +1,271 @@
+//! Reaping of child processes.
+//!
+//! A `ChildReaper` receives `SIGCHLD` via a `SignalFd` and collects the exit status of all
+//! terminated children with `waitpid(2)`, so long running daemons do not accumulate zombies.
+//! Exit statuses of tracked children are delivered to callbacks and, optionally, a channel:
+//!
+//! ```no_run
+//! # use std::process::Command;
+//! # use nix::unistd::Pid;
+//! # use proxmox::sys::linux::reaper::ChildReaper;
+//! # fn code() -> std::io::Result<()> {
+//! let mut reaper = ChildReaper::new()?;
+//! let exits = reaper.subscribe();
+//!
+//! let child = Command::new("backup-job").spawn()?;
+//! reaper.register(Pid::from_raw(child.id() as i32), |pid, status| {
+//!     println!("backup job {} finished: {}", pid, status);
+//! });
+//!
+//! loop {
+//!     reaper.wait(None)?;
+//!     # break;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Since all children are reaped, this must not be combined with other code waiting for specific
+//! children, like `std::process::Child::wait`. As with `SignalFd`, the reaper should be created
+//! before spawning any threads, otherwise `SIGCHLD` may be discarded by a thread not blocking it.
+//! Reaping is not affected by this, so `wait` should be used with a timeout in that case.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::sys::error::SysResult;
+use crate::sys::linux::signalfd::SignalFd;
+
+/// How a child process terminated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChildStatus {
+    /// The child exited with an exit code.
+    Exited(i32),
+    /// The child was killed by a signal.
+    Signaled { signal: Signal, core_dumped: bool },
+}
+
+impl ChildStatus {
+    /// Whether the child exited with code 0.
+    pub fn success(self) -> bool {
+        self == ChildStatus::Exited(0)
+    }
+}
+
+impl fmt::Display for ChildStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildStatus::Exited(code) => write!(f, "exit code {}", code),
+            ChildStatus::Signaled {
+                signal,
+                core_dumped: false,
+            } => write!(f, "killed by signal {}", signal),
+            ChildStatus::Signaled {
+                signal,
+                core_dumped: true,
+            } => write!(f, "killed by signal {} (core dumped)", signal),
+        }
+    }
+}
+
+type Callback = Box<dyn FnOnce(Pid, ChildStatus) + Send>;
+
+/// Collects terminated children, see the module documentation.
+pub struct ChildReaper {
+    signal: SignalFd,
+    children: HashMap<Pid, Option<Callback>>,
+    sender: Option<mpsc::Sender<(Pid, ChildStatus)>>,
+}
+
+impl ChildReaper {
+    /// Block `SIGCHLD` for the current thread and start receiving it via a signal file
+    /// descriptor.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            signal: SignalFd::new_nonblocking(&[Signal::SIGCHLD])?,
+            children: HashMap::new(),
+            sender: None,
+        })
+    }
+
+    /// Track a child and call `callback` once it terminated.
+    ///
+    /// Children have to be registered right after spawning them, before reaping again, otherwise
+    /// their exit is reported as the exit of an untracked child.
+    pub fn register<F>(&mut self, pid: Pid, callback: F)
+    where
+        F: FnOnce(Pid, ChildStatus) + Send + 'static,
+    {
+        self.children.insert(pid, Some(Box::new(callback)));
+    }
+
+    /// Track a child without a callback, its exit is only reported via the channel and the return
+    /// value of `reap`.
+    pub fn track(&mut self, pid: Pid) {
+        self.children.insert(pid, None);
+    }
+
+    /// Get a channel receiving the exit status of every tracked child. Replaces any previously
+    /// created channel.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<(Pid, ChildStatus)> {
+        let (sender, receiver) = mpsc::channel();
+        self.sender = Some(sender);
+        receiver
+    }
+
+    /// Whether a child is tracked and still running (or not reaped yet).
+    pub fn is_tracked(&self, pid: Pid) -> bool {
+        self.children.contains_key(&pid)
+    }
+
+    /// The number of tracked children which were not reaped yet.
+    pub fn pending(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Reap all terminated children without blocking, and report the tracked ones. Returns the
+    /// tracked children which were reaped.
+    pub fn reap(&mut self) -> io::Result<Vec<(Pid, ChildStatus)>> {
+        // drain the signal queue, the actual state is queried via waitpid
+        while self.signal.read_signal()?.is_some() {}
+
+        let mut reaped = Vec::new();
+        loop {
+            let (pid, status) =
+                match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)).into_io_result() {
+                    Ok(WaitStatus::Exited(pid, code)) => (pid, ChildStatus::Exited(code)),
+                    Ok(WaitStatus::Signaled(pid, signal, core_dumped)) => (
+                        pid,
+                        ChildStatus::Signaled {
+                            signal,
+                            core_dumped,
+                        },
+                    ),
+                    Ok(WaitStatus::StillAlive) => break,
+                    Ok(_) => continue,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(ref err) if err.raw_os_error() == Some(libc::ECHILD) => break,
+                    Err(err) => return Err(err),
+                };
+
+            let callback = match self.children.remove(&pid) {
+                Some(callback) => callback,
+                None => continue, // not ours to report, but not left as a zombie either
+            };
+
+            if let Some(callback) = callback {
+                callback(pid, status);
+            }
+            if let Some(ref sender) = self.sender {
+                if sender.send((pid, status)).is_err() {
+                    self.sender = None;
+                }
+            }
+            reaped.push((pid, status));
+        }
+
+        Ok(reaped)
+    }
+
+    /// Wait for `SIGCHLD` and reap children, see `reap`. With a timeout, children are reaped once
+    /// the timeout expired even if no signal was received.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(Pid, ChildStatus)>> {
+        let timeout_ms = match timeout {
+            None => -1,
+            Some(timeout) => {
+                timeout.as_millis().min(libc::c_int::max_value() as u128) as libc::c_int
+            }
+        };
+
+        let mut pfd = libc::pollfd {
+            fd: self.signal.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        self.reap()
+    }
+}
+
+impl AsRawFd for ChildReaper {
+    /// The signal file descriptor, which becomes readable on `SIGCHLD`.
+    fn as_raw_fd(&self) -> RawFd {
+        self.signal.as_raw_fd()
+    }
+}
+
+#[test]
+fn test_child_reaper() {
+    use nix::unistd::ForkResult;
+
+    // reaping all children would interfere with other tests, so run this in a child process
+    match unsafe { nix::unistd::fork() }.expect("fork failed") {
+        ForkResult::Child => {
+            let result = std::panic::catch_unwind(|| {
+                let mut reaper = ChildReaper::new().expect("failed to create reaper");
+                let exits = reaper.subscribe();
+
+                let spawn = |cmd: &str| {
+                    let child = std::process::Command::new("sh")
+                        .args(&["-c", cmd])
+                        .spawn()
+                        .expect("failed to spawn test process");
+                    Pid::from_raw(child.id() as i32)
+                };
+
+                let (tx, rx) = mpsc::channel();
+                let first = spawn("exit 3");
+                reaper.register(first, move |pid, status| tx.send((pid, status)).unwrap());
+                let second = spawn("kill -9 $$");
+                reaper.track(second);
+                // untracked, but reaped anyway
+                let third = spawn("exit 0");
+
+                while reaper.pending() > 0 {
+                    reaper
+                        .wait(Some(Duration::from_millis(100)))
+                        .expect("failed to reap children");
+                }
+
+                assert_eq!(rx.try_recv().unwrap(), (first, ChildStatus::Exited(3)));
+                let mut reported: Vec<_> = exits.try_iter().collect();
+                reported.sort_by_key(|(pid, _)| pid.as_raw());
+                let mut expected = vec![
+                    (first, ChildStatus::Exited(3)),
+                    (
+                        second,
+                        ChildStatus::Signaled {
+                            signal: Signal::SIGKILL,
+                            core_dumped: false,
+                        },
+                    ),
+                ];
+                expected.sort_by_key(|(pid, _)| pid.as_raw());
+                assert_eq!(reported, expected);
+
+                std::thread::sleep(Duration::from_millis(100));
+                reaper.reap().expect("failed to reap children");
+                assert!(waitpid(third, Some(WaitPidFlag::WNOHANG)).is_err());
+            });
+            unsafe { libc::_exit(if result.is_ok() { 0 } else { 1 }) };
+        }
+        ForkResult::Parent { child } => {
+            let status = waitpid(child, None).expect("waitpid failed");
+            assert_eq!(status, WaitStatus::Exited(child, 0));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Restarting and powering off the system via `reboot(2)`.
+//!
+//! These bypass the init system, so services are not stopped cleanly. They are meant for node
+//! management code which already shut down everything it cares about, or for emergencies.
+
+use std::convert::Infallible;
+use std::io;
+
+/// First magic value required by `reboot(2)`.
+pub const LINUX_REBOOT_MAGIC1: libc::c_int = 0xfee1_deadu32 as libc::c_int;
+/// Second magic value required by `reboot(2)` (Linus Torvalds' birthday).
+pub const LINUX_REBOOT_MAGIC2: libc::c_int = 672_274_793;
+
+const LINUX_REBOOT_CMD_RESTART: libc::c_int = 0x0123_4567;
+const LINUX_REBOOT_CMD_HALT: libc::c_int = 0xcdef_0123u32 as libc::c_int;
+const LINUX_REBOOT_CMD_POWER_OFF: libc::c_int = 0x4321_fedc;
+const LINUX_REBOOT_CMD_KEXEC: libc::c_int = 0x4558_4543;
+const LINUX_REBOOT_CMD_CAD_ON: libc::c_int = 0x89ab_cdefu32 as libc::c_int;
+const LINUX_REBOOT_CMD_CAD_OFF: libc::c_int = 0;
+
+/// What `reboot` should do.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RebootMode {
+    /// Restart the system.
+    Restart,
+    /// Stop the system and turn off the power.
+    PowerOff,
+    /// Stop the system, but leave the power on.
+    Halt,
+    /// Boot into the kernel previously loaded with `kexec_load(2)`.
+    Kexec,
+}
+
+impl RebootMode {
+    fn cmd(self) -> libc::c_int {
+        match self {
+            RebootMode::Restart => LINUX_REBOOT_CMD_RESTART,
+            RebootMode::PowerOff => LINUX_REBOOT_CMD_POWER_OFF,
+            RebootMode::Halt => LINUX_REBOOT_CMD_HALT,
+            RebootMode::Kexec => LINUX_REBOOT_CMD_KEXEC,
+        }
+    }
+}
+
+fn reboot_cmd(cmd: libc::c_int) -> io::Result<()> {
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_reboot,
+            LINUX_REBOOT_MAGIC1,
+            LINUX_REBOOT_MAGIC2,
+            cmd,
+            std::ptr::null::<libc::c_void>(),
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Restart, power off or halt the system, or boot into a kexec kernel. Requires
+/// `CAP_SYS_BOOT`.
+///
+/// With `sync` set, file system buffers are written to disk first, otherwise unwritten data is
+/// lost. Only returns on failure, eg. with `EPERM` when lacking privileges or `EINVAL` for
+/// `Kexec` without a loaded kernel.
+pub fn reboot(mode: RebootMode, sync: bool) -> io::Result<Infallible> {
+    if sync {
+        unsafe { libc::sync() };
+    }
+    reboot_cmd(mode.cmd())?;
+    // halting returns once the system is stopped, there is nothing left to do
+    loop {
+        unsafe { libc::pause() };
+    }
+}
+
+/// Choose whether Ctrl-Alt-Del restarts the system immediately (`true`), or sends `SIGINT` to
+/// the init process (`false`).
+pub fn set_ctrl_alt_del(immediate: bool) -> io::Result<()> {
+    reboot_cmd(if immediate {
+        LINUX_REBOOT_CMD_CAD_ON
+    } else {
+        LINUX_REBOOT_CMD_CAD_OFF
+    })
+}
@@ -0,0 +1,188 @@
+//! Resource limits, see `getrlimit(2)`.
+
+use std::io;
+
+use nix::unistd::Pid;
+
+/// The value representing an unlimited resource.
+pub const RLIM_INFINITY: u64 = libc::RLIM_INFINITY as u64;
+
+/// The resources which can be limited.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resource {
+    /// Address space size in bytes (`RLIMIT_AS`).
+    AddressSpace,
+    /// Core dump size in bytes (`RLIMIT_CORE`).
+    Core,
+    /// CPU time in seconds (`RLIMIT_CPU`).
+    Cpu,
+    /// Data segment size in bytes (`RLIMIT_DATA`).
+    Data,
+    /// File size in bytes (`RLIMIT_FSIZE`).
+    FileSize,
+    /// Number of file locks (`RLIMIT_LOCKS`).
+    Locks,
+    /// Locked memory in bytes (`RLIMIT_MEMLOCK`).
+    MemLock,
+    /// Bytes in POSIX message queues (`RLIMIT_MSGQUEUE`).
+    MsgQueue,
+    /// Ceiling of the nice value (`RLIMIT_NICE`).
+    Nice,
+    /// Number of open file descriptors (`RLIMIT_NOFILE`).
+    NoFile,
+    /// Number of processes of the real user id (`RLIMIT_NPROC`).
+    NProc,
+    /// Resident set size in bytes, unused by current kernels (`RLIMIT_RSS`).
+    Rss,
+    /// Ceiling of the real time priority (`RLIMIT_RTPRIO`).
+    RtPrio,
+    /// Real time CPU time in microseconds (`RLIMIT_RTTIME`).
+    RtTime,
+    /// Number of pending signals (`RLIMIT_SIGPENDING`).
+    SigPending,
+    /// Stack size in bytes (`RLIMIT_STACK`).
+    Stack,
+}
+
+impl Resource {
+    fn raw(self) -> libc::c_int {
+        (match self {
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::Locks => libc::RLIMIT_LOCKS,
+            Resource::MemLock => libc::RLIMIT_MEMLOCK,
+            Resource::MsgQueue => libc::RLIMIT_MSGQUEUE,
+            Resource::Nice => libc::RLIMIT_NICE,
+            Resource::NoFile => libc::RLIMIT_NOFILE,
+            Resource::NProc => libc::RLIMIT_NPROC,
+            Resource::Rss => libc::RLIMIT_RSS,
+            Resource::RtPrio => libc::RLIMIT_RTPRIO,
+            Resource::RtTime => libc::RLIMIT_RTTIME,
+            Resource::SigPending => libc::RLIMIT_SIGPENDING,
+            Resource::Stack => libc::RLIMIT_STACK,
+        }) as libc::c_int
+    }
+}
+
+/// A soft and hard limit pair. `RLIM_INFINITY` means unlimited.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RLimit {
+    /// The limit enforced by the kernel.
+    pub soft: u64,
+    /// The ceiling for the soft limit. Only privileged processes can raise it.
+    pub hard: u64,
+}
+
+impl RLimit {
+    pub fn new(soft: u64, hard: u64) -> Self {
+        Self { soft, hard }
+    }
+
+    /// A limit with the same soft and hard value.
+    pub fn fixed(limit: u64) -> Self {
+        Self::new(limit, limit)
+    }
+
+    pub fn unlimited() -> Self {
+        Self::fixed(RLIM_INFINITY)
+    }
+}
+
+// Use the prlimit64 system call directly, as it supports other processes and is the same on all
+// architectures.
+fn prlimit(pid: Pid, resource: Resource, new: Option<&RLimit>) -> io::Result<RLimit> {
+    let new = new.map(|limit| libc::rlimit64 {
+        rlim_cur: limit.soft as libc::rlim64_t,
+        rlim_max: limit.hard as libc::rlim64_t,
+    });
+    let mut old = libc::rlimit64 {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_prlimit64,
+            pid.as_raw(),
+            resource.raw(),
+            new.as_ref()
+                .map(|new| new as *const libc::rlimit64)
+                .unwrap_or(std::ptr::null()),
+            &mut old as *mut libc::rlimit64,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(RLimit {
+        soft: old.rlim_cur as u64,
+        hard: old.rlim_max as u64,
+    })
+}
+
+/// Get a resource limit of the current process.
+pub fn get_rlimit(resource: Resource) -> io::Result<RLimit> {
+    prlimit(Pid::from_raw(0), resource, None)
+}
+
+/// Set a resource limit of the current process, returns the previous limit.
+pub fn set_rlimit(resource: Resource, limit: RLimit) -> io::Result<RLimit> {
+    prlimit(Pid::from_raw(0), resource, Some(&limit))
+}
+
+/// Get a resource limit of another process.
+pub fn get_pid_rlimit(pid: Pid, resource: Resource) -> io::Result<RLimit> {
+    prlimit(pid, resource, None)
+}
+
+/// Set a resource limit of another process, returns the previous limit.
+pub fn set_pid_rlimit(pid: Pid, resource: Resource, limit: RLimit) -> io::Result<RLimit> {
+    prlimit(pid, resource, Some(&limit))
+}
+
+/// Raise the soft limit of open file descriptors to `target`, but at most to the hard limit.
+///
+/// The default soft limit of 1024 exists for the benefit of programs using `select(2)`, which
+/// cannot deal with higher file descriptor numbers. Daemons using `poll` or `epoll` should raise
+/// it. The limit is never lowered. Returns the new soft limit.
+pub fn raise_nofile_limit(target: u64) -> io::Result<u64> {
+    let limit = get_rlimit(Resource::NoFile)?;
+    let soft = target.min(limit.hard);
+    if soft <= limit.soft {
+        return Ok(limit.soft);
+    }
+
+    set_rlimit(Resource::NoFile, RLimit::new(soft, limit.hard))?;
+    Ok(soft)
+}
+
+#[test]
+fn test_rlimit() {
+    let limit = get_rlimit(Resource::NoFile).expect("failed to get RLIMIT_NOFILE");
+    assert!(limit.soft <= limit.hard);
+
+    // the limit is process wide, restore it even if an assertion fails
+    struct Restore(RLimit);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let _ = set_rlimit(Resource::NoFile, self.0);
+        }
+    }
+    let restore = Restore(limit);
+
+    let raised = raise_nofile_limit(limit.hard).expect("failed to raise RLIMIT_NOFILE");
+    assert_eq!(raised, limit.hard);
+    assert_eq!(get_rlimit(Resource::NoFile).unwrap().soft, limit.hard);
+
+    // never lowers the limit
+    assert_eq!(raise_nofile_limit(0).unwrap(), limit.hard);
+
+    drop(restore);
+
+    let pid = nix::unistd::getpid();
+    assert_eq!(get_pid_rlimit(pid, Resource::NoFile).unwrap(), limit);
+}
@@ -0,0 +1,242 @@
+//! A minimal seccomp-BPF filter builder, see `seccomp(2)`.
+//!
+//! Filters map system call numbers to actions, everything not explicitly listed results in the
+//! default action. This is enough to sandbox helper processes with an allow-list:
+//!
+//! ```no_run
+//! # use proxmox::sys::linux::seccomp::{Action, Filter};
+//! # fn code() -> std::io::Result<()> {
+//! Filter::new(Action::Errno(libc::EPERM))
+//!     .allow_all(&[
+//!         libc::SYS_read,
+//!         libc::SYS_write,
+//!         libc::SYS_close,
+//!         libc::SYS_exit,
+//!         libc::SYS_exit_group,
+//!     ])
+//!     .install()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Note that filters apply to the calling thread and are inherited by child processes, and that
+//! they cannot be removed once installed.
+
+use std::io;
+
+// BPF instruction classes and fields, see linux/bpf_common.h
+const BPF_LD: u16 = 0x00;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGE: u16 = 0x30;
+const BPF_K: u16 = 0x00;
+
+// see linux/seccomp.h
+const SECCOMP_MODE_FILTER: libc::c_ulong = 2;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+// offsets into `struct seccomp_data`
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+// see linux/audit.h
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH_CURRENT: Option<u32> = Some(0xC000_003E);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH_CURRENT: Option<u32> = Some(0xC000_00B7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH_CURRENT: Option<u32> = None;
+
+/// x32 system calls use the x86_64 audit architecture with this bit set in the number, see
+/// asm/unistd.h.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// The kernel refuses longer programs.
+const BPF_MAXINSNS: usize = 4096;
+
+/// What happens when a filter rule matches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Allow the system call.
+    Allow,
+    /// Fail the system call with the given error number without executing it.
+    Errno(i32),
+    /// Allow the system call, but log it.
+    Log,
+    /// Send a `SIGSYS` to the thread.
+    Trap,
+    /// Kill the calling thread.
+    KillThread,
+    /// Kill the whole process.
+    KillProcess,
+}
+
+impl Action {
+    fn to_ret(self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::Errno(errno) => SECCOMP_RET_ERRNO | (errno as u32 & SECCOMP_RET_DATA),
+            Action::Log => SECCOMP_RET_LOG,
+            Action::Trap => SECCOMP_RET_TRAP,
+            Action::KillThread => SECCOMP_RET_KILL_THREAD,
+            Action::KillProcess => SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// A seccomp filter.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    default_action: Action,
+    rules: Vec<(libc::c_long, Action)>,
+}
+
+impl Filter {
+    /// Create a filter which applies `default_action` to all system calls without a rule.
+    pub fn new(default_action: Action) -> Self {
+        Self {
+            default_action,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add a rule for a system call number (`libc::SYS_*`). The first matching rule wins.
+    pub fn rule(mut self, syscall: libc::c_long, action: Action) -> Self {
+        self.rules.push((syscall, action));
+        self
+    }
+
+    /// Allow a system call.
+    pub fn allow(self, syscall: libc::c_long) -> Self {
+        self.rule(syscall, Action::Allow)
+    }
+
+    /// Allow a list of system calls.
+    pub fn allow_all(mut self, syscalls: &[libc::c_long]) -> Self {
+        for syscall in syscalls {
+            self.rules.push((*syscall, Action::Allow));
+        }
+        self
+    }
+
+    /// Compile the filter into a BPF program.
+    ///
+    /// System calls of a foreign architecture (eg. 32 bit calls on x86_64) always kill the
+    /// process, since their numbers have a different meaning. The same goes for x32 system calls
+    /// on x86_64, which share the architecture but would bypass the rules. Only x86_64 and
+    /// aarch64 are supported.
+    pub fn compile(&self) -> io::Result<Vec<libc::sock_filter>> {
+        let arch = AUDIT_ARCH_CURRENT.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "seccomp filters are not supported on this architecture",
+            )
+        })?;
+
+        let mut program = Vec::with_capacity(7 + 2 * self.rules.len());
+
+        program.push(stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH));
+        program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, arch, 1, 0));
+        program.push(stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS));
+
+        program.push(stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR));
+        #[cfg(target_arch = "x86_64")]
+        {
+            program.push(jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS));
+        }
+        for (syscall, action) in self.rules.iter() {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *syscall as u32, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, action.to_ret()));
+        }
+        program.push(stmt(BPF_RET | BPF_K, self.default_action.to_ret()));
+
+        if program.len() > BPF_MAXINSNS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many seccomp filter rules",
+            ));
+        }
+
+        Ok(program)
+    }
+
+    /// Install the filter for the current thread.
+    ///
+    /// This sets the `no_new_privs` flag first, which allows unprivileged processes to install
+    /// filters and prevents gaining privileges via set-user-id executables afterwards.
+    pub fn install(&self) -> io::Result<()> {
+        let program = self.compile()?;
+        let fprog = libc::sock_fprog {
+            len: program.len() as libc::c_ushort,
+            filter: program.as_ptr() as *mut libc::sock_filter,
+        };
+
+        super::prctl::set_no_new_privs()?;
+
+        let rc = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                SECCOMP_MODE_FILTER,
+                &fprog as *const libc::sock_fprog,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn test_seccomp_filter() {
+    let filter = Filter::new(Action::Allow).rule(libc::SYS_getppid, Action::Errno(libc::EPERM));
+    let program = filter.compile().unwrap();
+    #[cfg(target_arch = "x86_64")]
+    {
+        assert_eq!(program.len(), 9);
+        // x32 system calls must not reach the rules
+        assert_eq!(program[4].code, BPF_JMP | BPF_JGE | BPF_K);
+        assert_eq!(program[4].k, X32_SYSCALL_BIT);
+        assert_eq!(program[5].code, BPF_RET | BPF_K);
+        assert_eq!(program[5].k, SECCOMP_RET_KILL_PROCESS);
+    }
+    #[cfg(target_arch = "aarch64")]
+    assert_eq!(program.len(), 7);
+
+    // filters only apply to the current thread, so don't mess with the test runner
+    std::thread::spawn(move || {
+        filter.install().expect("failed to install seccomp filter");
+        let rc = unsafe { libc::syscall(libc::SYS_getppid) };
+        assert_eq!(rc, -1);
+        assert_eq!(io::Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+        assert!(unsafe { libc::syscall(libc::SYS_getpid) } > 0);
+    })
+    .join()
+    .expect("seccomp test thread failed");
+}
@@ -0,0 +1,279 @@
+//! Receive signals via a file descriptor, see `signalfd(2)`.
+//!
+//! The signals handled via a `SignalFd` are blocked for the calling thread, so they are not
+//! delivered asynchronously anymore but can be read from the file descriptor, which in turn can be
+//! used in an event loop (eg. via `epoll`).
+//!
+//! Note that the signal mask is a per-thread property which is inherited by new threads, so a
+//! `SignalFd` should be created before spawning any other threads. Otherwise the signals may be
+//! delivered to a thread which does not block them.
+//!
+//! ```no_run
+//! # use nix::sys::signal::Signal;
+//! # use proxmox::sys::linux::signalfd::SignalFd;
+//! # fn code() -> std::io::Result<()> {
+//! let mut sigfd = SignalFd::new(&[Signal::SIGTERM, Signal::SIGHUP, Signal::SIGCHLD])?;
+//! while let Some(info) = sigfd.read_signal()? {
+//!     match info.signal() {
+//!         Some(Signal::SIGTERM) => break,
+//!         Some(Signal::SIGHUP) => { /* reload */ }
+//!         Some(Signal::SIGCHLD) => { /* reap children */ }
+//!         _ => (),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+
+use nix::sys::signal::{SigSet, SigmaskHow, Signal};
+use nix::sys::signalfd::{self, SfdFlags};
+use nix::unistd::{Pid, Uid};
+
+use crate::sys::error::SysResult;
+use crate::tools::fd::Fd;
+
+/// A file descriptor receiving a set of signals.
+pub struct SignalFd {
+    fd: Fd,
+    mask: SigSet,
+}
+
+impl SignalFd {
+    /// Block `signals` for the current thread and create a blocking signal file descriptor for
+    /// them. The file descriptor is created with `O_CLOEXEC`.
+    pub fn new(signals: &[Signal]) -> io::Result<Self> {
+        Self::with_flags(signals, SfdFlags::SFD_CLOEXEC)
+    }
+
+    /// Like `new`, but create a non-blocking file descriptor, suitable for use in event loops.
+    pub fn new_nonblocking(signals: &[Signal]) -> io::Result<Self> {
+        Self::with_flags(signals, SfdFlags::SFD_CLOEXEC | SfdFlags::SFD_NONBLOCK)
+    }
+
+    fn with_flags(signals: &[Signal], flags: SfdFlags) -> io::Result<Self> {
+        let mut mask = SigSet::empty();
+        for signal in signals {
+            mask.add(*signal);
+        }
+
+        nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), None)
+            .into_io_result()?;
+
+        let fd = signalfd::signalfd(signalfd::SIGNALFD_NEW, &mask, flags).into_io_result()?;
+
+        Ok(Self { fd: Fd(fd), mask })
+    }
+
+    /// The set of signals handled by this file descriptor.
+    pub fn mask(&self) -> &SigSet {
+        &self.mask
+    }
+
+    /// Replace the set of signals handled by this file descriptor. The new signals are blocked for
+    /// the current thread, signals which are not part of the new set stay blocked.
+    pub fn set_mask(&mut self, signals: &[Signal]) -> io::Result<()> {
+        let mut mask = SigSet::empty();
+        for signal in signals {
+            mask.add(*signal);
+        }
+
+        nix::sys::signal::pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), None)
+            .into_io_result()?;
+
+        // The flags are ignored when updating an existing file descriptor.
+        signalfd::signalfd(self.fd.as_raw_fd(), &mask, SfdFlags::empty()).into_io_result()?;
+        self.mask = mask;
+        Ok(())
+    }
+
+    /// Read the next pending signal.
+    ///
+    /// For non-blocking file descriptors this returns `Ok(None)` if no signal is pending.
+    pub fn read_signal(&mut self) -> io::Result<Option<SigInfo>> {
+        let mut info = mem::MaybeUninit::<libc::signalfd_siginfo>::uninit();
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+
+        loop {
+            let rc = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    info.as_mut_ptr() as *mut libc::c_void,
+                    size,
+                )
+            };
+
+            if rc < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return Ok(None),
+                    _ => return Err(err),
+                }
+            }
+
+            if rc as usize != size {
+                // the kernel always returns whole structures
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "short read on signalfd",
+                ));
+            }
+
+            return Ok(Some(SigInfo(unsafe { info.assume_init() })));
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for SignalFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl Iterator for SignalFd {
+    type Item = io::Result<SigInfo>;
+
+    /// Read signals until an error occurs or, for non-blocking file descriptors, no more signals
+    /// are pending.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_signal().transpose()
+    }
+}
+
+/// How a child process changed its state, decoded from a `SIGCHLD` signal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChildEvent {
+    /// The child exited with an exit status.
+    Exited(i32),
+    /// The child was killed by a signal.
+    Killed(Signal),
+    /// The child was killed by a signal and dumped core.
+    Dumped(Signal),
+    /// A traced child has trapped.
+    Trapped(Signal),
+    /// The child was stopped by a signal.
+    Stopped(Signal),
+    /// A stopped child was continued.
+    Continued,
+}
+
+/// Information about a signal received via a `SignalFd`.
+#[derive(Clone, Copy)]
+pub struct SigInfo(libc::signalfd_siginfo);
+
+impl SigInfo {
+    /// The raw signal number.
+    pub fn signo(&self) -> i32 {
+        self.0.ssi_signo as i32
+    }
+
+    /// The signal, if it is known to `nix`. Real time signals for instance are not.
+    pub fn signal(&self) -> Option<Signal> {
+        Signal::try_from(self.signo()).ok()
+    }
+
+    /// The raw signal code (`si_code`), describing where the signal came from.
+    pub fn code(&self) -> i32 {
+        self.0.ssi_code
+    }
+
+    /// Whether the signal was sent by a process via `kill(2)`, `sigqueue(3)` or similar, rather
+    /// than by the kernel.
+    pub fn is_user(&self) -> bool {
+        // SI_USER is 0, other user space sources (SI_QUEUE, SI_TKILL, ...) are negative
+        self.0.ssi_code <= 0
+    }
+
+    /// The process id of the sender, or, for `SIGCHLD`, of the child.
+    pub fn pid(&self) -> Pid {
+        Pid::from_raw(self.0.ssi_pid as libc::pid_t)
+    }
+
+    /// The real user id of the sender.
+    pub fn uid(&self) -> Uid {
+        Uid::from_raw(self.0.ssi_uid)
+    }
+
+    /// The exit status or signal for `SIGCHLD`.
+    pub fn status(&self) -> i32 {
+        self.0.ssi_status
+    }
+
+    /// The integer value sent via `sigqueue(3)`.
+    pub fn int(&self) -> i32 {
+        self.0.ssi_int
+    }
+
+    /// For `SIGCHLD` signals, decode how the child changed its state.
+    pub fn child_event(&self) -> Option<ChildEvent> {
+        if self.signo() != libc::SIGCHLD {
+            return None;
+        }
+
+        let signal = || Signal::try_from(self.0.ssi_status).ok();
+
+        Some(match self.0.ssi_code {
+            libc::CLD_EXITED => ChildEvent::Exited(self.0.ssi_status),
+            libc::CLD_KILLED => ChildEvent::Killed(signal()?),
+            libc::CLD_DUMPED => ChildEvent::Dumped(signal()?),
+            libc::CLD_TRAPPED => ChildEvent::Trapped(signal()?),
+            libc::CLD_STOPPED => ChildEvent::Stopped(signal()?),
+            libc::CLD_CONTINUED => ChildEvent::Continued,
+            _ => return None,
+        })
+    }
+
+    /// Access the raw `signalfd_siginfo` structure.
+    pub fn raw(&self) -> &libc::signalfd_siginfo {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SigInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SigInfo")
+            .field("signo", &self.signo())
+            .field("code", &self.code())
+            .field("pid", &self.pid())
+            .field("uid", &self.uid())
+            .field("status", &self.status())
+            .finish()
+    }
+}
+
+#[test]
+fn test_signalfd() {
+    // The test harness runs tests in separate threads, so blocking a signal only affects this
+    // test, and `raise()` sends it to this very thread.
+    let mut sigfd =
+        SignalFd::new_nonblocking(&[Signal::SIGUSR2]).expect("failed to create signalfd");
+
+    assert!(sigfd
+        .read_signal()
+        .expect("failed to read from signalfd")
+        .is_none());
+
+    nix::sys::signal::raise(Signal::SIGUSR2).expect("failed to raise signal");
+
+    let info = sigfd
+        .read_signal()
+        .expect("failed to read from signalfd")
+        .expect("no signal received");
+    assert_eq!(info.signal(), Some(Signal::SIGUSR2));
+    assert_eq!(info.pid(), nix::unistd::getpid());
+    assert!(info.is_user());
+    assert!(info.child_event().is_none());
+
+    assert!(sigfd.next().is_none());
+}
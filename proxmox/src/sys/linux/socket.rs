@@ -0,0 +1,255 @@
+//! Unix socket helpers.
+//!
+//! File descriptors can be passed between processes via `SCM_RIGHTS` control messages, eg. to
+//! hand accepted connections from a privileged process to an unprivileged worker:
+//!
+//! ```no_run
+//! # use std::os::unix::io::AsRawFd;
+//! # use std::os::unix::net::UnixStream;
+//! # use proxmox::sys::linux::socket::{recv_fds, send_fds};
+//! # fn code(worker: UnixStream, client: std::net::TcpStream) -> std::io::Result<()> {
+//! send_fds(&worker, b"conn", &[client.as_raw_fd()])?;
+//!
+//! // in the worker:
+//! let mut buf = [0u8; 4];
+//! let (len, fds) = recv_fds(&worker, &mut buf, 1)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Sockets in the abstract namespace have no file system representation, so there are no stale
+//! socket files to clean up, and they vanish when the last file descriptor is closed. They are
+//! bound to the network namespace instead of the mount namespace.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use nix::sys::socket::{
+    self, sockopt, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags, SockAddr,
+    SockFlag, SockType, UnixAddr,
+};
+use nix::sys::uio::IoVec;
+use nix::unistd::{Gid, Pid, Uid};
+
+use crate::sys::error::SysResult;
+use crate::tools::fd::Fd;
+
+/// The kernel refuses to pass more file descriptors in a single message (`SCM_MAX_FD`).
+pub const MAX_FDS_PER_MESSAGE: usize = 253;
+
+/// Send `data` along with a set of file descriptors over a unix socket.
+///
+/// At least one byte of data has to be sent, since the receiver cannot get the file descriptors
+/// otherwise. Returns the number of bytes of `data` sent, the file descriptors are always sent
+/// with the first byte.
+pub fn send_fds<S: ?Sized + AsRawFd>(sock: &S, data: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+    if data.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot send file descriptors without data",
+        ));
+    }
+    if fds.len() > MAX_FDS_PER_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many file descriptors for a single message",
+        ));
+    }
+
+    let iov = [IoVec::from_slice(data)];
+    let cmsgs = [ControlMessage::ScmRights(fds)];
+    let cmsgs: &[ControlMessage] = if fds.is_empty() { &[] } else { &cmsgs };
+
+    loop {
+        match socket::sendmsg(sock.as_raw_fd(), &iov, cmsgs, MsgFlags::MSG_NOSIGNAL, None)
+            .into_io_result()
+        {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            other => return other,
+        }
+    }
+}
+
+/// Receive data and up to `max_fds` file descriptors from a unix socket.
+///
+/// Returns the number of bytes received and the file descriptors, which have the close-on-exec
+/// flag set. File descriptors of several messages sent via `send_fds` are never merged, but
+/// messages without any file descriptors may be received along with the data.
+///
+/// If the sender passed more than `max_fds` file descriptors, the excess ones are closed by the
+/// kernel and an error is returned.
+pub fn recv_fds<S: ?Sized + AsRawFd>(
+    sock: &S,
+    buf: &mut [u8],
+    max_fds: usize,
+) -> io::Result<(usize, Vec<Fd>)> {
+    let max_fds = max_fds.min(MAX_FDS_PER_MESSAGE);
+    let cmsg_space = unsafe {
+        libc::CMSG_SPACE((max_fds * std::mem::size_of::<RawFd>()) as libc::c_uint) as usize
+    };
+    let mut cmsg_buffer = Vec::with_capacity(cmsg_space);
+
+    loop {
+        let iov = [IoVec::from_mut_slice(&mut buf[..])];
+        let msg = match socket::recvmsg(
+            sock.as_raw_fd(),
+            &iov,
+            if max_fds > 0 {
+                Some(&mut cmsg_buffer)
+            } else {
+                None
+            },
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .into_io_result()
+        {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            other => other?,
+        };
+
+        let mut fds = Vec::new();
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(received) = cmsg {
+                fds.extend(
+                    received
+                        .into_iter()
+                        .map(|fd| unsafe { Fd::from_raw_fd(fd) }),
+                );
+            }
+        }
+
+        if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "received more file descriptors than expected",
+            ));
+        }
+
+        return Ok((msg.bytes, fds));
+    }
+}
+
+fn abstract_address(name: &[u8]) -> io::Result<SockAddr> {
+    Ok(SockAddr::Unix(
+        UnixAddr::new_abstract(name).into_io_result()?,
+    ))
+}
+
+fn unix_stream_socket() -> io::Result<Fd> {
+    let fd = socket::socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .into_io_result()?;
+    Ok(Fd(fd))
+}
+
+/// Create a unix stream socket listening on `name` in the abstract namespace. The name must not
+/// include the leading nul byte.
+pub fn bind_abstract(name: &[u8]) -> io::Result<UnixListener> {
+    let addr = abstract_address(name)?;
+    let fd = unix_stream_socket()?;
+    socket::bind(fd.as_raw_fd(), &addr).into_io_result()?;
+    socket::listen(fd.as_raw_fd(), libc::SOMAXCONN as usize).into_io_result()?;
+    Ok(unsafe { UnixListener::from_raw_fd(fd.into_raw_fd()) })
+}
+
+/// Connect to a unix stream socket listening on `name` in the abstract namespace.
+pub fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+    let addr = abstract_address(name)?;
+    let fd = unix_stream_socket()?;
+    let mut interrupted = false;
+    loop {
+        match socket::connect(fd.as_raw_fd(), &addr).into_io_result() {
+            Ok(()) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => interrupted = true,
+            // the interrupted attempt may have completed the connection already
+            Err(ref err) if interrupted && err.raw_os_error() == Some(libc::EISCONN) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(unsafe { UnixStream::from_raw_fd(fd.into_raw_fd()) })
+}
+
+/// The credentials of the process on the other end of a unix socket.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    pub pid: Pid,
+    pub uid: Uid,
+    pub gid: Gid,
+}
+
+/// Get the credentials of the peer of a connected unix socket (`SO_PEERCRED`).
+///
+/// These are the credentials at the time the connection was established (or the socket pair
+/// was created), so they cannot be spoofed by passing the socket to another process.
+pub fn peer_credentials<S: ?Sized + AsRawFd>(sock: &S) -> io::Result<PeerCredentials> {
+    let creds = socket::getsockopt(sock.as_raw_fd(), sockopt::PeerCredentials).into_io_result()?;
+    Ok(PeerCredentials {
+        pid: Pid::from_raw(creds.pid()),
+        uid: Uid::from_raw(creds.uid()),
+        gid: Gid::from_raw(creds.gid()),
+    })
+}
+
+#[test]
+fn test_send_recv_fds() {
+    use std::io::{Read, Write};
+    use std::os::unix::io::IntoRawFd;
+
+    let (a, b) = std::os::unix::net::UnixStream::pair().expect("failed to create socket pair");
+    let (pipe_r, pipe_w) = nix::unistd::pipe().expect("failed to create pipe");
+    let (pipe_r, pipe_w) = unsafe { (Fd::from_raw_fd(pipe_r), Fd::from_raw_fd(pipe_w)) };
+
+    assert_eq!(
+        send_fds(&a, b"pipe", &[pipe_r.as_raw_fd(), pipe_w.as_raw_fd()]).expect("send failed"),
+        4
+    );
+    drop(pipe_r);
+    drop(pipe_w);
+
+    let mut buf = [0u8; 16];
+    let (len, mut fds) = recv_fds(&b, &mut buf, 4).expect("receive failed");
+    assert_eq!(&buf[..len], b"pipe");
+    assert_eq!(fds.len(), 2);
+
+    let mut writer = unsafe { std::fs::File::from_raw_fd(fds.pop().unwrap().into_raw_fd()) };
+    let mut reader = unsafe { std::fs::File::from_raw_fd(fds.pop().unwrap().into_raw_fd()) };
+    writer.write_all(b"passed").unwrap();
+    drop(writer);
+    let mut data = String::new();
+    reader.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "passed");
+
+    // too many file descriptors
+    send_fds(&a, b"x", &[0, 1, 2]).expect("send failed");
+    assert!(recv_fds(&b, &mut buf, 2).is_err());
+}
+
+#[test]
+fn test_abstract_socket() {
+    use std::io::{Read, Write};
+
+    let name = format!("proxmox-socket-test-{}", std::process::id());
+    let listener = bind_abstract(name.as_bytes()).expect("failed to bind abstract socket");
+    assert!(bind_abstract(name.as_bytes()).is_err());
+
+    let mut client = connect_abstract(name.as_bytes()).expect("failed to connect");
+    let (mut server, _) = listener.accept().expect("failed to accept connection");
+
+    client.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    let creds = peer_credentials(&server).expect("failed to get peer credentials");
+    assert_eq!(creds.pid, nix::unistd::getpid());
+    assert_eq!(creds.uid, nix::unistd::geteuid());
+    assert_eq!(creds.gid, nix::unistd::getegid());
+
+    drop(listener);
+    assert!(connect_abstract(name.as_bytes()).is_err());
+}
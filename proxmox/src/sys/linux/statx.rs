@@ -0,0 +1,263 @@
+//! `statx(2)` wrapper with access to the file creation time and mount id.
+//!
+//! On kernels without `statx` this falls back to `fstatat(2)`, in which case the fields only
+//! available via `statx` are `None`.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::fcntl::AtFlags;
+use nix::NixPath;
+
+use crate::c_str;
+use crate::sys::error::SysResult;
+
+const STATX_BASIC_STATS: u32 = 0x07ff;
+const STATX_BTIME: u32 = 0x0800;
+const STATX_MNT_ID: u32 = 0x1000;
+
+/// A file timestamp as returned by `statx`. Note that the seconds can be negative.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp {
+    pub secs: i64,
+    pub nsecs: u32,
+}
+
+impl Timestamp {
+    /// Convert to a `SystemTime`.
+    pub fn to_system_time(self) -> SystemTime {
+        if self.secs >= 0 {
+            UNIX_EPOCH + Duration::new(self.secs as u64, self.nsecs)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.secs.wrapping_neg() as u64)
+                + Duration::from_nanos(u64::from(self.nsecs))
+        }
+    }
+}
+
+/// File attribute flags reported by `statx` (`STATX_ATTR_*`).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StatxAttributes(u64);
+
+impl StatxAttributes {
+    pub const COMPRESSED: StatxAttributes = StatxAttributes(0x0004);
+    pub const IMMUTABLE: StatxAttributes = StatxAttributes(0x0010);
+    pub const APPEND: StatxAttributes = StatxAttributes(0x0020);
+    pub const NODUMP: StatxAttributes = StatxAttributes(0x0040);
+    pub const ENCRYPTED: StatxAttributes = StatxAttributes(0x0800);
+    pub const AUTOMOUNT: StatxAttributes = StatxAttributes(0x1000);
+    pub const MOUNT_ROOT: StatxAttributes = StatxAttributes(0x2000);
+    pub const VERITY: StatxAttributes = StatxAttributes(0x0010_0000);
+    pub const DAX: StatxAttributes = StatxAttributes(0x0020_0000);
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: StatxAttributes) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+/// File status information.
+#[derive(Clone, Debug)]
+pub struct Statx {
+    /// File type and permissions, like `st_mode`.
+    pub mode: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub ino: u64,
+    pub size: u64,
+    /// Number of allocated 512 byte blocks.
+    pub blocks: u64,
+    pub blksize: u64,
+    pub atime: Timestamp,
+    pub mtime: Timestamp,
+    pub ctime: Timestamp,
+    /// The creation time, if supported by the kernel and file system.
+    pub btime: Option<Timestamp>,
+    /// The device containing the file.
+    pub dev: u64,
+    /// The device represented by the file, for device nodes.
+    pub rdev: u64,
+    /// The mount id as seen in `/proc/self/mountinfo`. Requires Linux 5.8.
+    pub mnt_id: Option<u64>,
+    /// The attributes set on the file.
+    pub attributes: StatxAttributes,
+    /// The attributes supported by the file system.
+    pub attributes_mask: StatxAttributes,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawTimestamp {
+    tv_sec: i64,
+    tv_nsec: u32,
+    __reserved: i32,
+}
+
+#[repr(C)]
+struct RawStatx {
+    stx_mask: u32,
+    stx_blksize: u32,
+    stx_attributes: u64,
+    stx_nlink: u32,
+    stx_uid: u32,
+    stx_gid: u32,
+    stx_mode: u16,
+    __spare0: u16,
+    stx_ino: u64,
+    stx_size: u64,
+    stx_blocks: u64,
+    stx_attributes_mask: u64,
+    stx_atime: RawTimestamp,
+    stx_btime: RawTimestamp,
+    stx_ctime: RawTimestamp,
+    stx_mtime: RawTimestamp,
+    stx_rdev_major: u32,
+    stx_rdev_minor: u32,
+    stx_dev_major: u32,
+    stx_dev_minor: u32,
+    stx_mnt_id: u64,
+    __spare2: [u64; 13],
+}
+
+impl From<RawTimestamp> for Timestamp {
+    fn from(ts: RawTimestamp) -> Self {
+        Self {
+            secs: ts.tv_sec,
+            nsecs: ts.tv_nsec,
+        }
+    }
+}
+
+impl From<RawStatx> for Statx {
+    fn from(stx: RawStatx) -> Self {
+        Self {
+            mode: u32::from(stx.stx_mode),
+            nlink: u64::from(stx.stx_nlink),
+            uid: stx.stx_uid,
+            gid: stx.stx_gid,
+            ino: stx.stx_ino,
+            size: stx.stx_size,
+            blocks: stx.stx_blocks,
+            blksize: u64::from(stx.stx_blksize),
+            atime: stx.stx_atime.into(),
+            mtime: stx.stx_mtime.into(),
+            ctime: stx.stx_ctime.into(),
+            btime: if stx.stx_mask & STATX_BTIME != 0 {
+                Some(stx.stx_btime.into())
+            } else {
+                None
+            },
+            dev: nix::sys::stat::makedev(
+                u64::from(stx.stx_dev_major),
+                u64::from(stx.stx_dev_minor),
+            ),
+            rdev: nix::sys::stat::makedev(
+                u64::from(stx.stx_rdev_major),
+                u64::from(stx.stx_rdev_minor),
+            ),
+            mnt_id: if stx.stx_mask & STATX_MNT_ID != 0 {
+                Some(stx.stx_mnt_id)
+            } else {
+                None
+            },
+            attributes: StatxAttributes(stx.stx_attributes & stx.stx_attributes_mask),
+            attributes_mask: StatxAttributes(stx.stx_attributes_mask),
+        }
+    }
+}
+
+impl From<libc::stat> for Statx {
+    fn from(st: libc::stat) -> Self {
+        Self {
+            mode: st.st_mode as u32,
+            nlink: st.st_nlink as u64,
+            uid: st.st_uid,
+            gid: st.st_gid,
+            ino: st.st_ino as u64,
+            size: st.st_size as u64,
+            blocks: st.st_blocks as u64,
+            blksize: st.st_blksize as u64,
+            atime: Timestamp {
+                secs: st.st_atime as i64,
+                nsecs: st.st_atime_nsec as u32,
+            },
+            mtime: Timestamp {
+                secs: st.st_mtime as i64,
+                nsecs: st.st_mtime_nsec as u32,
+            },
+            ctime: Timestamp {
+                secs: st.st_ctime as i64,
+                nsecs: st.st_ctime_nsec as u32,
+            },
+            btime: None,
+            dev: st.st_dev as u64,
+            rdev: st.st_rdev as u64,
+            mnt_id: None,
+            attributes: StatxAttributes::default(),
+            attributes_mask: StatxAttributes::default(),
+        }
+    }
+}
+
+/// Get the status of a file relative to a directory.
+///
+/// Pass `AtFlags::AT_SYMLINK_NOFOLLOW` to get the status of a symlink itself, and
+/// `AtFlags::AT_EMPTY_PATH` with an empty path to get the status of `dirfd`.
+pub fn statx<D, P>(dirfd: &D, path: &P, flags: AtFlags) -> io::Result<Statx>
+where
+    D: ?Sized + AsRawFd,
+    P: ?Sized + NixPath,
+{
+    let mut stx = std::mem::MaybeUninit::<RawStatx>::zeroed();
+    let rc = path
+        .with_nix_path(|path| unsafe {
+            libc::syscall(
+                libc::SYS_statx,
+                dirfd.as_raw_fd(),
+                path.as_ptr(),
+                flags.bits(),
+                STATX_BASIC_STATS | STATX_BTIME | STATX_MNT_ID,
+                stx.as_mut_ptr(),
+            )
+        })
+        .into_io_result()?;
+
+    if rc == 0 {
+        return Ok(unsafe { stx.assume_init() }.into());
+    }
+
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(err);
+    }
+
+    nix::sys::stat::fstatat(dirfd.as_raw_fd(), path, flags)
+        .into_io_result()
+        .map(Statx::from)
+}
+
+/// Get the status of an open file.
+pub fn fstatx<F: ?Sized + AsRawFd>(fd: &F) -> io::Result<Statx> {
+    statx(fd, c_str!(""), AtFlags::AT_EMPTY_PATH)
+}
+
+#[test]
+fn test_statx() {
+    use std::os::unix::fs::MetadataExt;
+
+    let file = std::fs::File::open("/proc/self/mountinfo").expect("failed to open mountinfo");
+    let stx = fstatx(&file).expect("statx failed");
+
+    let meta = file.metadata().unwrap();
+    assert_eq!(stx.ino, meta.ino());
+    assert_eq!(stx.dev, meta.dev());
+    assert_eq!(stx.mode, meta.mode());
+
+    let stx = statx(&crate::tools::fd::Fd::cwd(), "/", AtFlags::empty()).expect("statx failed");
+    assert_eq!(stx.mode & libc::S_IFMT, libc::S_IFDIR);
+}
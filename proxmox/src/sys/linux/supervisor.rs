@@ -0,0 +1,395 @@
+//! Supervision of worker processes.
+//!
+//! A `Supervisor` starts a set of worker processes and restarts them with an exponential backoff
+//! when they exit abnormally. `SIGTERM` and `SIGINT` are forwarded to the workers as `SIGTERM`,
+//! after which the supervisor waits for them to exit, `SIGHUP` is forwarded as is.
+//!
+//! ```no_run
+//! # use std::process::Command;
+//! # use proxmox::sys::linux::supervisor::Supervisor;
+//! # fn code() -> std::io::Result<()> {
+//! let mut supervisor = Supervisor::new()?;
+//! for id in 0..4 {
+//!     supervisor.add_worker(format!("worker{}", id), move || {
+//!         let mut cmd = Command::new("/usr/lib/mydaemon/worker");
+//!         cmd.arg(id.to_string());
+//!         cmd
+//!     });
+//! }
+//! supervisor.run()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Signals and child processes are handled via signal file descriptors, so the supervisor must be
+//! created before spawning any threads.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::sys::linux::reaper::{ChildReaper, ChildStatus};
+use crate::sys::linux::signalfd::SignalFd;
+
+/// When to restart failed workers.
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    /// The delay before the first restart.
+    pub initial_delay: Duration,
+    /// The delay is doubled on every failure, up to this value.
+    pub max_delay: Duration,
+    /// A worker which was running for at least this long starts over with the initial delay.
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The state of a supervised worker.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorkerState {
+    /// The worker is running.
+    Running { pid: Pid, since: Instant },
+    /// The worker failed and is restarted at the given time.
+    Backoff { restart_at: Instant },
+    /// The worker exited successfully, or was stopped by the supervisor, and is not restarted.
+    Stopped,
+}
+
+/// Status information about a supervised worker.
+#[derive(Clone, Debug)]
+pub struct WorkerStatus {
+    pub name: String,
+    pub state: WorkerState,
+    /// How often the worker was restarted.
+    pub restarts: u64,
+    /// How the worker process exited the last time.
+    pub last_exit: Option<ChildStatus>,
+    /// The last error when trying to start the worker.
+    pub last_error: Option<String>,
+}
+
+struct Worker {
+    status: WorkerStatus,
+    command: Box<dyn FnMut() -> Command + Send>,
+    delay: Duration,
+}
+
+/// Starts, restarts and stops worker processes, see the module documentation.
+pub struct Supervisor {
+    workers: Vec<Worker>,
+    reaper: ChildReaper,
+    signals: SignalFd,
+    policy: RestartPolicy,
+    shutdown_timeout: Duration,
+    shutdown_deadline: Option<Instant>,
+}
+
+impl Supervisor {
+    /// Create a supervisor, blocking `SIGCHLD`, `SIGTERM`, `SIGINT` and `SIGHUP` for the current
+    /// thread.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            workers: Vec::new(),
+            reaper: ChildReaper::new()?,
+            signals: SignalFd::new_nonblocking(&[Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP])?,
+            policy: RestartPolicy::default(),
+            shutdown_timeout: Duration::from_secs(30),
+            shutdown_deadline: None,
+        })
+    }
+
+    /// Set the restart policy for all workers.
+    pub fn restart_policy(&mut self, policy: RestartPolicy) {
+        self.policy = policy;
+    }
+
+    /// Set how long to wait for workers to exit after forwarding `SIGTERM`, before killing them.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    /// Add a worker. `command` is called to create the command for every (re)start, and the
+    /// worker is started right away if the supervisor is already running.
+    pub fn add_worker<F>(&mut self, name: String, command: F)
+    where
+        F: FnMut() -> Command + Send + 'static,
+    {
+        let mut worker = Worker {
+            status: WorkerStatus {
+                name,
+                state: WorkerState::Backoff {
+                    restart_at: Instant::now(),
+                },
+                restarts: 0,
+                last_exit: None,
+                last_error: None,
+            },
+            command: Box::new(command),
+            delay: self.policy.initial_delay,
+        };
+        if self.shutdown_deadline.is_some() {
+            worker.status.state = WorkerState::Stopped;
+        }
+        self.workers.push(worker);
+    }
+
+    /// The current status of all workers.
+    pub fn status(&self) -> Vec<WorkerStatus> {
+        self.workers
+            .iter()
+            .map(|worker| worker.status.clone())
+            .collect()
+    }
+
+    /// Whether a shutdown was requested.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_deadline.is_some()
+    }
+
+    /// Stop all workers: send them `SIGTERM` and do not restart them anymore. Workers still
+    /// running after the shutdown timeout are killed.
+    pub fn shutdown(&mut self) {
+        if self.shutdown_deadline.is_some() {
+            return;
+        }
+        self.shutdown_deadline = Some(Instant::now() + self.shutdown_timeout);
+
+        for worker in self.workers.iter_mut() {
+            if let WorkerState::Backoff { .. } = worker.status.state {
+                worker.status.state = WorkerState::Stopped;
+            }
+        }
+        self.signal_workers(Signal::SIGTERM);
+    }
+
+    /// Send a signal to all running workers.
+    pub fn signal_workers(&self, signal: Signal) {
+        for worker in self.workers.iter() {
+            if let WorkerState::Running { pid, .. } = worker.status.state {
+                let _ = nix::sys::signal::kill(pid, signal);
+            }
+        }
+    }
+
+    /// Run until a shutdown was requested and all workers exited, or, without a shutdown, until
+    /// all workers exited successfully.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.poll(None)? {}
+        Ok(())
+    }
+
+    /// Handle pending signals and child exits, start due workers, and then wait for at most
+    /// `timeout` for something to happen. Returns `false` once all workers are stopped.
+    pub fn poll(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.handle_signals()?;
+        self.handle_exits()?;
+        self.start_due_workers();
+
+        if let Some(deadline) = self.shutdown_deadline {
+            if Instant::now() >= deadline {
+                self.signal_workers(Signal::SIGKILL);
+            }
+        }
+
+        let next_event = self
+            .workers
+            .iter()
+            .filter_map(|worker| match worker.status.state {
+                WorkerState::Backoff { restart_at } => Some(restart_at),
+                _ => None,
+            })
+            // once the deadline passed, the workers were killed and only their exit is missing
+            .chain(
+                self.shutdown_deadline
+                    .filter(|deadline| *deadline > Instant::now()),
+            )
+            .min();
+
+        if self
+            .workers
+            .iter()
+            .all(|worker| worker.status.state == WorkerState::Stopped)
+        {
+            return Ok(false);
+        }
+
+        let mut wait = next_event.map(|at| at.saturating_duration_since(Instant::now()));
+        if let Some(timeout) = timeout {
+            wait = Some(wait.map_or(timeout, |wait| wait.min(timeout)));
+        }
+        let timeout_ms = match wait {
+            None => -1,
+            // round up to not wake up too early
+            Some(wait) => ((wait.as_micros() + 999) / 1000).min(libc::c_int::max_value() as u128)
+                as libc::c_int,
+        };
+
+        let mut pfds = [
+            libc::pollfd {
+                fd: self.reaper.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.signals.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn handle_signals(&mut self) -> io::Result<()> {
+        while let Some(info) = self.signals.read_signal()? {
+            match info.signal() {
+                Some(Signal::SIGTERM) | Some(Signal::SIGINT) => self.shutdown(),
+                Some(Signal::SIGHUP) => self.signal_workers(Signal::SIGHUP),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_exits(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        for (pid, exit) in self.reaper.reap()? {
+            let shutting_down = self.shutdown_deadline.is_some();
+            let policy = self.policy;
+
+            let worker = match self
+                .workers
+                .iter_mut()
+                .find(|worker| match worker.status.state {
+                    WorkerState::Running { pid: running, .. } => running == pid,
+                    _ => false,
+                }) {
+                Some(worker) => worker,
+                None => continue,
+            };
+
+            let since = match worker.status.state {
+                WorkerState::Running { since, .. } => since,
+                _ => now,
+            };
+            worker.status.last_exit = Some(exit);
+
+            if shutting_down || exit.success() {
+                worker.status.state = WorkerState::Stopped;
+                continue;
+            }
+
+            if now.duration_since(since) >= policy.reset_after {
+                worker.delay = policy.initial_delay;
+            }
+            worker.status.state = WorkerState::Backoff {
+                restart_at: now + worker.delay,
+            };
+            worker.delay = (worker.delay * 2).min(policy.max_delay);
+        }
+        Ok(())
+    }
+
+    fn start_due_workers(&mut self) {
+        let now = Instant::now();
+        let policy = self.policy;
+        for worker in self.workers.iter_mut() {
+            match worker.status.state {
+                WorkerState::Backoff { restart_at } if restart_at <= now => (),
+                _ => continue,
+            }
+
+            if worker.status.last_exit.is_some() || worker.status.last_error.is_some() {
+                worker.status.restarts += 1;
+            }
+
+            match (worker.command)().spawn() {
+                Ok(child) => {
+                    let pid = Pid::from_raw(child.id() as libc::pid_t);
+                    self.reaper.track(pid);
+                    worker.status.state = WorkerState::Running { pid, since: now };
+                    worker.status.last_error = None;
+                }
+                Err(err) => {
+                    worker.status.last_error = Some(err.to_string());
+                    worker.status.state = WorkerState::Backoff {
+                        restart_at: now + worker.delay,
+                    };
+                    worker.delay = (worker.delay * 2).min(policy.max_delay);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_supervisor() {
+    use crate::test::process::{is_helper, run_in_helper};
+
+    const NAME: &str = "sys::linux::supervisor::test_supervisor";
+
+    // the supervisor reaps all children and handles signals, so run this in a helper process
+    if !is_helper(NAME) {
+        return run_in_helper(NAME, &[]);
+    }
+
+    let mut supervisor = Supervisor::new().expect("failed to create supervisor");
+    supervisor.restart_policy(RestartPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(40),
+        reset_after: Duration::from_secs(60),
+    });
+    supervisor.add_worker("failing".to_string(), || {
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "exit 1"]);
+        cmd
+    });
+    supervisor.add_worker("sleeping".to_string(), || {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("60");
+        cmd
+    });
+
+    let start = Instant::now();
+    while supervisor.status()[0].restarts < 3 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        supervisor
+            .poll(Some(Duration::from_millis(10)))
+            .expect("supervisor failed");
+    }
+    let status = supervisor.status();
+    assert_eq!(status[0].last_exit, Some(ChildStatus::Exited(1)));
+    assert_eq!(status[1].restarts, 0);
+
+    nix::sys::signal::raise(Signal::SIGTERM).expect("failed to raise signal");
+    supervisor.run().expect("supervisor failed");
+    assert!(supervisor.is_shutting_down());
+
+    let status = supervisor.status();
+    assert_eq!(status[1].state, WorkerState::Stopped);
+    assert_eq!(
+        status[1].last_exit,
+        Some(ChildStatus::Signaled {
+            signal: Signal::SIGTERM,
+            core_dumped: false,
+        })
+    );
+}
@@ -0,0 +1,116 @@
+//! Reading and writing kernel parameters via `/proc/sys`.
+//!
+//! Parameters are addressed by their dotted names as used by `sysctl(8)`, eg.
+//! `net.ipv4.ip_forward`. As with `sysctl(8)`, a slash in a name is used to denote a literal dot
+//! in a path component, eg. `net.ipv4.conf.eth0/100.forwarding` for the `eth0.100` interface.
+
+use std::fmt::Display;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Error};
+
+const PROC_SYS: &str = "/proc/sys";
+
+/// Map a dotted sysctl name to its path in `/proc/sys`.
+pub fn name_to_path(name: &str) -> Result<PathBuf, Error> {
+    let mut path = PathBuf::from(PROC_SYS);
+    for component in name.split('.') {
+        let component = component.replace('/', ".");
+        if component.is_empty() || component == "." || component == ".." {
+            bail!("invalid sysctl name {:?}", name);
+        }
+        path.push(component);
+    }
+    Ok(path)
+}
+
+/// Read a kernel parameter as a string, with the trailing newline removed.
+pub fn get_string(name: &str) -> Result<String, Error> {
+    let path = name_to_path(name)?;
+    let mut value = std::fs::read_to_string(&path)
+        .map_err(|err| format_err!("unable to read sysctl {} ({:?}) - {}", name, path, err))?;
+    if value.ends_with('\n') {
+        value.pop();
+    }
+    Ok(value)
+}
+
+/// Read and parse a kernel parameter.
+///
+/// ```no_run
+/// # use anyhow::Error;
+/// # fn code() -> Result<(), Error> {
+/// let forwarding: u8 = proxmox::sys::linux::sysctl::get("net.ipv4.ip_forward")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn get<T>(name: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
+{
+    let value = get_string(name)?;
+    value.trim().parse().map_err(|err| {
+        format_err!(
+            "unable to parse sysctl {} value {:?} - {}",
+            name,
+            value,
+            err
+        )
+    })
+}
+
+/// Set a kernel parameter.
+///
+/// ```no_run
+/// # use anyhow::Error;
+/// # fn code() -> Result<(), Error> {
+/// proxmox::sys::linux::sysctl::set("net.ipv4.ip_forward", 1)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn set<T: Display>(name: &str, value: T) -> Result<(), Error> {
+    let path = name_to_path(name)?;
+    let value = value.to_string();
+
+    // The kernel expects the whole value in a single write, and the files cannot be created.
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map_err(|err| format_err!("unable to open sysctl {} ({:?}) - {}", name, path, err))?;
+    file.write_all(value.as_bytes()).map_err(|err| {
+        format_err!(
+            "unable to set sysctl {} ({:?}) to {:?} - {}",
+            name,
+            path,
+            value,
+            err
+        )
+    })
+}
+
+#[test]
+fn test_name_to_path() {
+    assert_eq!(
+        name_to_path("net.ipv4.ip_forward").unwrap(),
+        PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+    );
+    assert_eq!(
+        name_to_path("net.ipv4.conf.eth0/100.forwarding").unwrap(),
+        PathBuf::from("/proc/sys/net/ipv4/conf/eth0.100/forwarding")
+    );
+    name_to_path("").expect_err("accepted empty sysctl name");
+    name_to_path("net..ipv4").expect_err("accepted empty sysctl name component");
+    name_to_path("net./.ipv4").expect_err("accepted '.' as sysctl name component");
+    name_to_path("net.//.ipv4").expect_err("accepted '..' as sysctl name component");
+}
+
+#[test]
+fn test_get() {
+    let max: u64 = get("kernel.pid_max").expect("failed to read kernel.pid_max");
+    assert!(max > 0);
+    get::<u64>("kernel.ostype").expect_err("parsed kernel.ostype as a number");
+    get_string("does.not.exist").expect_err("read a nonexistent sysctl");
+}
@@ -0,0 +1,207 @@
+//! Timers delivering their expirations via a file descriptor, see `timerfd_create(2)`.
+//!
+//! Contrary to the POSIX timers in `crate::sys::timer` these do not use signals, the file
+//! descriptor becomes readable when the timer expires, so it can be used with `epoll` or tokio's
+//! `AsyncFd` (when created via `TimerFd::new_nonblocking`).
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use proxmox::sys::timer::Clock;
+//! # use proxmox::sys::linux::timerfd::TimerFd;
+//! # fn code() -> std::io::Result<()> {
+//! let timer = TimerFd::new(Clock::Monotonic)?;
+//! timer.set_interval(Duration::from_secs(60))?;
+//! loop {
+//!     let _expirations = timer.read()?;
+//!     // do some periodic maintenance work
+//! }
+//! # }
+//! ```
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
+
+use crate::sys::timer::{Clock, TimerSpec};
+use crate::tools::fd::Fd;
+
+/// A timer file descriptor.
+pub struct TimerFd {
+    fd: Fd,
+}
+
+impl TimerFd {
+    /// Create a new, disarmed, timer using the specified clock. The file descriptor is created
+    /// with `O_CLOEXEC`.
+    pub fn new(clock: Clock) -> io::Result<Self> {
+        Self::with_flags(clock, libc::TFD_CLOEXEC)
+    }
+
+    /// Like `new`, but create a non-blocking file descriptor, suitable for use in event loops.
+    pub fn new_nonblocking(clock: Clock) -> io::Result<Self> {
+        Self::with_flags(clock, libc::TFD_CLOEXEC | libc::TFD_NONBLOCK)
+    }
+
+    fn with_flags(clock: Clock, flags: libc::c_int) -> io::Result<Self> {
+        let clkid = match clock {
+            Clock::Realtime => libc::CLOCK_REALTIME,
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+        };
+
+        let fd = unsafe { libc::timerfd_create(clkid, flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd: Fd(fd) })
+    }
+
+    fn settime(&self, flags: libc::c_int, spec: TimerSpec) -> io::Result<TimerSpec> {
+        let newspec = spec.to_itimerspec();
+        let mut oldspec = MaybeUninit::<libc::itimerspec>::uninit();
+
+        let rc = unsafe {
+            libc::timerfd_settime(self.fd.as_raw_fd(), flags, &newspec, oldspec.as_mut_ptr())
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(TimerSpec::from_itimerspec(unsafe { oldspec.assume_init() }))
+    }
+
+    /// Arm the timer with a `value` relative to the current time. This returns the previous timer
+    /// specification.
+    ///
+    /// A `value` of `None` disarms the timer.
+    pub fn arm(&self, spec: TimerSpec) -> io::Result<TimerSpec> {
+        self.settime(0, spec)
+    }
+
+    /// Arm the timer with `value` being an absolute time of the timer's clock. This returns the
+    /// previous timer specification.
+    ///
+    /// For `Clock::Realtime` this is the time since the unix epoch.
+    pub fn arm_absolute(&self, spec: TimerSpec) -> io::Result<TimerSpec> {
+        self.settime(libc::TFD_TIMER_ABSTIME, spec)
+    }
+
+    /// Let the timer expire once after `timeout`.
+    pub fn set_oneshot(&self, timeout: Duration) -> io::Result<()> {
+        self.arm(TimerSpec::new().value(Some(non_zero(timeout))))?;
+        Ok(())
+    }
+
+    /// Let the timer expire periodically, starting after the first `interval`.
+    pub fn set_interval(&self, interval: Duration) -> io::Result<()> {
+        let interval = non_zero(interval);
+        self.arm(
+            TimerSpec::new()
+                .value(Some(interval))
+                .interval(Some(interval)),
+        )?;
+        Ok(())
+    }
+
+    /// Stop the timer.
+    pub fn disarm(&self) -> io::Result<()> {
+        self.arm(TimerSpec::new())?;
+        Ok(())
+    }
+
+    /// Get the current timer specification. The `value` contains the time until the next
+    /// expiration and is `None` if the timer is disarmed.
+    pub fn get(&self) -> io::Result<TimerSpec> {
+        let mut spec = MaybeUninit::<libc::itimerspec>::uninit();
+        let rc = unsafe { libc::timerfd_gettime(self.fd.as_raw_fd(), spec.as_mut_ptr()) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(TimerSpec::from_itimerspec(unsafe { spec.assume_init() }))
+    }
+
+    /// Wait for the timer to expire and return the number of expirations since the last read.
+    ///
+    /// For non-blocking file descriptors this returns `0` if the timer has not expired yet.
+    pub fn read(&self) -> io::Result<u64> {
+        let mut count = 0u64;
+
+        loop {
+            let rc = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    &mut count as *mut u64 as *mut libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+
+            if rc < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return Ok(0),
+                    _ => return Err(err),
+                }
+            }
+
+            return Ok(count);
+        }
+    }
+}
+
+// A zero value disarms the timer, so use the smallest possible value instead.
+fn non_zero(value: Duration) -> Duration {
+    if value == Duration::from_secs(0) {
+        Duration::from_nanos(1)
+    } else {
+        value
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for TimerFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl FromRawFd for TimerFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self { fd: Fd(fd) }
+    }
+}
+
+#[test]
+fn test_timerfd() {
+    let timer = TimerFd::new_nonblocking(Clock::Monotonic).expect("failed to create timerfd");
+
+    assert_eq!(timer.read().expect("failed to read timerfd"), 0);
+    assert!(timer.get().expect("failed to query timer").value.is_none());
+
+    timer
+        .set_oneshot(Duration::from_millis(10))
+        .expect("failed to arm timer");
+    let spec = timer.get().expect("failed to query timer");
+    assert!(spec.value.is_some());
+    assert!(spec.interval.is_none());
+
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(timer.read().expect("failed to read timerfd"), 1);
+    assert_eq!(timer.read().expect("failed to read timerfd"), 0);
+
+    timer
+        .set_interval(Duration::from_millis(5))
+        .expect("failed to arm timer");
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(timer.read().expect("failed to read timerfd") >= 2);
+
+    timer.disarm().expect("failed to disarm timer");
+    assert!(timer.get().expect("failed to query timer").value.is_none());
+}
@@ -1,12 +1,14 @@
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
 use std::mem::MaybeUninit;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 use anyhow::*;
 use nix::fcntl::OFlag;
+use nix::sys::signal::Signal;
 use nix::sys::stat::Mode;
 
 use crate::sys::error::SysError;
+use crate::sys::linux::signalfd::SignalFd;
 use crate::tools::fd::Fd;
 use crate::{c_try, try_block};
 
@@ -15,14 +17,21 @@ use crate::{c_try, try_block};
 ///
 /// uses unsafe call to tty_ioctl, see man tty_ioctl(2).
 pub fn stdout_terminal_size() -> (usize, usize) {
+    terminal_size(&io::stdout()).unwrap_or((0, 0))
+}
+
+/// Get the size of the terminal referred to by `fd` as `(rows, columns)`.
+pub fn terminal_size<F: ?Sized + AsRawFd>(fd: &F) -> io::Result<(usize, usize)> {
     let mut winsize = libc::winsize {
         ws_row: 0,
         ws_col: 0,
         ws_xpixel: 0,
         ws_ypixel: 0,
     };
-    unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) };
-    (winsize.ws_row as usize, winsize.ws_col as usize)
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((winsize.ws_row as usize, winsize.ws_col as usize))
 }
 
 /// Returns whether the current stdout is a tty .
@@ -41,6 +50,269 @@ pub fn stdin_isatty() -> bool {
     unsafe { libc::isatty(std::io::stdin().as_raw_fd()) == 1 }
 }
 
+/// Check whether ANSI escape sequences (colors, cursor movement) should be written to `fd`.
+///
+/// This requires `fd` to be a terminal and `TERM` to be set to something other than `dumb`. As
+/// suggested by <https://no-color.org>, a non-empty `NO_COLOR` environment variable disables
+/// escape sequences as well.
+pub fn supports_ansi<F: ?Sized + AsRawFd>(fd: &F) -> bool {
+    if unsafe { libc::isatty(fd.as_raw_fd()) } != 1 {
+        return false;
+    }
+
+    if std::env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty()) {
+        return false;
+    }
+
+    match std::env::var_os("TERM") {
+        Some(term) => !term.is_empty() && term != "dumb",
+        None => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AnsiState {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    /// OSC, DCS, SOS, PM and APC strings, terminated by `ST` (or `BEL` for OSC).
+    String,
+    StringEscape,
+}
+
+/// Removes ANSI escape sequences from a byte stream. Sequences may be split across calls.
+#[derive(Clone, Debug)]
+pub struct AnsiStripper {
+    state: AnsiState,
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiStripper {
+    pub fn new() -> Self {
+        Self {
+            state: AnsiState::Ground,
+        }
+    }
+
+    /// Append `input` without escape sequences to `output`.
+    pub fn feed(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (AnsiState::Ground, 0x1b) => AnsiState::Escape,
+                (AnsiState::Ground, _) => {
+                    output.push(byte);
+                    AnsiState::Ground
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Csi,
+                (AnsiState::Escape, b']')
+                | (AnsiState::Escape, b'P')
+                | (AnsiState::Escape, b'X')
+                | (AnsiState::Escape, b'^')
+                | (AnsiState::Escape, b'_') => AnsiState::String,
+                (AnsiState::Escape, 0x20..=0x2f) => AnsiState::EscapeIntermediate,
+                (AnsiState::EscapeIntermediate, 0x20..=0x2f) => AnsiState::EscapeIntermediate,
+                // not part of a sequence, keep multibyte UTF-8 characters intact
+                (AnsiState::Escape, 0x80..=0xff) | (AnsiState::EscapeIntermediate, 0x80..=0xff) => {
+                    output.push(byte);
+                    AnsiState::Ground
+                }
+                (AnsiState::Escape, _) | (AnsiState::EscapeIntermediate, _) => AnsiState::Ground,
+                (AnsiState::Csi, 0x40..=0x7e) => AnsiState::Ground,
+                (AnsiState::Csi, _) => AnsiState::Csi,
+                (AnsiState::String, 0x07) => AnsiState::Ground,
+                (AnsiState::String, 0x1b) => AnsiState::StringEscape,
+                (AnsiState::String, _) => AnsiState::String,
+                (AnsiState::StringEscape, b'\\') => AnsiState::Ground,
+                (AnsiState::StringEscape, _) => AnsiState::String,
+            };
+        }
+    }
+
+    /// Whether the stream currently ends inside of an escape sequence.
+    pub fn in_sequence(&self) -> bool {
+        self.state != AnsiState::Ground
+    }
+}
+
+/// Remove ANSI escape sequences from a string.
+///
+/// ```
+/// # use proxmox::sys::linux::tty::strip_ansi;
+/// assert_eq!(strip_ansi("\x1b[1;31merror:\x1b[0m failed"), "error: failed");
+/// ```
+pub fn strip_ansi(text: &str) -> String {
+    let mut output = Vec::with_capacity(text.len());
+    AnsiStripper::new().feed(text.as_bytes(), &mut output);
+    // only ASCII bytes are removed, so this should still be valid UTF-8
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// A writer removing ANSI escape sequences from everything written to it, for output which is
+/// not going to a terminal.
+///
+/// ```
+/// # use std::io::Write;
+/// # use proxmox::sys::linux::tty::{supports_ansi, StripAnsi};
+/// # fn code() -> std::io::Result<()> {
+/// let stdout = std::io::stdout();
+/// let mut out: Box<dyn Write> = if supports_ansi(&stdout) {
+///     Box::new(stdout)
+/// } else {
+///     Box::new(StripAnsi::new(stdout))
+/// };
+/// writeln!(out, "\x1b[32mok\x1b[0m")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct StripAnsi<W: Write> {
+    inner: W,
+    stripper: AnsiStripper,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StripAnsi<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            stripper: AnsiStripper::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for StripAnsi<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.clear();
+        self.stripper.feed(buf, &mut self.buffer);
+        self.inner.write_all(&self.buffer)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Watches a terminal for size changes via `SIGWINCH`.
+///
+/// The signal is received via a [`SignalFd`], so the same restrictions regarding the per-thread
+/// signal mask apply: the watcher should be created before spawning other threads. The file
+/// descriptor can be used in an event loop, in which case [`changed`](WinsizeWatcher::changed)
+/// should be called whenever it becomes readable.
+///
+/// ```no_run
+/// # use proxmox::sys::linux::tty::WinsizeWatcher;
+/// # fn code() -> std::io::Result<()> {
+/// let mut watcher = WinsizeWatcher::new(&std::io::stdout())?;
+/// watcher.run(|rows, cols| {
+///     println!("terminal resized to {}x{}", cols, rows);
+///     true
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WinsizeWatcher {
+    fd: Fd,
+    signal: SignalFd,
+    size: (usize, usize),
+}
+
+impl WinsizeWatcher {
+    /// Start watching the terminal referred to by `fd`. The watcher keeps a duplicate of the file
+    /// descriptor.
+    pub fn new<F: ?Sized + AsRawFd>(fd: &F) -> io::Result<Self> {
+        let fd = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = Fd(fd);
+
+        // block the signal before querying the size so no change can be missed
+        let signal = SignalFd::new_nonblocking(&[Signal::SIGWINCH])?;
+        let size = terminal_size(&fd)?;
+
+        Ok(Self { fd, signal, size })
+    }
+
+    /// The most recently seen size as `(rows, columns)`.
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Check for pending `SIGWINCH` signals without blocking. Returns the new size if it changed.
+    pub fn changed(&mut self) -> io::Result<Option<(usize, usize)>> {
+        let mut signaled = false;
+        while self.signal.read_signal()?.is_some() {
+            signaled = true;
+        }
+        if !signaled {
+            return Ok(None);
+        }
+
+        let size = terminal_size(&self.fd)?;
+        if size == self.size {
+            return Ok(None);
+        }
+        self.size = size;
+        Ok(Some(size))
+    }
+
+    /// Block until the terminal size changes and return the new size.
+    pub fn wait(&mut self) -> io::Result<(usize, usize)> {
+        loop {
+            if let Some(size) = self.changed()? {
+                return Ok(size);
+            }
+
+            let mut pollfd = libc::pollfd {
+                fd: self.signal.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Call `callback` with `(rows, columns)` for every size change until it returns `false`.
+    pub fn run<F>(&mut self, mut callback: F) -> io::Result<()>
+    where
+        F: FnMut(usize, usize) -> bool,
+    {
+        loop {
+            let (rows, cols) = self.wait()?;
+            if !callback(rows, cols) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl AsRawFd for WinsizeWatcher {
+    /// The signal file descriptor, which becomes readable on `SIGWINCH`.
+    fn as_raw_fd(&self) -> RawFd {
+        self.signal.as_raw_fd()
+    }
+}
+
 pub enum TtyOutput {
     Stdout(std::io::Stdout),
     DevTty(Fd),
@@ -91,16 +363,158 @@ impl TtyOutput {
     }
 }
 
+fn tcgetattr(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { termios.assume_init() })
+}
+
+fn tcsetattr(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Switches a terminal into raw mode and restores the previous settings when dropped, including
+/// when unwinding from a panic.
+///
+/// ```no_run
+/// # use proxmox::sys::linux::tty::RawModeGuard;
+/// # fn code() -> std::io::Result<()> {
+/// let _raw_mode = RawModeGuard::controlling_tty()?;
+/// // read single key presses from stdin
+/// # Ok(())
+/// # }
+/// ```
+pub struct RawModeGuard {
+    fd: Fd,
+    original: libc::termios,
+    restored: bool,
+}
+
+impl RawModeGuard {
+    /// Switch the terminal referred to by `fd` into raw mode.
+    ///
+    /// The guard keeps a duplicate of the file descriptor, so it does not need to outlive it.
+    pub fn new<F: ?Sized + AsRawFd>(fd: &F) -> io::Result<Self> {
+        let fd = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = Fd(fd);
+
+        let original = tcgetattr(fd.as_raw_fd())?;
+        let mut raw = original; // termios is a 'Copy' type
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+        }
+        tcsetattr(fd.as_raw_fd(), &raw)?;
+
+        Ok(Self {
+            fd,
+            original,
+            restored: false,
+        })
+    }
+
+    /// Switch the controlling terminal of the process (`/dev/tty`) into raw mode.
+    pub fn controlling_tty() -> io::Result<Self> {
+        let fd = Fd::open(
+            "/dev/tty",
+            OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_NOCTTY,
+            Mode::empty(),
+        )
+        .map_err(SysError::into_io_error)?;
+        Self::new(&fd)
+    }
+
+    /// Restore the original settings, reporting errors instead of ignoring them.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restored = true;
+        tcsetattr(self.fd.as_raw_fd(), &self.original)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if !self.restored {
+            let _ = tcsetattr(self.fd.as_raw_fd(), &self.original);
+        }
+    }
+}
+
+/// Overwrite a buffer with zeroes in a way the compiler does not optimize away.
+fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Push to a buffer containing secret data, making sure no copies are left behind when it needs
+/// to grow.
+fn push_secret(buf: &mut Vec<u8>, byte: u8) {
+    if buf.len() == buf.capacity() {
+        let mut grown = Vec::with_capacity((buf.capacity() * 2).max(64));
+        grown.extend_from_slice(buf);
+        zeroize(buf);
+        *buf = grown;
+    }
+    buf.push(byte);
+}
+
+/// Read a single byte directly from a file descriptor, bypassing any buffering, so no copies of
+/// the data are kept around.
+fn read_byte(fd: RawFd) -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        let got = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if got == 1 {
+            return Ok(Some(byte));
+        } else if got == 0 {
+            return Ok(None);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
 /// Read a password from stdin.
 ///
 /// Masking the echoed output with asterisks and writing a query
-/// first.
+/// first. Pressing tab disables the asterisks.
+///
+/// If stdin is not a terminal (eg. a pipe), a single line is read without writing the query.
+/// Intermediate buffers are zeroed, the caller is responsible for the returned data.
 pub fn read_password(query: &str) -> Result<Vec<u8>, Error> {
     let input = std::io::stdin();
-    if unsafe { libc::isatty(input.as_raw_fd()) } != 1 {
-        let mut out = String::new();
-        input.read_line(&mut out)?;
-        return Ok(out.into_bytes());
+    let infd = input.as_raw_fd();
+
+    let mut password = Vec::<u8>::new();
+
+    if unsafe { libc::isatty(infd) } != 1 {
+        let ok: Result<(), Error> = try_block!({
+            while let Some(byte) = read_byte(infd)? {
+                if byte == b'\n' {
+                    break;
+                }
+                push_secret(&mut password, byte);
+            }
+            if password.last() == Some(&b'\r') {
+                password.pop();
+            }
+            Ok(())
+        });
+        if let Err(err) = ok {
+            zeroize(&mut password);
+            return Err(err);
+        }
+        return Ok(password);
     }
 
     let mut out = TtyOutput::open()?
@@ -109,26 +523,12 @@ pub fn read_password(query: &str) -> Result<Vec<u8>, Error> {
     let _ignore_error = out.write_all(query.as_bytes());
     let _ignore_error = out.flush();
 
-    let infd = input.as_raw_fd();
-    let mut termios = MaybeUninit::<libc::termios>::uninit();
-    if unsafe { libc::tcgetattr(infd, &mut *termios.as_mut_ptr()) } != 0 {
-        bail!("tcgetattr() failed");
-    }
-    let mut termios = unsafe { termios.assume_init() };
-    let old_termios = termios; // termios is a 'Copy' type
-    unsafe {
-        libc::cfmakeraw(&mut termios);
-    }
-    if unsafe { libc::tcsetattr(infd, libc::TCSANOW, &termios) } != 0 {
-        bail!("tcsetattr() failed");
-    }
+    let raw_mode = RawModeGuard::new(&input)?;
 
-    let mut password = Vec::<u8>::new();
     let mut asterisks = true;
 
     let ok: Result<(), Error> = try_block!({
-        for byte in input.bytes() {
-            let byte = byte?;
+        while let Some(byte) = read_byte(infd)? {
             match byte {
                 3 => bail!("cancelled"), // ^C
                 4 => break,              // ^D / EOF
@@ -139,9 +539,10 @@ pub fn read_password(query: &str) -> Result<Vec<u8>, Error> {
                     let _ignore_error = out.flush();
                     break;
                 }
-                0x7F => {
+                0x7F | 0x08 => {
                     // backspace
-                    if !password.is_empty() {
+                    if let Some(last) = password.last_mut() {
+                        *last = 0;
                         password.pop();
                         if asterisks {
                             let _ignore_error = out.write_all(b"\x08 \x08");
@@ -150,7 +551,7 @@ pub fn read_password(query: &str) -> Result<Vec<u8>, Error> {
                     }
                 }
                 other => {
-                    password.push(other);
+                    push_secret(&mut password, other);
                     if asterisks {
                         let _ignore_error = out.write_all(b"*");
                         let _ignore_error = out.flush();
@@ -160,28 +561,104 @@ pub fn read_password(query: &str) -> Result<Vec<u8>, Error> {
         }
         Ok(())
     });
-    if unsafe { libc::tcsetattr(infd, libc::TCSANOW, &old_termios) } != 0 {
+    if raw_mode.restore().is_err() {
         // not fatal...
         eprintln!("failed to reset terminal attributes!");
     }
     match ok {
         Ok(_) => Ok(password),
-        Err(e) => Err(e),
+        Err(e) => {
+            zeroize(&mut password);
+            Err(e)
+        }
     }
 }
 
 /// Read a password from stdin, then read again to verify it.
 pub fn read_and_verify_password(prompt: &str) -> Result<Vec<u8>, Error> {
-    let password = String::from_utf8(read_password(prompt)?)?;
-    let verify_password = String::from_utf8(read_password("Verify Password: ")?)?;
+    let mut password = read_password(prompt)?;
+    let mut verify_password = match read_password("Verify Password: ") {
+        Ok(verify_password) => verify_password,
+        Err(err) => {
+            zeroize(&mut password);
+            return Err(err);
+        }
+    };
+
+    let result = if password != verify_password {
+        Err(format_err!("Passwords do not match!"))
+    } else if std::str::from_utf8(&password).is_err() {
+        Err(format_err!("Password is not valid UTF-8!"))
+    } else if password.len() < 5 {
+        Err(format_err!("Password too short!"))
+    } else {
+        Ok(())
+    };
 
-    if password != verify_password {
-        bail!("Passwords do not match!");
+    zeroize(&mut verify_password);
+    match result {
+        Ok(()) => Ok(password),
+        Err(err) => {
+            zeroize(&mut password);
+            Err(err)
+        }
     }
+}
 
-    if password.len() < 5 {
-        bail!("Password too short!");
+#[test]
+fn test_push_secret() {
+    let mut buf = Vec::new();
+    for byte in 0..200u8 {
+        push_secret(&mut buf, byte);
     }
+    assert_eq!(buf, (0..200u8).collect::<Vec<u8>>());
+
+    zeroize(&mut buf);
+    assert!(buf.iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_winsize_watcher() {
+    // signal masks are per thread, and `raise()` targets the calling thread
+    std::thread::spawn(|| {
+        let (master, slave) = crate::sys::linux::pty::open_pty().expect("failed to open pty");
+        master.resize(80, 24).expect("failed to set window size");
+
+        let mut watcher = WinsizeWatcher::new(&slave).expect("failed to create watcher");
+        assert_eq!(watcher.size(), (24, 80));
+        assert!(watcher.changed().expect("failed to check size").is_none());
+
+        master.resize(120, 40).expect("failed to set window size");
+        nix::sys::signal::raise(Signal::SIGWINCH).expect("failed to raise signal");
+        assert_eq!(
+            watcher.wait().expect("failed to wait for change"),
+            (40, 120)
+        );
+        assert_eq!(watcher.size(), (40, 120));
+    })
+    .join()
+    .expect("test thread panicked");
+}
+
+#[test]
+fn test_strip_ansi() {
+    assert_eq!(strip_ansi("plain text"), "plain text");
+    assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m ünïcödé"), "red ünïcödé");
+    assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+    assert_eq!(
+        strip_ansi("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"),
+        "link"
+    );
+    assert_eq!(strip_ansi("\x1b(Bcharset \x1b7saved"), "charset saved");
+    assert_eq!(strip_ansi("\x1bü \x1b(ä"), "ü ä");
 
-    Ok(password.into_bytes())
+    // sequences split across writes
+    let mut out = StripAnsi::new(Vec::new());
+    out.write_all(b"a\x1b[").unwrap();
+    assert!(out.stripper.in_sequence());
+    out.write_all(b"38;5;1").unwrap();
+    out.write_all(b"mb\x1b").unwrap();
+    out.write_all(b"[Kc").unwrap();
+    assert!(!out.stripper.in_sequence());
+    assert_eq!(out.into_inner(), b"abc");
 }
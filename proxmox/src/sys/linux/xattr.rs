@@ -0,0 +1,353 @@
+//! Extended attribute helpers, see `xattr(7)`.
+//!
+//! All functions exist in a variant operating on an open file descriptor (prefixed with `f`) and
+//! one operating on a path. The path variants follow symlinks.
+
+use std::convert::TryInto;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::io::RawFd;
+
+use nix::NixPath;
+
+use crate::c_str;
+use crate::sys::error::SysResult;
+
+/// The name of the extended attribute storing file capabilities.
+pub fn xattr_name_fcaps() -> &'static CStr {
+    c_str!("security.capability")
+}
+
+/// Check whether `name` is the file capability attribute.
+pub fn is_security_capability(name: &CStr) -> bool {
+    name.to_bytes() == xattr_name_fcaps().to_bytes()
+}
+
+/// Check whether `name` is part of the `user.*` namespace.
+pub fn is_user_xattr(name: &CStr) -> bool {
+    name.to_bytes().starts_with(b"user.")
+}
+
+/// The list of attribute names returned by `flistxattr` and `listxattr`.
+pub struct ListXAttr {
+    data: Vec<u8>,
+}
+
+impl ListXAttr {
+    /// Iterate over the attribute names.
+    pub fn iter(&self) -> impl Iterator<Item = &CStr> {
+        self.data
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            // the split removed the terminating nul byte, but it is right after the slice
+            .map(|name| unsafe {
+                CStr::from_bytes_with_nul_unchecked(std::slice::from_raw_parts(
+                    name.as_ptr(),
+                    name.len() + 1,
+                ))
+            })
+    }
+}
+
+impl<'a> IntoIterator for &'a ListXAttr {
+    type Item = &'a CStr;
+    type IntoIter = Box<dyn Iterator<Item = &'a CStr> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+// The size of an attribute (list) may change between querying its size and reading it, so we
+// retry on ERANGE.
+fn read_sized<F>(mut func: F) -> io::Result<Vec<u8>>
+where
+    F: FnMut(*mut libc::c_void, libc::size_t) -> libc::ssize_t,
+{
+    loop {
+        let size = func(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let size = func(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+
+        buffer.truncate(size as usize);
+        return Ok(buffer);
+    }
+}
+
+fn check_rc(rc: libc::c_int) -> io::Result<()> {
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// List the extended attributes of an open file.
+pub fn flistxattr(fd: RawFd) -> io::Result<ListXAttr> {
+    let data = read_sized(|buf, size| unsafe { libc::flistxattr(fd, buf as *mut _, size) })?;
+    Ok(ListXAttr { data })
+}
+
+/// List the extended attributes of a file.
+pub fn listxattr<P: ?Sized + NixPath>(path: &P) -> io::Result<ListXAttr> {
+    let data = path
+        .with_nix_path(|path| {
+            read_sized(|buf, size| unsafe { libc::listxattr(path.as_ptr(), buf as *mut _, size) })
+        })
+        .into_io_result()??;
+    Ok(ListXAttr { data })
+}
+
+/// Read an extended attribute of an open file.
+pub fn fgetxattr(fd: RawFd, name: &CStr) -> io::Result<Vec<u8>> {
+    read_sized(|buf, size| unsafe { libc::fgetxattr(fd, name.as_ptr(), buf, size) })
+}
+
+/// Read an extended attribute of a file.
+pub fn getxattr<P: ?Sized + NixPath>(path: &P, name: &CStr) -> io::Result<Vec<u8>> {
+    path.with_nix_path(|path| {
+        read_sized(|buf, size| unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size) })
+    })
+    .into_io_result()?
+}
+
+/// Set an extended attribute of an open file.
+///
+/// `flags` may be `0`, `libc::XATTR_CREATE` to fail if the attribute exists, or
+/// `libc::XATTR_REPLACE` to fail if it does not exist.
+pub fn fsetxattr(fd: RawFd, name: &CStr, value: &[u8], flags: libc::c_int) -> io::Result<()> {
+    check_rc(unsafe {
+        libc::fsetxattr(
+            fd,
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            flags,
+        )
+    })
+}
+
+/// Set an extended attribute of a file. See `fsetxattr` for the `flags`.
+pub fn setxattr<P: ?Sized + NixPath>(
+    path: &P,
+    name: &CStr,
+    value: &[u8],
+    flags: libc::c_int,
+) -> io::Result<()> {
+    path.with_nix_path(|path| {
+        check_rc(unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                flags,
+            )
+        })
+    })
+    .into_io_result()?
+}
+
+/// Remove an extended attribute of an open file.
+pub fn fremovexattr(fd: RawFd, name: &CStr) -> io::Result<()> {
+    check_rc(unsafe { libc::fremovexattr(fd, name.as_ptr()) })
+}
+
+/// Remove an extended attribute of a file.
+pub fn removexattr<P: ?Sized + NixPath>(path: &P, name: &CStr) -> io::Result<()> {
+    path.with_nix_path(|path| check_rc(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) }))
+        .into_io_result()?
+}
+
+fn user_xattr_name(name: &str) -> io::Result<CString> {
+    CString::new(format!("user.{}", name)).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "attribute name contains a nul byte",
+        )
+    })
+}
+
+/// Read the attribute `user.<name>` of an open file, returns `None` if it does not exist.
+pub fn fget_user_xattr(fd: RawFd, name: &str) -> io::Result<Option<Vec<u8>>> {
+    match fgetxattr(fd, &user_xattr_name(name)?) {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Set the attribute `user.<name>` of an open file.
+pub fn fset_user_xattr(fd: RawFd, name: &str, value: &[u8]) -> io::Result<()> {
+    fsetxattr(fd, &user_xattr_name(name)?, value, 0)
+}
+
+/// Remove the attribute `user.<name>` of an open file. Removing a non-existent attribute is not
+/// an error.
+pub fn fremove_user_xattr(fd: RawFd, name: &str) -> io::Result<()> {
+    match fremovexattr(fd, &user_xattr_name(name)?) {
+        Err(err) if err.raw_os_error() != Some(libc::ENODATA) => Err(err),
+        _ => Ok(()),
+    }
+}
+
+const VFS_CAP_REVISION_MASK: u32 = 0xFF00_0000;
+const VFS_CAP_REVISION_1: u32 = 0x0100_0000;
+const VFS_CAP_REVISION_2: u32 = 0x0200_0000;
+const VFS_CAP_REVISION_3: u32 = 0x0300_0000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+/// File capabilities as stored in the `security.capability` attribute.
+///
+/// The capability sets are bit masks of `1 << CAP_*`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FileCaps {
+    pub permitted: u64,
+    pub inheritable: u64,
+    /// Whether the permitted capabilities are raised in the effective set on `execve`.
+    pub effective: bool,
+    /// The root user id of the user namespace the capabilities are valid in (version 3).
+    pub rootid: Option<u32>,
+}
+
+impl FileCaps {
+    /// Decode the `vfs_cap_data` structure.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid file capabilities");
+
+        let le32 = |ofs: usize| -> io::Result<u32> {
+            let bytes = data.get(ofs..(ofs + 4)).ok_or_else(invalid)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let magic = le32(0)?;
+        let (words, expected_len) = match magic & VFS_CAP_REVISION_MASK {
+            VFS_CAP_REVISION_1 => (1, 12),
+            VFS_CAP_REVISION_2 => (2, 20),
+            VFS_CAP_REVISION_3 => (2, 24),
+            _ => return Err(invalid()),
+        };
+        if data.len() != expected_len {
+            return Err(invalid());
+        }
+
+        let mut caps = FileCaps {
+            effective: (magic & VFS_CAP_FLAGS_EFFECTIVE) != 0,
+            ..Default::default()
+        };
+        for i in 0..words {
+            caps.permitted |= u64::from(le32(4 + i * 8)?) << (32 * i);
+            caps.inheritable |= u64::from(le32(8 + i * 8)?) << (32 * i);
+        }
+        if expected_len == 24 {
+            caps.rootid = Some(le32(20)?);
+        }
+
+        Ok(caps)
+    }
+
+    /// Encode as `vfs_cap_data` structure. This uses version 3 if a `rootid` is set, otherwise
+    /// version 2.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut magic = match self.rootid {
+            Some(_) => VFS_CAP_REVISION_3,
+            None => VFS_CAP_REVISION_2,
+        };
+        if self.effective {
+            magic |= VFS_CAP_FLAGS_EFFECTIVE;
+        }
+
+        let mut data = Vec::with_capacity(24);
+        data.extend_from_slice(&magic.to_le_bytes());
+        for i in 0..2 {
+            data.extend_from_slice(&((self.permitted >> (32 * i)) as u32).to_le_bytes());
+            data.extend_from_slice(&((self.inheritable >> (32 * i)) as u32).to_le_bytes());
+        }
+        if let Some(rootid) = self.rootid {
+            data.extend_from_slice(&rootid.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Read the file capabilities of an open file, returns `None` if there are none.
+pub fn fget_fcaps(fd: RawFd) -> io::Result<Option<FileCaps>> {
+    match fgetxattr(fd, xattr_name_fcaps()) {
+        Ok(data) => Ok(Some(FileCaps::parse(&data)?)),
+        Err(err) if err.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Set the file capabilities of an open file. This requires `CAP_SETFCAP`.
+pub fn fset_fcaps(fd: RawFd, caps: &FileCaps) -> io::Result<()> {
+    fsetxattr(fd, xattr_name_fcaps(), &caps.to_bytes(), 0)
+}
+
+#[test]
+fn test_file_caps() {
+    // CAP_NET_BIND_SERVICE (10) permitted + effective, as set by `setcap cap_net_bind_service+ep`
+    let data = b"\x01\x00\x00\x02\x00\x04\x00\x00\x00\x00\x00\x00\
+                 \x00\x00\x00\x00\x00\x00\x00\x00";
+    let caps = FileCaps::parse(&data[..]).expect("failed to parse file capabilities");
+    assert_eq!(
+        caps,
+        FileCaps {
+            permitted: 1 << 10,
+            inheritable: 0,
+            effective: true,
+            rootid: None,
+        }
+    );
+    assert_eq!(caps.to_bytes(), &data[..]);
+
+    let caps = FileCaps {
+        permitted: 1 << 38,
+        inheritable: 1,
+        effective: false,
+        rootid: Some(100_000),
+    };
+    assert_eq!(FileCaps::parse(&caps.to_bytes()).unwrap(), caps);
+
+    assert!(FileCaps::parse(&data[..12]).is_err());
+}
+
+#[test]
+fn test_user_xattr() {
+    use std::os::unix::io::AsRawFd;
+
+    let path = std::env::temp_dir().join(format!("proxmox-xattr-test-{}", std::process::id()));
+    let file = std::fs::File::create(&path).expect("failed to create test file");
+    let _ = std::fs::remove_file(&path);
+    let fd = file.as_raw_fd();
+
+    match fset_user_xattr(fd, "proxmox.test", b"value") {
+        Ok(()) => (),
+        // tmpfs on older kernels and some other file systems do not support user xattrs
+        Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+        Err(err) => panic!("failed to set xattr: {}", err),
+    }
+
+    assert_eq!(
+        fget_user_xattr(fd, "proxmox.test").expect("failed to get xattr"),
+        Some(b"value".to_vec())
+    );
+    let list = flistxattr(fd).expect("failed to list xattrs");
+    assert!(list
+        .iter()
+        .any(|name| name.to_bytes() == b"user.proxmox.test" && is_user_xattr(name)));
+
+    fremove_user_xattr(fd, "proxmox.test").expect("failed to remove xattr");
+    assert_eq!(fget_user_xattr(fd, "proxmox.test").unwrap(), None);
+    fremove_user_xattr(fd, "proxmox.test").expect("removing a missing xattr should succeed");
+}
@@ -122,14 +122,14 @@ fn timespec_to_opt_duration(v: libc::timespec) -> Option<Duration> {
 
 impl TimerSpec {
     // Helpers to convert between TimerSpec and libc::itimerspec
-    fn to_itimerspec(&self) -> libc::itimerspec {
+    pub(crate) fn to_itimerspec(&self) -> libc::itimerspec {
         libc::itimerspec {
             it_value: opt_duration_to_timespec(self.value),
             it_interval: opt_duration_to_timespec(self.interval),
         }
     }
 
-    fn from_itimerspec(ts: libc::itimerspec) -> Self {
+    pub(crate) fn from_itimerspec(ts: libc::itimerspec) -> Self {
         TimerSpec {
             value: timespec_to_opt_duration(ts.it_value),
             interval: timespec_to_opt_duration(ts.it_interval),
@@ -1,2 +1,3 @@
 pub mod io;
+pub mod process;
 pub mod task;
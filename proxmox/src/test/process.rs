@@ -0,0 +1,40 @@
+//! Running tests in a separate, single threaded process.
+//!
+//! Tests which fork, change process wide state or need a single threaded process re-execute the
+//! test binary for just that test instead of forking the multi threaded test runner:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_something() {
+//!     if !is_helper("module::test_something") {
+//!         return run_in_helper("module::test_something", &[]);
+//!     }
+//!     // this runs in the helper process
+//! }
+//! ```
+
+use std::process::{Command, Stdio};
+
+const HELPER_ENV: &str = "PROXMOX_TEST_HELPER";
+
+/// Whether the current process is the helper process for the test `name`.
+pub fn is_helper(name: &str) -> bool {
+    std::env::var_os(HELPER_ENV).map_or(false, |helper| helper == name)
+}
+
+/// Run only the test `name` (its path without the crate name) in a new instance of the test
+/// binary, with the additional environment variables `envs`, and assert that it succeeds.
+///
+/// With a single test thread the test runs on the main thread, so the helper process is single
+/// threaded.
+pub fn run_in_helper(name: &str, envs: &[(&str, &str)]) {
+    let status = Command::new(std::env::current_exe().expect("failed to get test binary"))
+        .args(&["--exact", name, "--test-threads=1", "--quiet"])
+        .env(HELPER_ENV, name)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .expect("failed to run test helper");
+    assert!(status.success(), "test helper {} failed: {}", name, status);
+}
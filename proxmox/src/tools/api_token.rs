@@ -0,0 +1,182 @@
+//! API token identifiers and secrets.
+//!
+//! An API token belongs to a user and is identified by `user@realm!tokenname`. Its secret is
+//! only shown once on creation; afterwards only a `crypt(3)` hash of it is kept, so the secret
+//! itself never needs to be stored.
+//!
+//! ```
+//! # use proxmox::tools::api_token::{self, ApiTokenId};
+//! # fn code() -> Result<(), anyhow::Error> {
+//! let tokenid: ApiTokenId = "root@pam!backup".parse()?;
+//! assert_eq!(tokenid.userid(), "root@pam");
+//! assert_eq!(tokenid.tokenname(), "backup");
+//!
+//! let secret = api_token::generate_secret()?;
+//! let hash = api_token::hash_secret(&secret)?;
+//! api_token::verify_secret(&secret, &hash)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use anyhow::{bail, Error};
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+use crate::sys::linux::random_array;
+use crate::tools::{bin_to_hex, crypt};
+
+/// An API token identifier (`user@realm!tokenname`).
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ApiTokenId {
+    user: String,
+    realm: String,
+    tokenname: String,
+}
+
+fn verify_user_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 64 {
+        bail!("user name must be between 1 and 64 characters long");
+    }
+    if name
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || matches!(c, ':' | '/' | '@' | '!'))
+    {
+        bail!("invalid user name {:?}", name);
+    }
+    Ok(())
+}
+
+fn verify_name(what: &str, name: &str, max_len: usize) -> Result<(), Error> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphanumeric() => {
+            chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        }
+        _ => false,
+    };
+    if !valid || name.len() > max_len {
+        bail!("invalid {} {:?}", what, name);
+    }
+    Ok(())
+}
+
+impl ApiTokenId {
+    pub fn new(user: &str, realm: &str, tokenname: &str) -> Result<Self, Error> {
+        verify_user_name(user)?;
+        verify_name("realm", realm, 32)?;
+        verify_name("token name", tokenname, 64)?;
+        Ok(Self {
+            user: user.to_string(),
+            realm: realm.to_string(),
+            tokenname: tokenname.to_string(),
+        })
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    pub fn tokenname(&self) -> &str {
+        &self.tokenname
+    }
+
+    /// The owning user's id (`user@realm`).
+    pub fn userid(&self) -> String {
+        format!("{}@{}", self.user, self.realm)
+    }
+}
+
+impl fmt::Display for ApiTokenId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}!{}", self.user, self.realm, self.tokenname)
+    }
+}
+
+impl std::str::FromStr for ApiTokenId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (userid, tokenname) = match s.rfind('!') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!("invalid API token id {:?} - missing token name", s),
+        };
+        let (user, realm) = match userid.rfind('@') {
+            Some(pos) => (&userid[..pos], &userid[(pos + 1)..]),
+            None => bail!("invalid API token id {:?} - missing realm", s),
+        };
+        Self::new(user, realm, tokenname)
+    }
+}
+
+forward_deserialize_to_from_str!(ApiTokenId);
+forward_serialize_to_display!(ApiTokenId);
+
+fn verify_api_token_id(s: &str) -> Result<(), Error> {
+    s.parse::<ApiTokenId>().map(drop)
+}
+
+pub const API_TOKEN_ID_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(verify_api_token_id);
+
+pub const API_TOKEN_ID_SCHEMA: Schema =
+    StringSchema::new("API token identifier (user@realm!tokenname).")
+        .format(&API_TOKEN_ID_FORMAT)
+        .min_length(5)
+        .max_length(164)
+        .schema();
+
+/// Generate a new random token secret from 32 bytes of random data, hex encoded.
+///
+/// This blocks until the kernel's entropy pool is initialized.
+pub fn generate_secret() -> Result<String, Error> {
+    Ok(bin_to_hex(&random_array::<32>()?))
+}
+
+/// Hash a token secret for storage.
+pub fn hash_secret(secret: &str) -> Result<String, Error> {
+    crypt::encrypt_pw(secret)
+}
+
+/// Check a token secret against its stored hash.
+pub fn verify_secret(secret: &str, hash: &str) -> Result<(), Error> {
+    crypt::verify_crypt_pw(secret, hash)
+}
+
+#[test]
+fn test_api_token() {
+    let tokenid: ApiTokenId = "some.user@ldap-corp!ci_token-1".parse().unwrap();
+    assert_eq!(tokenid.user(), "some.user");
+    assert_eq!(tokenid.realm(), "ldap-corp");
+    assert_eq!(tokenid.tokenname(), "ci_token-1");
+    assert_eq!(tokenid.userid(), "some.user@ldap-corp");
+    assert_eq!(tokenid.to_string(), "some.user@ldap-corp!ci_token-1");
+    assert_eq!(
+        serde_json::to_string(&tokenid).unwrap(),
+        "\"some.user@ldap-corp!ci_token-1\""
+    );
+
+    for invalid in &[
+        "root@pam",
+        "root!token",
+        "@pam!token",
+        "root@pam!",
+        "root@pam!-token",
+        "ro ot@pam!token",
+        "root@p!am!token",
+        "root@pam!to:ken",
+    ] {
+        assert!(invalid.parse::<ApiTokenId>().is_err(), "{:?}", invalid);
+    }
+
+    let secret = generate_secret().unwrap();
+    assert_eq!(secret.len(), 64);
+    assert_ne!(secret, generate_secret().unwrap());
+    let hash = hash_secret(&secret).unwrap();
+    assert!(!hash.contains(&secret));
+    verify_secret(&secret, &hash).unwrap();
+    assert!(verify_secret(&generate_secret().unwrap(), &hash).is_err());
+}
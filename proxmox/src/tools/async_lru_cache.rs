@@ -0,0 +1,201 @@
+//! An LRU cache for values which are fetched asynchronously.
+//!
+//! Concurrent accesses to a key which is not cached yet share a single fetch ("single flight"):
+//! the first access starts it, later ones wait for its result instead of fetching again.
+//!
+//! ```
+//! # use proxmox::tools::async_lru_cache::AsyncLruCache;
+//! # async fn code() -> Result<(), anyhow::Error> {
+//! let cache = AsyncLruCache::new(64);
+//!
+//! let chunk = cache.access("digest", async { Ok(Some(vec![1u8, 2, 3])) }).await?;
+//! assert_eq!(chunk, Some(vec![1u8, 2, 3]));
+//!
+//! // cached, this fetch is never polled
+//! let chunk = cache.access("digest", async { Ok(None) }).await?;
+//! assert_eq!(chunk, Some(vec![1u8, 2, 3]));
+//! # Ok(())
+//! # }
+//! # futures::executor::block_on(code()).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+
+use crate::tools::broadcast_future::BroadcastFuture;
+use crate::tools::lru_cache::{CacheStats, LruCache};
+
+struct CacheState<K, V> {
+    cache: LruCache<K, V>,
+    pending: HashMap<K, BroadcastFuture<Option<V>>>,
+}
+
+/// Removes a key from the pending fetches when the fetch completes, fails or panics.
+struct PendingGuard<K: Hash + Eq, V> {
+    state: Arc<Mutex<CacheState<K, V>>>,
+    key: K,
+}
+
+impl<K: Hash + Eq, V> Drop for PendingGuard<K, V> {
+    fn drop(&mut self) {
+        // don't panic while unwinding from a panicking fetch
+        if let Ok(mut state) = self.state.lock() {
+            state.pending.remove(&self.key);
+        }
+    }
+}
+
+/// A thread safe LRU cache with deduplicated asynchronous fetches. Cloning it gives another
+/// handle to the same cache.
+pub struct AsyncLruCache<K, V> {
+    state: Arc<Mutex<CacheState<K, V>>>,
+}
+
+impl<K, V> Clone for AsyncLruCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<K, V> AsyncLruCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Create a new cache holding up to `capacity` values. Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                cache: LruCache::new(capacity),
+                pending: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Get the value for `key`, using `fetch` to get it if it is not cached.
+    ///
+    /// If a fetch for the same key is already in progress, this waits for its result and `fetch`
+    /// is dropped without being polled. Successfully fetched values are cached, `Ok(None)` and
+    /// errors are passed on to all waiting callers but not cached.
+    pub async fn access<F>(&self, key: K, fetch: F) -> Result<Option<V>, Error>
+    where
+        F: Future<Output = Result<Option<V>, Error>> + Send + 'static,
+    {
+        let pending = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(value) = state.cache.get(&key) {
+                return Ok(Some(value.clone()));
+            }
+
+            match state.pending.get(&key) {
+                Some(pending) => pending.clone(),
+                None => {
+                    let guard = PendingGuard {
+                        state: Arc::clone(&self.state),
+                        key: key.clone(),
+                    };
+                    let pending = BroadcastFuture::new(async move {
+                        let result = fetch.await;
+
+                        if let Ok(Some(value)) = &result {
+                            let mut state = guard.state.lock().unwrap();
+                            state.cache.insert(guard.key.clone(), value.clone());
+                        }
+                        result
+                    });
+                    state.pending.insert(key, pending.clone());
+                    pending
+                }
+            }
+        };
+
+        pending.listen().await
+    }
+
+    /// Insert a value directly.
+    pub fn insert(&self, key: K, value: V) {
+        self.state.lock().unwrap().cache.insert(key, value);
+    }
+
+    /// Drop a cached value. Fetches in progress are not affected.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.state.lock().unwrap().cache.remove(key)
+    }
+
+    /// Hit and miss counters of the underlying cache. Accesses waiting for a pending fetch count
+    /// as misses.
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().cache.stats()
+    }
+}
+
+#[test]
+fn test_async_lru_cache() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::bail;
+    use futures::channel::oneshot;
+
+    let cache = AsyncLruCache::new(2);
+    let fetches = Arc::new(AtomicUsize::new(0));
+
+    let (sender, receiver) = oneshot::channel::<()>();
+    let first = {
+        let fetches = Arc::clone(&fetches);
+        cache.access(1, async move {
+            receiver.await.unwrap();
+            fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(10))
+        })
+    };
+    let second = {
+        let fetches = Arc::clone(&fetches);
+        cache.access(1, async move {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(20))
+        })
+    };
+    let release = async move { sender.send(()).unwrap() };
+
+    let (first, second, ()) =
+        futures::executor::block_on(async { futures::join!(first, second, release) });
+    assert_eq!(first.unwrap(), Some(10));
+    assert_eq!(second.unwrap(), Some(10));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    futures::executor::block_on(async {
+        let cached = cache
+            .access(1, async { bail!("must not be fetched") })
+            .await;
+        assert_eq!(cached.unwrap(), Some(10));
+
+        // errors and missing values are not cached
+        assert!(cache
+            .access(2, async { bail!("fetch failed") })
+            .await
+            .is_err());
+        assert_eq!(cache.access(2, async { Ok(None) }).await.unwrap(), None);
+        assert_eq!(
+            cache.access(2, async { Ok(Some(2)) }).await.unwrap(),
+            Some(2)
+        );
+        assert_eq!(cache.remove(&2), Some(2));
+    });
+
+    // a panicking fetch must not leave the key pending forever
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        futures::executor::block_on(cache.access(3, async { panic!("fetch panicked") }))
+    }));
+    assert!(panicked.is_err());
+    assert!(cache.state.lock().unwrap().pending.is_empty());
+    assert_eq!(
+        futures::executor::block_on(cache.access(3, async { Ok(Some(3)) })).unwrap(),
+        Some(3)
+    );
+}
@@ -0,0 +1,118 @@
+//! Base32 encoding and decoding with the RFC 4648 alphabet, as used for TOTP secrets.
+//!
+//! Decoding is case insensitive and accepts input with and without padding.
+//!
+//! ```
+//! use proxmox::tools::base32;
+//!
+//! assert_eq!(base32::encode("foob"), "MZXW6YQ=");
+//! assert_eq!(base32::encode_no_pad("foob"), "MZXW6YQ");
+//! assert_eq!(base32::decode("mzxw6yq").unwrap(), b"foob");
+//! ```
+
+use anyhow::{bail, Error};
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn encode_impl(data: &[u8], pad: bool) -> String {
+    let mut out = String::with_capacity((data.len() + 4) / 5 * 8);
+
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    if pad {
+        while out.len() % 8 != 0 {
+            out.push('=');
+        }
+    }
+
+    out
+}
+
+/// Encode with padding.
+pub fn encode<T: AsRef<[u8]>>(data: T) -> String {
+    encode_impl(data.as_ref(), true)
+}
+
+/// Encode without padding.
+pub fn encode_no_pad<T: AsRef<[u8]>>(data: T) -> String {
+    encode_impl(data.as_ref(), false)
+}
+
+/// Decode base32 data.
+pub fn decode<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, Error> {
+    let data = data.as_ref();
+
+    let unpadded = data.len() - data.iter().rev().take_while(|b| **b == b'=').count();
+    let (data, padding) = data.split_at(unpadded);
+    if !padding.is_empty() && data.len() + padding.len() != (data.len() + 7) / 8 * 8 {
+        bail!("base32 decode failed - invalid padding");
+    }
+    // 1, 3 and 6 trailing characters cannot be produced by an encoder
+    if let 1 | 3 | 6 = data.len() % 8 {
+        bail!("base32 decode failed - invalid length");
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a',
+            b'2'..=b'7' => byte - b'2' + 26,
+            _ => bail!(
+                "base32 decode failed - invalid character {:?}",
+                char::from(*byte)
+            ),
+        };
+        buffer = (buffer << 5) | u32::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(out)
+}
+
+#[test]
+fn test_base32() {
+    // RFC 4648 test vectors
+    let vectors = [
+        ("", ""),
+        ("f", "MY======"),
+        ("fo", "MZXQ===="),
+        ("foo", "MZXW6==="),
+        ("foob", "MZXW6YQ="),
+        ("fooba", "MZXW6YTB"),
+        ("foobar", "MZXW6YTBOI======"),
+    ];
+    for (plain, encoded) in vectors.iter() {
+        assert_eq!(encode(plain), *encoded);
+        assert_eq!(encode_no_pad(plain), encoded.trim_end_matches('='));
+        assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        assert_eq!(
+            decode(encoded.trim_end_matches('=').to_lowercase()).unwrap(),
+            plain.as_bytes()
+        );
+    }
+
+    assert!(decode("MZXW6YQ1").is_err());
+    assert!(decode("MZX").is_err());
+    assert!(decode("MZXW6YQ==").is_err());
+}
@@ -0,0 +1,72 @@
+//! Base64 encoding and decoding (RFC 4648), in the standard and the URL-safe variant.
+//!
+//! Decoding accepts input with and without padding.
+//!
+//! ```
+//! use proxmox::tools::base64;
+//!
+//! assert_eq!(base64::encode("user:pass"), "dXNlcjpwYXNz");
+//! assert_eq!(base64::encode_url_safe_no_pad(&[0xfb, 0xff]), "-_8");
+//! assert_eq!(base64::decode_url_safe("-_8=").unwrap(), [0xfb, 0xff]);
+//! ```
+
+use anyhow::{format_err, Error};
+
+fn encode_config<T: AsRef<[u8]>>(data: T, config: ::base64::Config) -> String {
+    ::base64::encode_config(data.as_ref(), config)
+}
+
+fn decode_config<T: AsRef<[u8]>>(data: T, config: ::base64::Config) -> Result<Vec<u8>, Error> {
+    ::base64::decode_config(data.as_ref(), config)
+        .map_err(|err| format_err!("base64 decode failed - {}", err))
+}
+
+/// Encode with the standard alphabet and padding.
+pub fn encode<T: AsRef<[u8]>>(data: T) -> String {
+    encode_config(data, ::base64::STANDARD)
+}
+
+/// Encode with the standard alphabet without padding.
+pub fn encode_no_pad<T: AsRef<[u8]>>(data: T) -> String {
+    encode_config(data, ::base64::STANDARD_NO_PAD)
+}
+
+/// Encode with the URL-safe alphabet (`-` and `_`) and padding.
+pub fn encode_url_safe<T: AsRef<[u8]>>(data: T) -> String {
+    encode_config(data, ::base64::URL_SAFE)
+}
+
+/// Encode with the URL-safe alphabet (`-` and `_`) without padding.
+pub fn encode_url_safe_no_pad<T: AsRef<[u8]>>(data: T) -> String {
+    encode_config(data, ::base64::URL_SAFE_NO_PAD)
+}
+
+/// Decode data using the standard alphabet.
+pub fn decode<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, Error> {
+    decode_config(data, ::base64::STANDARD)
+}
+
+/// Decode data using the URL-safe alphabet.
+pub fn decode_url_safe<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, Error> {
+    decode_config(data, ::base64::URL_SAFE)
+}
+
+#[test]
+fn test_base64() {
+    let data = b"\x00\x10\x83\x10\x51\x87\x20\x92\x8b\xfb\xff";
+
+    assert_eq!(encode(&data[..]), "ABCDEFGHIJKL+/8=");
+    assert_eq!(encode_no_pad(&data[..]), "ABCDEFGHIJKL+/8");
+    assert_eq!(encode_url_safe(&data[..]), "ABCDEFGHIJKL-_8=");
+    assert_eq!(encode_url_safe_no_pad(&data[..]), "ABCDEFGHIJKL-_8");
+
+    assert_eq!(decode("ABCDEFGHIJKL+/8=").unwrap(), &data[..]);
+    assert_eq!(decode("ABCDEFGHIJKL+/8").unwrap(), &data[..]);
+    assert_eq!(decode_url_safe("ABCDEFGHIJKL-_8").unwrap(), &data[..]);
+    assert!(decode("ABCDEFGHIJKL-_8").is_err());
+    assert!(decode_url_safe("ABCDEFGHIJKL+/8").is_err());
+    assert!(decode("A").is_err());
+
+    assert_eq!(encode(""), "");
+    assert_eq!(decode("").unwrap(), []);
+}
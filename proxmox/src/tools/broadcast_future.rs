@@ -0,0 +1,115 @@
+//! Share the result of a future with any number of listeners.
+//!
+//! ```
+//! # use proxmox::tools::broadcast_future::BroadcastFuture;
+//! # async fn code() -> Result<(), anyhow::Error> {
+//! let login = BroadcastFuture::new(async {
+//!     // connect and authenticate only once ...
+//!     Ok("ticket".to_string())
+//! });
+//!
+//! let (a, b) = futures::join!(login.listen(), login.listen());
+//! assert_eq!(a?, "ticket");
+//! assert_eq!(b?, "ticket");
+//!
+//! // late listeners get the stored result
+//! assert_eq!(login.listen().await?, "ticket");
+//! # Ok(())
+//! # }
+//! # futures::executor::block_on(code()).unwrap();
+//! ```
+
+use std::future::Future;
+
+use anyhow::{bail, format_err, Error};
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt, Shared};
+
+/// Runs an inner future once and hands clones of its result to all listeners.
+///
+/// The inner future is driven by whichever listener is polled, it does not need to be spawned.
+/// Errors are passed on as their message, since `anyhow::Error` cannot be cloned.
+pub struct BroadcastFuture<T> {
+    future: Shared<BoxFuture<'static, Result<T, String>>>,
+}
+
+impl<T> Clone for BroadcastFuture<T> {
+    fn clone(&self) -> Self {
+        Self {
+            future: self.future.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> BroadcastFuture<T> {
+    pub fn new<F>(source: F) -> Self
+    where
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        Self {
+            future: source
+                .map(|result| result.map_err(|err| err.to_string()))
+                .boxed()
+                .shared(),
+        }
+    }
+
+    /// Create a broadcast future which completes with the result sent through the returned
+    /// sender. Listeners get an error if the sender is dropped without sending anything.
+    pub fn new_oneshot() -> (Self, oneshot::Sender<Result<T, Error>>) {
+        let (sender, receiver) = oneshot::channel();
+        let future = Self::new(async move {
+            match receiver.await {
+                Ok(result) => result,
+                Err(_) => bail!("broadcast future sender was dropped"),
+            }
+        });
+        (future, sender)
+    }
+
+    /// Wait for the result.
+    pub fn listen(&self) -> impl Future<Output = Result<T, Error>> {
+        self.future
+            .clone()
+            .map(|result| result.map_err(|err| format_err!("{}", err)))
+    }
+
+    /// Get the result without waiting, if the inner future has already completed.
+    pub fn peek(&self) -> Option<Result<T, Error>> {
+        self.future
+            .peek()
+            .map(|result| result.clone().map_err(|err| format_err!("{}", err)))
+    }
+}
+
+#[test]
+fn test_broadcast_future() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let future = {
+        let runs = Arc::clone(&runs);
+        BroadcastFuture::new(async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(42)
+        })
+    };
+    assert!(future.peek().is_none());
+
+    let results =
+        futures::executor::block_on(futures::future::join_all((0..5).map(|_| future.listen())));
+    assert!(results.into_iter().all(|result| result.unwrap() == 42));
+    assert_eq!(future.clone().peek().unwrap().unwrap(), 42);
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+    let (future, sender) = BroadcastFuture::<u32>::new_oneshot();
+    let listener = future.listen();
+    sender.send(Err(format_err!("login failed"))).unwrap();
+    let err = futures::executor::block_on(listener).unwrap_err();
+    assert_eq!(err.to_string(), "login failed");
+
+    let (future, sender) = BroadcastFuture::<u32>::new_oneshot();
+    drop(sender);
+    assert!(futures::executor::block_on(future.listen()).is_err());
+}
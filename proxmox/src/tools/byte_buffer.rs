@@ -17,8 +17,9 @@
 //! ```
 
 use std::io::{Read, Result};
+use std::os::unix::io::RawFd;
 
-use crate::tools::vec;
+use crate::c_try;
 
 #[cfg(feature = "tokio")]
 use tokio::io::{AsyncRead, AsyncReadExt};
@@ -27,10 +28,14 @@ use tokio::io::{AsyncRead, AsyncReadExt};
 /// has convenience methods for reading (with any reader that
 /// implements std::io::Read or tokio::io::AsyncRead) onto the back
 /// and consuming from the front
+///
+/// The whole buffer is always initialized, so the free part can be handed out to readers
+/// without risking access to uninitialized memory.
 pub struct ByteBuffer {
-    buf: Box<[u8]>,
-    data_size: usize,
-    capacity: usize,
+    buf: Vec<u8>,
+    // the data lives in buf[start..end], consuming only advances `start`
+    start: usize,
+    end: usize,
 }
 
 impl ByteBuffer {
@@ -41,22 +46,74 @@ impl ByteBuffer {
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            buf: vec::undefined(capacity).into_boxed_slice(),
-            data_size: 0,
-            capacity,
+            buf: vec![0u8; capacity],
+            start: 0,
+            end: 0,
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn data_size(&self) -> usize {
+        self.end - self.start
+    }
+
     pub fn free_size(&self) -> usize {
-        self.capacity - self.data_size
+        self.capacity() - self.data_size()
     }
 
     pub fn is_full(&self) -> bool {
-        self.data_size >= self.capacity
+        self.data_size() >= self.capacity()
     }
 
     pub fn clear(&mut self) {
-        self.data_size = 0
+        self.start = 0;
+        self.end = 0;
+    }
+
+    /// Move the data to the front, so that all free space is at the back.
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+    }
+
+    /// Drop `size` bytes from the front.
+    fn advance(&mut self, size: usize) {
+        self.start += size;
+        if self.start == self.end {
+            self.clear();
+        }
+    }
+
+    /// Make sure there are at least `additional` bytes of free space, growing the buffer if
+    /// necessary.
+    ///
+    /// Example:
+    /// ```
+    /// # use proxmox::tools::byte_buffer::ByteBuffer;
+    /// let mut buf = ByteBuffer::with_capacity(16);
+    /// buf.reserve(100);
+    /// assert!(buf.free_size() >= 100);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        self.compact();
+        let needed = self.end + additional;
+        if needed > self.buf.len() {
+            let new_len = needed.max(self.buf.len() * 2);
+            self.buf.resize(new_len, 0);
+        }
+    }
+
+    /// Append data to the back of the buffer, growing it if necessary.
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.reserve(data.len());
+        self.buf[self.end..(self.end + data.len())].copy_from_slice(data);
+        self.end += data.len();
     }
 
     /// Sets the length of the data. Useful if data was manually added
@@ -79,18 +136,50 @@ impl ByteBuffer {
     /// buf.add_size(256);
     /// ```
     pub fn add_size(&mut self, size: usize) {
-        if self.data_size + size > self.capacity {
+        if self.end + size > self.capacity() {
             panic!("size bigger than capacity!");
         }
-        self.data_size += size;
+        self.end += size;
     }
 
     /// Returns a mutable reference to the free section of the
     /// Buffer. There are no guarantees about the content of the
-    /// free part of the Buffer (may even be uninitialized).
+    /// free part of the Buffer (it may contain old data).
     /// see [add_size](#method.add_size) for a usage example.
     pub fn get_free_mut_slice(&mut self) -> &mut [u8] {
-        &mut self.buf[self.data_size..self.capacity]
+        self.compact();
+        &mut self.buf[self.end..]
+    }
+
+    /// Reserve at least `min_free` bytes (see [reserve](#method.reserve)), pass the free part of
+    /// the buffer to `func` and add as many bytes to the data as it returns.
+    ///
+    /// This is the safe alternative to [get_free_mut_slice](#method.get_free_mut_slice) and
+    /// [add_size](#method.add_size).
+    ///
+    /// Example:
+    /// ```
+    /// # use proxmox::tools::byte_buffer::ByteBuffer;
+    /// let mut buf = ByteBuffer::with_capacity(1);
+    /// let amount = buf
+    ///     .read_buf(4, |free| {
+    ///         free[..4].copy_from_slice(b"data");
+    ///         Ok(4)
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(amount, 4);
+    /// assert_eq!(&buf[..], b"data");
+    /// ```
+    ///
+    /// Panics if `func` claims to have written more than the free space.
+    pub fn read_buf<F>(&mut self, min_free: usize, func: F) -> Result<usize>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize>,
+    {
+        self.reserve(min_free);
+        let amount = func(&mut self.buf[self.end..])?;
+        self.add_size(amount);
+        Ok(amount)
     }
 
     /// Removes up to max_amount of data from the front
@@ -113,10 +202,9 @@ impl ByteBuffer {
     /// ```
     #[must_use]
     pub fn remove_data(&mut self, max_amount: usize) -> Box<[u8]> {
-        let size = max_amount.min(self.data_size);
-        let tmp: Box<[u8]> = self.buf[..size].into();
-        self.buf.copy_within(size..self.capacity, 0);
-        self.data_size -= size;
+        let size = max_amount.min(self.data_size());
+        let tmp: Box<[u8]> = self.buf[self.start..(self.start + size)].into();
+        self.advance(size);
         tmp
     }
 
@@ -139,13 +227,8 @@ impl ByteBuffer {
     /// assert!(buf.is_empty());
     /// ```
     pub fn consume(&mut self, max_amount: usize) -> usize {
-        let size = max_amount.min(self.data_size);
-        if size < max_amount {
-            self.clear()
-        } else {
-            self.buf.copy_within(size..self.capacity, 0);
-            self.data_size -= size;
-        }
+        let size = max_amount.min(self.data_size());
+        self.advance(size);
         size
     }
 
@@ -169,6 +252,23 @@ impl ByteBuffer {
         Ok(amount)
     }
 
+    /// Reads everything currently available from a file descriptor (as reported by the
+    /// `FIONREAD` ioctl) into the back of the buffer, growing it if necessary.
+    ///
+    /// If nothing is available, this performs a regular `read` with the remaining free space,
+    /// so it blocks on blocking file descriptors and returns `Ok(0)` at the end of file.
+    pub fn read_available_from(&mut self, fd: RawFd) -> Result<usize> {
+        let mut available: libc::c_int = 0;
+        c_try!(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut available) });
+
+        self.read_buf(available.max(1) as usize, |free| {
+            let got = c_try!(unsafe {
+                libc::read(fd, free.as_mut_ptr() as *mut libc::c_void, free.len())
+            });
+            Ok(got as usize)
+        })
+    }
+
     /// Same as read_from, but for reader that implement tokio::io::AsyncRead.
     /// See [read_from](#method.read_from) for an example
     #[cfg(feature = "tokio")]
@@ -189,13 +289,13 @@ impl std::ops::Deref for ByteBuffer {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.buf[..self.data_size]
+        &self.buf[self.start..self.end]
     }
 }
 
 impl std::ops::DerefMut for ByteBuffer {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.buf[..self.data_size]
+        &mut self.buf[self.start..self.end]
     }
 }
 
@@ -224,4 +324,35 @@ mod test {
         assert_eq!(buffer.len(), size);
         assert_eq!(buffer[0], 54);
     }
+
+    #[test]
+    fn test_consume_and_grow() {
+        let mut buffer = ByteBuffer::with_capacity(8);
+        buffer.extend_from_slice(b"0123456");
+        assert_eq!(buffer.consume(3), 3);
+        assert_eq!(&buffer[..], b"3456");
+        assert_eq!(buffer.free_size(), 4);
+
+        // the consumed space is reused before growing
+        buffer.extend_from_slice(b"789a");
+        assert_eq!(buffer.capacity(), 8);
+        assert!(buffer.is_full());
+        assert_eq!(&buffer[..], b"3456789a");
+
+        buffer.extend_from_slice(b"bc");
+        assert_eq!(buffer.capacity(), 16);
+        assert_eq!(&buffer.remove_data(4)[..], b"3456");
+        assert_eq!(&buffer[..], b"789abc");
+
+        let (pipe_r, pipe_w) = nix::unistd::pipe().unwrap();
+        nix::unistd::write(pipe_w, &[1u8; 100]).unwrap();
+        let amount = buffer.read_available_from(pipe_r).unwrap();
+        assert_eq!(amount, 100);
+        assert_eq!(buffer.len(), 106);
+        assert_eq!(buffer[105], 1);
+
+        nix::unistd::close(pipe_w).unwrap();
+        assert_eq!(buffer.read_available_from(pipe_r).unwrap(), 0);
+        nix::unistd::close(pipe_r).unwrap();
+    }
 }
@@ -0,0 +1,359 @@
+//! Fast non-cryptographic checksums: CRC32 (IEEE) and XXH64.
+//!
+//! Both hashers can be fed incrementally, implement `Write`, and can be attached to another
+//! writer via `ChecksumWriter`:
+//!
+//! ```
+//! use std::io::Write;
+//! use proxmox::tools::checksum::{ChecksumWriter, Crc32};
+//!
+//! let mut writer = ChecksumWriter::new(Vec::new(), Crc32::new());
+//! writer.write_all(b"123456789").unwrap();
+//! let (data, crc) = writer.finish();
+//! assert_eq!(data, b"123456789");
+//! assert_eq!(crc, 0xcbf43926);
+//! ```
+
+use std::io;
+
+/// A checksum which can be computed incrementally.
+pub trait Checksum {
+    type Output;
+
+    /// Feed more data.
+    fn update(&mut self, data: &[u8]);
+
+    /// Get the checksum of all data fed so far.
+    fn finish(&self) -> Self::Output;
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC32 with the IEEE polynomial, as used by zlib, gzip and ethernet.
+#[derive(Clone, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for byte in data {
+            crc = CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.state = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Checksum for Crc32 {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        Crc32::update(self, data)
+    }
+
+    fn finish(&self) -> u32 {
+        Crc32::finish(self)
+    }
+}
+
+/// Compute the CRC32 of a byte slice.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+const PRIME64_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME64_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME64_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME64_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME64_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge_round(acc: u64, value: u64) -> u64 {
+    (acc ^ xxh64_round(0, value))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// The 64 bit variant of xxHash (XXH64).
+#[derive(Clone, Debug)]
+pub struct XxHash64 {
+    seed: u64,
+    acc: [u64; 4],
+    total_len: u64,
+    buffer: [u8; 32],
+    buffered: usize,
+}
+
+impl Default for XxHash64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XxHash64 {
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            total_len: 0,
+            buffer: [0u8; 32],
+            buffered: 0,
+        }
+    }
+
+    fn consume_stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+        for (i, acc) in acc.iter_mut().enumerate() {
+            *acc = xxh64_round(*acc, read_u64(&stripe[i * 8..]));
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.buffered > 0 {
+            let fill = (32 - self.buffered).min(data.len());
+            self.buffer[self.buffered..(self.buffered + fill)].copy_from_slice(&data[..fill]);
+            self.buffered += fill;
+            data = &data[fill..];
+            if self.buffered < 32 {
+                return;
+            }
+            Self::consume_stripe(&mut self.acc, &self.buffer);
+            self.buffered = 0;
+        }
+
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            Self::consume_stripe(&mut self.acc, stripe);
+        }
+
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.acc;
+
+        let mut hash = if self.total_len >= 32 {
+            let hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            self.acc
+                .iter()
+                .fold(hash, |hash, v| xxh64_merge_round(hash, *v))
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            hash ^= xxh64_round(0, read_u64(rest));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            hash ^= u64::from(read_u32(rest)).wrapping_mul(PRIME64_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            hash ^= u64::from(*byte).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^= hash >> 32;
+        hash
+    }
+}
+
+impl Checksum for XxHash64 {
+    type Output = u64;
+
+    fn update(&mut self, data: &[u8]) {
+        XxHash64::update(self, data)
+    }
+
+    fn finish(&self) -> u64 {
+        XxHash64::finish(self)
+    }
+}
+
+/// Compute the XXH64 hash of a byte slice.
+pub fn xxhash64(data: &[u8], seed: u64) -> u64 {
+    let mut hasher = XxHash64::with_seed(seed);
+    hasher.update(data);
+    hasher.finish()
+}
+
+impl io::Write for Crc32 {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.update(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Write for XxHash64 {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.update(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer computing a checksum of everything written through it.
+pub struct ChecksumWriter<W, C> {
+    inner: W,
+    checksum: C,
+}
+
+impl<W: io::Write, C: Checksum> ChecksumWriter<W, C> {
+    pub fn new(inner: W, checksum: C) -> Self {
+        Self { inner, checksum }
+    }
+
+    /// The checksum of the data written so far.
+    pub fn checksum(&self) -> C::Output {
+        self.checksum.finish()
+    }
+
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get the inner writer back together with the final checksum.
+    pub fn finish(self) -> (W, C::Output) {
+        let checksum = self.checksum.finish();
+        (self.inner, checksum)
+    }
+}
+
+impl<W: io::Write, C: Checksum> io::Write for ChecksumWriter<W, C> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(data)?;
+        self.checksum.update(&data[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_checksums() {
+    use std::io::Write;
+
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+    let text = b"Nobody inspects the spammish repetition";
+    assert_eq!(crc32(text), 0xad42_70ed);
+
+    assert_eq!(xxhash64(b"", 0), 0xef46_db37_51d8_e999);
+    assert_eq!(xxhash64(b"a", 0), 0xd24e_c4f1_a98c_6e5b);
+    assert_eq!(xxhash64(b"abc", 0), 0x44bc_2cf5_ad77_0999);
+    assert_eq!(xxhash64(text, 0), 0xfbce_a83c_8a37_8bf1);
+
+    let data: Vec<u8> = (0..100).collect();
+    assert_eq!(xxhash64(&data, 1), 0x3d19_a3a2_098a_7023);
+
+    // feeding in odd pieces has to give the same results
+    let mut crc = Crc32::new();
+    let mut xxh = XxHash64::with_seed(1);
+    for chunk in data.chunks(7) {
+        crc.write_all(chunk).unwrap();
+        xxh.write_all(chunk).unwrap();
+    }
+    assert_eq!(crc.finish(), crc32(&data));
+    assert_eq!(xxh.finish(), 0x3d19_a3a2_098a_7023);
+
+    let mut writer = ChecksumWriter::new(Vec::new(), XxHash64::new());
+    writer.write_all(&text[..10]).unwrap();
+    writer.write_all(&text[10..]).unwrap();
+    assert_eq!(writer.checksum(), 0xfbce_a83c_8a37_8bf1);
+    assert_eq!(writer.finish().0, &text[..]);
+}
@@ -0,0 +1,352 @@
+//! Running external commands.
+//!
+//! `run_command` captures the output of a command, enforces a timeout and checks the exit
+//! status:
+//!
+//! ```no_run
+//! # use std::process::Command;
+//! # use std::time::Duration;
+//! # use anyhow::Error;
+//! # use proxmox::tools::command::{run_command, CommandOptions};
+//! # fn code() -> Result<(), Error> {
+//! let mut cmd = Command::new("zpool");
+//! cmd.arg("list").arg("-H");
+//!
+//! let output = run_command(
+//!     cmd,
+//!     &CommandOptions::new()
+//!         .timeout(Duration::from_secs(10))
+//!         // 'zpool' exits with 1 if there are no pools
+//!         .exit_code_check(|code| code == 0 || code == 1),
+//! )?;
+//! println!("{}", output.stdout_utf8()?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+
+use crate::tools::fd::set_nonblocking;
+
+/// Options for `run_command`.
+pub struct CommandOptions {
+    timeout: Option<Duration>,
+    max_output: usize,
+    exit_code_check: Option<Box<dyn Fn(i32) -> bool>>,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandOptions {
+    /// No timeout, output is limited to 1 MiB per stream, and only exit code 0 is accepted.
+    pub fn new() -> Self {
+        Self {
+            timeout: None,
+            max_output: 1024 * 1024,
+            exit_code_check: None,
+        }
+    }
+
+    /// Kill the command (and its whole process group) after this time.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Limit how many bytes of stdout and stderr are kept. The rest of the output is read and
+    /// discarded, and the output is marked as truncated.
+    pub fn max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Decide which exit codes are considered successful. Commands killed by a signal always
+    /// fail.
+    pub fn exit_code_check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(i32) -> bool + 'static,
+    {
+        self.exit_code_check = Some(Box::new(check));
+        self
+    }
+
+    fn accepts(&self, status: &ExitStatus) -> bool {
+        match (status.code(), &self.exit_code_check) {
+            (Some(code), Some(check)) => check(code),
+            (Some(code), None) => code == 0,
+            (None, _) => false,
+        }
+    }
+}
+
+/// The result of a successful `run_command` call.
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether stdout exceeded the size limit.
+    pub stdout_truncated: bool,
+    /// Whether stderr exceeded the size limit.
+    pub stderr_truncated: bool,
+}
+
+impl CommandOutput {
+    /// Get stdout as a string, failing if it is not valid UTF-8.
+    pub fn stdout_utf8(&self) -> Result<&str, Error> {
+        std::str::from_utf8(&self.stdout)
+            .map_err(|err| format_err!("command output is not valid utf8 - {}", err))
+    }
+
+    /// The exit code, if the command was not killed by a signal.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.code()
+    }
+}
+
+struct Capture {
+    file: Option<File>,
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+impl Capture {
+    fn new<F: IntoRawFd>(pipe: Option<F>) -> Result<Self, Error> {
+        let file = match pipe {
+            Some(pipe) => {
+                let file = unsafe { File::from_raw_fd(pipe.into_raw_fd()) };
+                set_nonblocking(&file, true)?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Self {
+            file,
+            data: Vec::new(),
+            truncated: false,
+        })
+    }
+
+    /// Read what is available, returns `false` once the pipe is closed.
+    fn read(&mut self, max_output: usize) -> io::Result<bool> {
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => return Ok(false),
+        };
+
+        let mut buf = [0u8; 4096];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => {
+                    self.file = None;
+                    return Ok(false);
+                }
+                Ok(got) => {
+                    let keep = got.min(max_output - self.data.len());
+                    self.data.extend_from_slice(&buf[..keep]);
+                    if keep < got {
+                        self.truncated = true;
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+fn remaining_ms(deadline: Option<Instant>) -> libc::c_int {
+    match deadline {
+        None => -1,
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // round up to not wake up too early
+            ((remaining.as_micros() + 999) / 1000).min(libc::c_int::max_value() as u128)
+                as libc::c_int
+        }
+    }
+}
+
+/// Run a command, capturing its stdout and stderr, see the module documentation.
+///
+/// The command runs in a new process group, so the whole group can be killed when the timeout
+/// is reached. Stdin is connected to `/dev/null`. Fails if the command could not be started,
+/// timed out, or exited with a status not accepted by `CommandOptions::exit_code_check`, in
+/// which case the error includes the command's stderr.
+pub fn run_command(mut command: Command, options: &CommandOptions) -> Result<CommandOutput, Error> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| format_err!("failed to execute {:?} - {}", command, err))?;
+    let pgid = Pid::from_raw(child.id() as libc::pid_t);
+
+    let kill = |child: &mut std::process::Child| {
+        let _ = killpg(pgid, Signal::SIGKILL);
+        let _ = child.wait();
+    };
+
+    let mut stdout = Capture::new(child.stdout.take())?;
+    let mut stderr = Capture::new(child.stderr.take())?;
+
+    let timed_out = loop {
+        let mut pfds = Vec::with_capacity(2);
+        for capture in [&stdout, &stderr].iter() {
+            if let Some(ref file) = capture.file {
+                pfds.push(libc::pollfd {
+                    fd: file.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                });
+            }
+        }
+        if pfds.is_empty() {
+            break false;
+        }
+
+        let rc = unsafe {
+            libc::poll(
+                pfds.as_mut_ptr(),
+                pfds.len() as libc::nfds_t,
+                remaining_ms(deadline),
+            )
+        };
+        if rc == 0 {
+            break true;
+        } else if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                kill(&mut child);
+                return Err(err.into());
+            }
+        }
+
+        if let Err(err) = stdout
+            .read(options.max_output)
+            .and_then(|_| stderr.read(options.max_output))
+        {
+            kill(&mut child);
+            return Err(err.into());
+        }
+    };
+
+    // the output was closed, but the process may still be running
+    let status = if timed_out {
+        None
+    } else {
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if remaining_ms(deadline) == 0 {
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    let status = match status {
+        Some(status) => status,
+        None => {
+            kill(&mut child);
+            bail!(
+                "command {:?} timed out after {:?}",
+                command,
+                options.timeout.unwrap_or_default()
+            );
+        }
+    };
+
+    if !options.accepts(&status) {
+        let reason = match (status.code(), status.signal()) {
+            (Some(code), _) => format!("status code: {}", code),
+            (None, Some(signal)) => format!("killed by signal {}", signal),
+            (None, None) => "terminated".to_string(),
+        };
+        let msg = String::from_utf8_lossy(&stderr.data);
+        let msg = msg.trim();
+        if msg.is_empty() {
+            bail!("command {:?} failed - {}", command, reason);
+        }
+        bail!("command {:?} failed - {} - {}", command, reason, msg);
+    }
+
+    Ok(CommandOutput {
+        status,
+        stdout: stdout.data,
+        stderr: stderr.data,
+        stdout_truncated: stdout.truncated,
+        stderr_truncated: stderr.truncated,
+    })
+}
+
+#[test]
+fn test_run_command() {
+    let sh = |script: &str| {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    };
+
+    let output =
+        run_command(sh("echo out; echo err >&2"), &CommandOptions::new()).expect("command failed");
+    assert_eq!(output.stdout_utf8().unwrap(), "out\n");
+    assert_eq!(output.stderr, b"err\n");
+    assert_eq!(output.exit_code(), Some(0));
+
+    let err = run_command(sh("echo broken >&2; exit 2"), &CommandOptions::new())
+        .expect_err("failing command succeeded");
+    assert!(err.to_string().ends_with("status code: 2 - broken"));
+
+    let output = run_command(
+        sh("exit 2"),
+        &CommandOptions::new().exit_code_check(|code| code == 2),
+    )
+    .expect("exit code check was ignored");
+    assert_eq!(output.exit_code(), Some(2));
+
+    let output = run_command(
+        sh("head -c 10000 /dev/zero"),
+        &CommandOptions::new().max_output(100),
+    )
+    .expect("command failed");
+    assert_eq!(output.stdout.len(), 100);
+    assert!(output.stdout_truncated);
+
+    // the background process keeps the pipe open, and is killed along with the group
+    let start = Instant::now();
+    let err = run_command(
+        sh("sleep 10 & sleep 10"),
+        &CommandOptions::new().timeout(Duration::from_millis(200)),
+    )
+    .expect_err("command did not time out");
+    assert!(err.to_string().contains("timed out"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,137 @@
+//! Password hashing with `crypt(3)` from libxcrypt.
+//!
+//! New hashes use yescrypt, verification works with every method libxcrypt supports, including
+//! the sha512-crypt (`$6$`) and sha256-crypt (`$5$`) hashes found in older `shadow` files.
+//!
+//! ```no_run
+//! # use proxmox::tools::crypt::{encrypt_pw, verify_crypt_pw};
+//! # fn code() -> Result<(), anyhow::Error> {
+//! let hash = encrypt_pw("hunter2")?;
+//! verify_crypt_pw("hunter2", &hash)?;
+//! assert!(verify_crypt_pw("hunter3", &hash).is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::{CStr, CString};
+
+use anyhow::{bail, format_err, Error};
+
+// see /usr/include/crypt.h
+const CRYPT_OUTPUT_SIZE: usize = 384;
+const CRYPT_MAX_PASSPHRASE_SIZE: usize = 512;
+const CRYPT_DATA_RESERVED_SIZE: usize = 767;
+const CRYPT_DATA_INTERNAL_SIZE: usize = 30720;
+const CRYPT_GENSALT_OUTPUT_SIZE: usize = 192;
+
+#[repr(C)]
+struct CryptData {
+    output: [libc::c_char; CRYPT_OUTPUT_SIZE],
+    setting: [libc::c_char; CRYPT_OUTPUT_SIZE],
+    input: [libc::c_char; CRYPT_MAX_PASSPHRASE_SIZE],
+    reserved: [libc::c_char; CRYPT_DATA_RESERVED_SIZE],
+    initialized: libc::c_char,
+    internal: [libc::c_char; CRYPT_DATA_INTERNAL_SIZE],
+}
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt_r(
+        phrase: *const libc::c_char,
+        setting: *const libc::c_char,
+        data: *mut CryptData,
+    ) -> *mut libc::c_char;
+
+    fn crypt_gensalt_rn(
+        prefix: *const libc::c_char,
+        count: libc::c_ulong,
+        rbytes: *const libc::c_char,
+        nrbytes: libc::c_int,
+        output: *mut libc::c_char,
+        output_size: libc::c_int,
+    ) -> *mut libc::c_char;
+}
+
+/// Hash `password` with the method and salt given by `setting`, which is either a salt from
+/// `crypt_gensalt` or a complete hash.
+pub fn crypt(password: &[u8], setting: &[u8]) -> Result<String, Error> {
+    let password = CString::new(password)?;
+    let setting = CString::new(setting)?;
+
+    // 32k, so keep it off the stack of the caller; it has to be zeroed before the first use
+    let mut data: Box<CryptData> = Box::new(unsafe { std::mem::zeroed() });
+
+    let hash = unsafe {
+        let res = crypt_r(password.as_ptr(), setting.as_ptr(), &mut *data);
+        if res.is_null() {
+            bail!("crypt failed - {}", std::io::Error::last_os_error());
+        }
+        CStr::from_ptr(res)
+    };
+
+    // failures are reported as hashes starting with '*', which can never match
+    let hash = hash.to_str()?;
+    if hash.starts_with('*') {
+        bail!("crypt failed - invalid setting");
+    }
+
+    Ok(hash.to_string())
+}
+
+/// Create a setting string for `crypt` for the method identified by `prefix` (like `$y$` or
+/// `$6$`) from random bytes. A `count` of 0 selects the method's default cost.
+pub fn crypt_gensalt(prefix: &str, count: u64, random: &[u8]) -> Result<String, Error> {
+    let prefix = CString::new(prefix)?;
+    let mut output = [0 as libc::c_char; CRYPT_GENSALT_OUTPUT_SIZE];
+
+    let res = unsafe {
+        crypt_gensalt_rn(
+            prefix.as_ptr(),
+            count as libc::c_ulong,
+            random.as_ptr() as *const libc::c_char,
+            random.len() as libc::c_int,
+            output.as_mut_ptr(),
+            output.len() as libc::c_int,
+        )
+    };
+    if res.is_null() {
+        bail!("crypt_gensalt failed - {}", std::io::Error::last_os_error());
+    }
+
+    let setting = unsafe { CStr::from_ptr(output.as_ptr()) };
+    Ok(setting.to_str()?.to_string())
+}
+
+/// Hash a password with yescrypt and a random salt.
+pub fn encrypt_pw(password: &str) -> Result<String, Error> {
+    let mut random = [0u8; 16];
+    crate::sys::linux::fill_with_random_data(&mut random)?;
+    let setting = crypt_gensalt("$y$", 0, &random)?;
+    crypt(password.as_bytes(), setting.as_bytes())
+}
+
+/// Check a password against a hash created by `encrypt_pw` or any other `crypt(3)` hash.
+pub fn verify_crypt_pw(password: &str, enc_password: &str) -> Result<(), Error> {
+    let verify = crypt(password.as_bytes(), enc_password.as_bytes())
+        .map_err(|err| format_err!("unable to verify password - {}", err))?;
+    if !crate::tools::constant_time_eq(verify.as_bytes(), enc_password.as_bytes()) {
+        bail!("invalid credentials");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_crypt() {
+    let sha512 = "$6$saltsaltsaltsalt$dJX0KuyM7nCXuBPN.nYtdrJDotQEB1rFBumzix6FHzliKxLBinGY49pMJNvCoNf9fHmNyZ1IgX/glbcKhuXoh.";
+    verify_crypt_pw("hunter2", sha512).expect("failed to verify sha512-crypt hash");
+    assert!(verify_crypt_pw("hunter3", sha512).is_err());
+
+    let hash = encrypt_pw("hunter2").expect("failed to hash password");
+    assert!(hash.starts_with("$y$"));
+    verify_crypt_pw("hunter2", &hash).expect("failed to verify yescrypt hash");
+    assert!(verify_crypt_pw("hunter3", &hash).is_err());
+    assert_ne!(encrypt_pw("hunter2").unwrap(), hash);
+
+    assert!(crypt(b"hunter2", b"$invalid$").is_err());
+    assert!(verify_crypt_pw("hunter2", "").is_err());
+}
@@ -0,0 +1,180 @@
+//! SHA-256 digests of streams and files.
+//!
+//! ```
+//! use std::io::Write;
+//! use proxmox::tools::digest::{sha256, DigestWriter};
+//!
+//! let digest = sha256(&b"abc"[..]).unwrap();
+//! assert_eq!(
+//!     digest.to_string(),
+//!     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+//! );
+//!
+//! let mut writer = DigestWriter::new(Vec::new());
+//! writer.write_all(b"abc").unwrap();
+//! let (data, written_digest) = writer.finish();
+//! assert_eq!(data, b"abc");
+//! assert_eq!(written_digest, digest);
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use anyhow::{format_err, Error};
+use openssl::sha::Sha256;
+
+use crate::tools::hex;
+
+/// A SHA-256 digest, displayed as lower case hex string.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Sha256Digest(pub [u8; 32]);
+
+impl Sha256Digest {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl From<[u8; 32]> for Sha256Digest {
+    fn from(digest: [u8; 32]) -> Self {
+        Self(digest)
+    }
+}
+
+impl From<Sha256Digest> for [u8; 32] {
+    fn from(digest: Sha256Digest) -> Self {
+        digest.0
+    }
+}
+
+impl AsRef<[u8]> for Sha256Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Sha256Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&hex::AsHex(&self.0), f)
+    }
+}
+
+impl fmt::Debug for Sha256Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sha256Digest({})", self)
+    }
+}
+
+impl std::str::FromStr for Sha256Digest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut digest = [0u8; 32];
+        hex::decode_to_slice(s, &mut digest)?;
+        Ok(Self(digest))
+    }
+}
+
+forward_deserialize_to_from_str!(Sha256Digest);
+forward_serialize_to_display!(Sha256Digest);
+
+/// Compute the SHA-256 digest of everything read from `reader` until EOF.
+pub fn sha256<R: Read>(mut reader: R) -> io::Result<Sha256Digest> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(got) => hasher.update(&buffer[..got]),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Sha256Digest(hasher.finish()))
+}
+
+/// Compute the SHA-256 digest of a file's contents.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<Sha256Digest, Error> {
+    let path = path.as_ref();
+
+    std::fs::File::open(path)
+        .and_then(sha256)
+        .map_err(|err| format_err!("unable to compute digest of {:?} - {}", path, err))
+}
+
+/// A writer computing the SHA-256 digest of everything written through it.
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The digest of the data written so far.
+    pub fn digest(&self) -> Sha256Digest {
+        Sha256Digest(self.hasher.clone().finish())
+    }
+
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get the inner writer back together with the final digest.
+    pub fn finish(self) -> (W, Sha256Digest) {
+        (self.inner, Sha256Digest(self.hasher.finish()))
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(data)?;
+        self.hasher.update(&data[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_sha256() {
+    let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    assert_eq!(sha256(io::empty()).unwrap().to_string(), empty);
+
+    let data = vec![b'x'; 100_000];
+    let expected: Sha256Digest = "d69e68988157833272305aaf21f453c800346e8a3640db6578e260215542e5d4"
+        .parse()
+        .unwrap();
+    assert_eq!(sha256(&data[..]).unwrap(), expected);
+
+    let path = std::env::temp_dir().join(format!("proxmox-digest-test-{}", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    let result = sha256_file(&path);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(result.unwrap(), expected);
+    assert!(sha256_file(&path).is_err());
+
+    let mut writer = DigestWriter::new(io::sink());
+    assert_eq!(writer.digest().to_string(), empty);
+    for chunk in data.chunks(999) {
+        writer.write_all(chunk).unwrap();
+    }
+    assert_eq!(writer.finish().1, expected);
+
+    assert_eq!(
+        serde_json::to_string(&expected).unwrap(),
+        format!("\"{}\"", expected)
+    );
+}
@@ -1,6 +1,7 @@
 //! Raw file descriptor related structures.
 
 use std::borrow::Borrow;
+use std::io;
 use std::marker::PhantomData;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 
@@ -8,6 +9,8 @@ use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 use nix::NixPath;
 
+use crate::c_str;
+
 /// Guard a raw file descriptor with a drop handler. This is mostly useful when access to an owned
 /// `RawFd` is required without the corresponding handler object (such as when only the file
 /// descriptor number is required in a closure which may be dropped instead of being executed).
@@ -194,3 +197,188 @@ impl<'a, T: ?Sized + AsRawFd> From<&'a T> for BorrowedFd<'a> {
         Self::new(fd)
     }
 }
+
+fn fcntl_update(
+    fd: RawFd,
+    get: libc::c_int,
+    set: libc::c_int,
+    flag: libc::c_int,
+    on: bool,
+) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, get) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let new_flags = if on { flags | flag } else { flags & !flag };
+    if new_flags != flags && unsafe { libc::fcntl(fd, set, new_flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Set or clear the close-on-exec flag (`FD_CLOEXEC`) of a file descriptor.
+pub fn set_cloexec<F: ?Sized + AsRawFd>(fd: &F, on: bool) -> io::Result<()> {
+    fcntl_update(
+        fd.as_raw_fd(),
+        libc::F_GETFD,
+        libc::F_SETFD,
+        libc::FD_CLOEXEC,
+        on,
+    )
+}
+
+/// Set or clear the `O_NONBLOCK` flag of a file descriptor.
+///
+/// Note that this flag belongs to the open file description, so it affects all duplicates of the
+/// file descriptor, including ones in other processes.
+pub fn set_nonblocking<F: ?Sized + AsRawFd>(fd: &F, on: bool) -> io::Result<()> {
+    fcntl_update(
+        fd.as_raw_fd(),
+        libc::F_GETFL,
+        libc::F_SETFL,
+        libc::O_NONBLOCK,
+        on,
+    )
+}
+
+// close_range has the same number on all architectures
+const SYS_CLOSE_RANGE: libc::c_long = 436;
+
+/// Close all file descriptors starting from `min_fd`, eg. in a child process before executing
+/// another program.
+///
+/// This uses `close_range(2)` (Linux 5.9). On older kernels the open file descriptors are looked
+/// up in `/proc/self/fd`. Neither allocates memory, so this can be used after `fork()`ing a
+/// multi-threaded process.
+pub fn close_all_fds_from(min_fd: RawFd) -> io::Result<()> {
+    let min_fd = min_fd.max(0);
+
+    let rc = unsafe {
+        libc::syscall(
+            SYS_CLOSE_RANGE,
+            min_fd as libc::c_uint,
+            libc::c_uint::max_value(),
+            0 as libc::c_uint,
+        )
+    };
+    if rc == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(err);
+    }
+
+    close_fds_from_proc(min_fd)
+}
+
+/// Close file descriptors by reading `/proc/self/fd` with raw system calls only.
+fn close_fds_from_proc(min_fd: RawFd) -> io::Result<()> {
+    let dir = unsafe {
+        libc::open(
+            c_str!("/proc/self/fd").as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if dir < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let dir = Fd(dir);
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                dir.as_raw_fd(),
+                buf.as_mut_ptr(),
+                std::mem::size_of_val(&buf),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        } else if len == 0 {
+            return Ok(());
+        }
+
+        // `struct linux_dirent64`: 8 byte inode, 8 byte offset, 2 byte record length, 1 byte type,
+        // followed by the name
+        let data = &buf[..(len as usize)];
+        let mut closed = false;
+        let mut pos = 0;
+        while pos < data.len() {
+            let reclen = u16::from_ne_bytes([data[pos + 16], data[pos + 17]]) as usize;
+            let name = &data[(pos + 19)..(pos + reclen)];
+            pos += reclen;
+
+            // parse the name by hand, skipping "." and ".."
+            let mut fd: RawFd = 0;
+            let mut valid = false;
+            for &c in name {
+                match c {
+                    0 => break,
+                    c @ b'0'..=b'9' => {
+                        fd = fd.saturating_mul(10).saturating_add(RawFd::from(c - b'0'));
+                        valid = true;
+                    }
+                    _ => {
+                        valid = false;
+                        break;
+                    }
+                }
+            }
+
+            if valid && fd >= min_fd && fd != dir.as_raw_fd() {
+                unsafe { libc::close(fd) };
+                closed = true;
+            }
+        }
+
+        // closing entries changes the directory, so start over
+        if closed && unsafe { libc::lseek(dir.as_raw_fd(), 0, libc::SEEK_SET) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+}
+
+#[test]
+fn test_fd_flags() {
+    let (a, b) = nix::unistd::pipe().expect("failed to create pipe");
+    let (a, b) = unsafe { (Fd::from_raw_fd(a), Fd::from_raw_fd(b)) };
+
+    let fd_flags = |fd: &Fd| unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    let fl_flags = |fd: &Fd| unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+
+    set_cloexec(&a, true).expect("failed to set FD_CLOEXEC");
+    assert!(fd_flags(&a) & libc::FD_CLOEXEC != 0);
+    set_cloexec(&a, false).expect("failed to clear FD_CLOEXEC");
+    assert!(fd_flags(&a) & libc::FD_CLOEXEC == 0);
+
+    set_nonblocking(&b, true).expect("failed to set O_NONBLOCK");
+    assert!(fl_flags(&b) & libc::O_NONBLOCK != 0);
+    set_nonblocking(&b, false).expect("failed to clear O_NONBLOCK");
+    assert!(fl_flags(&b) & libc::O_NONBLOCK == 0);
+
+    // closing file descriptors affects the whole test process, so do this in a child, which may
+    // only use raw system calls
+    let (low, high) = (
+        a.as_raw_fd().min(b.as_raw_fd()),
+        a.as_raw_fd().max(b.as_raw_fd()),
+    );
+    match unsafe { nix::unistd::fork() }.expect("fork failed") {
+        nix::unistd::ForkResult::Child => {
+            let ok = close_fds_from_proc(high).is_ok()
+                && unsafe { libc::fcntl(low, libc::F_GETFD) } >= 0
+                && unsafe { libc::fcntl(high, libc::F_GETFD) } < 0
+                && close_all_fds_from(low).is_ok()
+                && unsafe { libc::fcntl(low, libc::F_GETFD) } < 0;
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+        nix::unistd::ForkResult::Parent { child } => {
+            let status = nix::sys::wait::waitpid(child, None).expect("waitpid failed");
+            assert_eq!(status, nix::sys::wait::WaitStatus::Exited(child, 0));
+        }
+    }
+}
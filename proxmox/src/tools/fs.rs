@@ -1,8 +1,9 @@
 //! File related utilities such as `replace_file`.
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -16,8 +17,48 @@ use serde_json::Value;
 
 use crate::sys::error::SysResult;
 use crate::sys::timer;
-use crate::tools::fd::Fd;
-use crate::try_block;
+use crate::tools::fd::{BorrowedFd, Fd};
+use crate::{c_str, try_block};
+
+/// Read a whole file, failing with `InvalidData` if it is larger than `max_size` bytes.
+fn read_bounded(path: &Path, max_size: Option<usize>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut data = Vec::new();
+    match max_size {
+        None => {
+            file.read_to_end(&mut data)?;
+        }
+        Some(max_size) => {
+            file.take((max_size as u64).saturating_add(1))
+                .read_to_end(&mut data)?;
+            if data.len() > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("file exceeds the maximum size of {} bytes", max_size),
+                ));
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn read_bounded_string(path: &Path, max_size: Option<usize>) -> io::Result<String> {
+    String::from_utf8(read_bounded(path, max_size)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn optional<T>(path: &Path, result: io::Result<T>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(content) => Ok(Some(content)),
+        Err(err) => {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                Ok(None)
+            } else {
+                bail!("unable to read '{:?}' - {}", path, err);
+            }
+        }
+    }
+}
 
 /// Read the entire contents of a file into a bytes vector
 ///
@@ -29,6 +70,18 @@ pub fn file_get_contents<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
     std::fs::read(path).map_err(|err| format_err!("unable to read {:?} - {}", path, err))
 }
 
+/// Read the entire contents of a file into a bytes vector, failing if the file is larger than
+/// `max_size` bytes.
+///
+/// Use this instead of `file_get_contents` for files which may be modified by others, to not
+/// run out of memory.
+pub fn file_get_contents_max<P: AsRef<Path>>(path: P, max_size: usize) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+
+    read_bounded(path, Some(max_size))
+        .map_err(|err| format_err!("unable to read {:?} - {}", path, err))
+}
+
 /// Read the entire contents of a file into a bytes vector if the file exists
 ///
 /// Same as file_get_contents(), but returns 'Ok(None)' instead of
@@ -36,16 +89,7 @@ pub fn file_get_contents<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
 pub fn file_get_optional_contents<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>, Error> {
     let path = path.as_ref();
 
-    match std::fs::read(path) {
-        Ok(content) => Ok(Some(content)),
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                Ok(None)
-            } else {
-                bail!("unable to read '{:?}' - {}", path, err);
-            }
-        }
-    }
+    optional(path, read_bounded(path, None))
 }
 
 /// Read the entire contents of a file into a String
@@ -65,16 +109,17 @@ pub fn file_read_string<P: AsRef<Path>>(path: P) -> Result<String, Error> {
 pub fn file_read_optional_string<P: AsRef<Path>>(path: P) -> Result<Option<String>, Error> {
     let path = path.as_ref();
 
-    match std::fs::read_to_string(path) {
-        Ok(content) => Ok(Some(content)),
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                Ok(None)
-            } else {
-                bail!("unable to read '{:?}' - {}", path, err);
-            }
-        }
-    }
+    optional(path, read_bounded_string(path, None))
+}
+
+/// Same as `file_read_optional_string`, but fails if the file is larger than `max_size` bytes.
+pub fn file_read_optional_string_max<P: AsRef<Path>>(
+    path: P,
+    max_size: usize,
+) -> Result<Option<String>, Error> {
+    let path = path.as_ref();
+
+    optional(path, read_bounded_string(path, Some(max_size)))
 }
 
 /// Read .json file into a ``Value``
@@ -121,6 +166,48 @@ pub fn file_read_firstline<P: AsRef<Path>>(path: P) -> Result<String, Error> {
     .map_err(|err: Error| format_err!("unable to read {:?} - {}", path, err))
 }
 
+/// Read the first line of a file as String, failing if the line (including the newline) is
+/// longer than `max_size` bytes.
+pub fn file_read_firstline_max<P: AsRef<Path>>(path: P, max_size: usize) -> Result<String, Error> {
+    let path = path.as_ref();
+
+    try_block!({
+        let file = std::fs::File::open(path)?;
+
+        let mut reader = BufReader::new(file.take((max_size as u64).saturating_add(1)));
+
+        let mut line = String::new();
+
+        let len = reader.read_line(&mut line)?;
+
+        if len > max_size {
+            bail!("first line exceeds the maximum size of {} bytes", max_size);
+        }
+
+        Ok(line)
+    })
+    .map_err(|err: Error| format_err!("unable to read {:?} - {}", path, err))
+}
+
+#[test]
+fn test_bounded_reads() {
+    let path = std::env::temp_dir().join(format!("proxmox-bounded-test-{}", std::process::id()));
+    std::fs::write(&path, b"first line\nsecond line\n").expect("failed to write test file");
+
+    let result = (|| -> Result<(), Error> {
+        assert_eq!(file_get_contents_max(&path, 23)?.len(), 23);
+        assert!(file_get_contents_max(&path, 22).is_err());
+        assert_eq!(file_read_firstline_max(&path, 11)?, "first line\n");
+        assert!(file_read_firstline_max(&path, 10).is_err());
+        assert!(file_read_optional_string_max(&path, 10).is_err());
+        Ok(())
+    })();
+    let _ = std::fs::remove_file(&path);
+    result.expect("bounded read failed");
+
+    assert_eq!(file_read_optional_string_max(&path, 10).unwrap(), None);
+}
+
 /// Takes a Path and CreateOptions, creates a tmpfile from it and returns
 /// a RawFd and PathBuf for it
 pub fn make_tmp_file<P: AsRef<Path>>(
@@ -158,33 +245,194 @@ pub fn make_tmp_file<P: AsRef<Path>>(
     Ok((fd, tmp_path))
 }
 
+/// An anonymous temporary file created via `make_tmp_file_in`.
+///
+/// The file has no name until `persist` is called, if it is dropped before that, its contents
+/// are simply discarded. There is no need to clean up after failures or crashes.
+pub struct TmpFile {
+    file: File,
+}
+
+impl TmpFile {
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Give the file a name, making it appear atomically with its complete contents.
+    ///
+    /// The `path` must be on the same file system as the directory the file was created in and
+    /// must not exist yet. Note that the data is not synced to disk, use
+    /// `as_file().sync_all()` before if this is required.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> Result<File, Error> {
+        let path = path.as_ref();
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| format_err!("path {:?} contains a nul byte", path))?;
+
+        let rc = unsafe {
+            libc::linkat(
+                self.file.as_raw_fd(),
+                c_str!("").as_ptr(),
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                libc::AT_EMPTY_PATH,
+            )
+        };
+        if rc == 0 {
+            return Ok(self.file);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOENT) {
+            bail!("linkat {:?} failed: {}", path, err);
+        }
+
+        // AT_EMPTY_PATH requires CAP_DAC_READ_SEARCH, the magic link in /proc works without
+        let proc_path = CString::new(format!("/proc/self/fd/{}", self.file.as_raw_fd())).unwrap();
+        let rc = unsafe {
+            libc::linkat(
+                libc::AT_FDCWD,
+                proc_path.as_ptr(),
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                libc::AT_SYMLINK_FOLLOW,
+            )
+        };
+        if rc != 0 {
+            bail!("linkat {:?} failed: {}", path, io::Error::last_os_error());
+        }
+
+        Ok(self.file)
+    }
+}
+
+/// Create an anonymous temporary file in the directory `dir` using `O_TMPFILE`.
+///
+/// Contrary to `make_tmp_file` the file has no name, so there are no name collisions and no
+/// leftovers after crashes. Use `TmpFile::persist` to link it into the file system once it is
+/// completely written.
+pub fn make_tmp_file_in<P: AsRef<Path>>(dir: P, options: CreateOptions) -> Result<TmpFile, Error> {
+    let dir = dir.as_ref();
+
+    // clippy bug?: from_bits_truncate is actually a const fn...
+    #[allow(clippy::or_fun_call)]
+    let mode: stat::Mode = options
+        .perm
+        .unwrap_or(stat::Mode::from_bits_truncate(0o644));
+
+    // must not use O_EXCL, as this prevents linking the file later on
+    let fd = Fd::open(
+        dir,
+        OFlag::O_TMPFILE | OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        mode,
+    )
+    .map_err(|err| format_err!("unable to create temporary file in {:?} - {}", dir, err))?;
+
+    // the mode passed to open() is subject to the umask
+    stat::fchmod(fd.as_raw_fd(), mode)
+        .map_err(|err| format_err!("fchmod temporary file in {:?} failed: {}", dir, err))?;
+
+    if options.owner.is_some() || options.group.is_some() {
+        fchown(fd.as_raw_fd(), options.owner, options.group)
+            .map_err(|err| format_err!("fchown temporary file in {:?} failed: {}", dir, err))?;
+    }
+
+    Ok(TmpFile {
+        file: unsafe { File::from_raw_fd(fd.into_raw_fd()) },
+    })
+}
+
+#[test]
+fn test_make_tmp_file_in() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("proxmox-tmpfile-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut tmp = match make_tmp_file_in(&dir, CreateOptions::new()) {
+        Ok(tmp) => tmp,
+        // O_TMPFILE is not supported by all file systems
+        Err(_) => return,
+    };
+    tmp.as_file_mut()
+        .write_all(b"data")
+        .expect("failed to write temporary file");
+    assert!(!path.exists());
+
+    tmp.persist(&path)
+        .expect("failed to persist temporary file");
+
+    let mut contents = Vec::new();
+    File::open(&path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .expect("failed to read persisted file");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(contents, b"data");
+}
+
 /// Atomically replace a file.
 ///
-/// This first creates a temporary file and then rotates it in place.
+/// This first creates a temporary file in the same directory, applies the permissions and
+/// ownership from `options` and then rotates it in place. Both the file and the directory are
+/// synced to disk, so after a crash either the old or the new contents are found.
 pub fn replace_file<P: AsRef<Path>>(
     path: P,
     data: &[u8],
     options: CreateOptions,
 ) -> Result<(), Error> {
-    let (fd, tmp_path) = make_tmp_file(&path, options)?;
+    let path = path.as_ref();
+    let (fd, tmp_path) = make_tmp_file(path, options)?;
 
     let mut file = unsafe { File::from_raw_fd(fd.into_raw_fd()) };
 
-    if let Err(err) = file.write_all(data) {
+    if let Err(err) = file.write_all(data).and_then(|_| file.sync_all()) {
         let _ = unistd::unlink(&tmp_path);
         bail!("write failed: {}", err);
     }
 
-    if let Err(err) = std::fs::rename(&tmp_path, &path) {
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
         let _ = unistd::unlink(&tmp_path);
-        bail!(
-            "Atomic rename failed for file {:?} - {}",
-            path.as_ref(),
-            err
-        );
+        bail!("Atomic rename failed for file {:?} - {}", path, err);
     }
 
-    Ok(())
+    fsync_parent_dir(path)
+}
+
+/// Sync the directory containing `path`, to persist renames and newly created entries.
+pub fn fsync_parent_dir<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| format_err!("fsync of directory {:?} failed - {}", dir, err))
+}
+
+#[test]
+fn test_replace_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("proxmox-replace-test-{}", std::process::id()));
+
+    replace_file(&path, b"first", CreateOptions::new()).expect("failed to create file");
+    replace_file(
+        &path,
+        b"second",
+        CreateOptions::new().perm(stat::Mode::from_bits_truncate(0o600)),
+    )
+    .expect("failed to replace file");
+
+    let contents = std::fs::read(&path).expect("failed to read replaced file");
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(contents, b"second");
+    assert_eq!(mode & 0o777, 0o600);
 }
 
 /// Change ownership of an open file handle
@@ -201,6 +449,87 @@ pub fn fchown(fd: RawFd, owner: Option<Uid>, group: Option<Gid>) -> Result<(), E
     Ok(())
 }
 
+const RENAME_NOREPLACE: libc::c_uint = 1;
+const RENAME_EXCHANGE: libc::c_uint = 2;
+
+/// Rename a file via `renameat2(2)`, with a combination of the `RENAME_*` flags.
+///
+/// Paths are relative to their directory file descriptors, unless they are absolute.
+pub fn renameat2<D1, P1, D2, P2>(
+    old_dirfd: &D1,
+    old_path: &P1,
+    new_dirfd: &D2,
+    new_path: &P2,
+    flags: libc::c_uint,
+) -> io::Result<()>
+where
+    D1: ?Sized + AsRawFd,
+    P1: ?Sized + nix::NixPath,
+    D2: ?Sized + AsRawFd,
+    P2: ?Sized + nix::NixPath,
+{
+    // use the syscall directly, older glibc versions do not provide a wrapper
+    let rc = old_path
+        .with_nix_path(|old_path| {
+            new_path.with_nix_path(|new_path| unsafe {
+                libc::syscall(
+                    libc::SYS_renameat2,
+                    old_dirfd.as_raw_fd(),
+                    old_path.as_ptr(),
+                    new_dirfd.as_raw_fd(),
+                    new_path.as_ptr(),
+                    flags,
+                )
+            })
+        })
+        .and_then(|rc| rc)
+        .into_io_result()?;
+
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Rename `from` to `to`, failing with `AlreadyExists` if `to` exists, instead of replacing it.
+///
+/// This allows atomically publishing a file only if nobody else did so already.
+pub fn rename_noreplace<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let cwd = Fd::cwd();
+    renameat2(&cwd, from.as_ref(), &cwd, to.as_ref(), RENAME_NOREPLACE)
+}
+
+/// Atomically exchange the two paths `a` and `b`, which both need to exist. They can be of
+/// different types, eg. a file and a directory.
+pub fn rename_exchange<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> io::Result<()> {
+    let cwd = Fd::cwd();
+    renameat2(&cwd, a.as_ref(), &cwd, b.as_ref(), RENAME_EXCHANGE)
+}
+
+#[test]
+fn test_renameat2() {
+    let base = std::env::temp_dir().join(format!("proxmox-rename-test-{}", std::process::id()));
+    let a = base.with_extension("a");
+    let b = base.with_extension("b");
+    let c = base.with_extension("c");
+    std::fs::write(&a, b"a").expect("failed to create test file");
+    std::fs::write(&b, b"b").expect("failed to create test file");
+
+    let err = rename_noreplace(&a, &b).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    rename_exchange(&a, &b).expect("failed to exchange files");
+    assert_eq!(std::fs::read(&a).unwrap(), b"b");
+    assert_eq!(std::fs::read(&b).unwrap(), b"a");
+
+    rename_noreplace(&a, &c).expect("failed to rename file");
+    assert!(!a.exists());
+    assert_eq!(std::fs::read(&c).unwrap(), b"b");
+
+    let _ = std::fs::remove_file(&b);
+    let _ = std::fs::remove_file(&c);
+}
+
 // FIXME: Consider using derive-builder!
 #[derive(Clone, Default)]
 pub struct CreateOptions {
@@ -258,6 +587,11 @@ pub fn create_dir<P: AsRef<Path>>(path: P, options: CreateOptions) -> Result<(),
 /// Returns `true` if the final directory was created. Otherwise `false` is returned and no changes
 /// to the directory's metadata have been performed.
 ///
+/// The metadata is only applied to newly created directories. Permissions are set explicitly
+/// after creating a directory, so they are not affected by the umask. Directories are opened
+/// relative to their parent one by one, so a concurrent rename of a component cannot make the
+/// metadata end up on a different directory.
+///
 /// ```no_run
 /// # use nix::sys::stat::Mode;
 /// # use nix::unistd::{Gid, Uid};
@@ -351,23 +685,32 @@ fn create_path_at_do(
                     final_opts.as_ref()
                 };
 
+                let perm = opts.and_then(|o| o.perm);
+
                 // clippy bug?: from_bits_truncate is actually a const fn...
                 #[allow(clippy::or_fun_call)]
-                let mode = opts
-                    .and_then(|o| o.perm)
-                    .unwrap_or(stat::Mode::from_bits_truncate(0o755));
+                let mode = perm.unwrap_or(stat::Mode::from_bits_truncate(0o755));
 
                 created = match stat::mkdirat(at.as_raw_fd(), path, mode) {
                     Err(nix::Error::Sys(Errno::EEXIST)) => false,
                     Err(e) => return Err(e.into()),
                     Ok(_) => true,
                 };
-                at = Fd::openat(&at, path, OFlag::O_DIRECTORY, stat::Mode::empty())?;
+                at = Fd::openat(
+                    &at,
+                    path,
+                    OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+                    stat::Mode::empty(),
+                )?;
 
                 if let (true, Some(opts)) = (created, opts) {
                     if opts.owner.is_some() || opts.group.is_some() {
                         fchown(at.as_raw_fd(), opts.owner, opts.group)?;
                     }
+                    // the mode passed to mkdirat() is subject to the umask
+                    if let Some(perm) = perm {
+                        stat::fchmod(at.as_raw_fd(), perm)?;
+                    }
                 }
             }
 
@@ -391,9 +734,538 @@ fn test_create_path() {
     .expect("expected create_path to work");
 }
 
-// /usr/include/linux/fs.h: #define BLKGETSIZE64 _IOR(0x12,114,size_t)
-// return device size in bytes (u64 *arg)
-nix::ioctl_read!(blkgetsize64, 0x12, 114, u64);
+#[test]
+fn test_create_path_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let base = std::env::temp_dir().join(format!("proxmox-create-path-{}", std::process::id()));
+    let path = base.join("testsub/testsub2/testfinal");
+
+    let created = create_path(
+        &path,
+        Some(CreateOptions::new().perm(stat::Mode::from_bits_truncate(0o755))),
+        Some(
+            CreateOptions::new()
+                .perm(stat::Mode::from_bits_truncate(0o777))
+                .owner(Uid::effective())
+                .group(Gid::effective()),
+        ),
+    );
+    let final_mode = std::fs::metadata(&path).map(|m| m.permissions().mode());
+    let inter_mode = std::fs::metadata(path.parent().unwrap()).map(|m| m.permissions().mode());
+    let again = create_path(&path, None, None);
+    let _ = std::fs::remove_dir_all(&base);
+
+    assert!(created.expect("expected create_path to work"));
+    assert!(!again.expect("expected create_path to work on existing path"));
+    // not affected by the umask
+    assert_eq!(final_mode.unwrap() & 0o777, 0o777);
+    assert_eq!(inter_mode.unwrap() & 0o777, 0o755);
+}
+
+// /usr/include/linux/fs.h: #define FS_IOC_GETFLAGS _IOR('f', 1, long)
+nix::ioctl_read!(fs_ioc_getflags, b'f', 1, libc::c_long);
+// /usr/include/linux/fs.h: #define FS_IOC_SETFLAGS _IOW('f', 2, long)
+nix::ioctl_write_ptr!(fs_ioc_setflags, b'f', 2, libc::c_long);
+
+const FS_IMMUTABLE_FL: libc::c_long = 0x0000_0010;
+const FS_APPEND_FL: libc::c_long = 0x0000_0020;
+
+/// Clear the immutable and append-only flags of an open file. Returns `false` if none were set
+/// or the file system does not support them. Requires `CAP_LINUX_IMMUTABLE`.
+fn clear_immutable_flags(fd: RawFd) -> Result<bool, nix::Error> {
+    // the kernel actually uses an int, the 'long' in the ioctl number is historical
+    let mut flags: libc::c_long = 0;
+    match unsafe { fs_ioc_getflags(fd, &mut flags) } {
+        Ok(_) => (),
+        Err(nix::Error::Sys(Errno::ENOTTY)) | Err(nix::Error::Sys(Errno::EOPNOTSUPP)) => {
+            return Ok(false)
+        }
+        Err(err) => return Err(err),
+    }
+
+    let flags = flags & 0xffff_ffff;
+    if flags & (FS_IMMUTABLE_FL | FS_APPEND_FL) == 0 {
+        return Ok(false);
+    }
+    let flags = flags & !(FS_IMMUTABLE_FL | FS_APPEND_FL);
+    unsafe { fs_ioc_setflags(fd, &flags) }?;
+    Ok(true)
+}
+
+struct RemoveRoot {
+    dev: u64,
+    mnt_id: Option<u64>,
+}
+
+impl RemoveRoot {
+    fn check_same_mount(&self, dir: &Fd, path: &Path) -> Result<(), Error> {
+        use crate::sys::linux::statx::{fstatx, StatxAttributes};
+
+        let stx = fstatx(dir).map_err(|err| format_err!("stat of {:?} failed - {}", path, err))?;
+        let mount_root = stx.attributes_mask.contains(StatxAttributes::MOUNT_ROOT)
+            && stx.attributes.contains(StatxAttributes::MOUNT_ROOT);
+        let other_mount = match (self.mnt_id, stx.mnt_id) {
+            (Some(a), Some(b)) => a != b,
+            _ => false,
+        };
+        if stx.dev != self.dev || mount_root || other_mount {
+            bail!("refusing to cross mount point at {:?}", path);
+        }
+        Ok(())
+    }
+}
+
+fn remove_entry_at(dir: &Fd, name: &CStr, is_dir: bool) -> Result<(), nix::Error> {
+    let flag = if is_dir {
+        unistd::UnlinkatFlags::RemoveDir
+    } else {
+        unistd::UnlinkatFlags::NoRemoveDir
+    };
+
+    match unistd::unlinkat(Some(dir.as_raw_fd()), name, flag) {
+        Err(nix::Error::Sys(Errno::EPERM)) if !is_dir => {
+            // symlinks cannot carry these flags and fail to open with ELOOP
+            let file = Fd::openat(
+                dir,
+                name,
+                OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+                stat::Mode::empty(),
+            )?;
+            if !clear_immutable_flags(file.as_raw_fd())? {
+                return Err(nix::Error::Sys(Errno::EPERM));
+            }
+            drop(file);
+            unistd::unlinkat(Some(dir.as_raw_fd()), name, flag)
+        }
+        other => other,
+    }
+}
+
+fn remove_dir_contents(dir: &Fd, path: &Path, root: &RemoveRoot) -> Result<(), Error> {
+    use nix::dir::{Dir, Type};
+
+    // entries of immutable or append-only directories cannot be removed
+    let _ = clear_immutable_flags(dir.as_raw_fd());
+
+    let mut entries = Vec::new();
+    let mut iter = Dir::openat(
+        dir.as_raw_fd(),
+        ".",
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        stat::Mode::empty(),
+    )
+    .map_err(|err| format_err!("unable to read directory {:?} - {}", path, err))?;
+    for entry in iter.iter() {
+        let entry =
+            entry.map_err(|err| format_err!("unable to read directory {:?} - {}", path, err))?;
+        let name = entry.file_name();
+        if name.to_bytes() == b"." || name.to_bytes() == b".." {
+            continue;
+        }
+        entries.push((name.to_owned(), entry.file_type()));
+    }
+    drop(iter);
+
+    for (name, file_type) in entries {
+        let entry_path = path.join(std::ffi::OsStr::from_bytes(name.to_bytes()));
+
+        let is_dir = match file_type {
+            Some(file_type) => file_type == Type::Directory,
+            None => {
+                let st = stat::fstatat(
+                    dir.as_raw_fd(),
+                    name.as_c_str(),
+                    nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+                )
+                .map_err(|err| format_err!("stat of {:?} failed - {}", entry_path, err))?;
+                (st.st_mode & libc::S_IFMT) == libc::S_IFDIR
+            }
+        };
+
+        if is_dir {
+            let subdir = Fd::openat(
+                dir,
+                name.as_c_str(),
+                OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+                stat::Mode::empty(),
+            )
+            .map_err(|err| format_err!("unable to open directory {:?} - {}", entry_path, err))?;
+            root.check_same_mount(&subdir, &entry_path)?;
+            remove_dir_contents(&subdir, &entry_path, root)?;
+            drop(subdir);
+            remove_entry_at(dir, &name, true)
+        } else {
+            remove_entry_at(dir, &name, false)
+        }
+        .map_err(|err| format_err!("unable to remove {:?} - {}", entry_path, err))?;
+    }
+
+    Ok(())
+}
+
+/// Recursively remove a directory, more carefully than `std::fs::remove_dir_all`.
+///
+/// * Symlinks are never followed, also not for `path` itself.
+/// * Mount points are not crossed, instead the removal fails once one is encountered, before
+///   anything on the mounted file system is touched.
+/// * Immutable and append-only flags are cleared if necessary (requires `CAP_LINUX_IMMUTABLE`).
+/// * With a `marker`, the directory is only removed if it contains a regular file of this name,
+///   as protection against misconfigured paths.
+///
+/// Refuses to remove `/`.
+pub fn remove_dir_all_safe<P: AsRef<Path>>(path: P, marker: Option<&str>) -> Result<(), Error> {
+    use crate::sys::linux::statx::fstatx;
+
+    let path = path.as_ref();
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => bail!("refusing to remove {:?}", path),
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+
+    let parent_fd = Fd::open(
+        parent,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        stat::Mode::empty(),
+    )
+    .map_err(|err| format_err!("unable to open directory {:?} - {}", parent, err))?;
+    let dir = Fd::openat(
+        &parent_fd,
+        name,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+        stat::Mode::empty(),
+    )
+    .map_err(|err| format_err!("unable to open directory {:?} - {}", path, err))?;
+
+    if let Some(marker) = marker {
+        let is_file = stat::fstatat(
+            dir.as_raw_fd(),
+            marker,
+            nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        )
+        .map(|st| (st.st_mode & libc::S_IFMT) == libc::S_IFREG)
+        .unwrap_or(false);
+        if !is_file {
+            bail!(
+                "refusing to remove {:?} - marker file {:?} not found",
+                path,
+                marker
+            );
+        }
+    }
+
+    let stx = fstatx(&dir).map_err(|err| format_err!("stat of {:?} failed - {}", path, err))?;
+    let root = RemoveRoot {
+        dev: stx.dev,
+        mnt_id: stx.mnt_id,
+    };
+
+    remove_dir_contents(&dir, path, &root)?;
+    // the directory itself cannot be removed if it is immutable
+    let _ = clear_immutable_flags(dir.as_raw_fd());
+    drop(dir);
+
+    unistd::unlinkat(
+        Some(parent_fd.as_raw_fd()),
+        name,
+        unistd::UnlinkatFlags::RemoveDir,
+    )
+    .map_err(|err| format_err!("unable to remove {:?} - {}", path, err))
+}
+
+#[test]
+fn test_remove_dir_all_safe() {
+    let base = std::env::temp_dir().join(format!("proxmox-remove-test-{}", std::process::id()));
+    let outside = std::env::temp_dir().join(format!("proxmox-remove-keep-{}", std::process::id()));
+
+    std::fs::create_dir_all(base.join("a/b/c")).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(base.join("a/file"), b"data").unwrap();
+    std::fs::write(base.join("a/b/c/file"), b"data").unwrap();
+    std::fs::write(outside.join("keep"), b"data").unwrap();
+    std::os::unix::fs::symlink(&outside, base.join("a/link")).unwrap();
+
+    remove_dir_all_safe(&base, Some(".marker")).expect_err("removed directory without marker");
+    assert!(base.join("a/file").exists());
+
+    std::fs::write(base.join(".marker"), b"").unwrap();
+    let result = remove_dir_all_safe(&base, Some(".marker"));
+    let removed = !base.exists();
+    let kept = outside.join("keep").exists();
+    let _ = std::fs::remove_dir_all(&outside);
+    let _ = std::fs::remove_dir_all(&base);
+
+    result.expect("failed to remove directory");
+    assert!(removed);
+    // the symlink was removed, not followed
+    assert!(kept);
+
+    remove_dir_all_safe("/", None).expect_err("tried to remove /");
+}
+
+/// Iterator over the entries of a directory, see `read_subdir`. The `.` and `..` entries are
+/// skipped.
+pub struct ReadDir {
+    dir: std::ptr::NonNull<libc::DIR>,
+    path: PathBuf,
+}
+
+unsafe impl Send for ReadDir {}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.dir.as_ptr()) };
+    }
+}
+
+impl AsRawFd for ReadDir {
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe { libc::dirfd(self.dir.as_ptr()) }
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<ReadDirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // readdir only signals errors via errno, so it has to be reset first
+            unsafe { *libc::__errno_location() = 0 };
+            let entry = unsafe { libc::readdir64(self.dir.as_ptr()) };
+            if entry.is_null() {
+                return match Errno::last() {
+                    Errno::UnknownErrno => None,
+                    err => Some(Err(format_err!(
+                        "unable to read directory {:?} - {}",
+                        self.path,
+                        err
+                    ))),
+                };
+            }
+
+            let entry = unsafe { &*entry };
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+            if name.to_bytes() == b"." || name.to_bytes() == b".." {
+                continue;
+            }
+
+            return Some(Ok(ReadDirEntry {
+                name: name.to_owned(),
+                ino: entry.d_ino,
+                d_type: entry.d_type,
+                parent_fd: self.as_raw_fd(),
+            }));
+        }
+    }
+}
+
+/// A directory entry returned by `ReadDir`.
+#[derive(Clone, Debug)]
+pub struct ReadDirEntry {
+    name: CString,
+    ino: u64,
+    d_type: u8,
+    parent_fd: RawFd,
+}
+
+impl ReadDirEntry {
+    /// The name of the entry.
+    pub fn file_name(&self) -> &CStr {
+        &self.name
+    }
+
+    /// The name of the entry, failing if it is not valid UTF-8.
+    pub fn file_name_utf8(&self) -> Result<&str, Error> {
+        self.name
+            .to_str()
+            .map_err(|_| format_err!("file name {:?} is not valid utf8", self.name))
+    }
+
+    /// The inode number of the entry.
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The file descriptor of the directory, for use with the `*at` functions. It is only valid
+    /// as long as the `ReadDir` is alive.
+    pub fn parent_fd(&self) -> RawFd {
+        self.parent_fd
+    }
+
+    /// The type of the entry. This is usually provided by the file system along with the name,
+    /// otherwise it is queried with `fstatat(2)`, without following symlinks.
+    pub fn file_type(&self) -> Result<nix::dir::Type, nix::Error> {
+        use nix::dir::Type;
+
+        let file_type = match self.d_type {
+            libc::DT_DIR => Type::Directory,
+            libc::DT_REG => Type::File,
+            libc::DT_LNK => Type::Symlink,
+            libc::DT_FIFO => Type::Fifo,
+            libc::DT_SOCK => Type::Socket,
+            libc::DT_CHR => Type::CharacterDevice,
+            libc::DT_BLK => Type::BlockDevice,
+            _ => {
+                let st = stat::fstatat(
+                    self.parent_fd,
+                    self.name.as_c_str(),
+                    nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+                )?;
+                match st.st_mode & libc::S_IFMT {
+                    libc::S_IFDIR => Type::Directory,
+                    libc::S_IFREG => Type::File,
+                    libc::S_IFLNK => Type::Symlink,
+                    libc::S_IFIFO => Type::Fifo,
+                    libc::S_IFSOCK => Type::Socket,
+                    libc::S_IFCHR => Type::CharacterDevice,
+                    libc::S_IFBLK => Type::BlockDevice,
+                    _ => return Err(nix::Error::Sys(Errno::EINVAL)),
+                }
+            }
+        };
+        Ok(file_type)
+    }
+}
+
+/// Open the directory `path` relative to `dirfd` (which may be `libc::AT_FDCWD`) and iterate over
+/// its entries.
+pub fn read_subdir<P: ?Sized + AsRef<Path>>(dirfd: RawFd, path: &P) -> Result<ReadDir, Error> {
+    let path = path.as_ref();
+
+    let fd = nix::fcntl::openat(
+        dirfd,
+        path,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        stat::Mode::empty(),
+    )
+    .map_err(|err| format_err!("unable to open directory {:?} - {}", path, err))?;
+
+    let dir = unsafe { libc::fdopendir(fd) };
+    match std::ptr::NonNull::new(dir) {
+        Some(dir) => Ok(ReadDir {
+            dir,
+            path: path.to_owned(),
+        }),
+        None => {
+            let err = io::Error::last_os_error();
+            let _ = unistd::close(fd);
+            bail!("unable to open directory {:?} - {}", path, err);
+        }
+    }
+}
+
+/// Which entries `scandir` should report.
+#[derive(Clone, Copy, Debug)]
+pub enum ScandirFilter<'a> {
+    /// All entries.
+    All,
+    /// Entries whose names match a regular expression.
+    Regex(&'a regex::Regex),
+    /// Entries with this file name extension (without the dot).
+    Extension(&'a str),
+}
+
+impl ScandirFilter<'_> {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            ScandirFilter::All => true,
+            ScandirFilter::Regex(regex) => regex.is_match(name),
+            ScandirFilter::Extension(ext) => Path::new(name)
+                .extension()
+                .map(|e| e.as_bytes() == ext.as_bytes())
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Call `callback` with the parent directory's file descriptor, name and type of every entry in
+/// the directory `path` relative to `dirfd` that passes the `filter`. Entries with names which are
+/// not valid UTF-8 are skipped.
+///
+/// ```no_run
+/// # use proxmox::tools::fs::{scandir, ScandirFilter};
+/// # fn code() -> Result<(), anyhow::Error> {
+/// scandir(libc::AT_FDCWD, "/etc/pve/qemu-server", ScandirFilter::Extension("conf"), |_, name, _| {
+///     println!("found config {}", name);
+///     Ok(())
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn scandir<P, F>(
+    dirfd: RawFd,
+    path: &P,
+    filter: ScandirFilter,
+    mut callback: F,
+) -> Result<(), Error>
+where
+    P: ?Sized + AsRef<Path>,
+    F: FnMut(RawFd, &str, nix::dir::Type) -> Result<(), Error>,
+{
+    let path = path.as_ref();
+
+    for entry in read_subdir(dirfd, path)? {
+        let entry = entry?;
+        let name = match entry.file_name().to_str() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !filter.matches(name) {
+            continue;
+        }
+
+        let file_type = entry
+            .file_type()
+            .map_err(|err| format_err!("stat of {:?} failed - {}", path.join(name), err))?;
+        callback(entry.parent_fd(), name, file_type)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_scandir() {
+    use nix::dir::Type;
+
+    let base = std::env::temp_dir().join(format!("proxmox-scandir-test-{}", std::process::id()));
+    std::fs::create_dir_all(base.join("subdir")).unwrap();
+    std::fs::write(base.join("100.conf"), b"").unwrap();
+    std::fs::write(base.join("101.conf"), b"").unwrap();
+    std::fs::write(base.join("notes.txt"), b"").unwrap();
+
+    let scan = |filter| -> Result<Vec<(String, Type)>, Error> {
+        let mut found = Vec::new();
+        scandir(libc::AT_FDCWD, &base, filter, |_, name, file_type| {
+            found.push((name.to_string(), file_type));
+            Ok(())
+        })?;
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(found)
+    };
+
+    let regex = regex::Regex::new(r"^\d+\.conf$").unwrap();
+    let all = scan(ScandirFilter::All);
+    let by_ext = scan(ScandirFilter::Extension("conf"));
+    let by_regex = scan(ScandirFilter::Regex(&regex));
+    let _ = std::fs::remove_dir_all(&base);
+
+    let all = all.expect("scandir failed");
+    assert_eq!(all.len(), 4);
+    assert!(all.contains(&("subdir".to_string(), Type::Directory)));
+    assert!(all.contains(&("notes.txt".to_string(), Type::File)));
+
+    let confs = vec![
+        ("100.conf".to_string(), Type::File),
+        ("101.conf".to_string(), Type::File),
+    ];
+    assert_eq!(by_ext.expect("scandir failed"), confs);
+    assert_eq!(by_regex.expect("scandir failed"), confs);
+}
 
 /// Return file or block device size
 pub fn image_size(path: &Path) -> Result<u64, Error> {
@@ -404,13 +1276,8 @@ pub fn image_size(path: &Path) -> Result<u64, Error> {
     let file_type = metadata.file_type();
 
     if file_type.is_block_device() {
-        let mut size: u64 = 0;
-        let res = unsafe { blkgetsize64(file.as_raw_fd(), &mut size) };
-
-        if let Err(err) = res {
-            bail!("blkgetsize64 failed for {:?} - {}", path, err);
-        }
-        Ok(size)
+        crate::sys::linux::blockdev::size(file.as_raw_fd())
+            .map_err(|err| format_err!("blkgetsize64 failed for {:?} - {}", path, err))
     } else if file_type.is_file() {
         Ok(metadata.len())
     } else {
@@ -474,6 +1341,243 @@ pub fn lock_file<F: AsRawFd>(
     Ok(())
 }
 
+/// Open file description (OFD) byte range lock, see `fcntl(2)`.
+///
+/// Contrary to classic POSIX record locks, OFD locks are bound to the open file description
+/// rather than the process. This means they are not released when the process closes some other
+/// file descriptor referring to the same file, and threads can lock against each other by opening
+/// the file separately.
+///
+/// Locks taken via the same open file description (including duplicated file descriptors) never
+/// conflict. They replace each other for overlapping ranges instead, so dropping any of their
+/// guards unlocks the range for all of them. Open the file separately for each independent lock.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use proxmox::tools::fs::OfdLock;
+/// # fn code() -> std::io::Result<()> {
+/// let file = std::fs::OpenOptions::new().write(true).open("/run/example.lck")?;
+/// let _guard = OfdLock::exclusive().lock(&file, Some(Duration::from_secs(10)))?;
+/// // ... modify the protected data, the lock is released when the guard is dropped
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct OfdLock {
+    exclusive: bool,
+    start: u64,
+    len: u64,
+}
+
+impl OfdLock {
+    /// An exclusive (write) lock of the whole file.
+    pub fn exclusive() -> Self {
+        Self {
+            exclusive: true,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// A shared (read) lock of the whole file.
+    pub fn shared() -> Self {
+        Self {
+            exclusive: false,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Only lock `len` bytes starting at `start`. A `len` of `0` locks up to the end of the file,
+    /// even if it grows.
+    pub fn range(mut self, start: u64, len: u64) -> Self {
+        self.start = start;
+        self.len = len;
+        self
+    }
+
+    fn flock(&self, lock_type: libc::c_int) -> libc::flock {
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        lock.l_type = lock_type as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        lock.l_start = self.start as libc::off_t;
+        lock.l_len = self.len as libc::off_t;
+        // l_pid must be zero for OFD locks
+        lock
+    }
+
+    fn lock_type(&self) -> libc::c_int {
+        if self.exclusive {
+            libc::F_WRLCK
+        } else {
+            libc::F_RDLCK
+        }
+    }
+
+    /// Try to acquire the lock without waiting. Returns `None` if a conflicting lock is held.
+    pub fn try_lock<'a, F: ?Sized + AsRawFd>(
+        &self,
+        file: &'a F,
+    ) -> io::Result<Option<OfdLockGuard<'a>>> {
+        let lock = self.flock(self.lock_type());
+        let rc = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &lock) };
+        if rc != 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EACCES) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        Ok(Some(OfdLockGuard {
+            fd: BorrowedFd::new(file),
+            lock: *self,
+        }))
+    }
+
+    /// Acquire the lock, waiting for at most `timeout` if it is set.
+    ///
+    /// Like `lock_file`, this uses the `SIGTIMEOUT` timer signal to interrupt the waiting call, an
+    /// expired timeout results in a `TimedOut` error.
+    pub fn lock<'a, F: ?Sized + AsRawFd>(
+        &self,
+        file: &'a F,
+        timeout: Option<Duration>,
+    ) -> io::Result<OfdLockGuard<'a>> {
+        let timeout = match timeout {
+            Some(timeout) if timeout.as_nanos() == 0 => {
+                return self
+                    .try_lock(file)?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "file is locked"));
+            }
+            other => other,
+        };
+
+        let mut _sigblock_guard = None;
+        let mut _timer = None;
+        if let Some(timeout) = timeout {
+            // unblock the timeout signal temporarily
+            _sigblock_guard = Some(timer::unblock_timeout_signal());
+
+            // setup a timeout timer
+            let mut timer = timer::Timer::create(
+                timer::Clock::Realtime,
+                timer::TimerEvent::ThisThreadSignal(timer::SIGTIMEOUT),
+            )?;
+
+            timer.arm(
+                timer::TimerSpec::new()
+                    .value(Some(timeout))
+                    .interval(Some(Duration::from_millis(10))),
+            )?;
+            _timer = Some(timer);
+        }
+
+        let lock = self.flock(self.lock_type());
+        let rc = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLKW, &lock) };
+        if rc != 0 {
+            let err = io::Error::last_os_error();
+            if timeout.is_some() && err.kind() == io::ErrorKind::Interrupted {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for lock",
+                ));
+            }
+            return Err(err);
+        }
+
+        Ok(OfdLockGuard {
+            fd: BorrowedFd::new(file),
+            lock: *self,
+        })
+    }
+}
+
+/// An acquired `OfdLock`, the lock is released when this is dropped.
+///
+/// This also releases locks of the same range taken via the same open file description, see
+/// `OfdLock`.
+pub struct OfdLockGuard<'a> {
+    fd: BorrowedFd<'a>,
+    lock: OfdLock,
+}
+
+impl OfdLockGuard<'_> {
+    /// Release the lock, reporting errors.
+    pub fn unlock(self) -> io::Result<()> {
+        let rc = self.do_unlock();
+        std::mem::forget(self);
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn do_unlock(&self) -> libc::c_int {
+        let lock = self.lock.flock(libc::F_UNLCK);
+        unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_OFD_SETLK, &lock) }
+    }
+}
+
+impl Drop for OfdLockGuard<'_> {
+    fn drop(&mut self) {
+        // unlocking can only fail on invalid arguments, which we already verified when locking
+        let _ = self.do_unlock();
+    }
+}
+
+#[test]
+fn test_ofd_lock() {
+    let path = std::env::temp_dir().join(format!("proxmox-ofd-lock-test-{}", std::process::id()));
+    let open = || {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .expect("failed to open lock file")
+    };
+    let file1 = open();
+    let file2 = open();
+    let _ = std::fs::remove_file(&path);
+
+    let guard = OfdLock::exclusive()
+        .range(0, 10)
+        .lock(&file1, None)
+        .expect("failed to lock");
+
+    assert!(OfdLock::shared()
+        .range(5, 1)
+        .try_lock(&file2)
+        .expect("try_lock failed")
+        .is_none());
+    let other = OfdLock::exclusive()
+        .range(10, 10)
+        .try_lock(&file2)
+        .expect("try_lock failed");
+    assert!(other.is_some());
+
+    let err = OfdLock::shared()
+        .lock(&file2, Some(Duration::from_millis(50)))
+        .map(|_| ())
+        .expect_err("locking should time out");
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    // the same open file description does not conflict with its own locks
+    let same = OfdLock::exclusive()
+        .range(0, 10)
+        .try_lock(&file1)
+        .expect("try_lock failed");
+    assert!(same.is_some());
+    drop(same);
+
+    guard.unlock().expect("failed to unlock");
+    let _shared = OfdLock::shared()
+        .range(0, 10)
+        .lock(&file2, Some(Duration::from_millis(50)))
+        .expect("failed to lock after unlock");
+}
+
 /// Open or create a lock file (append mode). Then try to
 /// acquire a lock using `lock_file()`.
 pub fn open_file_locked<P: AsRef<Path>>(
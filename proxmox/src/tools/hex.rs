@@ -0,0 +1,149 @@
+//! Hexadecimal encoding and decoding.
+//!
+//! ```
+//! use proxmox::tools::hex;
+//!
+//! assert_eq!(hex::encode(&[0x01, 0xab]), "01ab");
+//! assert_eq!(hex::encode_upper(&[0x01, 0xab]), "01AB");
+//! assert_eq!(hex::decode("01Ab").unwrap(), [0x01, 0xab]);
+//!
+//! let mut digest = [0u8; 4];
+//! hex::decode_to_slice("deadbeef", &mut digest).unwrap();
+//! assert_eq!(hex::AsFingerprint(&digest).to_string(), "de:ad:be:ef");
+//! ```
+
+use std::fmt;
+
+use anyhow::{bail, Error};
+
+use crate::tools::parse::hex_nibble;
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+const HEX_CHARS_UPPER: &[u8; 16] = b"0123456789ABCDEF";
+
+fn write_hex(f: &mut fmt::Formatter, data: &[u8], chars: &[u8; 16]) -> fmt::Result {
+    let mut buf = [0u8, 0u8];
+    for b in data {
+        buf[0] = chars[(*b >> 4) as usize];
+        buf[1] = chars[(*b & 0xf) as usize];
+        f.write_str(unsafe { std::str::from_utf8_unchecked(&buf[..]) })?;
+    }
+    Ok(())
+}
+
+/// Helper to provide a `Display` for arbitrary byte slices.
+///
+/// `Display` and `LowerHex` use lower case digits, `UpperHex` upper case ones.
+#[derive(Clone, Copy, Debug)]
+pub struct AsHex<'a>(pub &'a [u8]);
+
+impl AsHex<'_> {
+    pub fn display_len(self) -> usize {
+        self.0.len() * 2
+    }
+
+    pub fn to_hex_string(self) -> String {
+        use std::fmt::Write;
+        let mut s = String::with_capacity(self.display_len());
+        write!(&mut s, "{}", self).expect("failed to format hex string");
+        s
+    }
+}
+
+impl fmt::Display for AsHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0, HEX_CHARS)
+    }
+}
+
+impl fmt::LowerHex for AsHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0, HEX_CHARS)
+    }
+}
+
+impl fmt::UpperHex for AsHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(f, self.0, HEX_CHARS_UPPER)
+    }
+}
+
+/// Display digests the way certificate fingerprints are shown, as colon separated lower case
+/// bytes (`de:ad:be:ef`).
+#[derive(Clone, Copy, Debug)]
+pub struct AsFingerprint<'a>(pub &'a [u8]);
+
+impl fmt::Display for AsFingerprint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write_hex(f, std::slice::from_ref(b), HEX_CHARS)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encode bytes as lower case hexadecimal digits.
+pub fn encode<T: AsRef<[u8]>>(data: T) -> String {
+    AsHex(data.as_ref()).to_hex_string()
+}
+
+/// Encode bytes as upper case hexadecimal digits.
+pub fn encode_upper<T: AsRef<[u8]>>(data: T) -> String {
+    format!("{:X}", AsHex(data.as_ref()))
+}
+
+/// Decode hexadecimal digits into `out`, which must be exactly half as long as the input.
+pub fn decode_to_slice<T: AsRef<[u8]>>(hex: T, out: &mut [u8]) -> Result<(), Error> {
+    let hex = hex.as_ref();
+
+    if hex.len() != out.len() * 2 {
+        bail!(
+            "hexadecimal string has invalid length ({}, expected {})",
+            hex.len(),
+            out.len() * 2,
+        );
+    }
+
+    for (byte, digits) in out.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = (hex_nibble(digits[0])? << 4) | hex_nibble(digits[1])?;
+    }
+
+    Ok(())
+}
+
+/// Decode hexadecimal digits into a byte vector. Any non-digits are treated as an error, and the
+/// input must consist of an even number of digits.
+pub fn decode<T: AsRef<[u8]>>(hex: T) -> Result<Vec<u8>, Error> {
+    let hex = hex.as_ref();
+    if hex.len() % 2 != 0 {
+        bail!("hexadecimal string has odd length ({})", hex.len());
+    }
+
+    let mut out = vec![0u8; hex.len() / 2];
+    decode_to_slice(hex, &mut out)?;
+    Ok(out)
+}
+
+#[test]
+fn test_hex_encoding() {
+    let data = [0x00, 0x01, 0x7f, 0x80, 0xab, 0xff];
+    assert_eq!(encode(&data), "00017f80abff");
+    assert_eq!(encode_upper(&data), "00017F80ABFF");
+    assert_eq!(encode(&[]), "");
+    assert_eq!(AsFingerprint(&data[..2]).to_string(), "00:01");
+    assert_eq!(AsFingerprint(&[]).to_string(), "");
+
+    assert_eq!(decode("00017F80abff").unwrap(), data);
+    assert_eq!(decode("").unwrap(), []);
+    assert!(decode("abc").is_err());
+    assert!(decode("0g").is_err());
+
+    let mut out = [0u8; 3];
+    decode_to_slice("0a0b0c", &mut out).unwrap();
+    assert_eq!(out, [10, 11, 12]);
+    assert!(decode_to_slice("0a0b", &mut out).is_err());
+    assert!(decode_to_slice("0a0b0c0d", &mut out).is_err());
+}
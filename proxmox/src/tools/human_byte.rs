@@ -0,0 +1,296 @@
+//! Byte sizes with units, like `512M` or `1.5 GiB`.
+//!
+//! ```
+//! use proxmox::tools::human_byte::HumanByte;
+//!
+//! let size: HumanByte = "1.5GiB".parse().unwrap();
+//! assert_eq!(size.as_u64(), 1536 * 1024 * 1024);
+//! assert_eq!(size.to_string(), "1.5 GiB");
+//!
+//! assert_eq!(HumanByte::new_decimal(2_500_000.0).to_string(), "2.5 MB");
+//! assert_eq!(HumanByte::from(4096).to_string(), "4 KiB");
+//! ```
+//!
+//! Single letter units (`K`, `M`, ...) are binary units, as with most command line tools. SI
+//! units (`KB`, `MB`, ...) are powers of 1000.
+
+use std::fmt;
+
+use anyhow::{bail, Error};
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+
+/// The unit of a `HumanByte`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SizeUnit {
+    Byte,
+    // SI (base 10)
+    KByte,
+    MByte,
+    GByte,
+    TByte,
+    PByte,
+    // IEC (base 2)
+    Kibi,
+    Mebi,
+    Gibi,
+    Tebi,
+    Pebi,
+}
+
+const SI_UNITS: [SizeUnit; 6] = [
+    SizeUnit::Byte,
+    SizeUnit::KByte,
+    SizeUnit::MByte,
+    SizeUnit::GByte,
+    SizeUnit::TByte,
+    SizeUnit::PByte,
+];
+
+const IEC_UNITS: [SizeUnit; 6] = [
+    SizeUnit::Byte,
+    SizeUnit::Kibi,
+    SizeUnit::Mebi,
+    SizeUnit::Gibi,
+    SizeUnit::Tebi,
+    SizeUnit::Pebi,
+];
+
+impl SizeUnit {
+    /// The number of bytes of one unit.
+    pub fn factor(self) -> f64 {
+        match self {
+            SizeUnit::Byte => 1.0,
+            SizeUnit::KByte => 1_000.0,
+            SizeUnit::MByte => 1_000_000.0,
+            SizeUnit::GByte => 1_000_000_000.0,
+            SizeUnit::TByte => 1_000_000_000_000.0,
+            SizeUnit::PByte => 1_000_000_000_000_000.0,
+            SizeUnit::Kibi => 1024.0,
+            SizeUnit::Mebi => 1024.0 * 1024.0,
+            SizeUnit::Gibi => 1024.0 * 1024.0 * 1024.0,
+            SizeUnit::Tebi => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+            SizeUnit::Pebi => 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        }
+    }
+
+    /// The unit suffix used when formatting.
+    pub fn unit_str(self) -> &'static str {
+        match self {
+            SizeUnit::Byte => "B",
+            SizeUnit::KByte => "KB",
+            SizeUnit::MByte => "MB",
+            SizeUnit::GByte => "GB",
+            SizeUnit::TByte => "TB",
+            SizeUnit::PByte => "PB",
+            SizeUnit::Kibi => "KiB",
+            SizeUnit::Mebi => "MiB",
+            SizeUnit::Gibi => "GiB",
+            SizeUnit::Tebi => "TiB",
+            SizeUnit::Pebi => "PiB",
+        }
+    }
+
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        Some(match suffix {
+            "" | "B" => SizeUnit::Byte,
+            "KB" | "kB" => SizeUnit::KByte,
+            "MB" => SizeUnit::MByte,
+            "GB" => SizeUnit::GByte,
+            "TB" => SizeUnit::TByte,
+            "PB" => SizeUnit::PByte,
+            "K" | "k" | "KiB" => SizeUnit::Kibi,
+            "M" | "MiB" => SizeUnit::Mebi,
+            "G" | "GiB" => SizeUnit::Gibi,
+            "T" | "TiB" => SizeUnit::Tebi,
+            "P" | "PiB" => SizeUnit::Pebi,
+            _ => return None,
+        })
+    }
+
+    /// The largest unit in which `bytes` is at least 1.
+    fn auto_scale(bytes: f64, units: &[SizeUnit; 6]) -> Self {
+        units
+            .iter()
+            .rev()
+            .find(|unit| bytes >= unit.factor())
+            .copied()
+            .unwrap_or(SizeUnit::Byte)
+    }
+}
+
+/// A byte size with a unit, see the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HumanByte {
+    pub size: f64,
+    pub unit: SizeUnit,
+}
+
+impl HumanByte {
+    /// A size in the given unit.
+    pub fn with_unit(size: f64, unit: SizeUnit) -> Self {
+        Self { size, unit }
+    }
+
+    /// A size in bytes, formatted with SI units.
+    pub fn new_decimal(bytes: f64) -> Self {
+        let unit = SizeUnit::auto_scale(bytes, &SI_UNITS);
+        Self::with_unit(bytes / unit.factor(), unit)
+    }
+
+    /// A size in bytes, formatted with binary units.
+    pub fn new_binary(bytes: f64) -> Self {
+        let unit = SizeUnit::auto_scale(bytes, &IEC_UNITS);
+        Self::with_unit(bytes / unit.factor(), unit)
+    }
+
+    /// The size in bytes.
+    pub fn as_f64(&self) -> f64 {
+        self.size * self.unit.factor()
+    }
+
+    /// The size in whole bytes, rounded to the nearest byte, since decimal units are not exact
+    /// in floating point.
+    pub fn as_u64(&self) -> u64 {
+        self.as_f64().round() as u64
+    }
+}
+
+impl From<u64> for HumanByte {
+    /// Uses binary units.
+    fn from(bytes: u64) -> Self {
+        Self::new_binary(bytes as f64)
+    }
+}
+
+impl fmt::Display for HumanByte {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // at most 3 decimal places, without trailing zeros
+        let size = format!("{:.3}", self.size);
+        let size = size.trim_end_matches('0').trim_end_matches('.');
+        write!(f, "{} {}", size, self.unit.unit_str())
+    }
+}
+
+impl std::str::FromStr for HumanByte {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or_else(|| s.len());
+        let (number, suffix) = s.split_at(split);
+
+        let size: f64 = match number.parse() {
+            Ok(size) => size,
+            Err(_) => bail!("invalid byte size {:?}", s),
+        };
+        if !size.is_finite() {
+            bail!("invalid byte size {:?}", s);
+        }
+
+        match SizeUnit::from_suffix(suffix.trim_start()) {
+            Some(unit) => Ok(Self::with_unit(size, unit)),
+            None => bail!("invalid unit in byte size {:?}", s),
+        }
+    }
+}
+
+impl serde::Serialize for HumanByte {
+    /// Serialized as number of whole bytes, since the `Display` output is rounded to 3 decimal
+    /// places and would lose data.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u64(self.as_u64())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for HumanByte {
+    /// Accepts strings with units as well as plain numbers of bytes.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = HumanByte;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a byte size like '512M' or a number of bytes")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<HumanByte, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<HumanByte, E> {
+                Ok(HumanByte::from(value))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<HumanByte, E> {
+                if value < 0 {
+                    return Err(E::custom("byte size must not be negative"));
+                }
+                Ok(HumanByte::from(value as u64))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn verify_human_byte(s: &str) -> Result<(), Error> {
+    s.parse::<HumanByte>().map(drop)
+}
+
+/// Schema for byte size parameters.
+pub const API_SCHEMA: Schema = StringSchema::new(
+    "Byte size with optional unit (B, KB (base 10), MB, GB, ..., KiB (base 2), MiB, GiB, ...).",
+)
+.format(&ApiStringFormat::VerifyFn(verify_human_byte))
+.min_length(1)
+.max_length(64)
+.schema();
+
+#[test]
+fn test_human_byte() {
+    let parse = |s: &str| s.parse::<HumanByte>().map(|size| size.as_u64());
+
+    assert_eq!(parse("1000000").unwrap(), 1_000_000);
+    assert_eq!(parse("512M").unwrap(), 512 * 1024 * 1024);
+    assert_eq!(parse("512 MiB").unwrap(), 512 * 1024 * 1024);
+    assert_eq!(parse("1.5GiB").unwrap(), 1536 * 1024 * 1024);
+    assert_eq!(parse("2KB").unwrap(), 2000);
+    // 8.2 * 1000 * 1000 is slightly below 8200000 in floating point
+    assert_eq!(parse("8.2MB").unwrap(), 8_200_000);
+    assert_eq!(parse(" 7 B ").unwrap(), 7);
+    assert!(parse("").is_err());
+    assert!(parse("-5M").is_err());
+    assert!(parse("1.5 XB").is_err());
+    assert!(parse("1..5M").is_err());
+
+    assert_eq!(HumanByte::from(0).to_string(), "0 B");
+    assert_eq!(HumanByte::from(1023).to_string(), "1023 B");
+    assert_eq!(HumanByte::from(1536).to_string(), "1.5 KiB");
+    assert_eq!(HumanByte::from(1024 * 1024 + 1).to_string(), "1 MiB");
+    assert_eq!(HumanByte::new_decimal(1_234_567.0).to_string(), "1.235 MB");
+
+    let size: HumanByte = serde_json::from_str("\"4G\"").unwrap();
+    assert_eq!(size.as_u64(), 4 << 30);
+    let size: HumanByte = serde_json::from_str("4096").unwrap();
+    assert_eq!(serde_json::to_string(&size).unwrap(), "4096");
+    let size: HumanByte = "8.2MB".parse().unwrap();
+    assert_eq!(serde_json::to_string(&size).unwrap(), "8200000");
+    let json = serde_json::to_string(&HumanByte::from(1_234_567)).unwrap();
+    assert_eq!(
+        serde_json::from_str::<HumanByte>(&json).unwrap().as_u64(),
+        1_234_567
+    );
+
+    assert!(verify_human_byte("10 TiB").is_ok());
+    assert!(verify_human_byte("ten").is_err());
+}
@@ -1,9 +1,17 @@
 //! Module providing I/O helpers (sync and async).
 //!
 //! The [`ReadExt`] trait provides additional operations for handling byte buffers for types
-//! implementing [`Read`](std::io::Read).
+//! implementing [`Read`](std::io::Read), the [`WriteExt`] trait the matching operations for
+//! writing values of a specific endianess to types implementing [`Write`](std::io::Write).
 
+use std::fs::File;
 use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use nix::unistd::{lseek, Whence};
+
+use crate::sys::error::SysResult;
 
 mod read;
 pub use read::*;
@@ -86,6 +94,111 @@ pub fn sparse_copy<R: Read + ?Sized, W: Write + Seek + ?Sized>(
     }
 }
 
+const FILE_COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Copy the contents of `src` to `dst`, preserving holes.
+///
+/// The data segments of the source are found via `SEEK_DATA`/`SEEK_HOLE` and only those are
+/// copied, holes are punched into the destination where the source has holes, so `dst` does not
+/// need to be empty. The destination is resized to the size of the source. If its file system
+/// does not support punching holes, zeros are written instead.
+///
+/// Both files are accessed with positional reads and writes, their file offsets are not used.
+///
+/// Example:
+/// ```no_run
+/// # use std::fs::{File, OpenOptions};
+/// # use proxmox::tools::io::sparse_copy_file;
+/// # fn code() -> std::io::Result<()> {
+/// let src = File::open("disk.raw")?;
+/// let dst = OpenOptions::new().write(true).create(true).open("disk-copy.raw")?;
+/// sparse_copy_file(&src, &dst)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn sparse_copy_file(src: &File, dst: &File) -> io::Result<SparseCopyResult> {
+    let len = src.metadata()?.len();
+    dst.set_len(len)?;
+
+    let mut buf = vec![0u8; FILE_COPY_BUFFER_SIZE];
+    let mut offset = 0u64;
+    let mut seeked_last = false;
+
+    while offset < len {
+        let data = match lseek(src.as_raw_fd(), offset as i64, Whence::SeekData).into_io_result() {
+            Ok(pos) => (pos as u64).min(len),
+            // no more data after offset
+            Err(ref err) if err.raw_os_error() == Some(libc::ENXIO) => len,
+            Err(err) => return Err(err),
+        };
+
+        if data > offset {
+            punch_hole_or_zero(dst, offset, data - offset)?;
+            seeked_last = true;
+        }
+        if data >= len {
+            break;
+        }
+
+        let hole = lseek(src.as_raw_fd(), data as i64, Whence::SeekHole).into_io_result()?;
+        let hole = (hole as u64).min(len);
+        copy_range(src, dst, data, hole, &mut buf)?;
+        seeked_last = false;
+        offset = hole;
+    }
+
+    Ok(SparseCopyResult {
+        written: len,
+        seeked_last,
+    })
+}
+
+fn punch_hole_or_zero(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    match crate::sys::linux::io::punch_hole(file, offset, len) {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+            let zeroes = vec![0u8; (len as usize).min(FILE_COPY_BUFFER_SIZE)];
+            let end = offset + len;
+            let mut pos = offset;
+            while pos < end {
+                let size = ((end - pos) as usize).min(zeroes.len());
+                file.write_all_at(&zeroes[..size], pos)?;
+                pos += size as u64;
+            }
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn copy_range(src: &File, dst: &File, mut pos: u64, end: u64, buf: &mut [u8]) -> io::Result<()> {
+    while pos < end {
+        let size = ((end - pos) as usize).min(buf.len());
+        let got = match src.read_at(&mut buf[..size], pos) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "source file was truncated during copy",
+                ))
+            }
+            Ok(got) => got,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all_at(&buf[..got], pos)?;
+        pos += got as u64;
+    }
+    Ok(())
+}
+
+/// Async variant of [`sparse_copy_file`], running the copy on tokio's blocking thread pool.
+#[cfg(feature = "async")]
+pub async fn sparse_copy_file_async(src: File, dst: File) -> io::Result<SparseCopyResult> {
+    tokio::task::spawn_blocking(move || sparse_copy_file(&src, &dst))
+        .await
+        .map_err(|err| crate::io_format_err!("sparse copy task failed - {}", err))?
+}
+
 #[cfg(feature = "tokio")]
 use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
@@ -161,7 +274,7 @@ mod test {
     use std::io::Cursor;
 
     use crate::test::io::{AsyncBlockingReader, AsyncBlockingWriter};
-    use crate::tools::io::{sparse_copy, sparse_copy_async};
+    use crate::tools::io::{sparse_copy, sparse_copy_async, sparse_copy_file};
 
     const LEN: usize = 10000;
 
@@ -203,6 +316,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_sparse_copy_file() {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::FileExt;
+
+        let dir = std::env::temp_dir();
+        let pid = std::process::id();
+        let src_path = dir.join(format!("proxmox-sparse-copy-src-{}", pid));
+        let dst_path = dir.join(format!("proxmox-sparse-copy-dst-{}", pid));
+
+        let open = |path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .unwrap()
+        };
+
+        // data, hole, data, trailing hole
+        let src = open(&src_path);
+        src.write_all_at(&[1u8; LEN], 0).unwrap();
+        src.write_all_at(&[2u8; LEN], 1024 * 1024).unwrap();
+        src.set_len(4 * 1024 * 1024).unwrap();
+
+        // the destination has old data everywhere, which must not survive in the holes
+        let dst = open(&dst_path);
+        dst.write_all_at(&vec![0xffu8; 8 * 1024 * 1024], 0).unwrap();
+
+        let result = sparse_copy_file(&src, &dst);
+        let src_data = std::fs::read(&src_path).unwrap();
+        let dst_data = std::fs::read(&dst_path).unwrap();
+        let _ = std::fs::remove_file(&src_path);
+        let _ = std::fs::remove_file(&dst_path);
+
+        let result = result.expect("error during sparse file copy");
+        assert_eq!(result.written, 4 * 1024 * 1024);
+        assert_eq!(dst_data.len(), src_data.len());
+        assert!(dst_data == src_data);
+    }
+
     #[test]
     fn test_sparse_copy_async() {
         let fut = async {
@@ -71,6 +71,21 @@ pub trait ReadExt {
     /// ```
     fn read_exact_allocated(&mut self, size: usize) -> io::Result<Vec<u8>>;
 
+    /// Like [`read_exact_allocated`](ReadExt::read_exact_allocated), but refuses to allocate
+    /// more than `max_size` bytes. Use this when the size comes from untrusted input like a
+    /// header read from a file or socket.
+    ///
+    /// ```no_run
+    /// use proxmox::tools::io::ReadExt;
+    /// # fn code(mut reader: std::fs::File) -> std::io::Result<()> {
+    /// let len: u32 = unsafe { reader.read_le_value()? };
+    /// let data = reader.read_exact_allocated_limited(len as usize, 16 * 1024 * 1024)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn read_exact_allocated_limited(&mut self, size: usize, max_size: usize)
+        -> io::Result<Vec<u8>>;
+
     /// Append data to a vector, growing it as necessary. Returns the amount of data appended.
     fn append_to_vec(&mut self, out: &mut Vec<u8>, size: usize) -> io::Result<usize>;
 
@@ -235,6 +250,10 @@ pub trait ReadExt {
 
     /// Read until EOF
     fn skip_to_end(&mut self) -> io::Result<usize>;
+
+    /// Read and discard exactly `count` bytes. Fails with `UnexpectedEof` if the data ends
+    /// before that.
+    fn skip_bytes(&mut self, count: u64) -> io::Result<()>;
 }
 
 impl<R: io::Read> ReadExt for R {
@@ -244,6 +263,20 @@ impl<R: io::Read> ReadExt for R {
         Ok(out)
     }
 
+    fn read_exact_allocated_limited(
+        &mut self,
+        size: usize,
+        max_size: usize,
+    ) -> io::Result<Vec<u8>> {
+        if size > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("refusing to read {} bytes (limit is {})", size, max_size),
+            ));
+        }
+        self.read_exact_allocated(size)
+    }
+
     fn append_to_vec(&mut self, out: &mut Vec<u8>, size: usize) -> io::Result<usize> {
         let pos = out.len();
         unsafe {
@@ -332,4 +365,39 @@ impl<R: io::Read> ReadExt for R {
             }
         }
     }
+
+    fn skip_bytes(&mut self, count: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut self.by_ref().take(count), &mut io::sink())?;
+        if skipped != count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to skip requested amount of data",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_ext() {
+    let data: &[u8] = &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0xaa, 0xbb];
+    let mut reader = data;
+
+    let le: u16 = unsafe { reader.read_le_value().unwrap() };
+    assert_eq!(le, 0x0201);
+    let be: u16 = unsafe { reader.read_be_value().unwrap() };
+    assert_eq!(be, 0x0304);
+
+    reader.skip_bytes(2).unwrap();
+    assert!(reader.read_exact_allocated_limited(4, 2).is_err());
+    assert_eq!(
+        reader.read_exact_allocated_limited(2, 2).unwrap(),
+        [0x07, 0x08]
+    );
+
+    assert_eq!(
+        reader.skip_bytes(3).unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+    assert!(reader.is_empty());
 }
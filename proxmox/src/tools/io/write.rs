@@ -121,7 +121,7 @@ pub trait WriteExt {
     /// [`Endian`]: https://docs.rs/endian_trait/0.6/endian_trait/trait.Endian.html
     unsafe fn write_le_value<T: Endian>(&mut self, value: T) -> io::Result<()>;
 
-    /// Read a big endian value.
+    /// Write a big endian value.
     ///
     /// The input type is required to implement the [`Endian`] trait, and we make the assumption
     /// that this is only done for types which are supposed to be read/writable directly.
@@ -181,3 +181,23 @@ impl<W: io::Write> WriteExt for W {
         self.write_host_value::<T>(value.to_be())
     }
 }
+
+#[test]
+fn test_write_ext() {
+    use super::ReadExt;
+
+    let mut data = Vec::new();
+    unsafe {
+        data.write_le_value(0x0102u16).unwrap();
+        data.write_be_value(0x0304_0506u32).unwrap();
+        data.write_host_value(7u8).unwrap();
+    }
+    assert_eq!(data, [0x02, 0x01, 0x03, 0x04, 0x05, 0x06, 7]);
+
+    let mut reader = &data[..];
+    unsafe {
+        assert_eq!(reader.read_le_value::<u16>().unwrap(), 0x0102);
+        assert_eq!(reader.read_be_value::<u32>().unwrap(), 0x0304_0506);
+        assert_eq!(reader.read_host_value::<u8>().unwrap(), 7);
+    }
+}
@@ -0,0 +1,290 @@
+//! Least recently used cache with a fixed capacity.
+//!
+//! Entries are kept in a doubly linked list ordered by their last access, stored in a vector and
+//! linked by indices, with a `HashMap` from keys to list indices. All operations are `O(1)`.
+//!
+//! ```
+//! # use proxmox::tools::lru_cache::LruCache;
+//! let mut cache = LruCache::new(2);
+//! cache.insert(1, "one");
+//! cache.insert(2, "two");
+//! assert_eq!(cache.get(&1), Some(&"one"));
+//!
+//! // 2 is now the least recently used entry
+//! cache.insert(3, "three");
+//! assert_eq!(cache.get(&2), None);
+//!
+//! assert_eq!(*cache.get_or_insert_with(4, || "four"), "four");
+//! assert_eq!(cache.stats().hits, 1);
+//! assert_eq!(cache.stats().misses, 2);
+//! ```
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Cache hit and miss counters.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A cache holding up to `capacity` entries, evicting the least recently used entry when full.
+pub struct LruCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Vec<Entry<K, V>>,
+    // most recently used
+    head: Option<usize>,
+    // least recently used
+    tail: Option<usize>,
+    capacity: usize,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Create a new cache. Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "LRU cache capacity must not be zero");
+        Self {
+            map: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            head: None,
+            tail: None,
+            capacity,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.entries[index].prev, self.entries[index].next);
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.entries[index].prev = None;
+        self.entries[index].next = self.head;
+        match self.head {
+            Some(head) => self.entries[head].prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+
+    fn touch(&mut self, index: usize) {
+        if self.head != Some(index) {
+            self.unlink(index);
+            self.push_front(index);
+        }
+    }
+
+    fn lookup(&mut self, key: &K) -> Option<usize> {
+        match self.map.get(key) {
+            Some(&index) => {
+                self.stats.hits += 1;
+                self.touch(index);
+                Some(index)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Get a value and mark it as most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let index = self.lookup(key)?;
+        Some(&self.entries[index].value)
+    }
+
+    /// Get a mutable reference to a value and mark it as most recently used.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.lookup(key)?;
+        Some(&mut self.entries[index].value)
+    }
+
+    /// Get a value without changing the order of entries or the statistics.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&index| &self.entries[index].value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Insert a value as most recently used entry, evicting the least recently used one if the
+    /// cache is full. Returns the previous value for this key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(&index) = self.map.get(&key) {
+            self.touch(index);
+            return Some(std::mem::replace(&mut self.entries[index].value, value));
+        }
+
+        let entry = Entry {
+            key: key.clone(),
+            value,
+            prev: None,
+            next: None,
+        };
+
+        let index = if self.entries.len() >= self.capacity {
+            // reuse the slot of the least recently used entry
+            let index = self.tail.expect("full cache without tail");
+            self.unlink(index);
+            self.map.remove(&self.entries[index].key);
+            self.entries[index] = entry;
+            index
+        } else {
+            self.entries.push(entry);
+            self.entries.len() - 1
+        };
+
+        self.map.insert(key, index);
+        self.push_front(index);
+        None
+    }
+
+    /// Get a value, or insert the one returned by `func` if it is not cached.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, func: F) -> &mut V {
+        let index = match self.lookup(&key) {
+            Some(index) => index,
+            None => {
+                self.insert(key, func());
+                self.head.expect("cache empty after insert")
+            }
+        };
+        &mut self.entries[index].value
+    }
+
+    /// Remove an entry from the cache.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.map.remove(key)?;
+        self.unlink(index);
+
+        // keep the entries dense by moving the last one into the freed slot
+        let last = self.entries.len() - 1;
+        if index != last {
+            self.entries.swap(index, last);
+            match self.entries[index].prev {
+                Some(prev) => self.entries[prev].next = Some(index),
+                None => self.head = Some(index),
+            }
+            match self.entries[index].next {
+                Some(next) => self.entries[next].prev = Some(index),
+                None => self.tail = Some(index),
+            }
+            if let Some(moved) = self.map.get_mut(&self.entries[index].key) {
+                *moved = index;
+            }
+        }
+
+        self.entries.pop().map(|entry| entry.value)
+    }
+}
+
+#[test]
+fn test_lru_cache() {
+    let mut cache = LruCache::new(3);
+    assert!(cache.is_empty());
+
+    for i in 0..3 {
+        assert_eq!(cache.insert(i, i * 10), None);
+    }
+    assert_eq!(cache.len(), 3);
+
+    // order (most recent first): 0, 2, 1
+    assert_eq!(cache.get(&0), Some(&0));
+    cache.insert(3, 30);
+    assert!(!cache.contains_key(&1));
+    assert_eq!(cache.peek(&2), Some(&20));
+
+    // peek does not promote, so 2 is evicted next
+    cache.insert(4, 40);
+    assert!(!cache.contains_key(&2));
+    assert_eq!(cache.len(), 3);
+
+    assert_eq!(cache.insert(0, 1), Some(0));
+    *cache.get_mut(&0).unwrap() += 1;
+    assert_eq!(cache.peek(&0), Some(&2));
+
+    // order: 0, 4, 3
+    assert_eq!(cache.remove(&4), Some(40));
+    assert_eq!(cache.remove(&4), None);
+    assert_eq!(cache.len(), 2);
+    cache.insert(5, 50);
+    cache.insert(6, 60);
+    assert!(!cache.contains_key(&3));
+    assert_eq!(cache.peek(&0), Some(&2));
+
+    // order: 6, 5, 0
+    let mut computed = 0;
+    assert_eq!(
+        *cache.get_or_insert_with(5, || {
+            computed += 1;
+            0
+        }),
+        50
+    );
+    assert_eq!(
+        *cache.get_or_insert_with(7, || {
+            computed += 1;
+            70
+        }),
+        70
+    );
+    assert_eq!(computed, 1);
+    assert!(!cache.contains_key(&0));
+
+    let stats = cache.stats();
+    assert_eq!(stats.hits, 3);
+    assert_eq!(stats.misses, 1);
+
+    // remove everything in arbitrary order to exercise the index fixups
+    for key in &[5, 7, 6] {
+        assert!(cache.remove(key).is_some());
+    }
+    assert!(cache.is_empty());
+    cache.insert(8, 80);
+    assert_eq!(cache.get(&8), Some(&80));
+}
@@ -47,6 +47,14 @@ impl<T> Mmap<T> {
             len: count,
         })
     }
+
+    /// Get a raw pointer to the mapped memory without creating a reference to it.
+    ///
+    /// Use this for memory which may be modified concurrently by other processes or the kernel.
+    #[inline]
+    pub fn as_raw_ptr(&self) -> *mut T {
+        self.data
+    }
 }
 
 impl<T> std::ops::Deref for Mmap<T> {
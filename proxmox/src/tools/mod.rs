@@ -1,35 +1,61 @@
 //! This is a general utility crate used by all our rust projects.
 
-use std::fmt;
-
 use anyhow::*;
 use lazy_static::lazy_static;
 
+pub mod api_token;
 pub mod as_any;
+pub mod base32;
+pub mod base64;
 pub mod borrow;
 pub mod byte_buffer;
+pub mod checksum;
+pub mod command;
 pub mod common_regex;
 pub mod constnamedbitmap;
+pub mod crypt;
 pub mod email;
 pub mod fd;
 pub mod fs;
+pub mod hex;
+pub mod human_byte;
 pub mod io;
+pub mod lru_cache;
 pub mod mmap;
 pub mod parse;
 pub mod serde;
 pub mod time;
 pub mod uuid;
 pub mod vec;
+pub mod worker_task;
 
 #[cfg(feature = "websocket")]
 pub mod websocket;
 
+#[cfg(feature = "termproxy")]
+pub mod termproxy;
+
 #[cfg(feature = "tfa")]
 pub mod tfa;
 
+#[cfg(feature = "openssl")]
+pub mod digest;
+
+#[cfg(feature = "futures")]
+pub mod async_lru_cache;
+
+#[cfg(feature = "futures")]
+pub mod broadcast_future;
+
+#[cfg(feature = "openssl")]
+pub mod ticket;
+
 #[doc(inline)]
 pub use uuid::Uuid;
 
+#[doc(inline)]
+pub use hex::AsHex;
+
 #[doc(inline)]
 pub use as_any::AsAny;
 
@@ -92,37 +118,6 @@ macro_rules! try_block {
     { $($token:tt)* } => {{ (|| -> Result<_,_> { $($token)* })() }}
 }
 
-const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
-
-/// Helper to provide a `Display` for arbitrary byte slices.
-#[derive(Clone, Copy, Debug)]
-pub struct AsHex<'a>(pub &'a [u8]);
-
-impl AsHex<'_> {
-    pub fn display_len(self) -> usize {
-        self.0.len() * 2
-    }
-
-    pub fn to_hex_string(self) -> String {
-        use std::fmt::Write;
-        let mut s = String::with_capacity(self.display_len());
-        write!(&mut s, "{}", self).expect("failed to format hex string");
-        s
-    }
-}
-
-impl fmt::Display for AsHex<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut buf = [0u8, 0u8];
-        for b in self.0 {
-            buf[0] = HEX_CHARS[(*b >> 4) as usize];
-            buf[1] = HEX_CHARS[(*b & 0xf) as usize];
-            f.write_str(unsafe { std::str::from_utf8_unchecked(&buf[..]) })?;
-        }
-        Ok(())
-    }
-}
-
 pub fn digest_to_hex(digest: &[u8]) -> String {
     bin_to_hex(digest)
 }
@@ -136,38 +131,12 @@ pub fn digest_to_hex(digest: &[u8]) -> String {
 /// assert_eq!(text, "0102ff");
 /// ```
 pub fn bin_to_hex(digest: &[u8]) -> String {
-    AsHex(digest).to_string()
-}
-
-/// Convert an ascii character into a hex nibble.
-fn hex_nibble_to_byte(b: u8) -> Result<u8, Error> {
-    Ok(match b {
-        b'0'..=b'9' => b - b'0',
-        b'a'..=b'f' => b - b'a' + 0xA,
-        b'A'..=b'F' => b - b'A' + 0xA,
-        _ => bail!("not a hexadecimal digit: {}", char::from(b)),
-    })
+    hex::encode(digest)
 }
 
 /// Parse hexadecimal digits into a byte array.
 pub fn hex_to_bin_exact(hex: &str, out: &mut [u8]) -> Result<(), Error> {
-    let bytes = hex.as_bytes();
-
-    if bytes.len() != out.len() * 2 {
-        bail!(
-            "hexadecimal string has invalid length ({}, expected {})",
-            bytes.len(),
-            out.len() * 2,
-        );
-    }
-
-    for i in 0..out.len() {
-        let h = hex_nibble_to_byte(bytes[i * 2])?;
-        let l = hex_nibble_to_byte(bytes[i * 2 + 1])?;
-        out[i] = (h << 4) | l;
-    }
-
-    Ok(())
+    hex::decode_to_slice(hex, out)
 }
 
 /// Convert a string of hexadecimal digits to a byte vector. Any non-digits are treated as an
@@ -182,13 +151,7 @@ pub fn hex_to_bin_exact(hex: &str, out: &mut [u8]) -> Result<(), Error> {
 /// assert_eq!(&data, &[0xaa, 0xbb, 0x01, 0x23]);
 /// ```
 pub fn hex_to_bin(hex: &str) -> Result<Vec<u8>, Error> {
-    if (hex.len() % 2) != 0 {
-        bail!("hex_to_bin: got wrong input length.");
-    }
-
-    let mut out = unsafe { vec::uninitialized(hex.len() / 2) };
-    hex_to_bin_exact(hex, &mut out)?;
-    Ok(out)
+    hex::decode(hex)
 }
 
 // FIXME: This should be renamed to contain the digest algorithm, so that the array's size makes
@@ -216,8 +179,8 @@ fn test_hex() {
 pub fn nodename() -> &'static str {
     lazy_static! {
         static ref NODENAME: String = {
-            nix::sys::utsname::uname()
-                .nodename()
+            crate::sys::linux::uname()
+                .nodename
                 .split('.')
                 .next()
                 .unwrap()
@@ -227,3 +190,27 @@ pub fn nodename() -> &'static str {
 
     &NODENAME
 }
+
+/// Compare two byte slices in constant time, to check secrets, MACs and signatures without
+/// leaking the position of the first difference through timing.
+///
+/// Only the length of the inputs is not hidden, slices of different length compare unequal
+/// immediately. Use this instead of `==` whenever one side is secret, like when verifying
+/// tickets, tokens or one-time passwords.
+///
+/// ```
+/// # use proxmox::tools::constant_time_eq;
+/// assert!(constant_time_eq(b"secret", b"secret"));
+/// assert!(!constant_time_eq(b"secret", b"secreT"));
+/// assert!(!constant_time_eq(b"secret", b"secret2"));
+/// ```
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+
+    // keep the compiler from turning the loop into an early-exit comparison
+    unsafe { std::ptr::read_volatile(&diff) == 0 }
+}
@@ -0,0 +1,300 @@
+//! Bridge between a pseudo-terminal and a websocket, for browser based terminal consoles.
+//!
+//! The client sends a stream of messages in the format used by the xterm.js based consoles:
+//!
+//! * `0:<length>:<data>` - terminal input of `<length>` bytes
+//! * `1:<columns>:<rows>:` - resize the terminal
+//! * `2` - a keepalive ping, which needs no answer
+//!
+//! The terminal output is sent to the client as plain websocket frames without any framing of
+//! its own.
+//!
+//! ```no_run
+//! # use anyhow::Error;
+//! # use proxmox::sys::linux::pty::{AsyncPtyMaster, PtyCommand};
+//! # use proxmox::tools::termproxy::TermProxy;
+//! # async fn code(upgraded: hyper::upgrade::Upgraded) -> Result<(), Error> {
+//! let (master, _pid) = PtyCommand::new("/bin/login").spawn()?;
+//! TermProxy::new(AsyncPtyMaster::new(master)?)
+//!     .serve_connection(upgraded)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use futures::future::FutureExt;
+use futures::select;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, Interval};
+
+use crate::sys::linux::pty::AsyncPtyMaster;
+use crate::tools::byte_buffer::ByteBuffer;
+use crate::tools::websocket::{
+    OpCode, WebSocketError, WebSocketErrorKind, WebSocketReader, WebSocketWriter,
+};
+
+/// Input messages of more than this many bytes are rejected.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Room for the largest message including its header, so a full buffer always contains a
+/// complete message.
+const CLIENT_BUFFER_SIZE: usize = MAX_MESSAGE_SIZE + 16;
+
+/// A message sent by the terminal client.
+#[derive(Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Input for the terminal.
+    Data(Vec<u8>),
+    /// The client's terminal size changed.
+    Resize { cols: u16, rows: u16 },
+    /// Application level keepalive.
+    Ping,
+}
+
+/// Parse a decimal number terminated by a colon, starting at `pos`. Returns the number and the
+/// position after the colon, or `None` if more data is needed.
+fn parse_field(data: &[u8], pos: usize) -> Result<Option<(usize, usize)>, Error> {
+    let mut value: usize = 0;
+    for (i, byte) in data[pos..].iter().enumerate() {
+        match byte {
+            b':' if i > 0 => return Ok(Some((value, pos + i + 1))),
+            b'0'..=b'9' if i < 10 => value = value * 10 + (byte - b'0') as usize,
+            _ => bail!("invalid terminal message field"),
+        }
+    }
+    Ok(None)
+}
+
+/// Try to parse a client message at the start of `data`. Returns the message and the number of
+/// bytes it occupied, or `None` if the message is not complete yet.
+pub fn parse_message(data: &[u8]) -> Result<Option<(ClientMessage, usize)>, Error> {
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    if data[0] == b'2' {
+        return Ok(Some((ClientMessage::Ping, 1)));
+    }
+
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    if data[1] != b':' {
+        bail!("invalid terminal message");
+    }
+
+    match data[0] {
+        b'0' => {
+            let (len, start) = match parse_field(data, 2)? {
+                Some(field) => field,
+                None => return Ok(None),
+            };
+            if len > MAX_MESSAGE_SIZE {
+                bail!("terminal input message too large ({} bytes)", len);
+            }
+            if data.len() < start + len {
+                return Ok(None);
+            }
+            let input = data[start..(start + len)].to_vec();
+            Ok(Some((ClientMessage::Data(input), start + len)))
+        }
+        b'1' => {
+            let (cols, pos) = match parse_field(data, 2)? {
+                Some(field) => field,
+                None => return Ok(None),
+            };
+            let (rows, end) = match parse_field(data, pos)? {
+                Some(field) => field,
+                None => return Ok(None),
+            };
+            if cols > u16::max_value() as usize || rows > u16::max_value() as usize {
+                bail!("invalid terminal size {}x{}", cols, rows);
+            }
+            let msg = ClientMessage::Resize {
+                cols: cols as u16,
+                rows: rows as u16,
+            };
+            Ok(Some((msg, end)))
+        }
+        other => bail!("unknown terminal message type {:?}", char::from(other)),
+    }
+}
+
+enum Event {
+    Client(io::Result<usize>),
+    Terminal(io::Result<usize>),
+    Control(Option<Result<(OpCode, Box<[u8]>), WebSocketError>>),
+    Keepalive,
+}
+
+async fn next_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => futures::future::pending::<()>().await,
+    }
+}
+
+/// Pumps data between a pseudo-terminal master and a websocket connection.
+pub struct TermProxy {
+    master: AsyncPtyMaster,
+    text: bool,
+    keepalive: Option<Duration>,
+}
+
+impl TermProxy {
+    /// Create a proxy for a terminal. By default the output is sent as binary frames, and a
+    /// websocket ping is sent every 30 seconds.
+    pub fn new(master: AsyncPtyMaster) -> Self {
+        Self {
+            master,
+            text: false,
+            keepalive: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Send the terminal output as text instead of binary frames.
+    pub fn text(mut self, text: bool) -> Self {
+        self.text = text;
+        self
+    }
+
+    /// Set the interval for websocket pings, or disable them. If a ping was not answered by the
+    /// time the next one is due, the connection is considered dead.
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive = interval;
+        self
+    }
+
+    /// Serve a websocket connection, after the handshake was completed (eg. via
+    /// `WebSocket::new`). Returns once either side closed the connection.
+    pub async fn serve_connection<S>(mut self, upstream: S) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (usreader, uswriter) = tokio::io::split(upstream);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut wsreader = WebSocketReader::new(usreader, tx);
+        let mut wswriter = WebSocketWriter::new(None, self.text, uswriter);
+
+        let mut interval = self
+            .keepalive
+            .map(|period| tokio::time::interval_at(Instant::now() + period, period));
+        let mut pong_pending = false;
+
+        let mut client_buf = ByteBuffer::with_capacity(CLIENT_BUFFER_SIZE);
+        let mut term_buf = vec![0u8; 4096];
+
+        loop {
+            let event = select! {
+                res = client_buf.read_from_async(&mut wsreader).fuse() => Event::Client(res),
+                res = self.master.read(&mut term_buf).fuse() => Event::Terminal(res),
+                msg = rx.recv().fuse() => Event::Control(msg),
+                _ = next_tick(&mut interval).fuse() => Event::Keepalive,
+            };
+
+            match event {
+                Event::Client(res) => {
+                    if res? == 0 {
+                        return Ok(());
+                    }
+                    self.handle_client_data(&mut client_buf).await?;
+                }
+                Event::Terminal(res) => {
+                    let len = res?;
+                    if len == 0 {
+                        wswriter
+                            .send_control_frame(
+                                None,
+                                OpCode::Close,
+                                &WebSocketErrorKind::Normal.to_be_bytes(),
+                            )
+                            .await?;
+                        return Ok(());
+                    }
+                    wswriter.write_all(&term_buf[..len]).await?;
+                }
+                Event::Control(msg) => {
+                    match msg.ok_or_else(|| format_err!("control channel closed"))? {
+                        Ok((OpCode::Ping, data)) => {
+                            wswriter
+                                .send_control_frame(None, OpCode::Pong, &data)
+                                .await?
+                        }
+                        Ok((OpCode::Pong, _)) => pong_pending = false,
+                        Ok((OpCode::Close, data)) => {
+                            wswriter
+                                .send_control_frame(None, OpCode::Close, &data)
+                                .await?;
+                            return Ok(());
+                        }
+                        Ok(_) => (),
+                        Err(err) => {
+                            wswriter
+                                .send_control_frame(
+                                    None,
+                                    OpCode::Close,
+                                    &err.generate_frame_payload(),
+                                )
+                                .await?;
+                            return Err(err.into());
+                        }
+                    }
+                }
+                Event::Keepalive => {
+                    if pong_pending {
+                        bail!("websocket keepalive timed out");
+                    }
+                    wswriter.send_control_frame(None, OpCode::Ping, &[]).await?;
+                    pong_pending = true;
+                }
+            }
+        }
+    }
+
+    async fn handle_client_data(&mut self, buf: &mut ByteBuffer) -> Result<(), Error> {
+        while let Some((msg, len)) = parse_message(&buf[..])? {
+            buf.consume(len);
+            match msg {
+                ClientMessage::Data(data) => self.master.write_all(&data).await?,
+                ClientMessage::Resize { cols, rows } => self.master.get_ref().resize(cols, rows)?,
+                ClientMessage::Ping => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_message() {
+    let parse = |data: &[u8]| parse_message(data).expect("failed to parse message");
+
+    assert_eq!(parse(b""), None);
+    assert_eq!(parse(b"2"), Some((ClientMessage::Ping, 1)));
+    assert_eq!(parse(b"22"), Some((ClientMessage::Ping, 1)));
+    assert_eq!(
+        parse(b"0:5:hello0:1:x"),
+        Some((ClientMessage::Data(b"hello".to_vec()), 8))
+    );
+    assert_eq!(parse(b"0:5:hel"), None);
+    assert_eq!(parse(b"0:12"), None);
+    assert_eq!(
+        parse(b"1:80:24:"),
+        Some((ClientMessage::Resize { cols: 80, rows: 24 }, 8))
+    );
+    assert_eq!(parse(b"1:80:24"), None);
+
+    assert!(parse_message(b"3").is_err());
+    assert!(parse_message(b"0x").is_err());
+    assert!(parse_message(b"0::").is_err());
+    assert!(parse_message(b"0:1a:").is_err());
+    assert!(parse_message(b"0:99999999:").is_err());
+    assert!(parse_message(b"1:80:99999:").is_err());
+}
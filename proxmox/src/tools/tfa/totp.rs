@@ -11,6 +11,8 @@ use openssl::sign::Signer;
 use percent_encoding::{percent_decode, percent_encode};
 use serde::{Serialize, Serializer};
 
+use crate::tools::base32;
+
 /// Algorithms supported by the TOTP. This is simply an enum limited to the most common
 /// available implementations.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -304,7 +306,7 @@ impl Totp {
             out,
             "{}?secret={}",
             percent_encode(account_name.as_bytes(), percent_encoding::NON_ALPHANUMERIC),
-            base32::encode(base32::Alphabet::RFC4648 { padding: false }, &self.secret),
+            base32::encode_no_pad(&self.secret),
         )?;
         write!(out, "&digits={}", self.digits)?;
         write!(out, "&algorithm={}", self.algorithm)?;
@@ -372,11 +374,9 @@ impl std::str::FromStr for Totp {
 
             match &*key {
                 "secret" => {
-                    totp.secret = base32::decode(
-                        base32::Alphabet::RFC4648 { padding: false },
-                        &value.decode_utf8()?,
-                    )
-                    .ok_or_else(|| anyhow!("failed to decode otp secret in otpauth url"))?
+                    totp.secret = base32::decode(&*value.decode_utf8()?).map_err(|err| {
+                        anyhow!("failed to decode otp secret in otpauth url - {}", err)
+                    })?
                 }
                 "digits" => totp.digits = value.decode_utf8()?.parse()?,
                 "algorithm" => totp.algorithm = value.decode_utf8()?.parse()?,
@@ -453,19 +453,10 @@ impl PartialEq<u32> for TotpValue {
 }
 
 /// For convenience we allow directly comparing with a string. This will make sure the string has
-/// the exact number of digits while parsing it explicitly as a decimal string.
+/// the exact number of digits. The comparison is done in constant time.
 impl PartialEq<&str> for TotpValue {
     fn eq(&self, other: &&str) -> bool {
-        // Since we use `from_str_radix` with a radix of 10 explicitly, we can check the number of
-        // bytes against the number of digits.
-        if other.as_bytes().len() != (self.digits as usize) {
-            return false;
-        }
-
-        match u32::from_str_radix(*other, 10) {
-            Ok(value) => self.value() == value,
-            Err(_) => false,
-        }
+        crate::tools::constant_time_eq(self.to_string().as_bytes(), other.as_bytes())
     }
 }
 
@@ -10,6 +10,7 @@ use openssl::sha;
 use openssl::x509::X509;
 use serde::{Deserialize, Serialize};
 
+use crate::tools::constant_time_eq;
 use crate::tools::serde::{bytes_as_base64, bytes_as_base64url_nopad};
 
 const CHALLENGE_LEN: usize = 32;
@@ -175,7 +176,7 @@ impl U2f {
         let client_data: ClientData = serde_json::from_reader(&mut &client_data_decoded[..])
             .map_err(|err| format_err!("error parsing client data: {}", err))?;
 
-        if client_data.challenge != challenge {
+        if !constant_time_eq(client_data.challenge.as_bytes(), challenge.as_bytes()) {
             bail!("registration challenge did not match");
         }
 
@@ -253,7 +254,7 @@ impl U2f {
         let client_data: ClientData = serde_json::from_reader(&mut &client_data_decoded[..])
             .map_err(|err| format_err!("error parsing client data: {}", err))?;
 
-        if client_data.challenge != challenge {
+        if !constant_time_eq(client_data.challenge.as_bytes(), challenge.as_bytes()) {
             bail!("authentication challenge did not match");
         }
 
@@ -0,0 +1,310 @@
+//! Signed authentication tickets.
+//!
+//! A ticket has the form `PREFIX:DATA:TIME::SIGNATURE`, where `TIME` is the creation time as
+//! upper case hex unix epoch and `SIGNATURE` the unpadded base64 signature of everything before
+//! the `::`, optionally followed by `:` and additional authenticated data which is not part of
+//! the ticket itself (like the path a ticket is valid for).
+//!
+//! ```
+//! # use proxmox::tools::ticket::{Ticket, TicketKey};
+//! # fn code() -> Result<(), anyhow::Error> {
+//! let key = TicketKey::hmac(b"a long shared secret".to_vec())?;
+//!
+//! let ticket = Ticket::new("PBS", &"root@pam".to_string())?.sign(&key, None)?;
+//!
+//! let userid: String = Ticket::parse(&ticket)?.verify(&key, "PBS", None)?;
+//! assert_eq!(userid, "root@pam");
+//! # Ok(())
+//! # }
+//! # code().unwrap();
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{HasPublic, PKey, Private, Public};
+use openssl::sign::{Signer, Verifier};
+
+use crate::tools::{base64, constant_time_eq, time::epoch_i64};
+
+/// How long tickets are valid, in seconds.
+pub const TICKET_LIFETIME: i64 = 2 * 3600;
+
+/// How far a ticket's creation time may lie in the future, for hosts with slightly different
+/// clocks.
+pub const TICKET_CLOCK_SKEW: i64 = 300;
+
+/// The key used to sign and verify tickets.
+pub enum TicketKey {
+    /// A shared secret, tickets are signed with HMAC-SHA256.
+    Hmac(PKey<Private>),
+    /// A key pair, tickets are signed with a SHA-256 signature.
+    Private(PKey<Private>),
+    /// A public key, which can only verify tickets.
+    Public(PKey<Public>),
+}
+
+impl TicketKey {
+    /// Use a shared secret for HMAC-SHA256 signatures.
+    pub fn hmac(secret: Vec<u8>) -> Result<Self, Error> {
+        Ok(TicketKey::Hmac(PKey::hmac(&secret)?))
+    }
+
+    /// Use an RSA or EC key pair in PEM format.
+    pub fn private_key_from_pem(pem: &[u8]) -> Result<Self, Error> {
+        Ok(TicketKey::Private(PKey::private_key_from_pem(pem)?))
+    }
+
+    /// Use a public key in PEM format, for verification only.
+    pub fn public_key_from_pem(pem: &[u8]) -> Result<Self, Error> {
+        Ok(TicketKey::Public(PKey::public_key_from_pem(pem)?))
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = match self {
+            TicketKey::Hmac(key) | TicketKey::Private(key) => key,
+            TicketKey::Public(_) => bail!("cannot sign tickets with a public key"),
+        };
+        let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+        signer.update(data)?;
+        Ok(signer.sign_to_vec()?)
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool, Error> {
+        fn verify_with<T: HasPublic>(
+            key: &PKey<T>,
+            data: &[u8],
+            signature: &[u8],
+        ) -> Result<bool, Error> {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+            verifier.update(data)?;
+            Ok(verifier.verify(signature)?)
+        }
+
+        match self {
+            TicketKey::Hmac(_) => Ok(constant_time_eq(&self.sign(data)?, signature)),
+            TicketKey::Private(key) => verify_with(key, data, signature),
+            TicketKey::Public(key) => verify_with(key, data, signature),
+        }
+    }
+}
+
+/// An authentication ticket for data of type `T`, see the module documentation.
+pub struct Ticket<T> {
+    prefix: String,
+    data: String,
+    time: i64,
+    signature: Option<Vec<u8>>,
+    _type_marker: PhantomData<fn() -> T>,
+}
+
+impl<T: fmt::Display> Ticket<T> {
+    /// Create a new, unsigned ticket for `data` with the current time.
+    pub fn new(prefix: &str, data: &T) -> Result<Self, Error> {
+        if prefix.is_empty() || prefix.contains(':') {
+            bail!("invalid ticket prefix {:?}", prefix);
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            data: data.to_string(),
+            time: epoch_i64(),
+            signature: None,
+            _type_marker: PhantomData,
+        })
+    }
+}
+
+impl<T> Ticket<T> {
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The creation time as unix epoch.
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    /// The data as contained in the ticket, without verifying the signature.
+    pub fn raw_data(&self) -> &str {
+        &self.data
+    }
+
+    fn ticket_data(&self) -> String {
+        format!("{}:{}:{:08X}", self.prefix, self.data, self.time)
+    }
+
+    fn signed_data(&self, aad: Option<&str>) -> String {
+        match aad {
+            Some(aad) => format!("{}:{}", self.ticket_data(), aad),
+            None => self.ticket_data(),
+        }
+    }
+
+    /// Sign the ticket and return its string representation.
+    pub fn sign(&mut self, key: &TicketKey, aad: Option<&str>) -> Result<String, Error> {
+        let signature = key.sign(self.signed_data(aad).as_bytes())?;
+        let ticket = format!(
+            "{}::{}",
+            self.ticket_data(),
+            base64::encode_no_pad(&signature)
+        );
+        self.signature = Some(signature);
+        Ok(ticket)
+    }
+
+    /// Parse a ticket string. This does not verify anything, use `verify` to get at the data.
+    pub fn parse(ticket: &str) -> Result<Self, Error> {
+        let parse = || -> Option<Self> {
+            let sig_pos = ticket.rfind("::")?;
+            let (payload, signature) = (&ticket[..sig_pos], &ticket[(sig_pos + 2)..]);
+
+            let prefix_end = payload.find(':')?;
+            let time_start = payload.rfind(':')?;
+            if time_start <= prefix_end {
+                return None;
+            }
+
+            let time = &payload[(time_start + 1)..];
+            if time.is_empty() || !time.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            Some(Self {
+                prefix: payload[..prefix_end].to_string(),
+                data: payload[(prefix_end + 1)..time_start].to_string(),
+                time: i64::from_str_radix(time, 16).ok()?,
+                signature: Some(base64::decode(signature).ok()?),
+                _type_marker: PhantomData,
+            })
+        };
+
+        parse().ok_or_else(|| format_err!("failed to parse ticket"))
+    }
+
+    /// Verify the ticket's prefix, signature and age, and parse its data.
+    pub fn verify(&self, key: &TicketKey, prefix: &str, aad: Option<&str>) -> Result<T, Error>
+    where
+        T: std::str::FromStr,
+        T::Err: fmt::Display,
+    {
+        self.verify_with_time_frame(key, prefix, aad, -TICKET_CLOCK_SKEW..TICKET_LIFETIME)
+    }
+
+    /// Like `verify`, but with a custom range of accepted ticket ages in seconds. As usual, the
+    /// end of the range is exclusive.
+    pub fn verify_with_time_frame(
+        &self,
+        key: &TicketKey,
+        prefix: &str,
+        aad: Option<&str>,
+        time_frame: Range<i64>,
+    ) -> Result<T, Error>
+    where
+        T: std::str::FromStr,
+        T::Err: fmt::Display,
+    {
+        if self.prefix != prefix {
+            bail!("ticket with invalid prefix");
+        }
+
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => bail!("invalid ticket - not signed"),
+        };
+        if !key.verify(self.signed_data(aad).as_bytes(), signature)? {
+            bail!("ticket with invalid signature");
+        }
+
+        let age = epoch_i64() - self.time;
+        if age < time_frame.start {
+            bail!("invalid ticket - timestamp newer than expected");
+        }
+        if age >= time_frame.end {
+            bail!("invalid ticket - expired");
+        }
+
+        self.data
+            .parse()
+            .map_err(|err| format_err!("failed to parse ticket data - {}", err))
+    }
+}
+
+#[test]
+fn test_ticket() {
+    let hmac = TicketKey::hmac(b"a long shared secret".to_vec()).unwrap();
+
+    let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+    let public_pem = rsa.public_key_to_pem().unwrap();
+    let private = TicketKey::Private(PKey::from_rsa(rsa).unwrap());
+    let public = TicketKey::public_key_from_pem(&public_pem).unwrap();
+
+    let userid = "root@pam".to_string();
+
+    for (signer, verifier) in [(&hmac, &hmac), (&private, &public)].iter() {
+        let ticket = Ticket::new("PBS", &userid)
+            .unwrap()
+            .sign(signer, None)
+            .unwrap();
+        assert!(ticket.starts_with("PBS:root@pam:"));
+
+        let parsed = Ticket::<String>::parse(&ticket).unwrap();
+        assert_eq!(parsed.verify(verifier, "PBS", None).unwrap(), userid);
+        assert!(parsed.verify(verifier, "PVE", None).is_err());
+        assert!(parsed.verify(verifier, "PBS", Some("/path")).is_err());
+
+        let tampered = ticket.replacen("root@pam", "admin@pam", 1);
+        let parsed = Ticket::<String>::parse(&tampered).unwrap();
+        assert!(parsed.verify(verifier, "PBS", None).is_err());
+
+        let ticket = Ticket::new("PBSTERM", &userid)
+            .unwrap()
+            .sign(signer, Some("/nodes/localhost"))
+            .unwrap();
+        let parsed = Ticket::<String>::parse(&ticket).unwrap();
+        assert!(parsed.verify(verifier, "PBSTERM", None).is_err());
+        assert_eq!(
+            parsed
+                .verify(verifier, "PBSTERM", Some("/nodes/localhost"))
+                .unwrap(),
+            userid
+        );
+    }
+
+    assert!(Ticket::new("PBS", &userid)
+        .unwrap()
+        .sign(&public, None)
+        .is_err());
+
+    // data may contain colons
+    let ticket = Ticket::new("PBS", &"user:with:colons".to_string())
+        .unwrap()
+        .sign(&hmac, None)
+        .unwrap();
+    let parsed = Ticket::<String>::parse(&ticket).unwrap();
+    assert_eq!(
+        parsed.verify(&hmac, "PBS", None).unwrap(),
+        "user:with:colons"
+    );
+
+    let mut old = Ticket::new("PBS", &userid).unwrap();
+    old.time -= TICKET_LIFETIME + 1;
+    let ticket = old.sign(&hmac, None).unwrap();
+    let parsed = Ticket::<String>::parse(&ticket).unwrap();
+    assert!(parsed.verify(&hmac, "PBS", None).is_err());
+    assert!(parsed
+        .verify_with_time_frame(&hmac, "PBS", None, 0..(2 * TICKET_LIFETIME))
+        .is_ok());
+
+    for invalid in &[
+        "",
+        "PBS",
+        "PBS:root@pam",
+        "PBS:5F0A::",
+        "PBS:root@pam:xyz::AAAA",
+    ] {
+        assert!(Ticket::<String>::parse(invalid).is_err(), "{:?}", invalid);
+    }
+}
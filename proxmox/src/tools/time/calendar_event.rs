@@ -0,0 +1,612 @@
+use std::ops::{BitOr, BitOrAssign};
+
+use anyhow::{bail, format_err, Error};
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+
+use super::{localtime, TmEditor};
+
+/// A set of week days.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct WeekDays(u8);
+
+impl WeekDays {
+    // bits are indexed by `tm_wday`
+    pub const SUNDAY: WeekDays = WeekDays(1 << 0);
+    pub const MONDAY: WeekDays = WeekDays(1 << 1);
+    pub const TUESDAY: WeekDays = WeekDays(1 << 2);
+    pub const WEDNESDAY: WeekDays = WeekDays(1 << 3);
+    pub const THURSDAY: WeekDays = WeekDays(1 << 4);
+    pub const FRIDAY: WeekDays = WeekDays(1 << 5);
+    pub const SATURDAY: WeekDays = WeekDays(1 << 6);
+    pub const ALL: WeekDays = WeekDays(0x7f);
+
+    pub const fn empty() -> Self {
+        WeekDays(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: WeekDays) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The day for a `tm_wday` value, 0 being Sunday.
+    pub fn from_tm_wday(wday: i32) -> Self {
+        WeekDays(1 << wday.rem_euclid(7))
+    }
+}
+
+impl BitOr for WeekDays {
+    type Output = WeekDays;
+
+    fn bitor(self, other: WeekDays) -> WeekDays {
+        WeekDays(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for WeekDays {
+    fn bitor_assign(&mut self, other: WeekDays) {
+        self.0 |= other.0;
+    }
+}
+
+// in the order used for ranges
+const WEEK_DAY_NAMES: [(&str, &str, WeekDays); 7] = [
+    ("mon", "monday", WeekDays::MONDAY),
+    ("tue", "tuesday", WeekDays::TUESDAY),
+    ("wed", "wednesday", WeekDays::WEDNESDAY),
+    ("thu", "thursday", WeekDays::THURSDAY),
+    ("fri", "friday", WeekDays::FRIDAY),
+    ("sat", "saturday", WeekDays::SATURDAY),
+    ("sun", "sunday", WeekDays::SUNDAY),
+];
+
+fn parse_week_day(name: &str) -> Result<usize, Error> {
+    let name = name.to_lowercase();
+    WEEK_DAY_NAMES
+        .iter()
+        .position(|(short, long, _)| name == *short || name == *long)
+        .ok_or_else(|| format_err!("invalid week day {:?}", name))
+}
+
+pub(super) fn parse_week_days(spec: &str) -> Result<WeekDays, Error> {
+    let mut days = WeekDays::empty();
+    for item in spec.split(',') {
+        let (first, last) = match item.find("..") {
+            Some(pos) => (
+                parse_week_day(&item[..pos])?,
+                parse_week_day(&item[pos + 2..])?,
+            ),
+            None => {
+                let day = parse_week_day(item)?;
+                (day, day)
+            }
+        };
+        if first > last {
+            bail!("invalid week day range {:?}", item);
+        }
+        for (_, _, day) in &WEEK_DAY_NAMES[first..=last] {
+            days |= *day;
+        }
+    }
+    Ok(days)
+}
+
+/// A single element of a date or time component of a calendar event.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DateTimeValue {
+    /// A single value.
+    Single(u32),
+    /// An inclusive range, `start..end`.
+    Range(u32, u32),
+    /// A start value and its repetitions, `start/step`.
+    Repeated(u32, u32),
+}
+
+impl DateTimeValue {
+    pub fn contains(&self, value: u32) -> bool {
+        match *self {
+            DateTimeValue::Single(single) => value == single,
+            DateTimeValue::Range(start, end) => value >= start && value <= end,
+            DateTimeValue::Repeated(start, step) => value >= start && (value - start) % step == 0,
+        }
+    }
+
+    /// Whether any value of `list` contains `value`. An empty list contains all values.
+    pub fn list_contains(list: &[DateTimeValue], value: u32) -> bool {
+        list.is_empty() || list.iter().any(|item| item.contains(value))
+    }
+
+    /// The smallest value of `list` greater than `value` and not greater than `max`.
+    fn find_next(list: &[DateTimeValue], value: u32, max: u32) -> Option<u32> {
+        list.iter()
+            .filter_map(|item| match *item {
+                DateTimeValue::Single(single) if single > value => Some(single),
+                DateTimeValue::Range(start, _) if value < start => Some(start),
+                DateTimeValue::Range(_, end) if value < end => Some(value + 1),
+                DateTimeValue::Repeated(start, _) if value < start => Some(start),
+                DateTimeValue::Repeated(start, step) => {
+                    Some(start + ((value - start) / step + 1) * step)
+                }
+                _ => None,
+            })
+            .filter(|next| *next <= max)
+            .min()
+    }
+}
+
+fn parse_number(s: &str, what: &str, min: u32, max: u32) -> Result<u32, Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("invalid {} {:?}", what, s);
+    }
+    match s.parse::<u32>() {
+        Ok(value) if value >= min && value <= max => Ok(value),
+        _ => bail!("{} {:?} out of range ({}..{})", what, s, min, max),
+    }
+}
+
+fn parse_values(spec: &str, what: &str, min: u32, max: u32) -> Result<Vec<DateTimeValue>, Error> {
+    if spec == "*" {
+        return Ok(Vec::new());
+    }
+
+    let mut list = Vec::new();
+    for item in spec.split(',') {
+        let (base, step) = match item.find('/') {
+            Some(pos) => {
+                let step = parse_number(&item[pos + 1..], "repetition", 1, max)?;
+                (&item[..pos], Some(step))
+            }
+            None => (item, None),
+        };
+
+        let value = if base == "*" {
+            match step {
+                Some(step) => DateTimeValue::Repeated(min, step),
+                None => bail!("invalid {} {:?}", what, item),
+            }
+        } else if let Some(pos) = base.find("..") {
+            if step.is_some() {
+                bail!("repetitions of ranges are not supported ({:?})", item);
+            }
+            let start = parse_number(&base[..pos], what, min, max)?;
+            let end = parse_number(&base[pos + 2..], what, min, max)?;
+            if start > end {
+                bail!("invalid {} range {:?}", what, item);
+            }
+            DateTimeValue::Range(start, end)
+        } else {
+            let value = parse_number(base, what, min, max)?;
+            match step {
+                Some(step) => DateTimeValue::Repeated(value, step),
+                None => DateTimeValue::Single(value),
+            }
+        };
+        list.push(value);
+    }
+    Ok(list)
+}
+
+/// A systemd calendar event, see `systemd.time(7)`.
+///
+/// Empty week day sets and value lists match any value.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CalendarEvent {
+    pub days: WeekDays,
+    pub second: Vec<DateTimeValue>,
+    pub minute: Vec<DateTimeValue>,
+    pub hour: Vec<DateTimeValue>,
+    pub day: Vec<DateTimeValue>,
+    pub month: Vec<DateTimeValue>,
+    pub year: Vec<DateTimeValue>,
+}
+
+impl CalendarEvent {
+    fn daily() -> Self {
+        let zero = vec![DateTimeValue::Single(0)];
+        CalendarEvent {
+            second: zero.clone(),
+            minute: zero.clone(),
+            hour: zero,
+            ..Default::default()
+        }
+    }
+
+    fn with_months(months: &[u32]) -> Self {
+        CalendarEvent {
+            day: vec![DateTimeValue::Single(1)],
+            month: months.iter().map(|m| DateTimeValue::Single(*m)).collect(),
+            ..Self::daily()
+        }
+    }
+
+    fn parse_date(&mut self, spec: &str) -> Result<(), Error> {
+        let parts: Vec<&str> = spec.split('-').collect();
+        let (year, month, day) = match parts[..] {
+            [year, month, day] => (Some(year), month, day),
+            [month, day] => (None, month, day),
+            _ => bail!("invalid date specification {:?}", spec),
+        };
+        if let Some(year) = year {
+            self.year = parse_values(year, "year", 1970, 9999)?;
+        }
+        self.month = parse_values(month, "month", 1, 12)?;
+        self.day = parse_values(day, "day", 1, 31)?;
+        Ok(())
+    }
+
+    fn parse_time(&mut self, spec: &str) -> Result<(), Error> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (hour, minute, second) = match parts[..] {
+            [hour, minute, second] => (Some(hour), minute, Some(second)),
+            [hour, minute] => (Some(hour), minute, None),
+            [minute] => (None, minute, None),
+            _ => bail!("invalid time specification {:?}", spec),
+        };
+        if let Some(hour) = hour {
+            self.hour = parse_values(hour, "hour", 0, 23)?;
+        }
+        self.minute = parse_values(minute, "minute", 0, 59)?;
+        self.second = match second {
+            Some(second) => parse_values(second, "second", 0, 59)?,
+            None => vec![DateTimeValue::Single(0)],
+        };
+        Ok(())
+    }
+}
+
+/// Parse a calendar event.
+///
+/// The format is `[WEEKDAYS] [[YEAR-]MONTH-DAY] [[HOUR:]MINUTE[:SECOND]]`, where each component
+/// is `*`, a value, a range (`1..5`) or a repetition (`*/15`, `2/4`), or a comma separated list of
+/// those. A missing date matches every day, a missing time means midnight. A single time component
+/// is the minute of every hour. The shortcuts `minutely`, `hourly`, `daily`, `weekly`, `monthly`,
+/// `yearly` (`annually`), `quarterly` and `semiannually` are also supported.
+pub fn parse_calendar_event(input: &str) -> Result<CalendarEvent, Error> {
+    let input = input.trim();
+
+    let event = match input {
+        "minutely" => CalendarEvent {
+            second: vec![DateTimeValue::Single(0)],
+            ..Default::default()
+        },
+        "hourly" => CalendarEvent {
+            hour: Vec::new(),
+            ..CalendarEvent::daily()
+        },
+        "daily" => CalendarEvent::daily(),
+        "weekly" => CalendarEvent {
+            days: WeekDays::MONDAY,
+            ..CalendarEvent::daily()
+        },
+        "monthly" => CalendarEvent {
+            day: vec![DateTimeValue::Single(1)],
+            ..CalendarEvent::daily()
+        },
+        "yearly" | "annually" => CalendarEvent::with_months(&[1]),
+        "quarterly" => CalendarEvent::with_months(&[1, 4, 7, 10]),
+        "semiannually" | "semi-annually" => CalendarEvent::with_months(&[1, 7]),
+        _ => parse_calendar_spec(input)
+            .map_err(|err| format_err!("unable to parse calendar event {:?} - {}", input, err))?,
+    };
+
+    Ok(event)
+}
+
+fn parse_calendar_spec(input: &str) -> Result<CalendarEvent, Error> {
+    let mut event = CalendarEvent::default();
+
+    let mut parts = input.split_whitespace().peekable();
+    match parts.peek() {
+        None => bail!("empty calendar event"),
+        Some(first) if first.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+            event.days = parse_week_days(first)?;
+            parts.next();
+        }
+        Some(_) => (),
+    }
+
+    let mut has_date = false;
+    let mut has_time = false;
+    for part in parts {
+        if !has_date && !has_time && part.contains('-') {
+            event.parse_date(part)?;
+            has_date = true;
+        } else if !has_time {
+            event.parse_time(part)?;
+            has_time = true;
+        } else {
+            bail!("unexpected {:?}", part);
+        }
+    }
+
+    if !has_time {
+        let daily = CalendarEvent::daily();
+        event.hour = daily.hour;
+        event.minute = daily.minute;
+        event.second = daily.second;
+    }
+
+    Ok(event)
+}
+
+impl std::str::FromStr for CalendarEvent {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_calendar_event(s)
+    }
+}
+
+forward_deserialize_to_from_str!(CalendarEvent);
+
+fn verify_calendar_event(s: &str) -> Result<(), Error> {
+    parse_calendar_event(s).map(drop)
+}
+
+/// Schema for schedule parameters.
+pub const CALENDAR_EVENT_SCHEMA: Schema =
+    StringSchema::new("Calendar event, for example 'daily' or 'Mon..Fri *-*-* 02:30'.")
+        .format(&ApiStringFormat::VerifyFn(verify_calendar_event))
+        .min_length(1)
+        .max_length(128)
+        .schema();
+
+/// Compute the first time after `last` (a unix epoch) at which `event` triggers.
+///
+/// The event is evaluated in UTC or local time. Local times which do not exist because of a
+/// daylight saving time transition are skipped, ambiguous ones trigger once. Returns `None` if
+/// the event never triggers again.
+pub fn compute_next_event(
+    event: &CalendarEvent,
+    last: i64,
+    utc: bool,
+) -> Result<Option<i64>, Error> {
+    let last = last + 1; // at least one second later
+
+    let all_days = event.days.is_empty() || event.days == WeekDays::ALL;
+
+    let mut t = TmEditor::with_epoch(last, utc)?;
+
+    // every step moves forward and resets the smaller fields, so this is plenty
+    for _ in 0..1000 {
+        if !event.year.is_empty() {
+            let year = t.year() as u32;
+            if !DateTimeValue::list_contains(&event.year, year) {
+                match DateTimeValue::find_next(&event.year, year, 9999) {
+                    Some(next) => t.add_years((next - year) as libc::c_int)?,
+                    None => return Ok(None),
+                }
+                continue;
+            }
+        }
+
+        if !event.month.is_empty() {
+            let month = t.month() as u32;
+            if !DateTimeValue::list_contains(&event.month, month) {
+                match DateTimeValue::find_next(&event.month, month, 12) {
+                    Some(next) => t.add_months((next - month) as libc::c_int)?,
+                    None => t.add_years(1)?,
+                }
+                continue;
+            }
+        }
+
+        if !event.day.is_empty() {
+            let day = t.day() as u32;
+            if !DateTimeValue::list_contains(&event.day, day) {
+                // days beyond the end of the month wrap into the next one and get checked again
+                match DateTimeValue::find_next(&event.day, day, 31) {
+                    Some(next) => t.add_days((next - day) as libc::c_int)?,
+                    None => t.add_months(1)?,
+                }
+                continue;
+            }
+        }
+
+        if !all_days {
+            let wday = (t.day_num() + 1) % 7;
+            if !event.days.contains(WeekDays::from_tm_wday(wday)) {
+                let offset = (1..7)
+                    .find(|offset| event.days.contains(WeekDays::from_tm_wday(wday + offset)))
+                    .unwrap_or(7);
+                t.add_days(offset)?;
+                continue;
+            }
+        }
+
+        let hour = t.hour() as u32;
+        if !DateTimeValue::list_contains(&event.hour, hour) {
+            match DateTimeValue::find_next(&event.hour, hour, 23) {
+                Some(next) => t.set_time(next as libc::c_int, 0, 0)?,
+                None => t.add_days(1)?,
+            }
+            continue;
+        }
+
+        let minute = t.min() as u32;
+        if !DateTimeValue::list_contains(&event.minute, minute) {
+            match DateTimeValue::find_next(&event.minute, minute, 59) {
+                Some(next) => t.set_min_sec(next as libc::c_int, 0)?,
+                None => t.set_time(hour as libc::c_int + 1, 0, 0)?,
+            }
+            continue;
+        }
+
+        let second = t.sec() as u32;
+        if !DateTimeValue::list_contains(&event.second, second) {
+            match DateTimeValue::find_next(&event.second, second, 59) {
+                Some(next) => t.set_sec(next as libc::c_int)?,
+                None => t.set_min_sec(minute as libc::c_int + 1, 0)?,
+            }
+            continue;
+        }
+
+        let mut next = t.clone().into_epoch()?;
+        if next < last {
+            // an ambiguous local time, for which mktime picked the earlier occurrence
+            next += i64::from(localtime(next)?.tm_gmtoff - localtime(last)?.tm_gmtoff);
+        }
+        if next >= last {
+            return Ok(Some(next));
+        }
+
+        // every occurrence of this local time is before `last`, so continue right after it
+        // instead of skipping the rest of the day
+        let second = t.sec();
+        t.set_sec(second + 1)?;
+    }
+
+    Ok(None)
+}
+
+#[test]
+fn test_parse_calendar_event() {
+    use DateTimeValue::*;
+
+    let event = parse_calendar_event("Mon..Fri *-*-* 02:30:00").unwrap();
+    assert!(event.days.contains(WeekDays::MONDAY | WeekDays::FRIDAY));
+    assert!(!event.days.contains(WeekDays::SUNDAY));
+    assert!(event.year.is_empty() && event.month.is_empty() && event.day.is_empty());
+    assert_eq!(event.hour, [Single(2)]);
+    assert_eq!(event.minute, [Single(30)]);
+    assert_eq!(event.second, [Single(0)]);
+
+    assert_eq!(
+        parse_calendar_event("daily").unwrap(),
+        CalendarEvent::daily()
+    );
+    assert_eq!(
+        parse_calendar_event("*-*-* 00:00").unwrap(),
+        CalendarEvent::daily()
+    );
+
+    let event = parse_calendar_event("*/15").unwrap();
+    assert!(event.hour.is_empty());
+    assert_eq!(event.minute, [Repeated(0, 15)]);
+    assert_eq!(event.second, [Single(0)]);
+
+    let event = parse_calendar_event("sat,sun 2021-1..6,12-1/7 8,20:0").unwrap();
+    assert_eq!(event.days, WeekDays::SATURDAY | WeekDays::SUNDAY);
+    assert_eq!(event.year, [Single(2021)]);
+    assert_eq!(event.month, [Range(1, 6), Single(12)]);
+    assert_eq!(event.day, [Repeated(1, 7)]);
+    assert_eq!(event.hour, [Single(8), Single(20)]);
+    assert!(DateTimeValue::list_contains(&event.day, 15));
+    assert!(!DateTimeValue::list_contains(&event.day, 16));
+
+    let event = parse_calendar_event("quarterly").unwrap();
+    assert_eq!(event.month, [Single(1), Single(4), Single(7), Single(10)]);
+    assert_eq!(event.day, [Single(1)]);
+
+    for invalid in &[
+        "",
+        "Fri..Mon",
+        "Mon 25:00",
+        "*-13-01",
+        "*/0",
+        "1..5/2",
+        "12:00 *-*-*",
+        "hourly 12:00",
+    ] {
+        assert!(
+            parse_calendar_event(invalid).is_err(),
+            "{:?} should fail",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_compute_next_event() {
+    use super::parse_rfc3339;
+
+    let next = |spec: &str, last: &str| {
+        let event = parse_calendar_event(spec).unwrap();
+        compute_next_event(&event, parse_rfc3339(last).unwrap(), true)
+            .unwrap()
+            .map(|epoch| super::epoch_to_rfc3339_utc(epoch).unwrap())
+    };
+    let expect = |spec: &str, last: &str, expected: &str| {
+        assert_eq!(
+            next(spec, last).as_deref(),
+            Some(expected),
+            "{:?} after {}",
+            spec,
+            last
+        );
+    };
+
+    expect("daily", "2021-01-01T12:00:00Z", "2021-01-02T00:00:00Z");
+    expect("daily", "2021-01-01T00:00:00Z", "2021-01-02T00:00:00Z");
+    expect("*/15", "2021-01-01T12:07:00Z", "2021-01-01T12:15:00Z");
+    expect("*:*/25", "2021-01-01T12:51:00Z", "2021-01-01T13:00:00Z");
+    expect("hourly", "2021-01-31T23:30:00Z", "2021-02-01T00:00:00Z");
+    // 2021-01-02 is a Saturday
+    expect(
+        "Mon..Fri 02:30",
+        "2021-01-02T00:00:00Z",
+        "2021-01-04T02:30:00Z",
+    );
+    expect("sun", "2021-01-04T00:00:00Z", "2021-01-10T00:00:00Z");
+    expect("*-*-31", "2021-04-01T00:00:00Z", "2021-05-31T00:00:00Z");
+    expect("*-02-29", "2021-01-01T00:00:00Z", "2024-02-29T00:00:00Z");
+    expect("monthly", "2021-12-15T00:00:00Z", "2022-01-01T00:00:00Z");
+    expect("fri *-*-13", "2021-01-01T00:00:00Z", "2021-08-13T00:00:00Z");
+    expect("*:0/20:30", "2021-01-01T12:50:00Z", "2021-01-01T13:00:30Z");
+
+    assert_eq!(next("2020-*-* 00:00", "2021-01-01T00:00:00Z"), None);
+}
+
+#[test]
+fn test_compute_next_event_dst() {
+    use crate::test::process::{is_helper, run_in_helper};
+
+    use super::parse_rfc3339;
+
+    const NAME: &str = "tools::time::calendar_event::test_compute_next_event_dst";
+
+    // The time zone is process wide, so don't change it for the other tests.
+    if !is_helper(NAME) {
+        // Europe/Vienna, spelled out so it works without tzdata
+        return run_in_helper(NAME, &[("TZ", "CET-1CEST,M3.5.0,M10.5.0/3")]);
+    }
+
+    let expect = |spec: &str, last: &str, expected: &str| {
+        let event = parse_calendar_event(spec).unwrap();
+        let next = compute_next_event(&event, parse_rfc3339(last).unwrap(), false)
+            .unwrap()
+            .map(|epoch| super::epoch_to_rfc3339_utc(epoch).unwrap());
+        assert_eq!(next.as_deref(), Some(expected), "{:?} after {}", spec, last);
+    };
+
+    // 2021-03-28 02:00 CET jumps to 03:00 CEST, so that day has 23 hours
+    expect("daily", "2021-03-27T00:00:00+01:00", "2021-03-27T23:00:00Z");
+    expect("daily", "2021-03-28T00:00:00+01:00", "2021-03-28T22:00:00Z");
+    expect(
+        "hourly",
+        "2021-03-28T01:00:00+01:00",
+        "2021-03-28T01:00:00Z",
+    );
+    // 02:30 does not exist on that day
+    expect("02:30", "2021-03-27T02:30:00+01:00", "2021-03-29T00:30:00Z");
+
+    // 2021-10-31 03:00 CEST goes back to 02:00 CET, so that day has 25 hours
+    expect("daily", "2021-10-30T00:00:00+02:00", "2021-10-30T22:00:00Z");
+    expect("daily", "2021-10-31T00:00:00+02:00", "2021-10-31T23:00:00Z");
+    expect(
+        "*-11-01 12:00",
+        "2021-10-01T00:00:00+02:00",
+        "2021-11-01T11:00:00Z",
+    );
+    // within and right after the repeated hour, the next hourly events are on the same day
+    expect("*:45", "2021-10-31T02:30:00+01:00", "2021-10-31T01:45:00Z");
+    expect(
+        "hourly",
+        "2021-10-31T02:00:00+01:00",
+        "2021-10-31T02:00:00Z",
+    );
+}
@@ -0,0 +1,167 @@
+use anyhow::{bail, format_err, Error};
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+
+use super::calendar_event::parse_week_days;
+use super::{gmtime, localtime, WeekDays};
+
+/// A time of day, hours and minutes.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct HmTime {
+    pub hour: u32,
+    pub minute: u32,
+}
+
+impl HmTime {
+    fn minutes(&self) -> u32 {
+        self.hour * 60 + self.minute
+    }
+
+    /// Accepts `24:00` as the end of the day.
+    fn parse(s: &str) -> Result<Self, Error> {
+        let (hour, minute) = match s.find(':') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => (s, "0"),
+        };
+        let number = |s: &str| -> Result<u32, Error> {
+            if s.is_empty() || s.len() > 2 || !s.bytes().all(|b| b.is_ascii_digit()) {
+                bail!("invalid time {:?}", s);
+            }
+            Ok(s.parse::<u32>()?)
+        };
+        let time = HmTime {
+            hour: number(hour)?,
+            minute: number(minute)?,
+        };
+        if time.minute > 59 || time.minutes() > 24 * 60 {
+            bail!("time {:?} out of range", s);
+        }
+        Ok(time)
+    }
+}
+
+/// A time window on some or all days of the week, like `mon..fri 8:00-16:30`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DailyDuration {
+    /// The week days, empty for every day.
+    pub days: WeekDays,
+    pub start: HmTime,
+    /// The end of the window, exclusive.
+    pub end: HmTime,
+}
+
+impl DailyDuration {
+    /// Whether `epoch` lies within the window, in UTC or local time.
+    pub fn time_match(&self, epoch: i64, utc: bool) -> Result<bool, Error> {
+        let t = if utc {
+            gmtime(epoch)?
+        } else {
+            localtime(epoch)?
+        };
+
+        if !self.days.is_empty() && !self.days.contains(WeekDays::from_tm_wday(t.tm_wday)) {
+            return Ok(false);
+        }
+
+        let minutes = (t.tm_hour * 60 + t.tm_min) as u32;
+        Ok(minutes >= self.start.minutes() && minutes < self.end.minutes())
+    }
+}
+
+/// Parse a daily duration, `[WEEKDAYS] HH:MM-HH:MM`.
+///
+/// Week days use the calendar event syntax (`mon..fri`, `sat,sun`). The end time may be `24:00`
+/// but has to be after the start time, windows spanning midnight are not supported.
+pub fn parse_daily_duration(input: &str) -> Result<DailyDuration, Error> {
+    parse_daily_duration_do(input.trim())
+        .map_err(|err| format_err!("unable to parse daily duration {:?} - {}", input, err))
+}
+
+fn parse_daily_duration_do(input: &str) -> Result<DailyDuration, Error> {
+    let (days, range) = match input.find(char::is_whitespace) {
+        Some(pos) => (parse_week_days(&input[..pos])?, input[pos..].trim_start()),
+        None => (WeekDays::empty(), input),
+    };
+
+    let (start, end) = match range.find('-') {
+        Some(pos) => (
+            HmTime::parse(range[..pos].trim())?,
+            HmTime::parse(range[pos + 1..].trim())?,
+        ),
+        None => bail!("missing end time"),
+    };
+    if start >= end {
+        bail!("end time has to be after the start time");
+    }
+
+    Ok(DailyDuration { days, start, end })
+}
+
+impl std::str::FromStr for DailyDuration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_daily_duration(s)
+    }
+}
+
+forward_deserialize_to_from_str!(DailyDuration);
+
+fn verify_daily_duration(s: &str) -> Result<(), Error> {
+    parse_daily_duration(s).map(drop)
+}
+
+/// Schema for daily time window parameters.
+pub const DAILY_DURATION_SCHEMA: Schema =
+    StringSchema::new("Daily time window, for example 'mon..fri 8:00-16:30'.")
+        .format(&ApiStringFormat::VerifyFn(verify_daily_duration))
+        .min_length(1)
+        .max_length(128)
+        .schema();
+
+#[test]
+fn test_daily_duration() {
+    let duration = parse_daily_duration("mon..fri 8:00-16:30").unwrap();
+    assert!(duration.days.contains(WeekDays::MONDAY | WeekDays::FRIDAY));
+    assert!(!duration.days.contains(WeekDays::SATURDAY));
+    assert_eq!(duration.start, HmTime { hour: 8, minute: 0 });
+    assert_eq!(
+        duration.end,
+        HmTime {
+            hour: 16,
+            minute: 30
+        }
+    );
+
+    // 2021-01-04 is a Monday
+    let monday = 1609718400;
+    let matches =
+        |duration: &DailyDuration, offset: i64| duration.time_match(monday + offset, true).unwrap();
+    assert!(!matches(&duration, 7 * 3600 + 59 * 60));
+    assert!(matches(&duration, 8 * 3600));
+    assert!(matches(&duration, 16 * 3600 + 29 * 60));
+    assert!(!matches(&duration, 16 * 3600 + 30 * 60));
+    // Saturday
+    assert!(!matches(&duration, 5 * 86400 + 12 * 3600));
+
+    let duration = parse_daily_duration("22:00-24:00").unwrap();
+    assert!(duration.days.is_empty());
+    assert!(matches(&duration, 5 * 86400 + 23 * 3600));
+    assert!(!matches(&duration, 6 * 86400));
+
+    for invalid in &[
+        "",
+        "8:00",
+        "mon 16:00-8:00",
+        "xyz 8-9",
+        "8:60-9",
+        "8-25:00",
+        "8-9-10",
+    ] {
+        assert!(
+            parse_daily_duration(invalid).is_err(),
+            "{:?} should fail",
+            invalid
+        );
+    }
+}
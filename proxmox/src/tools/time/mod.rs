@@ -1,9 +1,18 @@
 use anyhow::{bail, format_err, Error};
 use std::ffi::{CStr, CString};
 
+mod calendar_event;
+pub use calendar_event::*;
+
+mod daily_duration;
+pub use daily_duration::*;
+
 mod tm_editor;
 pub use tm_editor::*;
 
+mod time_span;
+pub use time_span::*;
+
 /// Safe bindings to libc timelocal
 ///
 /// We set tm_isdst to -1.
@@ -201,8 +210,28 @@ pub fn epoch_to_rfc3339(epoch: i64) -> Result<String, Error> {
 }
 
 /// Parse RFC3339 into Unix epoch
+///
+/// Fractional seconds are accepted but truncated. Invalid dates like `2021-02-30` are
+/// rejected instead of being normalized.
 pub fn parse_rfc3339(input_str: &str) -> Result<i64, Error> {
-    let input = input_str.as_bytes();
+    // strip fractional seconds and normalize lower case separators
+    let mut input = input_str.as_bytes().to_vec();
+    if input.len() > 20 && input[19] == b'.' {
+        let digits = input[20..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if digits > 0 {
+            input.drain(19..(20 + digits));
+        }
+    }
+    if input.len() > 10 && input[10] == b't' {
+        input[10] = b'T';
+    }
+    if input.len() > 19 && input[19] == b'z' {
+        input[19] = b'Z';
+    }
+    let input = &input[..];
 
     let expect = |pos: usize, c: u8| {
         if input[pos] != c {
@@ -226,6 +255,13 @@ pub fn parse_rfc3339(input_str: &str) -> Result<i64, Error> {
         Ok(i)
     };
 
+    let check_range = |i: i32, min: i32, max: i32| {
+        if i < min {
+            bail!("value too small ({} < {})", i, min);
+        }
+        check_max(i, max)
+    };
+
     crate::try_block!({
         if input.len() < 20 || input.len() > 25 {
             bail!("timestamp of unexpected length");
@@ -251,9 +287,13 @@ pub fn parse_rfc3339(input_str: &str) -> Result<i64, Error> {
 
         tm.set_year(digit(0)? * 1000 + digit(1)? * 100 + digit(2)? * 10 + digit(3)?)?;
         expect(4, b'-')?;
-        tm.set_mon(check_max(digit(5)? * 10 + digit(6)?, 12)?)?;
+        tm.set_mon(check_range(digit(5)? * 10 + digit(6)?, 1, 12)?)?;
         expect(7, b'-')?;
-        tm.set_mday(check_max(digit(8)? * 10 + digit(9)?, 31)?)?;
+        let mday = check_range(digit(8)? * 10 + digit(9)?, 1, 31)?;
+        tm.set_mday(mday)?;
+        if tm.day() != mday {
+            bail!("invalid day of month {}", mday);
+        }
 
         expect(10, b'T')?;
 
@@ -398,3 +438,18 @@ fn test_timezones() {
     let res = epoch_to_rfc3339_utc(parsed).expect("converting to RFC failed");
     assert_eq!(expected_utc, res);
 }
+
+#[test]
+fn test_rfc3339_variants() {
+    let epoch = 1609459200; // 2021-01-01T00:00:00Z
+
+    assert_eq!(parse_rfc3339("2021-01-01T00:00:00.123456Z").unwrap(), epoch);
+    assert_eq!(parse_rfc3339("2021-01-01t00:00:00z").unwrap(), epoch);
+    assert_eq!(parse_rfc3339("2021-01-01T01:00:00.5+01:00").unwrap(), epoch);
+
+    assert!(parse_rfc3339("2021-02-29T00:00:00Z").is_err());
+    assert!(parse_rfc3339("2021-00-10T00:00:00Z").is_err());
+    assert!(parse_rfc3339("2021-01-00T00:00:00Z").is_err());
+    assert!(parse_rfc3339("2021-01-01T00:00:00.Z").is_err());
+    assert!(parse_rfc3339("2020-02-29T00:00:00Z").is_ok());
+}
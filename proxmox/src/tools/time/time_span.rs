@@ -0,0 +1,232 @@
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_HOUR: u64 = 60 * SECONDS_PER_MINUTE;
+const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+const SECONDS_PER_WEEK: u64 = 7 * SECONDS_PER_DAY;
+// average lengths, like systemd (30.44 and 365.25 days)
+const SECONDS_PER_MONTH: u64 = 2_629_800;
+const SECONDS_PER_YEAR: u64 = 31_557_600;
+
+/// A time span like `1h 30min` or `2weeks`, using the systemd.time(7) syntax.
+///
+/// Numbers without unit are seconds. Units can be combined, with or without spaces, for example
+/// `1h30m`. Months and years are the average lengths used by systemd.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use proxmox::tools::time::TimeSpan;
+/// let span: TimeSpan = "1h30m".parse().unwrap();
+/// assert_eq!(Duration::from(span), Duration::from_secs(5400));
+/// assert_eq!(span.to_string(), "1h 30min");
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TimeSpan {
+    pub nsec: u64,
+    pub usec: u64,
+    pub msec: u64,
+    pub seconds: u64,
+    pub minutes: u64,
+    pub hours: u64,
+    pub days: u64,
+    pub weeks: u64,
+    pub months: u64,
+    pub years: u64,
+}
+
+impl TimeSpan {
+    fn unit_mut(&mut self, unit: &str) -> Option<&mut u64> {
+        Some(match unit {
+            "" | "seconds" | "second" | "sec" | "s" => &mut self.seconds,
+            "msec" | "ms" => &mut self.msec,
+            "usec" | "us" | "µs" => &mut self.usec,
+            "nsec" | "ns" => &mut self.nsec,
+            "minutes" | "minute" | "min" | "m" => &mut self.minutes,
+            "hours" | "hour" | "hr" | "h" => &mut self.hours,
+            "days" | "day" | "d" => &mut self.days,
+            "weeks" | "week" | "w" => &mut self.weeks,
+            "months" | "month" | "M" => &mut self.months,
+            "years" | "year" | "y" | "Y" => &mut self.years,
+            _ => return None,
+        })
+    }
+
+    /// The span in whole seconds, ignoring the sub-second parts.
+    fn whole_seconds(&self) -> u64 {
+        [
+            (self.seconds, 1),
+            (self.minutes, SECONDS_PER_MINUTE),
+            (self.hours, SECONDS_PER_HOUR),
+            (self.days, SECONDS_PER_DAY),
+            (self.weeks, SECONDS_PER_WEEK),
+            (self.months, SECONDS_PER_MONTH),
+            (self.years, SECONDS_PER_YEAR),
+        ]
+        .iter()
+        .fold(0u64, |sum, (count, factor)| {
+            sum.saturating_add(count.saturating_mul(*factor))
+        })
+    }
+
+    /// The span in seconds, saturating at `u64::MAX`.
+    pub fn as_secs(&self) -> u64 {
+        Duration::from(*self).as_secs()
+    }
+}
+
+impl From<TimeSpan> for Duration {
+    /// Saturates at the largest representable duration.
+    fn from(span: TimeSpan) -> Duration {
+        let nanos = span
+            .nsec
+            .saturating_add(span.usec.saturating_mul(1_000))
+            .saturating_add(span.msec.saturating_mul(1_000_000));
+        Duration::from_secs(span.whole_seconds())
+            .checked_add(Duration::from_nanos(nanos))
+            .unwrap_or_else(|| Duration::new(u64::max_value(), 999_999_999))
+    }
+}
+
+impl From<Duration> for TimeSpan {
+    /// Splits the duration into weeks and smaller units.
+    fn from(duration: Duration) -> TimeSpan {
+        let secs = duration.as_secs();
+        let nanos = u64::from(duration.subsec_nanos());
+        TimeSpan {
+            nsec: nanos % 1_000,
+            usec: (nanos / 1_000) % 1_000,
+            msec: nanos / 1_000_000,
+            seconds: secs % SECONDS_PER_MINUTE,
+            minutes: (secs % SECONDS_PER_HOUR) / SECONDS_PER_MINUTE,
+            hours: (secs % SECONDS_PER_DAY) / SECONDS_PER_HOUR,
+            days: (secs % SECONDS_PER_WEEK) / SECONDS_PER_DAY,
+            weeks: secs / SECONDS_PER_WEEK,
+            months: 0,
+            years: 0,
+        }
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts = [
+            (self.years, "y"),
+            (self.months, "M"),
+            (self.weeks, "w"),
+            (self.days, "d"),
+            (self.hours, "h"),
+            (self.minutes, "min"),
+            (self.seconds, "s"),
+            (self.msec, "ms"),
+            (self.usec, "us"),
+            (self.nsec, "ns"),
+        ];
+
+        let mut first = true;
+        for (count, unit) in parts.iter() {
+            if *count == 0 {
+                continue;
+            }
+            if !first {
+                f.write_str(" ")?;
+            }
+            first = false;
+            write!(f, "{}{}", count, unit)?;
+        }
+
+        if first {
+            f.write_str("0s")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for TimeSpan {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut span = TimeSpan::default();
+
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            bail!("empty time span");
+        }
+
+        while !rest.is_empty() {
+            let number_len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or_else(|| rest.len());
+            if number_len == 0 {
+                bail!("expected a number in time span {:?}", s);
+            }
+            let count: u64 = rest[..number_len]
+                .parse()
+                .map_err(|err| format_err!("invalid number in time span {:?} - {}", s, err))?;
+            rest = rest[number_len..].trim_start();
+
+            let unit_len = rest
+                .find(|c: char| !c.is_alphabetic())
+                .unwrap_or_else(|| rest.len());
+            let unit = &rest[..unit_len];
+            rest = rest[unit_len..].trim_start();
+
+            let value = span
+                .unit_mut(unit)
+                .ok_or_else(|| format_err!("unknown unit {:?} in time span {:?}", unit, s))?;
+            *value = match value.checked_add(count) {
+                Some(value) => value,
+                None => bail!("time span {:?} is too large", s),
+            };
+        }
+
+        Ok(span)
+    }
+}
+
+forward_deserialize_to_from_str!(TimeSpan);
+forward_serialize_to_display!(TimeSpan);
+
+fn verify_time_span(s: &str) -> Result<(), Error> {
+    s.parse::<TimeSpan>().map(drop)
+}
+
+/// Schema for time span parameters.
+pub const TIME_SPAN_SCHEMA: Schema =
+    StringSchema::new("Time span, for example '1h 30min', '2weeks' or '90' (seconds).")
+        .format(&ApiStringFormat::VerifyFn(verify_time_span))
+        .min_length(1)
+        .max_length(64)
+        .schema();
+
+#[test]
+fn test_time_span() {
+    let secs = |s: &str| s.parse::<TimeSpan>().map(|span| span.as_secs());
+
+    assert_eq!(secs("90").unwrap(), 90);
+    assert_eq!(secs("1h30m").unwrap(), 5400);
+    assert_eq!(secs("1h 30 min 15").unwrap(), 5415);
+    assert_eq!(secs("2weeks").unwrap(), 14 * 86400);
+    assert_eq!(secs("1 year").unwrap(), SECONDS_PER_YEAR);
+    assert_eq!(secs("1m 1M").unwrap(), 60 + SECONDS_PER_MONTH);
+    assert!(secs("").is_err());
+    assert!(secs("h").is_err());
+    assert!(secs("5 fortnights").is_err());
+    assert!(secs("1.5h").is_err());
+
+    let span: TimeSpan = "1500ms".parse().unwrap();
+    assert_eq!(Duration::from(span), Duration::from_millis(1500));
+
+    let span = TimeSpan::from(Duration::new(8 * 86400 + 3661, 2_000_003));
+    assert_eq!(span.to_string(), "1w 1d 1h 1min 1s 2ms 3ns");
+    assert_eq!(span.to_string().parse::<TimeSpan>().unwrap(), span);
+    assert_eq!(TimeSpan::default().to_string(), "0s");
+
+    let span: TimeSpan = serde_json::from_str("\"2d 12h\"").unwrap();
+    assert_eq!(span.as_secs(), 60 * 3600);
+    assert_eq!(serde_json::to_string(&span).unwrap(), "\"2d 12h\"");
+}
@@ -3,6 +3,7 @@ use anyhow::Error;
 use super::{gmtime, localtime, timegm, timelocal};
 
 /// Safely Manipulate Date and Time
+#[derive(Clone)]
 pub struct TmEditor {
     utc: bool,
     t: libc::tm,
@@ -87,6 +88,17 @@ impl TmEditor {
         self.normalize_time()
     }
 
+    /// increases the hour by 'hours' and resets all smaller fields to their minimum
+    pub fn add_hours(&mut self, hours: libc::c_int) -> Result<(), Error> {
+        if hours == 0 {
+            return Ok(());
+        }
+        self.t.tm_min = 0;
+        self.t.tm_sec = 0;
+        self.t.tm_hour += hours;
+        self.normalize_time()
+    }
+
     pub fn year(&self) -> libc::c_int {
         self.t.tm_year + 1900
     } // see man mktime
@@ -169,3 +181,26 @@ impl TmEditor {
         self.normalize_time()
     }
 }
+
+#[test]
+fn test_tm_editor() {
+    // 2021-01-31T22:30:15Z
+    let mut t = TmEditor::with_epoch(1612132215, true).unwrap();
+    t.add_hours(3).unwrap();
+    assert_eq!((t.year(), t.month(), t.day()), (2021, 2, 1));
+    assert_eq!((t.hour(), t.min(), t.sec()), (1, 0, 0));
+
+    t.add_months(11).unwrap();
+    assert_eq!((t.year(), t.month(), t.day(), t.hour()), (2022, 1, 1, 0));
+
+    t.set_mday(31).unwrap();
+    t.add_days(29).unwrap();
+    assert_eq!((t.month(), t.day()), (3, 1));
+    // 2022-03-01 is a Tuesday
+    assert_eq!(t.day_num(), 1);
+
+    t.add_years(-2).unwrap();
+    t.set_time(25, 0, 0).unwrap();
+    assert_eq!((t.year(), t.month(), t.day(), t.hour()), (2020, 1, 2, 1));
+    assert_eq!(t.into_epoch().unwrap(), 1577926800);
+}
@@ -1,21 +1,14 @@
-//! Simple bindings to libuuid's `uuid_generate`.
+//! Random (version 4) UUIDs.
 
 use std::borrow::{Borrow, BorrowMut};
 use std::fmt;
 
 use anyhow::{bail, Error};
-use serde::{Serialize, Serializer};
 
-use crate::tools::parse::hex_nibble;
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+use crate::tools::hex;
 
-#[link(name = "uuid")]
-extern "C" {
-    fn uuid_generate(out: *mut [u8; 16]);
-    fn uuid_unparse_lower(input: *const [u8; 16], out: *mut u8);
-    fn uuid_unparse_upper(input: *const [u8; 16], out: *mut u8);
-}
-
-/// Uuid generated with the system's native libuuid.
+/// A UUID, version 4 ones can be generated from the kernel's random number generator.
 ///
 /// ```
 /// use proxmox::tools::uuid::Uuid;
@@ -36,13 +29,28 @@ extern "C" {
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Uuid(Box<[u8; 16]>);
 
+// byte offsets of the hyphens in the hyphenated format
+const HYPHENS: [usize; 4] = [8, 13, 18, 23];
+
 impl Uuid {
-    /// Generate a uuid with `uuid_generate(3)`.
+    /// Generate a random (version 4) uuid.
+    ///
+    /// This panics if no random data is available, use `new_v4` to handle that case.
     pub fn generate() -> Self {
-        use std::alloc::{alloc, Layout};
-        let uuid = unsafe { alloc(Layout::new::<[u8; 16]>()) as *mut [u8; 16] };
-        unsafe { uuid_generate(uuid) };
-        Self(unsafe { Box::from_raw(uuid) })
+        Self::new_v4().expect("failed to generate uuid")
+    }
+
+    /// Generate a random (version 4) uuid.
+    ///
+    /// This uses `fill_with_urandom`, so it does not block early during boot, but the result may
+    /// be predictable then. The uuid is unique enough to identify things, but must not be used as
+    /// a secret.
+    pub fn new_v4() -> Result<Self, Error> {
+        let mut uuid = [0u8; 16];
+        crate::sys::linux::fill_with_urandom(&mut uuid)?;
+        uuid[6] = (uuid[6] & 0x0f) | 0x40; // version 4
+        uuid[8] = (uuid[8] & 0x3f) | 0x80; // RFC 4122 variant
+        Ok(Self::from(uuid))
     }
 
     /// Get a reference to the internal 16 byte array.
@@ -55,6 +63,16 @@ impl Uuid {
         self.0
     }
 
+    /// The version number stored in the uuid, 4 for random ones.
+    pub fn version(&self) -> u8 {
+        self.0[6] >> 4
+    }
+
+    /// Format without hyphens, as 32 lower case hex digits.
+    pub fn to_simple_string(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+
     /// Parse a uuid in optionally-hyphenated format.
     ///
     /// ```
@@ -70,41 +88,34 @@ impl Uuid {
     /// assert_eq!(uuid1, uuid2);
     /// ```
     pub fn parse_str(src: &str) -> Result<Self, Error> {
-        use std::alloc::{alloc, Layout};
-        let uuid: *mut [u8; 16] = unsafe { alloc(Layout::new::<[u8; 16]>()) as *mut [u8; 16] };
-        if src.len() == 36 {
-            // Unfortunately the manpage of `uuid_parse(3)` states that it technically requiers a
-            // terminating null byte at the end, which we don't have, so do this manually:
-            let uuid: &mut [u8] = unsafe { &mut (*uuid)[..] };
-            let src = src.as_bytes();
-            if src[8] != b'-' || src[13] != b'-' || src[18] != b'-' || src[23] != b'-' {
-                bail!("failed to parse uuid");
-            }
-            for i in 0..4 {
-                uuid[i] = hex_nibble(src[2 * i])? << 4 | hex_nibble(src[2 * i + 1])?;
-            }
-            for i in 4..6 {
-                uuid[i] = hex_nibble(src[2 * i + 1])? << 4 | hex_nibble(src[2 * i + 2])?;
-            }
-            for i in 6..8 {
-                uuid[i] = hex_nibble(src[2 * i + 2])? << 4 | hex_nibble(src[2 * i + 3])?;
+        let src = src.as_bytes();
+        let digits: Vec<u8> = match src.len() {
+            36 => {
+                if HYPHENS.iter().any(|&pos| src[pos] != b'-') {
+                    bail!("failed to parse uuid");
+                }
+                src.iter().copied().filter(|&b| b != b'-').collect()
             }
-            for i in 8..10 {
-                uuid[i] = hex_nibble(src[2 * i + 3])? << 4 | hex_nibble(src[2 * i + 4])?;
+            32 => src.to_vec(),
+            _ => bail!("unrecognized uuid format"),
+        };
+        let mut uuid = [0u8; 16];
+        hex::decode_to_slice(&digits, &mut uuid)?;
+        Ok(Self::from(uuid))
+    }
+
+    fn format(&self, f: &mut fmt::Formatter, upper: bool) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                f.write_str("-")?;
             }
-            for i in 10..16 {
-                uuid[i] = hex_nibble(src[2 * i + 4])? << 4 | hex_nibble(src[2 * i + 5])?;
+            if upper {
+                write!(f, "{:02X}", byte)?;
+            } else {
+                write!(f, "{:02x}", byte)?;
             }
-        } else if src.len() == 32 {
-            let uuid: &mut [u8] = unsafe { &mut (*uuid)[..] };
-            let src = src.as_bytes();
-            for i in 0..16 {
-                uuid[i] = hex_nibble(src[2 * i])? << 4 | hex_nibble(src[2 * i + 1])?;
-            }
-        } else {
-            bail!("unrecognized uuid format");
         }
-        Ok(Self(unsafe { Box::from_raw(uuid) }))
+        Ok(())
     }
 }
 
@@ -162,25 +173,13 @@ impl fmt::Display for Uuid {
 
 impl fmt::LowerHex for Uuid {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut buf = [0u8; 37];
-        unsafe {
-            uuid_unparse_lower(self.as_bytes(), buf.as_mut_ptr());
-        }
-        write!(f, "{}", unsafe {
-            std::str::from_utf8_unchecked(&buf[..36])
-        })
+        self.format(f, false)
     }
 }
 
 impl fmt::UpperHex for Uuid {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut buf = [0u8; 37];
-        unsafe {
-            uuid_unparse_upper(self.as_bytes(), buf.as_mut_ptr());
-        }
-        write!(f, "{}", unsafe {
-            std::str::from_utf8_unchecked(&buf[..36])
-        })
+        self.format(f, true)
     }
 }
 
@@ -192,20 +191,22 @@ impl std::str::FromStr for Uuid {
     }
 }
 
-impl Serialize for Uuid {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut buf = [0u8; 37];
-        unsafe {
-            uuid_unparse_lower(self.as_bytes(), buf.as_mut_ptr());
-        }
-        serializer.serialize_str(unsafe { std::str::from_utf8_unchecked(&buf[..36]) })
-    }
+forward_serialize_to_display!(Uuid);
+forward_deserialize_to_from_str!(Uuid);
+
+fn verify_uuid(s: &str) -> Result<(), Error> {
+    Uuid::parse_str(s).map(drop)
 }
 
-forward_deserialize_to_from_str!(Uuid);
+/// Accepts uuids in hyphenated and simple format.
+pub const UUID_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(verify_uuid);
+
+/// Schema for uuids in hyphenated and simple format.
+pub const UUID_SCHEMA: Schema = StringSchema::new("UUID.")
+    .format(&UUID_FORMAT)
+    .min_length(32)
+    .max_length(36)
+    .schema();
 
 #[test]
 fn test_uuid() {
@@ -213,4 +214,23 @@ fn test_uuid() {
     let ser: String = serde_json::to_string(&uuid).expect("failed to serialize uuid");
     let de: Uuid = serde_json::from_str(&ser).expect("failed to deserialize uuid");
     assert_eq!(uuid, de);
+
+    let uuid: Uuid = "65B85639-78d7-4330-85c6-39502b2f9b01".parse().unwrap();
+    assert_eq!(uuid.to_string(), "65b85639-78d7-4330-85c6-39502b2f9b01");
+    assert_eq!(
+        format!("{:X}", uuid),
+        "65B85639-78D7-4330-85C6-39502B2F9B01"
+    );
+    assert_eq!(uuid.to_simple_string(), "65b8563978d7433085c639502b2f9b01");
+    assert_eq!(uuid.version(), 4);
+
+    assert!(Uuid::parse_str("65b85639x78d7-4330-85c6-39502b2f9b01").is_err());
+    assert!(Uuid::parse_str("65b85639-78d7-4330-85c6-39502b2f9b0g").is_err());
+    assert!(Uuid::parse_str("65b8563978d7433085c639502b2f9b0").is_err());
+    assert!(verify_uuid("65b8563978d7433085c639502b2f9b01").is_ok());
+
+    let uuid = Uuid::generate();
+    assert_eq!(uuid.version(), 4);
+    assert_eq!(uuid.as_bytes()[8] & 0xc0, 0x80);
+    assert_ne!(uuid, Uuid::generate());
 }
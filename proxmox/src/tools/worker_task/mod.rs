@@ -0,0 +1,458 @@
+//! Long running tasks with persistent, timestamped logs.
+//!
+//! Every task gets a [`UPID`] and a log file below the [`TaskManager`]'s log directory. When a
+//! task finishes, its final state is written as the last log line, so the state of a task can be
+//! queried later, and from other processes, by looking at its log file.
+//!
+//! ```no_run
+//! # use proxmox::tools::fs::CreateOptions;
+//! # use proxmox::tools::worker_task::{TaskManager, WorkerTask};
+//! # fn code() -> Result<(), anyhow::Error> {
+//! let manager = TaskManager::new(
+//!     "/var/log/mydaemon/tasks",
+//!     CreateOptions::new(),
+//!     CreateOptions::new(),
+//! );
+//!
+//! let worker_id = Some("store1".to_string());
+//! let upid = WorkerTask::new_thread(&manager, "verify", worker_id, "root@pam", false, |worker| {
+//!     worker.log("verifying store1");
+//!     worker.fail_on_abort()?;
+//!     Ok(())
+//! })?;
+//!
+//! println!("{}: {}", upid, manager.task_state(&upid)?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+
+use crate::sys::linux::procfs;
+use crate::tools::fs::{create_path, make_tmp_file_in, CreateOptions};
+use crate::tools::time::{epoch_i64, epoch_to_rfc3339, parse_rfc3339};
+
+mod upid;
+pub use upid::*;
+
+/// The state of a task.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TaskState {
+    /// The task is still running.
+    Running,
+    /// The task's process is gone without recording a final state.
+    Unknown,
+    /// The task finished successfully.
+    Ok { endtime: i64 },
+    /// The task finished, but logged warnings.
+    Warnings { count: u64, endtime: i64 },
+    /// The task failed.
+    Error { message: String, endtime: i64 },
+}
+
+impl TaskState {
+    pub fn endtime(&self) -> Option<i64> {
+        match self {
+            TaskState::Running | TaskState::Unknown => None,
+            TaskState::Ok { endtime }
+            | TaskState::Warnings { endtime, .. }
+            | TaskState::Error { endtime, .. } => Some(*endtime),
+        }
+    }
+
+    /// Parse a final log line as written by `WorkerTask::log_result`.
+    fn from_log_line(line: &str) -> Option<Self> {
+        let pos = line.find(": ")?;
+        let endtime = parse_rfc3339(&line[..pos]).ok()?;
+        let state = line[(pos + 2)..].strip_prefix("TASK ")?;
+
+        if state == "OK" {
+            Some(TaskState::Ok { endtime })
+        } else if let Some(count) = state.strip_prefix("WARNINGS: ") {
+            Some(TaskState::Warnings {
+                count: count.parse().ok()?,
+                endtime,
+            })
+        } else {
+            state
+                .strip_prefix("ERROR: ")
+                .map(|message| TaskState::Error {
+                    message: message.to_string(),
+                    endtime,
+                })
+        }
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskState::Running => f.write_str("running"),
+            TaskState::Unknown => f.write_str("unknown"),
+            TaskState::Ok { .. } => f.write_str("OK"),
+            TaskState::Warnings { count, .. } => write!(f, "WARNINGS: {}", count),
+            TaskState::Error { message, .. } => write!(f, "ERROR: {}", message),
+        }
+    }
+}
+
+/// Keeps track of the tasks started by this process and where task logs are stored.
+pub struct TaskManager {
+    log_dir: PathBuf,
+    dir_opts: CreateOptions,
+    file_opts: CreateOptions,
+    running: Mutex<HashMap<UPID, Arc<WorkerTask>>>,
+}
+
+impl TaskManager {
+    /// Create a task manager storing logs below `log_dir`. Missing directories are created with
+    /// `dir_opts`, log files with `file_opts`.
+    pub fn new<P: Into<PathBuf>>(
+        log_dir: P,
+        dir_opts: CreateOptions,
+        file_opts: CreateOptions,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            log_dir: log_dir.into(),
+            dir_opts,
+            file_opts,
+            running: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The path of a task's log file.
+    pub fn log_path(&self, upid: &UPID) -> PathBuf {
+        // spread the logs over 256 directories
+        let mut path = self.log_dir.join(format!("{:02X}", upid.pstart % 256));
+        path.push(upid.to_string());
+        path
+    }
+
+    /// The tasks of this process which are still running.
+    pub fn running_tasks(&self) -> Vec<UPID> {
+        self.running.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Get a running task of this process.
+    pub fn find_running(&self, upid: &UPID) -> Option<Arc<WorkerTask>> {
+        self.running.lock().unwrap().get(upid).cloned()
+    }
+
+    /// Query the state of a task, which may belong to another process.
+    pub fn task_state(&self, upid: &UPID) -> Result<TaskState, Error> {
+        if self.running.lock().unwrap().contains_key(upid) {
+            return Ok(TaskState::Running);
+        }
+
+        let path = self.log_path(upid);
+        let last_line = read_last_line(&path)
+            .map_err(|err| format_err!("unable to read task log {:?} - {}", path, err))?;
+        if let Some(state) = TaskState::from_log_line(&last_line) {
+            return Ok(state);
+        }
+
+        if procfs::check_process_running_pstart(upid.pid, upid.pstart).is_some() {
+            Ok(TaskState::Running)
+        } else {
+            Ok(TaskState::Unknown)
+        }
+    }
+}
+
+/// The last line of a file, reading at most its last 4 KiB.
+fn read_last_line(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(4096)))?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let data = String::from_utf8_lossy(&data);
+    Ok(data.trim_end().lines().last().unwrap_or("").to_string())
+}
+
+struct TaskLog {
+    file: File,
+    warnings: u64,
+}
+
+/// A running task, see the module documentation.
+pub struct WorkerTask {
+    manager: Arc<TaskManager>,
+    upid: UPID,
+    log: Mutex<TaskLog>,
+    abort_requested: AtomicBool,
+    to_stdout: bool,
+}
+
+impl WorkerTask {
+    /// Register a new task and create its log file. The task is running until `log_result` is
+    /// called. If `to_stdout` is set, log lines are printed as well.
+    ///
+    /// The task manager keeps running tasks alive, so callers must always call `log_result`,
+    /// even on errors, otherwise the task is never freed and stays in the running state until
+    /// the process exits. `new_thread` takes care of this.
+    pub fn new(
+        manager: &Arc<TaskManager>,
+        worker_type: &str,
+        worker_id: Option<String>,
+        auth_id: &str,
+        to_stdout: bool,
+    ) -> Result<Arc<Self>, Error> {
+        let upid = UPID::new(worker_type, worker_id, auth_id)?;
+
+        let path = manager.log_path(&upid);
+        let dir = path.parent().unwrap();
+        create_path(dir, None, Some(manager.dir_opts.clone()))?;
+        let file = make_tmp_file_in(dir, manager.file_opts.clone())?.persist(&path)?;
+
+        let worker = Arc::new(Self {
+            manager: Arc::clone(manager),
+            upid: upid.clone(),
+            log: Mutex::new(TaskLog { file, warnings: 0 }),
+            abort_requested: AtomicBool::new(false),
+            to_stdout,
+        });
+
+        manager
+            .running
+            .lock()
+            .unwrap()
+            .insert(upid, Arc::clone(&worker));
+
+        worker.log(format!("starting task {}", worker.upid));
+
+        Ok(worker)
+    }
+
+    /// Run `func` as a new task in its own thread and return its UPID. The result of `func` is
+    /// logged as the task's final state, a panic counts as error.
+    pub fn new_thread<F>(
+        manager: &Arc<TaskManager>,
+        worker_type: &str,
+        worker_id: Option<String>,
+        auth_id: &str,
+        to_stdout: bool,
+        func: F,
+    ) -> Result<UPID, Error>
+    where
+        F: FnOnce(Arc<WorkerTask>) -> Result<(), Error> + Send + 'static,
+    {
+        let worker = Self::new(manager, worker_type, worker_id, auth_id, to_stdout)?;
+        let upid = worker.upid.clone();
+        let task = Arc::clone(&worker);
+
+        let spawned = std::thread::Builder::new()
+            .name(format!("worker-{}", upid.task_id))
+            .spawn(move || {
+                let task = Arc::clone(&worker);
+                let result = match std::panic::catch_unwind(AssertUnwindSafe(move || func(task))) {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        let msg = panic
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown cause");
+                        Err(format_err!("task panicked - {}", msg))
+                    }
+                };
+                worker.log_result(&result);
+            });
+
+        if let Err(err) = spawned {
+            let err = format_err!("unable to spawn task thread - {}", err);
+            task.log_result(&Err(format_err!("{}", err)));
+            return Err(err);
+        }
+
+        Ok(upid)
+    }
+
+    pub fn upid(&self) -> &UPID {
+        &self.upid
+    }
+
+    fn write_line(&self, log: &mut TaskLog, time: &str, line: &str) {
+        if let Err(err) = writeln!(log.file, "{}: {}", time, line) {
+            eprintln!("unable to write log of task {} - {}", self.upid, err);
+        }
+        if self.to_stdout {
+            println!("{}", line);
+        }
+    }
+
+    fn write_lines(&self, log: &mut TaskLog, msg: &str) {
+        let time = epoch_to_rfc3339(epoch_i64()).unwrap_or_else(|_| "unknown time".to_string());
+        for line in msg.lines() {
+            // only `log_result` may write lines which look like a final state
+            if line.starts_with("TASK ") {
+                self.write_line(log, &time, &format!("\\{}", line));
+            } else {
+                self.write_line(log, &time, line);
+            }
+        }
+    }
+
+    /// Add a message to the task log.
+    pub fn log<S: AsRef<str>>(&self, msg: S) {
+        let mut log = self.log.lock().unwrap();
+        self.write_lines(&mut log, msg.as_ref());
+    }
+
+    /// Add a warning to the task log. Tasks with warnings finish in the `Warnings` state.
+    pub fn warn<S: AsRef<str>>(&self, msg: S) {
+        let mut log = self.log.lock().unwrap();
+        log.warnings += 1;
+        self.write_lines(&mut log, &format!("WARN: {}", msg.as_ref()));
+    }
+
+    /// Ask the task to stop. The task has to check this via `abort_requested` or
+    /// `fail_on_abort`.
+    pub fn request_abort(&self) {
+        if !self.abort_requested.swap(true, Ordering::SeqCst) {
+            self.log("received abort request");
+        }
+    }
+
+    pub fn abort_requested(&self) -> bool {
+        self.abort_requested.load(Ordering::SeqCst)
+    }
+
+    /// Fail if an abort was requested.
+    pub fn fail_on_abort(&self) -> Result<(), Error> {
+        if self.abort_requested() {
+            bail!("abort requested - aborting task");
+        }
+        Ok(())
+    }
+
+    /// Record the final state of the task and remove it from the running tasks.
+    pub fn log_result(&self, result: &Result<(), Error>) {
+        {
+            let mut log = self.log.lock().unwrap();
+            let endtime = epoch_i64();
+            let time = epoch_to_rfc3339(endtime).unwrap_or_else(|_| "unknown time".to_string());
+            let state = match result {
+                Err(err) => TaskState::Error {
+                    // the state has to fit into the last line of the log
+                    message: err.to_string().replace('\n', " "),
+                    endtime,
+                },
+                Ok(()) if log.warnings > 0 => TaskState::Warnings {
+                    count: log.warnings,
+                    endtime,
+                },
+                Ok(()) => TaskState::Ok { endtime },
+            };
+            self.write_line(&mut log, &time, &format!("TASK {}", state));
+            let _ = log.file.sync_data();
+        }
+
+        self.manager.running.lock().unwrap().remove(&self.upid);
+    }
+}
+
+#[test]
+fn test_worker_task() {
+    let log_dir =
+        std::env::temp_dir().join(format!("proxmox-worker-task-test-{}", std::process::id()));
+    let manager = TaskManager::new(&log_dir, CreateOptions::new(), CreateOptions::new());
+
+    let worker = WorkerTask::new(
+        &manager,
+        "test",
+        Some("a:b\\c".to_string()),
+        "root@pam",
+        false,
+    )
+    .unwrap();
+    let upid = worker.upid().clone();
+    assert_eq!(manager.running_tasks(), [upid.clone()]);
+    assert_eq!(manager.task_state(&upid).unwrap(), TaskState::Running);
+
+    let parsed: UPID = upid.to_string().parse().unwrap();
+    assert_eq!(parsed, upid);
+    assert_eq!(parsed.worker_id.as_deref(), Some("a:b\\c"));
+
+    worker.warn("something odd");
+    worker.log("TASK OK");
+    let last_line = read_last_line(&manager.log_path(&upid)).unwrap();
+    assert_eq!(TaskState::from_log_line(&last_line), None);
+    worker.request_abort();
+    assert!(worker.fail_on_abort().is_err());
+    worker.log_result(&Ok(()));
+    assert!(manager.running_tasks().is_empty());
+    match manager.task_state(&upid).unwrap() {
+        TaskState::Warnings { count: 1, .. } => (),
+        other => panic!("unexpected task state {:?}", other),
+    }
+
+    let upid = WorkerTask::new_thread(&manager, "test", None, "root@pam", false, |worker| {
+        worker.log("line 1\nline 2");
+        bail!("failed\nbadly");
+    })
+    .unwrap();
+    while manager.find_running(&upid).is_some() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let state = manager.task_state(&upid);
+    let log = std::fs::read_to_string(manager.log_path(&upid));
+
+    match state.unwrap() {
+        TaskState::Error { message, .. } => assert_eq!(message, "failed badly"),
+        other => panic!("unexpected task state {:?}", other),
+    }
+    let log = log.unwrap();
+    assert_eq!(log.lines().count(), 4);
+    assert!(log.lines().nth(2).unwrap().ends_with(": line 2"));
+
+    let upid = WorkerTask::new_thread(&manager, "test", None, "root@pam", false, |_| {
+        panic!("oops");
+    })
+    .unwrap();
+    while manager.find_running(&upid).is_some() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let panic_state = manager.task_state(&upid);
+
+    // worker ids must not escape the log directory
+    let worker = WorkerTask::new(
+        &manager,
+        "test",
+        Some("../../store/ns".to_string()),
+        "root@pam",
+        false,
+    )
+    .unwrap();
+    worker.log_result(&Ok(()));
+    let path = manager.log_path(worker.upid());
+    let path_exists = path.exists();
+    let _ = std::fs::remove_dir_all(&log_dir);
+
+    match panic_state.unwrap() {
+        TaskState::Error { message, .. } => assert_eq!(message, "task panicked - oops"),
+        other => panic!("unexpected task state {:?}", other),
+    }
+    assert_eq!(path.parent().unwrap().parent().unwrap(), log_dir);
+    assert!(path_exists);
+    assert!(UPID::new("te/st", None, "root@pam").is_err());
+
+    for invalid in &[
+        "",
+        "UPID:",
+        "UPID:node:1:2:3:4:type::root@pam",
+        "UPID:node:1:2:3:x:type::root@pam:",
+    ] {
+        assert!(invalid.parse::<UPID>().is_err(), "{:?}", invalid);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{bail, format_err, Error};
+use nix::unistd::Pid;
+
+use crate::api::schema::{ApiStringFormat, Schema, StringSchema};
+use crate::sys::linux::procfs::PidStat;
+use crate::tools::time::epoch_i64;
+
+/// Unique Process/Task IDentifier.
+///
+/// Identifies a worker task across processes and restarts, and is used as the name of its log
+/// file. The string representation is
+/// `UPID:NODE:PID:PSTART:TASK_ID:STARTTIME:WORKER_TYPE:WORKER_ID:AUTH_ID:`, where the numbers are
+/// upper case hex and `WORKER_ID` has colons, slashes and backslashes escaped as `\xNN`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UPID {
+    /// The id of the process running the task.
+    pub pid: libc::pid_t,
+    /// The start time of that process in clock ticks since boot, to detect reused pids.
+    pub pstart: u64,
+    /// The start time of the task as unix epoch.
+    pub starttime: i64,
+    /// Distinguishes tasks started by the same process.
+    pub task_id: usize,
+    /// The kind of task, like `backup` or `garbage_collection`.
+    pub worker_type: String,
+    /// The object the task works on, if any.
+    pub worker_id: Option<String>,
+    /// The user or API token which started the task.
+    pub auth_id: String,
+    /// The node the task runs on.
+    pub node: String,
+}
+
+static NEXT_TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn verify_upid_component(what: &str, value: &str) -> Result<(), Error> {
+    // the UPID is used as a file name, so no component may contain a slash
+    if value.is_empty()
+        || value.contains(|c| c == ':' || c == '/')
+        || value.chars().any(char::is_control)
+    {
+        bail!("invalid {} {:?} for UPID", what, value);
+    }
+    Ok(())
+}
+
+fn escape_worker_id(id: &str) -> String {
+    let mut escaped = String::with_capacity(id.len());
+    for c in id.chars() {
+        match c {
+            ':' | '/' | '\\' => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_worker_id(id: &str) -> Result<String, Error> {
+    let mut unescaped = String::with_capacity(id.len());
+    let mut rest = id;
+    while let Some(pos) = rest.find('\\') {
+        unescaped.push_str(&rest[..pos]);
+        let code = rest
+            .get((pos + 2)..(pos + 4))
+            .filter(|_| rest[(pos + 1)..].starts_with('x'))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format_err!("invalid escape sequence in worker id {:?}", id))?;
+        unescaped.push(char::from(code));
+        rest = &rest[(pos + 4)..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+impl UPID {
+    /// Create a new UPID for a task started now by the current process.
+    pub fn new(worker_type: &str, worker_id: Option<String>, auth_id: &str) -> Result<Self, Error> {
+        verify_upid_component("worker type", worker_type)?;
+        verify_upid_component("auth id", auth_id)?;
+
+        let pid = Pid::this();
+        let pstart = PidStat::read_from_pid(pid)?.starttime;
+        let node = nix::sys::utsname::uname().nodename().to_string();
+        verify_upid_component("node", &node)?;
+
+        Ok(Self {
+            pid: pid.as_raw(),
+            pstart,
+            starttime: epoch_i64(),
+            task_id: NEXT_TASK_ID.fetch_add(1, Ordering::SeqCst),
+            worker_type: worker_type.to_string(),
+            worker_id,
+            auth_id: auth_id.to_string(),
+            node,
+        })
+    }
+}
+
+impl fmt::Display for UPID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let worker_id = match &self.worker_id {
+            Some(id) => escape_worker_id(id),
+            None => String::new(),
+        };
+
+        write!(
+            f,
+            "UPID:{}:{:08X}:{:08X}:{:08X}:{:08X}:{}:{}:{}:",
+            self.node,
+            self.pid,
+            self.pstart,
+            self.task_id,
+            self.starttime,
+            self.worker_type,
+            worker_id,
+            self.auth_id,
+        )
+    }
+}
+
+impl std::str::FromStr for UPID {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let parse = || -> Result<Self, Error> {
+            let inner = match s.strip_prefix("UPID:").and_then(|s| s.strip_suffix(':')) {
+                Some(inner) => inner,
+                None => bail!("missing UPID prefix or suffix"),
+            };
+
+            let parts: Vec<&str> = inner.split(':').collect();
+            if parts.len() != 8 {
+                bail!("wrong number of components");
+            }
+
+            for (what, value) in &[
+                ("node", parts[0]),
+                ("worker type", parts[5]),
+                ("auth id", parts[7]),
+            ] {
+                verify_upid_component(what, value)?;
+            }
+
+            Ok(Self {
+                node: parts[0].to_string(),
+                pid: libc::pid_t::from_str_radix(parts[1], 16)?,
+                pstart: u64::from_str_radix(parts[2], 16)?,
+                task_id: usize::from_str_radix(parts[3], 16)?,
+                starttime: i64::from_str_radix(parts[4], 16)?,
+                worker_type: parts[5].to_string(),
+                worker_id: match parts[6] {
+                    "" => None,
+                    id => Some(unescape_worker_id(id)?),
+                },
+                auth_id: parts[7].to_string(),
+            })
+        };
+
+        parse().map_err(|err| format_err!("unable to parse UPID {:?} - {}", s, err))
+    }
+}
+
+forward_deserialize_to_from_str!(UPID);
+forward_serialize_to_display!(UPID);
+
+fn verify_upid(s: &str) -> Result<(), Error> {
+    s.parse::<UPID>().map(drop)
+}
+
+pub const UPID_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(verify_upid);
+
+pub const UPID_SCHEMA: Schema = StringSchema::new("Unique Process/Task Identifier")
+    .format(&UPID_FORMAT)
+    .min_length(45)
+    .max_length(512)
+    .schema();